@@ -0,0 +1,132 @@
+//! Measures GET/SET throughput for several `keyspace-shards` values.
+//!
+//! Each run starts the server, drives it from a number of connections pipelining
+//! commands on random keys, and reports the operations per second. Sharding only pays off
+//! with several cores to run the shards on:
+//!
+//!     cargo bench --bench throughput -- [seconds] [connections]
+
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const PORT: u16 = 7690;
+const PIPELINE: usize = 16;
+const KEYS: u64 = 100_000;
+
+fn main() {
+    let arguments: Vec<String> = std::env::args().skip(1).filter(|argument| !argument.starts_with("--")).collect();
+    let seconds: u64 = arguments.first().and_then(|value| value.parse().ok()).unwrap_or(3);
+    let connections: usize = arguments.get(1).and_then(|value| value.parse().ok()).unwrap_or(32);
+    let cores: usize = std::thread::available_parallelism().map(|cores| cores.get()).unwrap_or(1);
+
+    let mut shard_counts: Vec<usize> = vec![1, 2, 4, cores];
+    shard_counts.sort();
+    shard_counts.dedup();
+
+    println!("{} cores, {} connections, {} commands per pipeline, {}s per run", cores, connections, PIPELINE, seconds);
+    let runtime: tokio::runtime::Runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut baseline: Option<f64> = None;
+    for shards in shard_counts {
+        let mut server: Child = start_server(shards);
+        let throughput: f64 = runtime.block_on(measure(connections, Duration::from_secs(seconds)));
+        let _ = server.kill();
+        let _ = server.wait();
+
+        let speedup: f64 = throughput / *baseline.get_or_insert(throughput);
+        println!("keyspace-shards {:>3}: {:>10.0} ops/s  x{:.2}", shards, throughput, speedup);
+    }
+}
+
+fn start_server(shards: usize) -> Child {
+    let child: Child = Command::new(env!("CARGO_BIN_EXE_codecrafters-redis"))
+        .args(["--port", &PORT.to_string(), "--keyspace-shards", &shards.to_string()])
+        .args(["--dir", &std::env::temp_dir().display().to_string(), "--dbfilename", "throughput-bench.rdb"])
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("could not start the server");
+
+    let deadline: Instant = Instant::now() + Duration::from_secs(5);
+    while std::net::TcpStream::connect(("127.0.0.1", PORT)).is_err() {
+        assert!(Instant::now() < deadline, "the server did not start listening");
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    child
+}
+
+async fn measure(connections: usize, duration: Duration) -> f64 {
+    let completed: Arc<AtomicU64> = Arc::new(AtomicU64::new(0));
+    let deadline: Instant = Instant::now() + duration;
+
+    let clients: Vec<tokio::task::JoinHandle<()>> = (0..connections as u64)
+        .map(|client| tokio::spawn(run_client(client, deadline, completed.clone())))
+        .collect();
+    for client in clients {
+        client.await.unwrap();
+    }
+
+    completed.load(Ordering::Relaxed) as f64 / duration.as_secs_f64()
+}
+
+/// Sends batches of `PIPELINE` commands, half SET and half GET, until the deadline.
+async fn run_client(client: u64, deadline: Instant, completed: Arc<AtomicU64>) {
+    let mut stream: TcpStream = TcpStream::connect(("127.0.0.1", PORT)).await.unwrap();
+    stream.set_nodelay(true).unwrap();
+    let mut state: u64 = client.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+    let mut buffer: Vec<u8> = Vec::new();
+
+    while Instant::now() < deadline {
+        let mut batch: Vec<u8> = Vec::new();
+        for index in 0..PIPELINE {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let key: String = format!("key:{}", state % KEYS);
+            if index % 2 == 0 {
+                batch.extend_from_slice(&encode(&["SET", &key, "value"]));
+            } else {
+                batch.extend_from_slice(&encode(&["GET", &key]));
+            }
+        }
+        stream.write_all(&batch).await.unwrap();
+
+        let mut replies: usize = 0;
+        while replies < PIPELINE {
+            let mut chunk: [u8; 4096] = [0; 4096];
+            let read: usize = stream.read(&mut chunk).await.unwrap();
+            assert!(read > 0, "the server closed the connection");
+            buffer.extend_from_slice(&chunk[..read]);
+            while let Some(length) = reply_length(&buffer) {
+                buffer.drain(..length);
+                replies += 1;
+            }
+        }
+        completed.fetch_add(PIPELINE as u64, Ordering::Relaxed);
+    }
+}
+
+fn encode(arguments: &[&str]) -> Vec<u8> {
+    let mut command: Vec<u8> = format!("*{}\r\n", arguments.len()).into_bytes();
+    for argument in arguments {
+        command.extend_from_slice(format!("${}\r\n{}\r\n", argument.len(), argument).as_bytes());
+    }
+    command
+}
+
+/// Length of the simple string or bulk string reply at the start of `buffer`, if it is
+/// complete.
+fn reply_length(buffer: &[u8]) -> Option<usize> {
+    let line_end: usize = buffer.windows(2).position(|window| window == b"\r\n")? + 2;
+    if buffer[0] != b'$' {
+        return Some(line_end);
+    }
+    let length: i64 = std::str::from_utf8(&buffer[1..line_end - 2]).ok()?.parse().ok()?;
+    if length < 0 {
+        return Some(line_end);
+    }
+    let total: usize = line_end + length as usize + 2;
+    (buffer.len() >= total).then_some(total)
+}
@@ -0,0 +1,704 @@
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::cluster::now_millis;
+use crate::command::{AclCategory, CommandFlag, CommandSpec};
+use crate::config::{self, Config};
+use crate::parser::{self, COMMAND_TABLE};
+use crate::{glob, sha256};
+
+/// The user connections start as, which `AUTH <password>` authenticates.
+pub const DEFAULT_USER: &str = "default";
+
+/// Denials of the same kind repeated within this many milliseconds share an ACL LOG entry.
+const LOG_ENTRY_GROUPING_MILLIS: u64 = 60_000;
+
+const UNKNOWN_COMMAND: &str = "Unknown command or category name in ACL";
+const PASSWORD_NOT_FOUND: &str = "The password you are trying to remove from the user does not exist";
+
+/// Whether the default user needs a password, mirrored here so connections can check it
+/// without asking the data manager.
+static PASSWORD_REQUIRED: AtomicBool = AtomicBool::new(false);
+
+/// Whether connections that did not authenticate are refused: the default user has a
+/// password or is disabled.
+pub fn password_required() -> bool {
+    PASSWORD_REQUIRED.load(Ordering::Relaxed)
+}
+
+/// What the ACL LOG records about the client whose command was denied.
+pub fn client_info(id: u64, user: &str) -> String {
+    format!("id={} user={}", id, user)
+}
+
+/// Every command and subcommand of the table, which command rules choose from.
+fn all_commands() -> impl Iterator<Item = &'static CommandSpec> {
+    COMMAND_TABLE.iter().flat_map(|spec| std::iter::once(spec).chain(spec.subcommands))
+}
+
+/// A glob pattern of the keys a selector may access, and how.
+#[derive(Clone)]
+struct KeyPattern {
+    pattern: String,
+    read: bool,
+    write: bool,
+}
+
+impl KeyPattern {
+    fn describe(&self) -> String {
+        match (self.read, self.write) {
+            (true, false) => format!("%R~{}", self.pattern),
+            (false, true) => format!("%W~{}", self.pattern),
+            _ => format!("~{}", self.pattern),
+        }
+    }
+}
+
+/// The commands, keys and pub/sub channels a set of rules allows. A user has a root
+/// selector and possibly others: a command is allowed when one of them allows it all.
+#[derive(Clone, Default)]
+pub struct Selector {
+    /// Names of the allowed commands and subcommands, as in the command table.
+    commands: HashSet<&'static str>,
+    /// Whether the command rules started from all commands rather than none.
+    from_all_commands: bool,
+    /// The command rules applied since, which describe the selector.
+    command_rules: Vec<String>,
+    all_keys: bool,
+    keys: Vec<KeyPattern>,
+    all_channels: bool,
+    channels: Vec<String>,
+}
+
+impl Selector {
+    /// A selector allowing everything, the way the default user starts.
+    fn unrestricted() -> Self {
+        let mut selector: Selector = Selector { all_keys: true, all_channels: true, ..Selector::default() };
+        selector.reset_commands(true);
+        selector
+    }
+
+    fn apply(&mut self, rule: &str) -> Result<(), String> {
+        match rule.to_ascii_lowercase().as_str() {
+            "allkeys" | "~*" => {
+                self.all_keys = true;
+                self.keys.clear();
+            }
+            "resetkeys" => {
+                self.all_keys = false;
+                self.keys.clear();
+            }
+            "allchannels" | "&*" => {
+                self.all_channels = true;
+                self.channels.clear();
+            }
+            "resetchannels" => {
+                self.all_channels = false;
+                self.channels.clear();
+            }
+            "allcommands" | "+@all" => self.reset_commands(true),
+            "nocommands" | "-@all" => self.reset_commands(false),
+            _ => {
+                if let Some(pattern) = rule.strip_prefix('~') {
+                    self.add_key_pattern(pattern, true, true)?;
+                } else if let Some(permissions) = rule.strip_prefix('%') {
+                    let (permissions, pattern) = permissions.split_once('~').ok_or("Syntax error")?;
+                    if permissions.is_empty() || !permissions.chars().all(|permission| "RrWw".contains(permission)) {
+                        return Err("Syntax error".to_string());
+                    }
+                    let read: bool = permissions.contains(['R', 'r']);
+                    let write: bool = permissions.contains(['W', 'w']);
+                    self.add_key_pattern(pattern, read, write)?;
+                } else if let Some(pattern) = rule.strip_prefix('&') {
+                    self.add_channel_pattern(pattern)?;
+                } else if let Some(name) = rule.strip_prefix('+') {
+                    self.apply_command_rule(name, true)?;
+                } else if let Some(name) = rule.strip_prefix('-') {
+                    self.apply_command_rule(name, false)?;
+                } else {
+                    return Err("Syntax error".to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn reset_commands(&mut self, allowed: bool) {
+        self.from_all_commands = allowed;
+        self.command_rules.clear();
+        self.commands.clear();
+        if allowed {
+            self.commands.extend(all_commands().map(|spec| spec.name));
+        }
+    }
+
+    fn add_key_pattern(&mut self, pattern: &str, read: bool, write: bool) -> Result<(), String> {
+        if self.all_keys {
+            return Err("Adding a pattern after the * pattern (or the 'allkeys' flag) is not valid and does not have \
+                any effect. Try 'resetkeys' to start with an empty list of patterns".to_string());
+        }
+        if pattern == "*" && read && write {
+            self.all_keys = true;
+            self.keys.clear();
+            return Ok(());
+        }
+        match self.keys.iter_mut().find(|existing| existing.pattern == pattern) {
+            Some(existing) => {
+                existing.read |= read;
+                existing.write |= write;
+            }
+            None => self.keys.push(KeyPattern { pattern: pattern.to_string(), read, write }),
+        }
+        Ok(())
+    }
+
+    fn add_channel_pattern(&mut self, pattern: &str) -> Result<(), String> {
+        if self.all_channels {
+            return Err("Adding a pattern after the * pattern (or the 'allchannels' flag) is not valid and does not \
+                have any effect. Try 'resetchannels' to start with an empty list of channels".to_string());
+        }
+        if !self.channels.iter().any(|existing| existing == pattern) {
+            self.channels.push(pattern.to_string());
+        }
+        Ok(())
+    }
+
+    /// Allows or denies a command, with its subcommands, or an `@category` of commands.
+    fn apply_command_rule(&mut self, name: &str, allowed: bool) -> Result<(), String> {
+        let sign: char = if allowed { '+' } else { '-' };
+        let mut set = |name: &'static str| {
+            if allowed {
+                self.commands.insert(name);
+            } else {
+                self.commands.remove(name);
+            }
+        };
+
+        if let Some(category) = name.strip_prefix('@') {
+            let category: AclCategory = AclCategory::from_name(category).ok_or(UNKNOWN_COMMAND)?;
+            for spec in all_commands().filter(|spec| spec.acl_categories().contains(&category)) {
+                set(spec.name);
+            }
+            self.command_rules.push(format!("{}@{}", sign, category.name()));
+            return Ok(());
+        }
+
+        let spec: &'static CommandSpec = parser::find_command(name).ok_or(UNKNOWN_COMMAND)?;
+        set(spec.name);
+        for subcommand in spec.subcommands {
+            set(subcommand.name);
+        }
+        // Earlier rules about the command or its subcommands are superseded by this one.
+        self.command_rules.retain(|rule| {
+            let other: &str = &rule[1..];
+            other != spec.name && other.strip_prefix(spec.name).is_none_or(|rest| !rest.starts_with('|'))
+        });
+        self.command_rules.push(format!("{}{}", sign, spec.name));
+        Ok(())
+    }
+
+    fn check(&self, spec: &CommandSpec, arguments: &[Vec<u8>]) -> Result<(), Denial> {
+        // Connections must always be able to authenticate.
+        if !spec.has(CommandFlag::NoAuth) && !self.commands.contains(spec.name) {
+            return Err(Denial::Command);
+        }
+        if !self.all_keys {
+            let (read, write): (bool, bool) = spec.keys.permissions();
+            for key in spec.key_arguments(arguments) {
+                let permitted: bool = self.keys.iter().any(|pattern| {
+                    (pattern.read || !read) && (pattern.write || !write)
+                        && glob::matches(pattern.pattern.as_bytes(), key, false)
+                });
+                if !permitted {
+                    return Err(Denial::Key(String::from_utf8_lossy(key).into_owned()));
+                }
+            }
+        }
+        if !self.all_channels {
+            let (channels, literal): (&[Vec<u8>], bool) = channel_arguments(spec, arguments);
+            for channel in channels {
+                let permitted: bool = self.channels.iter().any(|pattern| match literal {
+                    true => pattern.as_bytes() == channel.as_slice(),
+                    false => glob::matches(pattern.as_bytes(), channel, false),
+                });
+                if !permitted {
+                    return Err(Denial::Channel(String::from_utf8_lossy(channel).into_owned()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn describe_commands(&self) -> String {
+        let mut rules: Vec<&str> = vec![if self.from_all_commands { "+@all" } else { "-@all" }];
+        rules.extend(self.command_rules.iter().map(String::as_str));
+        rules.join(" ")
+    }
+
+    pub fn describe_keys(&self) -> String {
+        if self.all_keys {
+            return "~*".to_string();
+        }
+        self.keys.iter().map(KeyPattern::describe).collect::<Vec<String>>().join(" ")
+    }
+
+    pub fn describe_channels(&self) -> String {
+        if self.all_channels {
+            return "&*".to_string();
+        }
+        self.channels.iter().map(|pattern| format!("&{}", pattern)).collect::<Vec<String>>().join(" ")
+    }
+
+    /// The rules recreating the selector, as listed by `ACL LIST`.
+    fn describe(&self) -> String {
+        let mut parts: Vec<String> = Vec::new();
+        let keys: String = self.describe_keys();
+        if !keys.is_empty() {
+            parts.push(keys);
+        }
+        if !self.all_channels {
+            parts.push("resetchannels".to_string());
+        }
+        let channels: String = self.describe_channels();
+        if !channels.is_empty() {
+            parts.push(channels);
+        }
+        parts.push(self.describe_commands());
+        parts.join(" ")
+    }
+}
+
+/// An ACL user: whether it may authenticate, with which passwords, and what it may run.
+#[derive(Clone)]
+pub struct User {
+    pub name: String,
+    pub enabled: bool,
+    /// Whether any password is accepted.
+    pub nopass: bool,
+    /// SHA-256 digests of the passwords, in hexadecimal.
+    pub passwords: Vec<String>,
+    pub root: Selector,
+    pub selectors: Vec<Selector>,
+}
+
+impl User {
+    /// A new user, disabled and allowed nothing until rules say otherwise.
+    fn new(name: &str) -> Self {
+        User {
+            name: name.to_string(),
+            enabled: false,
+            nopass: false,
+            passwords: Vec::new(),
+            root: Selector::default(),
+            selectors: Vec::new(),
+        }
+    }
+
+    /// The default user as it is without configuration: anyone may run anything as it.
+    fn default_user() -> Self {
+        User { enabled: true, nopass: true, root: Selector::unrestricted(), ..User::new(DEFAULT_USER) }
+    }
+
+    /// Applies one rule of `ACL SETUSER` or of the ACL file.
+    fn apply(&mut self, rule: &str) -> Result<(), String> {
+        match rule.to_ascii_lowercase().as_str() {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "reset" => *self = User::new(&self.name),
+            "clearselectors" => self.selectors.clear(),
+            _ => {
+                if let Some(password) = rule.strip_prefix('>') {
+                    self.add_password_hash(sha256::hex_digest(password.as_bytes()));
+                } else if let Some(password) = rule.strip_prefix('<') {
+                    self.remove_password_hash(&sha256::hex_digest(password.as_bytes()))?;
+                } else if let Some(hash) = rule.strip_prefix('#') {
+                    self.add_password_hash(validate_hash(hash)?);
+                } else if let Some(hash) = rule.strip_prefix('!') {
+                    self.remove_password_hash(&validate_hash(hash)?)?;
+                } else if let Some(rules) = rule.strip_prefix('(').and_then(|rule| rule.strip_suffix(')')) {
+                    let mut selector: Selector = Selector::default();
+                    for rule in rules.split_whitespace() {
+                        selector.apply(rule)?;
+                    }
+                    self.selectors.push(selector);
+                } else {
+                    self.root.apply(rule)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn add_password_hash(&mut self, hash: String) {
+        self.nopass = false;
+        if !self.passwords.contains(&hash) {
+            self.passwords.push(hash);
+        }
+    }
+
+    fn remove_password_hash(&mut self, hash: &str) -> Result<(), String> {
+        let position: usize = self.passwords.iter().position(|existing| existing == hash)
+            .ok_or(PASSWORD_NOT_FOUND)?;
+        self.passwords.remove(position);
+        Ok(())
+    }
+
+    /// Whether `password` authenticates the user, comparing digests in constant time.
+    fn accepts(&self, password: &str) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        let hash: String = sha256::hex_digest(password.as_bytes());
+        // Every password is compared, so the time taken does not reveal which one matched.
+        let matched: usize = self.passwords.iter().filter(|expected| digests_match(&hash, expected)).count();
+        self.nopass || matched > 0
+    }
+
+    pub fn flags(&self) -> Vec<&'static str> {
+        let mut flags: Vec<&'static str> = vec![if self.enabled { "on" } else { "off" }];
+        if self.nopass {
+            flags.push("nopass");
+        }
+        flags
+    }
+
+    /// The rules recreating the user, one line of `ACL LIST` and of the ACL file.
+    pub fn describe(&self) -> String {
+        let mut parts: Vec<String> = vec![format!("user {}", self.name)];
+        parts.extend(self.flags().into_iter().map(String::from));
+        parts.extend(self.passwords.iter().map(|hash| format!("#{}", hash)));
+        parts.push(self.root.describe());
+        parts.extend(self.selectors.iter().map(|selector| format!("({})", selector.describe())));
+        parts.join(" ")
+    }
+
+    /// Checks a call against the selectors, reporting the most specific denial when none
+    /// allows it: a key the user may not access says more than a denied command.
+    fn check(&self, spec: &CommandSpec, arguments: &[Vec<u8>]) -> Result<(), Denial> {
+        let mut denial: Denial = match self.root.check(spec, arguments) {
+            Ok(()) => return Ok(()),
+            Err(denial) => denial,
+        };
+        for selector in &self.selectors {
+            match selector.check(spec, arguments) {
+                Ok(()) => return Ok(()),
+                Err(other) if matches!(denial, Denial::Command) => denial = other,
+                Err(_) => {}
+            }
+        }
+        Err(denial)
+    }
+}
+
+/// The pub/sub channels a call accesses, and whether they are patterns, which channel
+/// rules only allow when they are the same pattern.
+fn channel_arguments<'a>(spec: &CommandSpec, arguments: &'a [Vec<u8>]) -> (&'a [Vec<u8>], bool) {
+    match spec.name {
+        "publish" => (arguments.get(1..2).unwrap_or_default(), false),
+        "subscribe" => (arguments.get(1..).unwrap_or_default(), false),
+        "psubscribe" => (arguments.get(1..).unwrap_or_default(), true),
+        _ => (&[], false),
+    }
+}
+
+fn validate_hash(hash: &str) -> Result<String, String> {
+    if hash.len() != 64 || !hash.bytes().all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f')) {
+        return Err("The password hash must be exactly 64 characters and contain only lowercase hexadecimal \
+            characters".to_string());
+    }
+    Ok(hash.to_string())
+}
+
+/// Compares two digests in a time independent of where they differ.
+fn digests_match(given: &str, expected: &str) -> bool {
+    let mut difference: usize = given.len() ^ expected.len();
+    for (given, expected) in given.bytes().zip(expected.bytes()) {
+        difference |= (given ^ expected) as usize;
+    }
+    std::hint::black_box(difference) == 0
+}
+
+/// Joins the rules of selectors that were split over several arguments, like `(~key*` and
+/// `+get)` typed in a terminal, back into one rule.
+fn merge_selector_rules(rules: &[&str]) -> Result<Vec<String>, String> {
+    let mut merged: Vec<String> = Vec::new();
+    let mut open: Option<String> = None;
+    for rule in rules {
+        match open.as_mut() {
+            Some(selector) => {
+                selector.push(' ');
+                selector.push_str(rule);
+            }
+            None if rule.starts_with('(') => open = Some(rule.to_string()),
+            None => {
+                merged.push(rule.to_string());
+                continue;
+            }
+        }
+        if rule.ends_with(')') {
+            merged.extend(open.take());
+        }
+    }
+    match open {
+        Some(selector) => Err(format!("Unmatched parenthesis in acl selector starting at '{}'.", selector)),
+        None => Ok(merged),
+    }
+}
+
+/// Why a command was refused.
+pub enum Denial {
+    Command,
+    Key(String),
+    Channel(String),
+}
+
+impl Denial {
+    /// The reason recorded in the ACL LOG.
+    fn reason(&self) -> &'static str {
+        match self {
+            Denial::Command => "command",
+            Denial::Key(_) => "key",
+            Denial::Channel(_) => "channel",
+        }
+    }
+
+    /// The error replied to the refused command.
+    pub fn message(&self, username: &str, spec: &CommandSpec) -> String {
+        match self {
+            Denial::Command => {
+                format!("NOPERM User {} has no permissions to run the '{}' command", username, spec.name)
+            }
+            Denial::Key(_) => "NOPERM No permissions to access a key".to_string(),
+            Denial::Channel(_) => "NOPERM No permissions to access a channel".to_string(),
+        }
+    }
+
+    /// What `ACL DRYRUN` tells about the refusal.
+    pub fn describe(&self, spec: &CommandSpec) -> String {
+        match self {
+            Denial::Command => format!("This user has no permissions to run the '{}' command", spec.name),
+            Denial::Key(key) => format!("This user has no permissions to access the '{}' key", key),
+            Denial::Channel(channel) => format!("This user has no permissions to access the '{}' channel", channel),
+        }
+    }
+}
+
+/// An entry of the ACL LOG, counting the repetitions of a denial.
+pub struct LogEntry {
+    pub count: u64,
+    /// What was denied: `command`, `key` or `auth`.
+    pub reason: &'static str,
+    /// Where the command ran; only `toplevel` until scripts or transactions run commands.
+    pub context: &'static str,
+    /// The command, key or channel that was denied.
+    pub object: String,
+    pub username: String,
+    pub client_info: String,
+    pub entry_id: u64,
+    pub created: u64,
+    pub updated: u64,
+}
+
+/// The users and the log of what was denied to them, shared by every keyspace shard.
+pub struct Acl {
+    users: BTreeMap<String, User>,
+    /// Most recent entry first.
+    log: VecDeque<LogEntry>,
+    next_entry_id: u64,
+}
+
+impl Acl {
+    /// The ACLs of a server without an ACL file: the default user only, its password
+    /// `requirepass` if one is set.
+    pub fn new(requirepass: Option<&str>) -> Self {
+        let mut acl: Acl = Acl { users: BTreeMap::new(), log: VecDeque::new(), next_entry_id: 0 };
+        acl.users.insert(DEFAULT_USER.to_string(), User::default_user());
+        acl.set_default_password(requirepass);
+        acl
+    }
+
+    /// The ACLs at startup, read from `aclfile` when the parameter is set.
+    pub fn load(config: &Config) -> Result<Self, String> {
+        let mut acl: Acl = Acl::new(config.requirepass.as_deref());
+        if let Some(path) = config.aclfile.as_deref() {
+            acl.load_file(path)?;
+        }
+        Ok(acl)
+    }
+
+    pub fn user(&self, name: &str) -> Option<&User> {
+        self.users.get(name)
+    }
+
+    pub fn users(&self) -> impl Iterator<Item = &User> {
+        self.users.values()
+    }
+
+    /// `ACL SETUSER`: creates or changes a user, applying every rule or none of them.
+    pub fn set_user(&mut self, name: &str, rules: &[&str]) -> Result<(), String> {
+        if name.contains(|character: char| character.is_whitespace() || character == '\0') {
+            return Err("ERR Usernames can't contain spaces or null characters".to_string());
+        }
+        let mut user: User = self.users.get(name).cloned().unwrap_or_else(|| User::new(name));
+        for rule in merge_selector_rules(rules).map_err(|e| format!("ERR {}", e))? {
+            user.apply(&rule).map_err(|e| format!("ERR Error in ACL SETUSER modifier '{}': {}", rule, e))?;
+        }
+        self.users.insert(name.to_string(), user);
+        self.update_password_required();
+        Ok(())
+    }
+
+    /// `ACL DELUSER`: removes a user other than the default one, telling whether it existed.
+    pub fn delete_user(&mut self, name: &str) -> bool {
+        name != DEFAULT_USER && self.users.remove(name).is_some()
+    }
+
+    /// Makes `requirepass` the only password of the default user, or lets it in without
+    /// one when unset.
+    pub fn set_default_password(&mut self, password: Option<&str>) {
+        if let Some(user) = self.users.get_mut(DEFAULT_USER) {
+            user.passwords.clear();
+            user.nopass = password.is_none();
+            if let Some(password) = password {
+                user.passwords.push(sha256::hex_digest(password.as_bytes()));
+            }
+        }
+        self.update_password_required();
+    }
+
+    fn update_password_required(&self) {
+        let required: bool = self.users.get(DEFAULT_USER).is_none_or(|user| !user.enabled || !user.nopass);
+        PASSWORD_REQUIRED.store(required, Ordering::Relaxed);
+    }
+
+    /// Checks the credentials of `AUTH` or `HELLO AUTH`, logging failed attempts.
+    pub fn authenticate(&mut self, username: &str, password: &str, client_info: String, log_max_len: usize) -> bool {
+        let accepted: bool = self.users.get(username).is_some_and(|user| user.accepts(password));
+        if !accepted {
+            self.log("auth", "AUTH", username, client_info, log_max_len);
+        }
+        accepted
+    }
+
+    /// Checks whether `username` may run a call. The user must exist.
+    pub fn check(&self, username: &str, spec: &CommandSpec, arguments: &[Vec<u8>]) -> Result<(), Denial> {
+        match self.users.get(username) {
+            Some(user) => user.check(spec, arguments),
+            None => Err(Denial::Command),
+        }
+    }
+
+    /// Records a refused command in the ACL LOG.
+    pub fn log_denial(&mut self, denial: &Denial, spec: &CommandSpec, username: &str, client_info: String, log_max_len: usize) {
+        let object: &str = match denial {
+            Denial::Command => spec.name,
+            Denial::Key(key) | Denial::Channel(key) => key,
+        };
+        self.log(denial.reason(), object, username, client_info, log_max_len);
+    }
+
+    /// Adds an entry to the log, or counts it in a recent one for the same denial.
+    fn log(&mut self, reason: &'static str, object: &str, username: &str, client_info: String, max_len: usize) {
+        let now: u64 = now_millis();
+        let recent: Option<usize> = self.log.iter().position(|entry| {
+            entry.reason == reason && entry.object == object && entry.username == username
+                && now.saturating_sub(entry.updated) < LOG_ENTRY_GROUPING_MILLIS
+        });
+
+        let entry: LogEntry = match recent.and_then(|position| self.log.remove(position)) {
+            Some(entry) => LogEntry { count: entry.count + 1, client_info, updated: now, ..entry },
+            None => {
+                self.next_entry_id += 1;
+                LogEntry {
+                    count: 1,
+                    reason,
+                    context: "toplevel",
+                    object: object.to_string(),
+                    username: username.to_string(),
+                    client_info,
+                    entry_id: self.next_entry_id - 1,
+                    created: now,
+                    updated: now,
+                }
+            }
+        };
+        self.log.push_front(entry);
+        self.log.truncate(max_len);
+    }
+
+    /// The log entries, most recent first.
+    pub fn log_entries(&self) -> impl Iterator<Item = &LogEntry> {
+        self.log.iter()
+    }
+
+    pub fn reset_log(&mut self) {
+        self.log.clear();
+    }
+
+    /// Replaces the users with those of the ACL file, keeping the current ones if the file
+    /// has an error. The default user is created as usual when the file does not define it.
+    pub fn load_file(&mut self, path: &str) -> Result<(), String> {
+        let contents: String = fs::read_to_string(path)
+            .map_err(|e| format!("Error loading ACLs, opening file '{}': {}", path, e))?;
+
+        let mut users: BTreeMap<String, User> = BTreeMap::new();
+        for (number, line) in contents.lines().enumerate() {
+            let line: &str = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: String| format!("{}:{}: {}", path, number + 1, message);
+
+            let arguments: Vec<String> = config::split_arguments(line).map_err(error)?;
+            let arguments: Vec<&str> = arguments.iter().map(String::as_str).collect();
+            let (name, rules): (&str, &[&str]) = match arguments.as_slice() {
+                ["user", name, rules @ ..] => (name, rules),
+                _ => return Err(error("should start with user keyword".to_string())),
+            };
+            if name.contains('\0') {
+                return Err(error(format!("username '{}' contains invalid characters", name)));
+            }
+            if users.contains_key(name) {
+                return Err(error(format!("Duplicate user '{}' found", name)));
+            }
+
+            let mut user: User = User::new(name);
+            for rule in merge_selector_rules(rules).map_err(error)? {
+                user.apply(&rule).map_err(|e| error(format!("Error in user declaration '{}': {}", rule, e)))?;
+            }
+            users.insert(name.to_string(), user);
+        }
+
+        users.entry(DEFAULT_USER.to_string()).or_insert_with(User::default_user);
+        self.users = users;
+        self.update_password_required();
+        Ok(())
+    }
+
+    /// Writes every user to the ACL file, replacing it atomically.
+    pub fn save_file(&self, path: &str) -> Result<(), String> {
+        let mut contents: String = String::new();
+        for user in self.users.values() {
+            contents.push_str(&user.describe());
+            contents.push('\n');
+        }
+
+        let path: &Path = Path::new(path);
+        let temporary: PathBuf = path.with_extension(format!("tmp-{}", std::process::id()));
+        fs::write(&temporary, contents)
+            .and_then(|_| fs::rename(&temporary, path))
+            .map_err(|e| {
+                let _ = fs::remove_file(&temporary);
+                format!("Saving the ACL file {}: {}", path.display(), e)
+            })
+    }
+}
@@ -0,0 +1,136 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
+
+/// Number of counters the allocated bytes are spread over. Threads beyond that share them.
+const COUNTERS: usize = 64;
+
+/// A counter on a cache line of its own, so that threads counting their allocations do
+/// not contend with each other.
+#[repr(align(128))]
+struct Counter(AtomicIsize);
+
+/// Bytes allocated minus bytes freed by the threads using each counter. Memory freed by
+/// another thread than the one that allocated it makes single counters negative, only
+/// their sum is meaningful.
+static ALLOCATED: [Counter; COUNTERS] = [const { Counter(AtomicIsize::new(0)) }; COUNTERS];
+static NEXT_COUNTER: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+static STARTUP: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// The counter of the current thread, picked on its first allocation.
+    static COUNTER: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// The system allocator, counting the bytes currently allocated so the server can report
+/// its memory usage the way Redis does with its own allocator wrapper.
+pub struct CountingAllocator;
+
+impl CountingAllocator {
+    fn counter() -> &'static AtomicIsize {
+        // Threads being torn down no longer have their own, and count on the first one.
+        let index: usize = COUNTER.try_with(|counter| match counter.get() {
+            Some(index) => index,
+            None => {
+                let index: usize = NEXT_COUNTER.fetch_add(1, Ordering::Relaxed) % COUNTERS;
+                counter.set(Some(index));
+                index
+            }
+        }).unwrap_or(0);
+        &ALLOCATED[index].0
+    }
+
+    fn grow(size: usize) {
+        Self::counter().fetch_add(size as isize, Ordering::Relaxed);
+    }
+
+    fn shrink(size: usize) {
+        Self::counter().fetch_sub(size as isize, Ordering::Relaxed);
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let pointer: *mut u8 = System.alloc(layout);
+        if !pointer.is_null() {
+            Self::grow(layout.size());
+        }
+        pointer
+    }
+
+    unsafe fn dealloc(&self, pointer: *mut u8, layout: Layout) {
+        System.dealloc(pointer, layout);
+        Self::shrink(layout.size());
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let pointer: *mut u8 = System.alloc_zeroed(layout);
+        if !pointer.is_null() {
+            Self::grow(layout.size());
+        }
+        pointer
+    }
+
+    unsafe fn realloc(&self, pointer: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_pointer: *mut u8 = System.realloc(pointer, layout, new_size);
+        if !new_pointer.is_null() {
+            if new_size > layout.size() {
+                Self::grow(new_size - layout.size());
+            } else {
+                Self::shrink(layout.size() - new_size);
+            }
+        }
+        new_pointer
+    }
+}
+
+/// Bytes currently allocated by the process, adding up the counters of every thread. The
+/// peak is kept up to date by these reads, like Redis updates it from its cron.
+pub fn used_memory() -> usize {
+    let allocated: isize = ALLOCATED.iter().map(|counter| counter.0.load(Ordering::Relaxed)).sum();
+    let used: usize = allocated.max(0) as usize;
+    PEAK.fetch_max(used, Ordering::Relaxed);
+    used
+}
+
+/// Highest memory usage `used_memory` saw since startup.
+pub fn peak_memory() -> usize {
+    used_memory();
+    PEAK.load(Ordering::Relaxed)
+}
+
+/// Records the memory used once the server is initialized, before the dataset is loaded.
+pub fn mark_startup() {
+    STARTUP.store(used_memory(), Ordering::Relaxed);
+}
+
+/// Memory used by the server before it held any data, as recorded by `mark_startup`.
+pub fn startup_memory() -> usize {
+    STARTUP.load(Ordering::Relaxed)
+}
+
+/// Resident set size as reported by the operating system, where it is available.
+pub fn resident_memory() -> Option<usize> {
+    const PAGE_SIZE: usize = 4096;
+
+    let statm: String = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: usize = statm.split_whitespace().nth(1)?.parse().ok()?;
+    Some(pages * PAGE_SIZE)
+}
+
+/// Formats a byte count like Redis' `*_human` INFO fields, e.g. `1.50M`.
+pub fn format_bytes(bytes: usize) -> String {
+    const UNITS: [&str; 5] = ["K", "M", "G", "T", "P"];
+
+    if bytes < 1024 {
+        return format!("{}B", bytes);
+    }
+    let mut value: f64 = bytes as f64 / 1024.0;
+    let mut unit: usize = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.2}{}", value, UNITS[unit])
+}
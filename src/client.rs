@@ -0,0 +1,203 @@
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch, Notify};
+use crate::command::CommandSpec;
+use crate::reply::{Protocol, Value};
+
+/// A client connection, shared by its task with the registry and with the commands it sends.
+pub struct Connection {
+    pub id: u64,
+    /// File descriptor of the socket.
+    pub fd: i32,
+    pub created: Instant,
+    activity: Mutex<Activity>,
+    unblock: watch::Sender<Unblock>,
+    killed: Notify,
+    /// Out-of-band messages for the connection, like pub/sub messages and invalidations.
+    pushes: mpsc::UnboundedSender<Value>,
+}
+
+/// Size of the buffer a connection reads into.
+pub const READ_BUFFER_SIZE: usize = 16 * 1024;
+
+/// What the task of a connection reports about it as it serves commands.
+pub struct Activity {
+    /// Full name of the last command, `NULL` before the first one.
+    pub last_command: &'static str,
+    pub last_interaction: Instant,
+    /// The user and protocol of the connection as of its last command.
+    pub user: String,
+    pub protocol: Protocol,
+    /// Bytes received but not parsed yet, and room left for more in the query buffer.
+    pub query_buffer: usize,
+    pub query_buffer_free: usize,
+    /// Most bytes received by a single read.
+    pub read_peak: usize,
+    /// Bytes taken by the arguments of the command being run.
+    pub arguments_memory: usize,
+    /// Bytes of replies not written yet, and the size of the buffer holding them.
+    pub output_buffer: usize,
+    pub output_memory: usize,
+    /// Whether the connection waits for a blocked command to be served.
+    pub blocked: bool,
+    /// Whether the connection became a replica, receiving the replication stream.
+    pub replica: bool,
+}
+
+/// How `CLIENT UNBLOCK` ends the wait of a blocked command.
+#[derive(Clone, Copy)]
+pub enum Unblock {
+    /// As if its timeout expired.
+    Timeout,
+    /// With an `UNBLOCKED` error.
+    Error,
+}
+
+impl Connection {
+    /// A new connection, whose task writes what is sent to `pushes` between replies.
+    pub fn new(id: u64, fd: i32, user: &str, pushes: mpsc::UnboundedSender<Value>) -> Self {
+        let now: Instant = Instant::now();
+        Connection {
+            id,
+            fd,
+            created: now,
+            activity: Mutex::new(Activity {
+                last_command: "NULL",
+                last_interaction: now,
+                user: user.to_string(),
+                protocol: Protocol::Resp2,
+                query_buffer: 0,
+                query_buffer_free: 0,
+                read_peak: 0,
+                arguments_memory: 0,
+                output_buffer: 0,
+                output_memory: 0,
+                blocked: false,
+                replica: false,
+            }),
+            unblock: watch::channel(Unblock::Timeout).0,
+            killed: Notify::new(),
+            pushes,
+        }
+    }
+
+    /// Sends an out-of-band message to the connection, returning false once it is closed.
+    pub fn push(&self, value: Value) -> bool {
+        self.pushes.send(value).is_ok()
+    }
+
+    /// Whether the task of the connection still serves it.
+    pub fn is_open(&self) -> bool {
+        !self.pushes.is_closed()
+    }
+
+    pub fn activity(&self) -> MutexGuard<'_, Activity> {
+        self.activity.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Subscribes a blocked command of this connection to `CLIENT UNBLOCK`.
+    pub fn unblocked(&self) -> watch::Receiver<Unblock> {
+        self.unblock.subscribe()
+    }
+
+    /// Wakes up the blocked command of the connection, returning whether there was one.
+    pub fn unblock(&self, unblock: Unblock) -> bool {
+        if !self.activity().blocked {
+            return false;
+        }
+        self.unblock.send_replace(unblock);
+        true
+    }
+
+    /// Asks the task of the connection to close it, after the replies already written.
+    pub fn kill(&self) {
+        self.killed.notify_one();
+    }
+
+    /// Resolves once the connection has been killed.
+    pub async fn killed(&self) {
+        self.killed.notified().await
+    }
+}
+
+/// Resolves when a blocked command stops waiting without being served: after `timeout`, if
+/// it has one, or when `CLIENT UNBLOCK` wakes the client up. Gives the error to reply with
+/// when the client was unblocked with one, `None` when the command timed out.
+pub async fn expired(timeout: Option<Duration>, unblocked: Option<watch::Receiver<Unblock>>) -> Option<Value> {
+    let unblocked = async {
+        if let Some(mut unblocked) = unblocked {
+            if unblocked.changed().await.is_ok() {
+                return *unblocked.borrow();
+            }
+        }
+        std::future::pending().await
+    };
+    let unblock: Unblock = match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, unblocked).await.unwrap_or(Unblock::Timeout),
+        None => unblocked.await,
+    };
+    match unblock {
+        Unblock::Timeout => None,
+        Unblock::Error => Some(Value::Error("UNBLOCKED client unblocked via CLIENT UNBLOCK".to_string())),
+    }
+}
+
+/// Which commands `CLIENT PAUSE` holds back.
+#[derive(Clone, Copy, PartialEq)]
+pub enum PauseMode {
+    /// Commands that may change the dataset.
+    Write,
+    All,
+}
+
+/// The pause in effect, until its deadline or `CLIENT UNPAUSE`.
+static PAUSE: Mutex<Option<(Instant, PauseMode)>> = Mutex::new(None);
+/// Whether there may be a pause, so commands only look at it while there is one.
+static PAUSED: AtomicBool = AtomicBool::new(false);
+static UNPAUSED: Notify = Notify::const_new();
+
+/// Holds back the commands of `mode` until `deadline`. A pause already in effect is only
+/// extended: the later deadline and the stricter mode win.
+pub fn pause(deadline: Instant, mode: PauseMode) {
+    let mut pause = PAUSE.lock().unwrap_or_else(PoisonError::into_inner);
+    *pause = Some(match *pause {
+        Some((current_deadline, current_mode)) if current_deadline > Instant::now() => (
+            deadline.max(current_deadline),
+            if current_mode == PauseMode::All { PauseMode::All } else { mode },
+        ),
+        _ => (deadline, mode),
+    });
+    PAUSED.store(true, Ordering::Relaxed);
+}
+
+pub fn unpause() {
+    *PAUSE.lock().unwrap_or_else(PoisonError::into_inner) = None;
+    PAUSED.store(false, Ordering::Relaxed);
+    UNPAUSED.notify_waiters();
+}
+
+/// The deadline of the pause holding `spec` back, if there is one.
+fn paused_until(spec: &CommandSpec) -> Option<Instant> {
+    if !PAUSED.load(Ordering::Relaxed) {
+        return None;
+    }
+    let pause = PAUSE.lock().unwrap_or_else(PoisonError::into_inner);
+    pause.filter(|(deadline, mode)| *deadline > Instant::now() && (*mode == PauseMode::All || spec.is_write()))
+        .map(|(deadline, _)| deadline)
+}
+
+/// Waits for the end of a pause holding `spec` back.
+pub async fn wait_while_paused(spec: &CommandSpec) {
+    loop {
+        // Registered before the pause is looked at, so an unpause in between is not missed.
+        let unpaused = UNPAUSED.notified();
+        let Some(deadline) = paused_until(spec) else {
+            return;
+        };
+        tokio::select! {
+            _ = unpaused => {}
+            _ = tokio::time::sleep_until(deadline.into()) => {}
+        }
+    }
+}
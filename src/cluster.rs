@@ -0,0 +1,720 @@
+pub mod bus;
+pub mod gossip;
+pub mod message;
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::cluster::gossip::Election;
+use crate::key_value_store::KeyValueStore;
+use crate::replication::ReplicationState;
+
+pub const CLUSTER_SLOTS: usize = 16384;
+pub const DEFAULT_CONFIG_FILE: &str = "nodes.conf";
+pub const DEFAULT_NODE_TIMEOUT: u64 = 15000;
+pub const BUS_PORT_OFFSET: u16 = 10000;
+
+/// How long a forgotten node is kept from being re-added through gossip, in milliseconds.
+const FORGET_BLACKLIST_TTL: u64 = 60000;
+
+const fn crc16_table() -> [u16; 256] {
+    let mut table: [u16; 256] = [0; 256];
+    let mut index: usize = 0;
+    while index < 256 {
+        let mut crc: u16 = (index as u16) << 8;
+        let mut bit: usize = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+            bit += 1;
+        }
+        table[index] = crc;
+        index += 1;
+    }
+    table
+}
+
+/// CRC16-CCITT (XMODEM), the checksum Redis Cluster uses to map keys to hash slots.
+const CRC16_TABLE: [u16; 256] = crc16_table();
+
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0, |crc: u16, byte| {
+        (crc << 8) ^ CRC16_TABLE[(((crc >> 8) as u8) ^ byte) as usize]
+    })
+}
+
+/// Maps a key to its hash slot. When the key contains a non-empty `{...}` section only that
+/// part is hashed, so related keys can be forced into the same slot.
+pub fn key_hash_slot(key: &str) -> u16 {
+    let bytes: &[u8] = key.as_bytes();
+    let hashed: &[u8] = bytes.iter().position(|byte| *byte == b'{')
+        .and_then(|open| {
+            bytes[open + 1..].iter().position(|byte| *byte == b'}')
+                .filter(|length| *length > 0)
+                .map(|length| &bytes[open + 1..open + 1 + length])
+        })
+        .unwrap_or(bytes);
+
+    crc16(hashed) % CLUSTER_SLOTS as u16
+}
+
+#[derive(Clone, PartialEq)]
+pub enum NodeRole {
+    Master,
+    Replica(String),
+}
+
+/// `PFail` is this node's local suspicion; `Fail` is agreed on by a majority of masters.
+#[derive(Clone, Copy, PartialEq)]
+pub enum FailureState {
+    Ok,
+    PFail,
+    Fail,
+}
+
+pub struct ClusterNode {
+    pub id: String,
+    pub ip: String,
+    pub port: u16,
+    pub bus_port: u16,
+    pub role: NodeRole,
+    pub config_epoch: u64,
+    pub ping_sent: u64,
+    pub pong_received: u64,
+    pub link_connected: bool,
+    pub failure: FailureState,
+    pub fail_time: u64,
+    /// Masters that flagged this node as failing in their gossip, with the report time.
+    pub fail_reports: HashMap<String, u64>,
+    pub repl_offset: u64,
+    /// When this node, as a master, last had one of its replicas voted for.
+    pub voted_time: u64,
+    last_ping: u64,
+}
+
+impl ClusterNode {
+    fn new(id: String, ip: String, port: u16) -> Self {
+        ClusterNode {
+            id,
+            ip,
+            port,
+            bus_port: port.wrapping_add(BUS_PORT_OFFSET),
+            role: NodeRole::Master,
+            config_epoch: 0,
+            ping_sent: 0,
+            pong_received: now_millis(),
+            link_connected: true,
+            failure: FailureState::Ok,
+            fail_time: 0,
+            fail_reports: HashMap::new(),
+            repl_offset: 0,
+            voted_time: 0,
+            last_ping: 0,
+        }
+    }
+
+    pub fn endpoint(&self) -> String {
+        format!("{}:{}", self.ip, self.port)
+    }
+
+    pub fn is_master(&self) -> bool {
+        self.role == NodeRole::Master
+    }
+
+    fn bus_address(&self) -> (String, u16) {
+        (self.ip.clone(), self.bus_port)
+    }
+}
+
+/// An encoded bus message waiting to be sent to the bus port at `address`.
+pub struct Outgoing {
+    pub address: (String, u16),
+    pub payload: Vec<u8>,
+}
+
+struct PendingMeet {
+    ip: String,
+    port: u16,
+    bus_port: u16,
+    since: u64,
+    last_sent: u64,
+}
+
+/// The cluster topology as seen by this node: every known node, who serves each of the
+/// 16384 hash slots, and the slots currently being moved in or out.
+pub struct ClusterState {
+    myself: String,
+    nodes: HashMap<String, ClusterNode>,
+    slots: Vec<Option<String>>,
+    migrating: HashMap<u16, String>,
+    importing: HashMap<u16, String>,
+    current_epoch: u64,
+    last_vote_epoch: u64,
+    config_file: String,
+    node_timeout: u64,
+    pending_meets: Vec<PendingMeet>,
+    forgotten: HashMap<String, u64>,
+    election: Option<Election>,
+    outbox: Vec<Outgoing>,
+    /// Set when gossip changed something that belongs in nodes.conf.
+    dirty: bool,
+}
+
+impl ClusterState {
+    /// Loads the node table from `config_file`, or creates a fresh node owning no slots and
+    /// writes it out when the file does not exist yet.
+    pub fn load_or_create(config_file: String, node_timeout: u64, ip: &str, port: u16) -> Result<Self, Error> {
+        let mut state: ClusterState = ClusterState {
+            myself: String::new(),
+            nodes: HashMap::new(),
+            slots: vec![None; CLUSTER_SLOTS],
+            migrating: HashMap::new(),
+            importing: HashMap::new(),
+            current_epoch: 0,
+            last_vote_epoch: 0,
+            config_file,
+            node_timeout,
+            pending_meets: Vec::new(),
+            forgotten: HashMap::new(),
+            election: None,
+            outbox: Vec::new(),
+            dirty: false,
+        };
+
+        match fs::read_to_string(&state.config_file) {
+            Ok(contents) => state.parse_config(&contents)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                let myself: ClusterNode = ClusterNode::new(
+                    crate::replication::generate_replication_id(), ip.to_string(), port);
+                state.myself = myself.id.clone();
+                state.nodes.insert(myself.id.clone(), myself);
+                state.save()?;
+            }
+            Err(e) => return Err(e),
+        }
+
+        if let Some(myself) = state.nodes.get_mut(&state.myself) {
+            myself.port = port;
+            myself.bus_port = port.wrapping_add(BUS_PORT_OFFSET);
+        }
+
+        Ok(state)
+    }
+
+    pub fn myself(&self) -> &ClusterNode {
+        &self.nodes[&self.myself]
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &ClusterNode> {
+        self.nodes.values()
+    }
+
+    pub fn slot_owner(&self, slot: u16) -> Option<&ClusterNode> {
+        self.slots[slot as usize].as_ref().and_then(|id| self.nodes.get(id))
+    }
+
+    pub fn current_epoch(&self) -> u64 {
+        self.current_epoch
+    }
+
+    pub fn set_node_timeout(&mut self, node_timeout: u64) {
+        self.node_timeout = node_timeout;
+    }
+
+    pub fn drain_outbox(&mut self) -> Vec<Outgoing> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    fn myself_mut(&mut self) -> &mut ClusterNode {
+        self.nodes.get_mut(&self.myself).expect("the local node is always known")
+    }
+
+    fn masters_with_slots(&self) -> usize {
+        self.nodes.values()
+            .filter(|node| node.is_master() && self.slots.iter().any(|owner| owner.as_ref() == Some(&node.id)))
+            .count()
+    }
+
+    /// Majority of the slot-serving masters, required both to mark a node as failed and to
+    /// win a failover election.
+    fn quorum(&self) -> usize {
+        self.masters_with_slots() / 2 + 1
+    }
+
+    fn assign_slot(&mut self, slot: u16, id: &str) {
+        self.slots[slot as usize] = Some(id.to_string());
+    }
+
+    fn save_config(&self) {
+        if let Err(e) = self.save() {
+            println!("could not save the cluster configuration: {}", e);
+        }
+    }
+
+    /// `CLUSTER MEET`: the node is added once it answers the handshake with a PONG.
+    pub fn meet(&mut self, ip: String, port: u16, bus_port: Option<u16>) {
+        let bus_port: u16 = bus_port.unwrap_or(port.wrapping_add(BUS_PORT_OFFSET));
+        self.start_handshake(ip, port, bus_port);
+    }
+
+    fn start_handshake(&mut self, ip: String, port: u16, bus_port: u16) {
+        let known: bool = self.nodes.values().any(|node| node.ip == ip && node.port == port)
+            || self.pending_meets.iter().any(|meet| meet.ip == ip && meet.port == port);
+        if !known {
+            self.pending_meets.push(PendingMeet { ip, port, bus_port, since: now_millis(), last_sent: 0 });
+        }
+    }
+
+    pub fn add_slots(&mut self, slots: &[u16]) -> Result<(), String> {
+        if let Some(slot) = slots.iter().find(|slot| self.slots[**slot as usize].is_some()) {
+            return Err(format!("ERR Slot {} is already busy", slot));
+        }
+
+        let myself: String = self.myself.clone();
+        slots.iter().for_each(|slot| self.assign_slot(*slot, &myself));
+        self.save_config();
+        Ok(())
+    }
+
+    /// `CLUSTER SETSLOT <slot> MIGRATING <id>`: keys of the slot that are no longer here are
+    /// looked up on `id` through `ASK` redirections.
+    pub fn set_slot_migrating(&mut self, slot: u16, id: &str) -> Result<(), String> {
+        if self.slots[slot as usize].as_deref() != Some(&self.myself) {
+            return Err(format!("ERR I'm not the owner of hash slot {}", slot));
+        }
+        self.check_slot_peer(id)?;
+
+        self.migrating.insert(slot, id.to_string());
+        self.save_config();
+        Ok(())
+    }
+
+    /// `CLUSTER SETSLOT <slot> IMPORTING <id>`: clients sending `ASKING` may use the slot here
+    /// before it is officially assigned to this node.
+    pub fn set_slot_importing(&mut self, slot: u16, id: &str) -> Result<(), String> {
+        if self.slots[slot as usize].as_deref() == Some(&self.myself) {
+            return Err(format!("ERR I'm already the owner of hash slot {}", slot));
+        }
+        self.check_slot_peer(id)?;
+
+        self.importing.insert(slot, id.to_string());
+        self.save_config();
+        Ok(())
+    }
+
+    pub fn set_slot_stable(&mut self, slot: u16) {
+        self.migrating.remove(&slot);
+        self.importing.remove(&slot);
+        self.save_config();
+    }
+
+    fn check_slot_peer(&self, id: &str) -> Result<(), String> {
+        match self.nodes.get(id) {
+            None => Err(format!("ERR I don't know about node {}", id)),
+            Some(node) if !node.is_master() => Err("ERR Target node is not a master".to_string()),
+            Some(node) if node.id == self.myself => Err("ERR I can't migrate a slot to myself".to_string()),
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// `CLUSTER SETSLOT <slot> NODE <id>`. Taking over a slot that was being imported bumps
+    /// the config epoch so the new ownership wins when it is gossiped.
+    pub fn set_slot_node(&mut self, slot: u16, id: &str, store: &dyn KeyValueStore) -> Result<(), String> {
+        if !self.nodes.contains_key(id) {
+            return Err(format!("ERR Unknown node {}", id));
+        }
+        let owns_slot: bool = self.slots[slot as usize].as_deref() == Some(&self.myself);
+        if owns_slot && id != self.myself && store.iter().any(|(key, _)| key_hash_slot(key) == slot) {
+            return Err(format!(
+                "ERR Can't assign hashslot {} to a different node while I still hold keys for this hash slot.", slot));
+        }
+
+        if id == self.myself && self.importing.remove(&slot).is_some() {
+            self.bump_config_epoch();
+        }
+        if id != self.myself {
+            self.migrating.remove(&slot);
+        }
+
+        self.assign_slot(slot, id);
+        self.save_config();
+        Ok(())
+    }
+
+    pub fn forget(&mut self, id: &str) -> Result<(), String> {
+        if id == self.myself {
+            return Err("ERR I tried hard but I can't forget myself...".to_string());
+        }
+        if self.myself().role == NodeRole::Replica(id.to_string()) {
+            return Err("ERR Can't forget my master!".to_string());
+        }
+        if self.nodes.remove(id).is_none() {
+            return Err(format!("ERR Unknown node {}", id));
+        }
+
+        self.forgotten.insert(id.to_string(), now_millis() + FORGET_BLACKLIST_TTL);
+        self.release_node(id);
+        self.save_config();
+        Ok(())
+    }
+
+    /// `CLUSTER REPLICATE <id>`: only an empty master without slots may become a replica.
+    pub fn replicate(
+        &mut self,
+        id: &str,
+        store: &dyn KeyValueStore,
+        replication: &mut ReplicationState
+    ) -> Result<(), String> {
+        let master: &ClusterNode = self.nodes.get(id).ok_or_else(|| format!("ERR Unknown node {}", id))?;
+        if id == self.myself {
+            return Err("ERR Can't replicate myself".to_string());
+        }
+        if !master.is_master() {
+            return Err("ERR I can only replicate a master, not a replica.".to_string());
+        }
+        if self.myself().is_master() && (!self.slot_ranges(&self.myself).is_empty() || store.iter().next().is_some()) {
+            return Err("ERR To set a master the node must be empty and without assigned slots.".to_string());
+        }
+
+        self.follow(id.to_string(), replication);
+        self.save_config();
+        Ok(())
+    }
+
+    /// `CLUSTER RESET`: forgets every other node and drops all slots. A hard reset also picks
+    /// a new node ID and starts the epochs over.
+    pub fn reset(
+        &mut self,
+        hard: bool,
+        store: &dyn KeyValueStore,
+        replication: &mut ReplicationState
+    ) -> Result<(), String> {
+        if self.myself().is_master() && store.iter().next().is_some() {
+            return Err("ERR CLUSTER RESET can't be called with master nodes containing keys".to_string());
+        }
+
+        if !self.myself().is_master() {
+            replication.promote();
+        }
+
+        let mut myself: ClusterNode = self.nodes.remove(&self.myself).expect("the local node is always known");
+        myself.role = NodeRole::Master;
+        if hard {
+            myself.id = crate::replication::generate_replication_id();
+            myself.config_epoch = 0;
+            self.current_epoch = 0;
+            self.last_vote_epoch = 0;
+        }
+
+        self.myself = myself.id.clone();
+        self.nodes.clear();
+        self.nodes.insert(myself.id.clone(), myself);
+        self.slots.iter_mut().for_each(|owner| *owner = None);
+        self.migrating.clear();
+        self.importing.clear();
+        self.pending_meets.clear();
+        self.election = None;
+        self.save_config();
+        Ok(())
+    }
+
+    /// Makes this node a replica of `master_id` and starts replicating from it.
+    fn follow(&mut self, master_id: String, replication: &mut ReplicationState) {
+        if let Some(master) = self.nodes.get(&master_id) {
+            replication.replicate_from(master.ip.clone(), master.port);
+        }
+        let myself: String = self.myself.clone();
+        self.release_node(&myself);
+        self.myself_mut().role = NodeRole::Replica(master_id);
+        self.election = None;
+    }
+
+    /// Unassigns every slot owned by `id`.
+    fn release_node(&mut self, id: &str) {
+        self.slots.iter_mut()
+            .filter(|owner| owner.as_deref() == Some(id))
+            .for_each(|owner| *owner = None);
+    }
+
+    /// Claims a fresh config epoch without running an election, as done when a slot is
+    /// adopted through `SETSLOT` or a failover is forced with TAKEOVER.
+    fn bump_config_epoch(&mut self) {
+        self.current_epoch += 1;
+        let epoch: u64 = self.current_epoch;
+        self.myself_mut().config_epoch = epoch;
+    }
+
+    /// The master this node replicates from according to the cluster configuration.
+    pub fn replicated_master(&self) -> Option<&ClusterNode> {
+        match &self.myself().role {
+            NodeRole::Replica(master_id) => self.nodes.get(master_id),
+            NodeRole::Master => None,
+        }
+    }
+
+    pub fn assigned_slots(&self) -> usize {
+        self.slots.iter().filter(|owner| owner.is_some()).count()
+    }
+
+    /// Number of slots whose owner is in the given failure state.
+    pub fn slots_in_failure(&self, failure: FailureState) -> usize {
+        self.slots.iter()
+            .filter(|owner| owner.as_ref()
+                .and_then(|owner| self.nodes.get(owner))
+                .is_some_and(|owner| owner.failure == failure))
+            .count()
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.assigned_slots() == CLUSTER_SLOTS && self.slots_in_failure(FailureState::Fail) == 0
+    }
+
+    /// Finds the node that must serve a command touching `keys`, returning the error to send
+    /// back when it is not this one, or when the keys cannot be served together. `asking`
+    /// lets a client redirected with `ASK` use a slot this node is importing.
+    pub fn redirect(&self, keys: &[&str], store: &dyn KeyValueStore, asking: bool) -> Option<String> {
+        let slot: u16 = key_hash_slot(keys.first()?);
+        if keys[1..].iter().any(|key| key_hash_slot(key) != slot) {
+            return Some("CROSSSLOT Keys in request don't hash to the same slot".to_string());
+        }
+
+        let owner: &ClusterNode = match self.slot_owner(slot) {
+            Some(owner) => owner,
+            None => return Some("CLUSTERDOWN Hash slot not served".to_string()),
+        };
+
+        if owner.id != self.myself {
+            if !asking || !self.importing.contains_key(&slot) {
+                return Some(format!("MOVED {} {}", slot, owner.endpoint()));
+            }
+            if keys.len() > 1 && keys.iter().any(|key| store.get(key).is_none()) {
+                return Some("TRYAGAIN Multiple keys request during rehashing of slot".to_string());
+            }
+            return None;
+        }
+
+        if let Some(target) = self.migrating.get(&slot).and_then(|id| self.nodes.get(id)) {
+            let missing: usize = keys.iter().filter(|key| store.get(key).is_none()).count();
+            if missing == keys.len() {
+                return Some(format!("ASK {} {}", slot, target.endpoint()));
+            }
+            if missing > 0 {
+                return Some("TRYAGAIN Multiple keys request during rehashing of slot".to_string());
+            }
+        }
+
+        None
+    }
+
+    /// Renders the node table in the `CLUSTER NODES` / nodes.conf line format.
+    pub fn describe_nodes(&self) -> String {
+        let mut description: String = String::new();
+
+        for node in self.nodes.values() {
+            let is_myself: bool = node.id == self.myself;
+            let mut flags: Vec<&str> = Vec::new();
+            if is_myself {
+                flags.push("myself");
+            }
+            flags.push(match node.role {
+                NodeRole::Master => "master",
+                NodeRole::Replica(_) => "slave",
+            });
+            match node.failure {
+                FailureState::PFail => flags.push("fail?"),
+                FailureState::Fail => flags.push("fail"),
+                FailureState::Ok => {}
+            }
+            let master_id: &str = match &node.role {
+                NodeRole::Replica(master_id) => master_id,
+                NodeRole::Master => "-",
+            };
+
+            write!(
+                description, "{} {}:{}@{} {} {} {} {} {} {}",
+                node.id, node.ip, node.port, node.bus_port, flags.join(","), master_id,
+                node.ping_sent, node.pong_received, node.config_epoch,
+                if node.link_connected || is_myself { "connected" } else { "disconnected" },
+            ).unwrap();
+
+            for (start, end) in self.slot_ranges(&node.id) {
+                if start == end {
+                    write!(description, " {}", start).unwrap();
+                } else {
+                    write!(description, " {}-{}", start, end).unwrap();
+                }
+            }
+
+            if is_myself {
+                let mut moving: Vec<(&u16, String)> = self.migrating.iter()
+                    .map(|(slot, target)| (slot, format!(" [{}->-{}]", slot, target)))
+                    .chain(self.importing.iter()
+                        .map(|(slot, source)| (slot, format!(" [{}-<-{}]", slot, source))))
+                    .collect();
+                moving.sort();
+                moving.into_iter().for_each(|(_, entry)| description.push_str(&entry));
+            }
+
+            description.push('\n');
+        }
+
+        description
+    }
+
+    /// Contiguous slot ranges served by the node `id`, in ascending order.
+    pub fn slot_ranges(&self, id: &str) -> Vec<(u16, u16)> {
+        let mut ranges: Vec<(u16, u16)> = Vec::new();
+
+        for (slot, owner) in self.slots.iter().enumerate() {
+            if owner.as_deref() != Some(id) {
+                continue;
+            }
+            let slot: u16 = slot as u16;
+            match ranges.last_mut() {
+                Some((_, end)) if *end + 1 == slot => *end = slot,
+                _ => ranges.push((slot, slot)),
+            }
+        }
+
+        ranges
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        let mut contents: String = self.describe_nodes();
+        writeln!(contents, "vars currentEpoch {} lastVoteEpoch {}", self.current_epoch, self.last_vote_epoch)
+            .unwrap();
+        fs::write(&self.config_file, contents)
+    }
+
+    fn parse_config(&mut self, contents: &str) -> Result<(), Error> {
+        let config_file: String = self.config_file.clone();
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidData, format!("{}: {}", config_file, message));
+
+        for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+
+            if fields[0] == "vars" {
+                for pair in fields[1..].chunks_exact(2) {
+                    match pair[0] {
+                        "currentEpoch" => self.current_epoch = pair[1].parse().unwrap_or(0),
+                        "lastVoteEpoch" => self.last_vote_epoch = pair[1].parse().unwrap_or(0),
+                        _ => {}
+                    }
+                }
+                continue;
+            }
+
+            if fields.len() < 8 {
+                return Err(invalid("node line has too few fields"));
+            }
+
+            let (address, bus_port) = fields[1].split_once('@').ok_or_else(|| invalid("missing bus port"))?;
+            let bus_port: u16 = bus_port.split(',').next().unwrap_or_default().parse()
+                .map_err(|_| invalid("invalid bus port"))?;
+            let (ip, port) = address.rsplit_once(':').ok_or_else(|| invalid("missing node port"))?;
+            let port: u16 = port.parse().map_err(|_| invalid("invalid node port"))?;
+
+            let mut node: ClusterNode = ClusterNode::new(fields[0].to_string(), ip.to_string(), port);
+            node.bus_port = bus_port;
+            node.config_epoch = fields[6].parse().unwrap_or(0);
+            node.link_connected = fields[7] == "connected";
+
+            let flags: Vec<&str> = fields[2].split(',').collect();
+            if flags.contains(&"slave") && fields[3] != "-" {
+                node.role = NodeRole::Replica(fields[3].to_string());
+            }
+            if flags.contains(&"myself") {
+                self.myself = node.id.clone();
+            }
+
+            for slot in &fields[8..] {
+                self.parse_slot_field(&node.id, slot).ok_or_else(|| invalid("invalid slot"))?;
+            }
+
+            self.nodes.insert(node.id.clone(), node);
+        }
+
+        if !self.nodes.contains_key(&self.myself) {
+            return Err(invalid("no node is flagged as myself"));
+        }
+
+        Ok(())
+    }
+
+    fn parse_slot_field(&mut self, id: &str, field: &str) -> Option<()> {
+        if let Some(moving) = field.strip_prefix('[').and_then(|field| field.strip_suffix(']')) {
+            if let Some((slot, target)) = moving.split_once("->-") {
+                self.migrating.insert(parse_slot(slot)?, target.to_string());
+            } else if let Some((slot, source)) = moving.split_once("-<-") {
+                self.importing.insert(parse_slot(slot)?, source.to_string());
+            }
+            return Some(());
+        }
+
+        let (start, end) = match field.split_once('-') {
+            Some((start, end)) => (parse_slot(start)?, parse_slot(end)?),
+            None => (parse_slot(field)?, parse_slot(field)?),
+        };
+        for slot in start..=end {
+            self.slots[slot as usize] = Some(id.to_string());
+        }
+        Some(())
+    }
+}
+
+pub fn parse_slot(slot: &str) -> Option<u16> {
+    slot.parse().ok().filter(|slot| (*slot as usize) < CLUSTER_SLOTS)
+}
+
+/// Formats slot ranges as `0-5460,5462`, or `-` when there are none.
+pub fn format_slot_ranges(ranges: &[(u16, u16)]) -> String {
+    if ranges.is_empty() {
+        return "-".to_string();
+    }
+
+    ranges.iter()
+        .map(|(start, end)| if start == end { start.to_string() } else { format!("{}-{}", start, end) })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+pub fn parse_slot_ranges(ranges: &str) -> Option<Vec<(u16, u16)>> {
+    if ranges == "-" {
+        return Some(Vec::new());
+    }
+
+    ranges.split(',')
+        .map(|range| match range.split_once('-') {
+            Some((start, end)) => Some((parse_slot(start)?, parse_slot(end)?)),
+            None => parse_slot(range).map(|slot| (slot, slot)),
+        })
+        .collect()
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_millis() as u64).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc16, key_hash_slot, CLUSTER_SLOTS};
+
+    #[test]
+    fn crc16_matches_the_cluster_specification() {
+        assert_eq!(crc16(b"123456789"), 0x31C3);
+        assert_eq!(crc16(b""), 0);
+    }
+
+    #[test]
+    fn hash_tags_select_the_hashed_part_of_a_key() {
+        assert_eq!(key_hash_slot("{user1000}.following"), key_hash_slot("{user1000}.followers"));
+        assert_eq!(key_hash_slot("{user1000}.following"), crc16(b"user1000") % CLUSTER_SLOTS as u16);
+        // Only the first `{` counts, and an empty tag leaves the whole key hashed.
+        assert_eq!(key_hash_slot("foo{}{bar}"), crc16(b"foo{}{bar}") % CLUSTER_SLOTS as u16);
+        assert_eq!(key_hash_slot("{}"), crc16(b"{}") % CLUSTER_SLOTS as u16);
+        assert_eq!(key_hash_slot("foo{{bar}}zap"), crc16(b"{bar") % CLUSTER_SLOTS as u16);
+        assert_eq!(key_hash_slot("foo{bar}{zap}"), crc16(b"bar") % CLUSTER_SLOTS as u16);
+        assert_eq!(key_hash_slot("123456789"), 12739);
+    }
+}
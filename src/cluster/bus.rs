@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use std::io::Error;
+use std::sync::MutexGuard;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot};
+use crate::Msg;
+use crate::cluster::{ClusterState, Outgoing};
+use crate::cluster::message::BusMessage;
+use crate::command::DataRequester;
+use crate::key_value_store::KeyValueStore;
+use crate::parser::parse_frame;
+use crate::server::{request, ServerState};
+
+const CRON_PERIOD: Duration = Duration::from_millis(100);
+
+/// Serves the cluster bus on `bus_port`. Links are one-way: messages to a node go over a
+/// connection this node opened, and replies come back over the connection the peer opened.
+pub async fn run(bus_port: u16, store_tx: mpsc::Sender<Msg>) {
+    let listener: TcpListener = match TcpListener::bind(("127.0.0.1", bus_port)).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("could not bind the cluster bus port {}: {}", bus_port, e);
+            return;
+        }
+    };
+
+    let (outgoing_tx, outgoing_rx): (mpsc::UnboundedSender<Vec<Outgoing>>, mpsc::UnboundedReceiver<Vec<Outgoing>>)
+        = mpsc::unbounded_channel();
+    tokio::spawn(dispatch(outgoing_rx));
+    tokio::spawn(cron(store_tx.clone(), outgoing_tx.clone()));
+
+    loop {
+        match listener.accept().await {
+            Ok((socket, _addr)) => {
+                tokio::spawn(read_link(socket, store_tx.clone(), outgoing_tx.clone()));
+            }
+            Err(e) => println!("cluster bus error: {}", e),
+        }
+    }
+}
+
+async fn cron(store_tx: mpsc::Sender<Msg>, outgoing_tx: mpsc::UnboundedSender<Vec<Outgoing>>) {
+    let mut interval: tokio::time::Interval = tokio::time::interval(CRON_PERIOD);
+
+    loop {
+        interval.tick().await;
+        match request(&store_tx, |tx| ClusterCronRequest { tx }).await {
+            Ok(outgoing) => {
+                let _ = outgoing_tx.send(outgoing);
+            }
+            Err(_) => return,
+        }
+    }
+}
+
+async fn read_link(
+    mut socket: TcpStream,
+    store_tx: mpsc::Sender<Msg>,
+    outgoing_tx: mpsc::UnboundedSender<Vec<Outgoing>>
+) -> Result<(), Error> {
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut chunk: [u8; 4096] = [0; 4096];
+
+    loop {
+        let length: usize = socket.read(&mut chunk).await?;
+        if length == 0 {
+            return Ok(());
+        }
+        buffer.extend_from_slice(&chunk[..length]);
+
+        while let Some((fields, frame_length)) = parse_frame(&buffer)? {
+            buffer.drain(..frame_length);
+            let fields: Option<Vec<String>> = fields.into_iter().map(|field| String::from_utf8(field).ok()).collect();
+            let message: BusMessage = match fields.as_deref().and_then(BusMessage::decode) {
+                Some(message) => message,
+                None => {
+                    println!("ignoring malformed cluster bus message");
+                    continue;
+                }
+            };
+
+            let outgoing: Vec<Outgoing> = request(&store_tx, |tx| ClusterMessageRequest { message, tx }).await?;
+            let _ = outgoing_tx.send(outgoing);
+        }
+    }
+}
+
+/// Routes outgoing messages to one writer task per destination bus address.
+async fn dispatch(mut outgoing_rx: mpsc::UnboundedReceiver<Vec<Outgoing>>) {
+    let mut links: HashMap<(String, u16), mpsc::UnboundedSender<Vec<u8>>> = HashMap::new();
+
+    while let Some(batch) = outgoing_rx.recv().await {
+        for Outgoing { address, payload } in batch {
+            let link: &mut mpsc::UnboundedSender<Vec<u8>> = links.entry(address.clone())
+                .or_insert_with(|| spawn_link(address.clone()));
+            if let Err(mpsc::error::SendError(payload)) = link.send(payload) {
+                *link = spawn_link(address);
+                let _ = link.send(payload);
+            }
+        }
+    }
+}
+
+fn spawn_link(address: (String, u16)) -> mpsc::UnboundedSender<Vec<u8>> {
+    let (tx, rx): (mpsc::UnboundedSender<Vec<u8>>, mpsc::UnboundedReceiver<Vec<u8>>) = mpsc::unbounded_channel();
+    tokio::spawn(write_link(address, rx));
+    tx
+}
+
+/// Writes messages to a peer, reconnecting on demand. Messages that cannot be delivered are
+/// dropped; the failure detector notices the missing replies.
+async fn write_link(address: (String, u16), mut rx: mpsc::UnboundedReceiver<Vec<u8>>) {
+    let mut stream: Option<TcpStream> = None;
+
+    while let Some(payload) = rx.recv().await {
+        if stream.is_none() {
+            stream = TcpStream::connect((address.0.as_str(), address.1)).await.ok();
+        }
+        if let Some(connection) = stream.as_mut() {
+            if connection.write_all(&payload).await.is_err() {
+                stream = None;
+            }
+        }
+    }
+}
+
+struct ClusterCronRequest {
+    tx: oneshot::Sender<Vec<Outgoing>>,
+}
+
+impl DataRequester for ClusterCronRequest {
+    fn request(
+        self: Box<Self>,
+        _store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState
+    ) {
+        if let Some(cluster) = server.cluster.as_ref() {
+            let mut cluster: MutexGuard<'_, ClusterState> = cluster.lock();
+            cluster.cron(&mut server.replication.lock());
+            let _ = self.tx.send(cluster.drain_outbox());
+        }
+    }
+}
+
+struct ClusterMessageRequest {
+    message: BusMessage,
+    tx: oneshot::Sender<Vec<Outgoing>>,
+}
+
+impl DataRequester for ClusterMessageRequest {
+    fn request(
+        self: Box<Self>,
+        _store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState
+    ) {
+        if let Some(cluster) = server.cluster.as_ref() {
+            let mut cluster: MutexGuard<'_, ClusterState> = cluster.lock();
+            cluster.handle_message(self.message, &mut server.replication.lock());
+            let _ = self.tx.send(cluster.drain_outbox());
+        }
+    }
+}
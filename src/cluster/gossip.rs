@@ -0,0 +1,548 @@
+use std::collections::HashSet;
+use crate::cluster::{now_millis, ClusterNode, ClusterState, FailureState, NodeRole, Outgoing};
+use crate::cluster::message::{BusMessage, GossipEntry, MessageKind};
+use crate::random::random_below;
+use crate::replication::ReplicationState;
+
+/// Shortest time a handshake started by `CLUSTER MEET` is kept alive, in milliseconds.
+const MIN_HANDSHAKE_TIMEOUT: u64 = 1000;
+const MEET_RETRY_PERIOD: u64 = 1000;
+/// Never wait longer than this between two pings to the same node, in milliseconds.
+const MAX_PING_PERIOD: u64 = 1000;
+const MIN_AUTH_TIMEOUT: u64 = 2000;
+
+/// A replica's attempt to replace its master. The election starts at `start_time`, delayed
+/// by the replica's rank so the most up-to-date replica usually asks for votes first.
+pub struct Election {
+    start_time: u64,
+    epoch: u64,
+    requested: bool,
+    votes: HashSet<String>,
+    /// Manual failovers do not require the master to be flagged as failing.
+    force: bool,
+}
+
+impl ClusterState {
+    /// Runs every 100 milliseconds: retries handshakes, pings nodes, detects failures and
+    /// drives this replica's failover election when its master is down.
+    pub fn cron(&mut self, replication: &mut ReplicationState) {
+        let now: u64 = now_millis();
+        let timeout: u64 = self.node_timeout;
+        let ping_period: u64 = (timeout / 2).clamp(1, MAX_PING_PERIOD);
+        self.myself_mut().repl_offset = replication.master_repl_offset();
+        self.forgotten.retain(|_, until| *until > now);
+
+        let handshake_timeout: u64 = timeout.max(MIN_HANDSHAKE_TIMEOUT);
+        self.pending_meets.retain(|meet| now - meet.since < handshake_timeout);
+        let mut meets: Vec<(String, u16)> = Vec::new();
+        for meet in self.pending_meets.iter_mut().filter(|meet| now - meet.last_sent >= MEET_RETRY_PERIOD) {
+            meet.last_sent = now;
+            meets.push((meet.ip.clone(), meet.bus_port));
+        }
+        for address in meets {
+            self.send(address, MessageKind::Meet);
+        }
+
+        let mut pings: Vec<(String, u16)> = Vec::new();
+        let myself: String = self.myself.clone();
+        for node in self.nodes.values_mut().filter(|node| node.id != myself) {
+            let awaiting_pong: bool = node.ping_sent != 0;
+            if now - node.pong_received > ping_period && now - node.last_ping > ping_period {
+                if !awaiting_pong {
+                    node.ping_sent = now;
+                }
+                node.last_ping = now;
+                pings.push(node.bus_address());
+            }
+
+            if awaiting_pong && now - node.ping_sent > timeout / 2 {
+                node.link_connected = false;
+            }
+            if awaiting_pong && now - node.ping_sent > timeout && node.failure == FailureState::Ok {
+                println!("*** NODE {} possibly failing", node.id);
+                node.failure = FailureState::PFail;
+            }
+        }
+        for address in pings {
+            self.send(address, MessageKind::Ping);
+        }
+
+        self.confirm_failures(now);
+        self.replica_cron(now, replication);
+        self.save_if_dirty();
+    }
+
+    /// Applies a message received on the cluster bus.
+    pub fn handle_message(&mut self, message: BusMessage, replication: &mut ReplicationState) {
+        let now: u64 = now_millis();
+        if self.forgotten.contains_key(&message.sender) || message.sender == self.myself {
+            return;
+        }
+
+        if message.current_epoch > self.current_epoch {
+            self.current_epoch = message.current_epoch;
+            self.dirty = true;
+        }
+
+        let known: bool = self.nodes.contains_key(&message.sender);
+        match message.kind {
+            MessageKind::Meet if !known => self.add_node(&message),
+            MessageKind::Pong if !known => {
+                let meet: Option<usize> = self.pending_meets.iter()
+                    .position(|meet| meet.ip == message.ip && meet.port == message.port);
+                if let Some(meet) = meet {
+                    self.pending_meets.remove(meet);
+                    self.add_node(&message);
+                }
+            }
+            _ => {}
+        }
+
+        if matches!(message.kind, MessageKind::Ping | MessageKind::Meet) {
+            self.send((message.ip.clone(), message.bus_port), MessageKind::Pong);
+        }
+
+        if !self.nodes.contains_key(&message.sender) {
+            self.save_if_dirty();
+            return;
+        }
+
+        match message.kind {
+            MessageKind::Ping | MessageKind::Pong | MessageKind::Meet => {
+                if message.kind == MessageKind::Pong {
+                    self.pong_received(&message.sender, now);
+                }
+                self.update_sender(&message, replication);
+                self.process_gossip(&message, now);
+            }
+            MessageKind::Fail => {
+                let subject: Option<&mut ClusterNode> = message.subject.as_ref()
+                    .filter(|subject| **subject != self.myself)
+                    .and_then(|subject| self.nodes.get_mut(subject));
+                if let Some(node) = subject.filter(|node| node.failure != FailureState::Fail) {
+                    println!("FAIL message received from {} about {}", message.sender, node.id);
+                    node.failure = FailureState::Fail;
+                    node.fail_time = now;
+                    self.dirty = true;
+                }
+            }
+            MessageKind::Update => {
+                let subject: Option<String> = message.subject.clone()
+                    .filter(|subject| self.nodes.contains_key(subject));
+                if let Some(subject) = subject {
+                    let node: &mut ClusterNode = self.nodes.get_mut(&subject).expect("checked above");
+                    if message.subject_epoch > node.config_epoch {
+                        node.config_epoch = message.subject_epoch;
+                        self.dirty = true;
+                    }
+                    self.claim_slots(&subject, message.subject_epoch, &message.subject_slots, replication);
+                }
+            }
+            MessageKind::FailoverAuthRequest => self.handle_vote_request(&message, now),
+            MessageKind::FailoverAuthAck => {
+                let voter_serves_slots: bool = self.nodes.get(&message.sender)
+                    .is_some_and(|voter| voter.is_master())
+                    && !self.slot_ranges(&message.sender).is_empty();
+                if let Some(election) = self.election.as_mut() {
+                    if election.requested && voter_serves_slots && message.current_epoch >= election.epoch {
+                        election.votes.insert(message.sender.clone());
+                    }
+                }
+            }
+        }
+
+        self.save_if_dirty();
+    }
+
+    /// `CLUSTER FAILOVER`. `FORCE` (and, without the coordination with the master that
+    /// Redis does, the default mode) runs an election right away even if the master is up;
+    /// `TAKEOVER` skips the election entirely.
+    pub fn manual_failover(
+        &mut self,
+        force: bool,
+        takeover: bool,
+        replication: &mut ReplicationState
+    ) -> Result<(), String> {
+        let master: &ClusterNode = match &self.myself().role {
+            NodeRole::Master => return Err("ERR You should send CLUSTER FAILOVER to a replica".to_string()),
+            NodeRole::Replica(master_id) => self.nodes.get(master_id)
+                .ok_or_else(|| "ERR I'm a replica but my master is unknown to me".to_string())?,
+        };
+        if !force && !takeover && master.failure == FailureState::Fail {
+            return Err("ERR Master is down or failed, please use CLUSTER FAILOVER FORCE".to_string());
+        }
+
+        if takeover {
+            self.bump_config_epoch();
+            let epoch: u64 = self.myself().config_epoch;
+            self.become_master(epoch, replication);
+        } else {
+            self.election = Some(Election {
+                start_time: now_millis(),
+                epoch: 0,
+                requested: false,
+                votes: HashSet::new(),
+                force: true,
+            });
+        }
+
+        self.save_if_dirty();
+        Ok(())
+    }
+
+    fn add_node(&mut self, message: &BusMessage) {
+        let mut node: ClusterNode = ClusterNode::new(message.sender.clone(), message.ip.clone(), message.port);
+        node.bus_port = message.bus_port;
+        self.nodes.insert(node.id.clone(), node);
+        self.dirty = true;
+    }
+
+    fn pong_received(&mut self, id: &str, now: u64) {
+        let timeout: u64 = self.node_timeout;
+        let serves_slots: bool = !self.slot_ranges(id).is_empty();
+        let node: &mut ClusterNode = match self.nodes.get_mut(id) {
+            Some(node) => node,
+            None => return,
+        };
+
+        node.ping_sent = 0;
+        node.pong_received = now;
+        node.link_connected = true;
+
+        match node.failure {
+            FailureState::PFail => node.failure = FailureState::Ok,
+            // A failed master that still serves slots long after being flagged was evidently
+            // not replaced, so it can serve them again.
+            FailureState::Fail if !node.is_master() || !serves_slots || now - node.fail_time > 2 * timeout => {
+                println!("Clear FAIL state for node {}", node.id);
+                node.failure = FailureState::Ok;
+                node.fail_reports.clear();
+                self.dirty = true;
+            }
+            _ => {}
+        }
+    }
+
+    /// Refreshes what the sender tells about itself: address, role, offset and slots.
+    fn update_sender(&mut self, message: &BusMessage, replication: &mut ReplicationState) {
+        let role: NodeRole = match &message.master_id {
+            Some(master_id) => NodeRole::Replica(master_id.clone()),
+            None => NodeRole::Master,
+        };
+        let node: &mut ClusterNode = self.nodes.get_mut(&message.sender).expect("sender is known");
+        node.ip = message.ip.clone();
+        node.port = message.port;
+        node.bus_port = message.bus_port;
+        node.repl_offset = message.repl_offset;
+
+        if node.role != role {
+            let became_replica: bool = node.is_master();
+            node.role = role;
+            self.dirty = true;
+            if became_replica {
+                self.release_node(&message.sender);
+            }
+        }
+
+        if message.master_id.is_none() {
+            let node: &mut ClusterNode = self.nodes.get_mut(&message.sender).expect("sender is known");
+            if node.config_epoch != message.config_epoch {
+                node.config_epoch = message.config_epoch;
+                self.dirty = true;
+            }
+            self.claim_slots(&message.sender, message.config_epoch, &message.slots, replication);
+            self.resolve_epoch_collision(message);
+        }
+
+        self.reply_to_stale_claims(message);
+    }
+
+    /// Gives `owner` every slot in `ranges` whose current owner has an older config epoch.
+    /// When that leaves this node's master without slots, this node follows the new owner.
+    fn claim_slots(
+        &mut self,
+        owner: &str,
+        config_epoch: u64,
+        ranges: &[(u16, u16)],
+        replication: &mut ReplicationState
+    ) {
+        let serving_master: String = match &self.myself().role {
+            NodeRole::Replica(master_id) => master_id.clone(),
+            NodeRole::Master => self.myself.clone(),
+        };
+        let mut lost_slots: bool = false;
+
+        for slot in ranges.iter().flat_map(|(start, end)| *start..=*end) {
+            let current: Option<String> = self.slots[slot as usize].clone();
+            let current_epoch: Option<u64> = current.as_ref()
+                .and_then(|current| self.nodes.get(current))
+                .map(|current| current.config_epoch);
+            if current.as_deref() == Some(owner) || current_epoch.is_some_and(|epoch| epoch >= config_epoch)
+                || self.importing.contains_key(&slot) {
+                continue;
+            }
+
+            lost_slots |= current.as_ref() == Some(&serving_master);
+            self.assign_slot(slot, owner);
+            self.dirty = true;
+        }
+
+        if lost_slots && self.slot_ranges(&serving_master).is_empty() {
+            println!("Configuration change detected. Reconfiguring myself as a replica of {}", owner);
+            self.follow(owner.to_string(), replication);
+            self.dirty = true;
+        }
+    }
+
+    /// Two masters ended up with the same config epoch; the one with the smaller ID moves on
+    /// to a new epoch so slot ownership stays unambiguous.
+    fn resolve_epoch_collision(&mut self, message: &BusMessage) {
+        let myself: &ClusterNode = self.myself();
+        if myself.is_master() && message.config_epoch == myself.config_epoch && message.sender < myself.id {
+            self.bump_config_epoch();
+            self.dirty = true;
+        }
+    }
+
+    /// Tells a sender that claims slots with an outdated config about their current owner.
+    fn reply_to_stale_claims(&mut self, message: &BusMessage) {
+        let claimer: &str = message.master_id.as_deref().unwrap_or(&message.sender);
+        let newer_owner: Option<&ClusterNode> = message.slots.iter()
+            .flat_map(|(start, end)| *start..=*end)
+            .filter_map(|slot| self.slot_owner(slot))
+            .find(|owner| owner.id != claimer && owner.config_epoch > message.config_epoch);
+
+        if let Some(owner) = newer_owner {
+            let mut update: BusMessage = self.header(MessageKind::Update);
+            update.subject = Some(owner.id.clone());
+            update.subject_epoch = owner.config_epoch;
+            update.subject_slots = self.slot_ranges(&owner.id);
+            self.outbox.push(Outgoing {
+                address: (message.ip.clone(), message.bus_port),
+                payload: update.encode(),
+            });
+        }
+    }
+
+    /// Learns about unknown nodes and records the failure reports of the sending master.
+    fn process_gossip(&mut self, message: &BusMessage, now: u64) {
+        let sender_is_master: bool = message.master_id.is_none();
+
+        for entry in &message.gossip {
+            if entry.id == self.myself {
+                continue;
+            }
+
+            match self.nodes.get_mut(&entry.id) {
+                Some(node) if sender_is_master => {
+                    if entry.failing {
+                        node.fail_reports.insert(message.sender.clone(), now);
+                    } else {
+                        node.fail_reports.remove(&message.sender);
+                    }
+                }
+                Some(_) => {}
+                None if !entry.failing && !self.forgotten.contains_key(&entry.id) => {
+                    self.start_handshake(entry.ip.clone(), entry.port, entry.bus_port);
+                }
+                None => {}
+            }
+        }
+    }
+
+    /// Promotes `PFAIL` to `FAIL` once a majority of masters agrees, and tells everyone.
+    fn confirm_failures(&mut self, now: u64) {
+        let report_validity: u64 = self.node_timeout * 2;
+        let quorum: usize = self.quorum();
+        let myself_votes: usize = if self.myself().is_master() { 1 } else { 0 };
+        let mut failed: Vec<String> = Vec::new();
+
+        for node in self.nodes.values_mut() {
+            node.fail_reports.retain(|_, reported| now - *reported <= report_validity);
+            if node.failure == FailureState::PFail && node.fail_reports.len() + myself_votes >= quorum {
+                println!("Marking node {} as failing (quorum reached).", node.id);
+                node.failure = FailureState::Fail;
+                node.fail_time = now;
+                failed.push(node.id.clone());
+            }
+        }
+
+        for id in failed {
+            let mut fail: BusMessage = self.header(MessageKind::Fail);
+            fail.subject = Some(id);
+            self.broadcast(&fail);
+            self.dirty = true;
+        }
+    }
+
+    fn replica_cron(&mut self, now: u64, replication: &mut ReplicationState) {
+        let (master_id, master_failed): (String, bool) = match self.replicated_master() {
+            Some(master) => (master.id.clone(), master.failure == FailureState::Fail),
+            None => {
+                self.election = None;
+                return;
+            }
+        };
+        let forced: bool = self.election.as_ref().is_some_and(|election| election.force);
+        if (!master_failed && !forced) || self.slot_ranges(&master_id).is_empty() {
+            self.election = None;
+            return;
+        }
+
+        let auth_timeout: u64 = (self.node_timeout * 2).max(MIN_AUTH_TIMEOUT);
+        let quorum: usize = self.quorum();
+        let my_offset: u64 = self.myself().repl_offset;
+        let rank: u64 = self.nodes.values()
+            .filter(|node| node.role == NodeRole::Replica(master_id.clone()) && node.repl_offset > my_offset)
+            .count() as u64;
+
+        let election: &mut Election = self.election.get_or_insert_with(|| Election {
+            start_time: now + 500 + random_below(500) + rank * 1000,
+            epoch: 0,
+            requested: false,
+            votes: HashSet::new(),
+            force: false,
+        });
+
+        if !election.requested {
+            if now < election.start_time {
+                return;
+            }
+            election.epoch = self.current_epoch + 1;
+            election.requested = true;
+            let force: bool = election.force;
+            self.current_epoch += 1;
+            println!("Starting a failover election for epoch {}.", self.current_epoch);
+
+            let mut request: BusMessage = self.header(MessageKind::FailoverAuthRequest);
+            request.force = force;
+            self.broadcast(&request);
+            self.dirty = true;
+        } else if election.votes.len() >= quorum {
+            println!("Failover election won: I'm the new master.");
+            let epoch: u64 = election.epoch;
+            self.become_master(epoch, replication);
+        } else if now - election.start_time > auth_timeout * 2 {
+            self.election = None;
+        }
+    }
+
+    /// Grants this master's vote for a replica's failover when nothing makes it unsafe.
+    fn handle_vote_request(&mut self, message: &BusMessage, now: u64) {
+        let timeout: u64 = self.node_timeout;
+        if !self.myself().is_master() || self.slot_ranges(&self.myself).is_empty()
+            || message.current_epoch < self.current_epoch || self.last_vote_epoch == self.current_epoch {
+            return;
+        }
+
+        let master: &ClusterNode = match message.master_id.as_ref().and_then(|master_id| self.nodes.get(master_id)) {
+            Some(master) => master,
+            None => return,
+        };
+        if (!message.force && master.failure != FailureState::Fail) || now - master.voted_time < timeout * 2 {
+            return;
+        }
+
+        let outdated: bool = message.slots.iter()
+            .flat_map(|(start, end)| *start..=*end)
+            .filter_map(|slot| self.slot_owner(slot))
+            .any(|owner| owner.config_epoch > message.config_epoch);
+        if outdated {
+            return;
+        }
+
+        let master_id: String = master.id.clone();
+        self.last_vote_epoch = self.current_epoch;
+        if let Some(master) = self.nodes.get_mut(&master_id) {
+            master.voted_time = now;
+        }
+        self.send((message.ip.clone(), message.bus_port), MessageKind::FailoverAuthAck);
+        self.dirty = true;
+    }
+
+    /// Finishes a failover: takes over the old master's slots with `config_epoch` and
+    /// announces the new configuration to every node.
+    fn become_master(&mut self, config_epoch: u64, replication: &mut ReplicationState) {
+        let old_master: String = match &self.myself().role {
+            NodeRole::Replica(master_id) => master_id.clone(),
+            NodeRole::Master => return,
+        };
+
+        let myself: String = self.myself.clone();
+        for (start, end) in self.slot_ranges(&old_master) {
+            (start..=end).for_each(|slot| self.assign_slot(slot, &myself));
+        }
+
+        let node: &mut ClusterNode = self.myself_mut();
+        node.role = NodeRole::Master;
+        node.config_epoch = config_epoch;
+        replication.promote();
+        self.election = None;
+        self.dirty = true;
+
+        let pong: BusMessage = self.header(MessageKind::Pong);
+        self.broadcast(&pong);
+    }
+
+    /// Describes this node; replicas advertise the slots and config epoch of their master.
+    fn header(&self, kind: MessageKind) -> BusMessage {
+        let myself: &ClusterNode = self.myself();
+        let serving: &ClusterNode = self.replicated_master().unwrap_or(myself);
+
+        BusMessage {
+            kind,
+            sender: myself.id.clone(),
+            ip: myself.ip.clone(),
+            port: myself.port,
+            bus_port: myself.bus_port,
+            master_id: match &myself.role {
+                NodeRole::Replica(master_id) => Some(master_id.clone()),
+                NodeRole::Master => None,
+            },
+            current_epoch: self.current_epoch,
+            config_epoch: serving.config_epoch,
+            repl_offset: myself.repl_offset,
+            slots: self.slot_ranges(&serving.id),
+            force: false,
+            subject: None,
+            subject_epoch: 0,
+            subject_slots: Vec::new(),
+            gossip: Vec::new(),
+        }
+    }
+
+    fn send(&mut self, address: (String, u16), kind: MessageKind) {
+        let mut message: BusMessage = self.header(kind);
+        if matches!(kind, MessageKind::Ping | MessageKind::Pong | MessageKind::Meet) {
+            message.gossip = self.nodes.values()
+                .filter(|node| node.id != self.myself)
+                .map(|node| GossipEntry {
+                    id: node.id.clone(),
+                    ip: node.ip.clone(),
+                    port: node.port,
+                    bus_port: node.bus_port,
+                    failing: node.failure != FailureState::Ok,
+                })
+                .collect();
+        }
+        self.outbox.push(Outgoing { address, payload: message.encode() });
+    }
+
+    fn broadcast(&mut self, message: &BusMessage) {
+        let payload: Vec<u8> = message.encode();
+        let addresses: Vec<(String, u16)> = self.nodes.values()
+            .filter(|node| node.id != self.myself)
+            .map(|node| node.bus_address())
+            .collect();
+        for address in addresses {
+            self.outbox.push(Outgoing { address, payload: payload.clone() });
+        }
+    }
+
+    fn save_if_dirty(&mut self) {
+        if self.dirty {
+            self.dirty = false;
+            self.save_config();
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use crate::cluster::{format_slot_ranges, parse_slot_ranges};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MessageKind {
+    Ping,
+    Pong,
+    Meet,
+    Fail,
+    FailoverAuthRequest,
+    FailoverAuthAck,
+    Update,
+}
+
+impl MessageKind {
+    fn name(self) -> &'static str {
+        match self {
+            MessageKind::Ping => "PING",
+            MessageKind::Pong => "PONG",
+            MessageKind::Meet => "MEET",
+            MessageKind::Fail => "FAIL",
+            MessageKind::FailoverAuthRequest => "AUTH_REQUEST",
+            MessageKind::FailoverAuthAck => "AUTH_ACK",
+            MessageKind::Update => "UPDATE",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "PING" => Some(MessageKind::Ping),
+            "PONG" => Some(MessageKind::Pong),
+            "MEET" => Some(MessageKind::Meet),
+            "FAIL" => Some(MessageKind::Fail),
+            "AUTH_REQUEST" => Some(MessageKind::FailoverAuthRequest),
+            "AUTH_ACK" => Some(MessageKind::FailoverAuthAck),
+            "UPDATE" => Some(MessageKind::Update),
+            _ => None,
+        }
+    }
+}
+
+/// What the sender knows about another node, piggybacked on PING, PONG and MEET messages.
+pub struct GossipEntry {
+    pub id: String,
+    pub ip: String,
+    pub port: u16,
+    pub bus_port: u16,
+    pub failing: bool,
+}
+
+/// A cluster bus message. Every message carries the sender's view of itself; the
+/// remaining fields depend on the message kind:
+/// - PING/PONG/MEET carry `gossip` about other nodes,
+/// - FAIL names the failed node in `subject`,
+/// - UPDATE tells the receiver the `subject_epoch` and `subject_slots` of `subject`,
+/// - AUTH_REQUEST sets `force` for manual failovers that skip the failed-master check.
+pub struct BusMessage {
+    pub kind: MessageKind,
+    pub sender: String,
+    pub ip: String,
+    pub port: u16,
+    pub bus_port: u16,
+    /// The sender's master, or `None` when the sender is itself a master.
+    pub master_id: Option<String>,
+    pub current_epoch: u64,
+    /// For replicas, the config epoch of their master.
+    pub config_epoch: u64,
+    pub repl_offset: u64,
+    /// Slots served by the sender, or by its master when the sender is a replica.
+    pub slots: Vec<(u16, u16)>,
+    pub force: bool,
+    pub subject: Option<String>,
+    pub subject_epoch: u64,
+    pub subject_slots: Vec<(u16, u16)>,
+    pub gossip: Vec<GossipEntry>,
+}
+
+const HEADER_FIELDS: usize = 15;
+const GOSSIP_FIELDS: usize = 5;
+
+impl BusMessage {
+    /// Encodes the message as a RESP array of bulk strings.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut fields: Vec<String> = vec![
+            self.kind.name().to_string(),
+            self.sender.clone(),
+            self.ip.clone(),
+            self.port.to_string(),
+            self.bus_port.to_string(),
+            self.master_id.clone().unwrap_or_else(|| "-".to_string()),
+            self.current_epoch.to_string(),
+            self.config_epoch.to_string(),
+            self.repl_offset.to_string(),
+            format_slot_ranges(&self.slots),
+            if self.force { "force" } else { "-" }.to_string(),
+            self.subject.clone().unwrap_or_else(|| "-".to_string()),
+            self.subject_epoch.to_string(),
+            format_slot_ranges(&self.subject_slots),
+            self.gossip.len().to_string(),
+        ];
+
+        for entry in &self.gossip {
+            fields.push(entry.id.clone());
+            fields.push(entry.ip.clone());
+            fields.push(entry.port.to_string());
+            fields.push(entry.bus_port.to_string());
+            fields.push(if entry.failing { "fail" } else { "-" }.to_string());
+        }
+
+        let mut encoded: String = format!("*{}\r\n", fields.len());
+        for field in fields {
+            encoded.push_str(&format!("${}\r\n{}\r\n", field.len(), field));
+        }
+        encoded.into_bytes()
+    }
+
+    pub fn decode(fields: &[String]) -> Option<Self> {
+        if fields.len() < HEADER_FIELDS {
+            return None;
+        }
+
+        let optional = |field: &String| Some(field.clone()).filter(|field| field != "-");
+        let gossip_count: usize = fields[14].parse().ok()?;
+        let gossip_fields: &[String] = &fields[HEADER_FIELDS..];
+        if gossip_fields.len() != gossip_count * GOSSIP_FIELDS {
+            return None;
+        }
+
+        let gossip: Option<Vec<GossipEntry>> = gossip_fields
+            .chunks_exact(GOSSIP_FIELDS)
+            .map(|entry| Some(GossipEntry {
+                id: entry[0].clone(),
+                ip: entry[1].clone(),
+                port: entry[2].parse().ok()?,
+                bus_port: entry[3].parse().ok()?,
+                failing: entry[4] == "fail",
+            }))
+            .collect();
+
+        Some(BusMessage {
+            kind: MessageKind::from_name(&fields[0])?,
+            sender: fields[1].clone(),
+            ip: fields[2].clone(),
+            port: fields[3].parse().ok()?,
+            bus_port: fields[4].parse().ok()?,
+            master_id: optional(&fields[5]),
+            current_epoch: fields[6].parse().ok()?,
+            config_epoch: fields[7].parse().ok()?,
+            repl_offset: fields[8].parse().ok()?,
+            slots: parse_slot_ranges(&fields[9])?,
+            force: fields[10] == "force",
+            subject: optional(&fields[11]),
+            subject_epoch: fields[12].parse().ok()?,
+            subject_slots: parse_slot_ranges(&fields[13])?,
+            gossip: gossip?,
+        })
+    }
+}
@@ -8,32 +8,473 @@ pub mod lpush;
 pub mod llen;
 pub mod lpop;
 pub mod blpop;
+pub mod replconf;
+pub mod psync;
+pub mod replicaof;
+pub mod wait;
+pub mod cluster;
+pub mod asking;
+pub mod del;
+pub mod restore;
+pub mod migrate;
+pub mod dump;
+pub mod object;
+pub mod config;
+pub mod info;
+pub mod memory;
+pub mod introspection;
+pub mod hello;
+pub mod debug;
+pub mod auth;
+pub mod acl;
+pub mod client;
+pub mod pubsub;
+pub mod eval;
+pub mod script;
+pub mod function;
+pub mod save;
 
 use std::future::Future;
-use std::io::Error;
+use std::io::{Error, Write};
 use std::pin::Pin;
+use tokio::sync::mpsc;
 use crate::key_value_store::KeyValueStore;
+use crate::reply::{ReplyWriter, Value};
+use crate::server::ServerState;
 
-pub trait CommandFactory: Sized + DataRequester
-where Self: 'static {
-    fn new(arguments: &[&str]) -> Result<Box<Self>, Error>;
+/// A command parsed from the arguments following its name. Commands only reading their
+/// arguments can borrow them, so parsing allocates nothing.
+pub trait Command<'a>: Sized {
+    fn new(arguments: &[&'a str]) -> Result<Self, Error>;
 
-    fn new_command(arguments: &[&str]) -> Result<Box<dyn DataRequester + 'static>, Error> {
-        Self::new(arguments).map(|box_of_self| box_of_self as Box<dyn DataRequester>)
-    }
+    /// Runs the command, writing its reply unless it is only known later.
+    fn execute(
+        self,
+        store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply;
 }
 
+/// Work the server hands to the data manager itself, like registering a new connection.
 pub trait DataRequester: Send + 'static {
-    fn request(self: Box<Self>, store: &mut Box<dyn KeyValueStore>) -> Box<dyn CommandRunner>;
+    fn request(
+        self: Box<Self>,
+        store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState
+    );
+
+    /// Whether the request reads or changes the keyspace. A sharded keyspace runs those that
+    /// do not without stopping the shards.
+    fn uses_keyspace(&self) -> bool {
+        true
+    }
 }
 
-type ResponseFuture = Pin<Box<dyn Future<Output = Vec<u8>> + Send + 'static>>;
+type ResponseFuture = Pin<Box<dyn Future<Output = Value> + Send + 'static>>;
 
 pub enum Reply {
-    Immediate(Vec<u8>),
+    /// The reply has been written to the connection's output buffer.
+    Immediate,
+    /// The reply is only known once the future resolves, e.g. when a blocked `BLPOP` is served.
     Deferred(ResponseFuture),
+    /// After the reply, keeps forwarding everything sent over the channel, e.g. the
+    /// replication stream of an attached replica.
+    Stream(mpsc::UnboundedReceiver<Vec<u8>>),
+}
+
+/// Runs a command taking text arguments: `arguments` holds the command name first, like in
+/// the command table.
+pub type Handler = fn(&[Vec<u8>], &mut Box<dyn KeyValueStore>, &mut ServerState, &mut ReplyWriter) -> Reply;
+
+/// Reply to a command working on a type of value other than the one held by its key.
+pub const WRONG_TYPE_ERROR: &str = "WRONGTYPE Operation against a key holding the wrong kind of value";
+
+/// Number of arguments converted to text without allocating.
+const INLINE_ARGUMENTS: usize = 8;
+
+/// Calls `run` with the arguments after the command name as text, replying with an error
+/// instead if one of them is not valid UTF-8.
+pub fn with_text_arguments<F>(arguments: &[Vec<u8>], reply: &mut ReplyWriter, run: F) -> Reply
+where
+    F: FnOnce(&[&str], &mut ReplyWriter) -> Reply,
+{
+    let arguments: &[Vec<u8>] = arguments.get(1..).unwrap_or_default();
+    let mut inline: [&str; INLINE_ARGUMENTS] = [""; INLINE_ARGUMENTS];
+    let spilled: Vec<&str>;
+    let text: &[&str] = if arguments.len() <= INLINE_ARGUMENTS {
+        for (slot, argument) in inline.iter_mut().zip(arguments) {
+            match std::str::from_utf8(argument) {
+                Ok(argument) => *slot = argument,
+                Err(_) => return invalid_utf8(reply),
+            }
+        }
+        &inline[..arguments.len()]
+    } else {
+        match arguments.iter().map(|argument| std::str::from_utf8(argument)).collect() {
+            Ok(collected) => {
+                spilled = collected;
+                &spilled
+            }
+            Err(_) => return invalid_utf8(reply),
+        }
+    };
+
+    run(text, reply)
 }
 
-pub trait CommandRunner: Send + 'static {
-    fn run(self: Box<Self>) -> Reply;
+fn invalid_utf8(reply: &mut ReplyWriter) -> Reply {
+    reply.error("ERR Argument is not valid UTF-8");
+    Reply::Immediate
+}
+
+/// Parses and runs a command, replying with the parse error if there is one.
+pub fn run<'a, C: Command<'a>>(
+    command: Result<C, Error>,
+    store: &mut Box<dyn KeyValueStore>,
+    server: &mut ServerState,
+    reply: &mut ReplyWriter
+) -> Reply {
+    match command {
+        Ok(command) => command.execute(store, server, reply),
+        Err(e) => {
+            reply.error(&format!("ERR {}", e));
+            Reply::Immediate
+        }
+    }
+}
+
+/// The `Handler` of a command implementing `Command`.
+macro_rules! handler {
+    ($command:ident) => {
+        |arguments, store, server, reply| {
+            crate::command::with_text_arguments(arguments, reply, |text, reply| {
+                crate::command::run($command::new(text), store, server, reply)
+            })
+        }
+    };
+}
+pub(crate) use handler;
+
+/// Encodes a command as a RESP array of binary-safe bulk strings.
+pub fn encode_command(arguments: &[&[u8]]) -> Vec<u8> {
+    let mut frame: Vec<u8> = Vec::new();
+    encode_command_into(&mut frame, arguments);
+    frame
+}
+
+/// Appends a command encoded as a RESP array of bulk strings to `frame`.
+pub fn encode_command_into<A: AsRef<[u8]>>(frame: &mut Vec<u8>, arguments: &[A]) {
+    let _ = write!(frame, "*{}\r\n", arguments.len());
+    for argument in arguments {
+        let argument: &[u8] = argument.as_ref();
+        let _ = write!(frame, "${}\r\n", argument.len());
+        frame.extend_from_slice(argument);
+        frame.extend_from_slice(b"\r\n");
+    }
+}
+
+/// Flags of a command, as listed by `COMMAND INFO`.
+#[derive(Clone, Copy, PartialEq)]
+pub enum CommandFlag {
+    /// Propagated to replicas and refused by read-only replicas.
+    Write,
+    ReadOnly,
+    /// Refused with an OOM error when memory cannot be freed below `maxmemory`.
+    DenyOom,
+    Admin,
+    NoScript,
+    Blocking,
+    /// Allowed while the dataset is loading.
+    Loading,
+    /// Allowed on a replica with stale data.
+    Stale,
+    /// May be served in a slot being imported without a preceding `ASKING`.
+    Asking,
+    /// Runs in constant or logarithmic time.
+    Fast,
+    /// Allowed before the connection authenticated.
+    NoAuth,
+    /// A pub/sub command.
+    PubSub,
+    /// Changes the dataset through the commands it runs rather than by itself, like a script;
+    /// those commands are propagated instead of it.
+    MayReplicate,
+    /// Answered while a script runs, without waiting for it to end.
+    AllowBusy,
+}
+
+impl CommandFlag {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CommandFlag::Write => "write",
+            CommandFlag::ReadOnly => "readonly",
+            CommandFlag::DenyOom => "denyoom",
+            CommandFlag::Admin => "admin",
+            CommandFlag::NoScript => "noscript",
+            CommandFlag::Blocking => "blocking",
+            CommandFlag::Loading => "loading",
+            CommandFlag::Stale => "stale",
+            CommandFlag::Asking => "asking",
+            CommandFlag::Fast => "fast",
+            CommandFlag::NoAuth => "no_auth",
+            CommandFlag::PubSub => "pubsub",
+            CommandFlag::MayReplicate => "may_replicate",
+            CommandFlag::AllowBusy => "allow_busy",
+        }
+    }
+}
+
+/// The group a command is documented under by `COMMAND DOCS`.
+#[derive(Clone, Copy, PartialEq)]
+pub enum CommandGroup {
+    Generic,
+    String,
+    List,
+    Connection,
+    Server,
+    Cluster,
+    PubSub,
+    Scripting,
+}
+
+impl CommandGroup {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CommandGroup::Generic => "generic",
+            CommandGroup::String => "string",
+            CommandGroup::List => "list",
+            CommandGroup::Connection => "connection",
+            CommandGroup::Server => "server",
+            CommandGroup::Cluster => "cluster",
+            CommandGroup::PubSub => "pubsub",
+            CommandGroup::Scripting => "scripting",
+        }
+    }
+
+    /// The ACL category of the commands of the group, for groups that have one.
+    fn category(&self) -> Option<AclCategory> {
+        match self {
+            CommandGroup::Generic => Some(AclCategory::Keyspace),
+            CommandGroup::String => Some(AclCategory::String),
+            CommandGroup::List => Some(AclCategory::List),
+            CommandGroup::Connection => Some(AclCategory::Connection),
+            CommandGroup::Scripting => Some(AclCategory::Scripting),
+            // Pub/sub commands are told apart by their flag.
+            CommandGroup::Server | CommandGroup::Cluster | CommandGroup::PubSub => None,
+        }
+    }
+}
+
+/// Categories of commands that ACL rules allow or deny as a whole, written `@write`.
+#[derive(Clone, Copy, PartialEq)]
+pub enum AclCategory {
+    Keyspace,
+    Read,
+    Write,
+    String,
+    List,
+    Admin,
+    Fast,
+    Slow,
+    Blocking,
+    Dangerous,
+    Connection,
+    PubSub,
+    Scripting,
+}
+
+impl AclCategory {
+    pub const ALL: &'static [AclCategory] = &[
+        AclCategory::Keyspace,
+        AclCategory::Read,
+        AclCategory::Write,
+        AclCategory::String,
+        AclCategory::List,
+        AclCategory::Admin,
+        AclCategory::Fast,
+        AclCategory::Slow,
+        AclCategory::Blocking,
+        AclCategory::Dangerous,
+        AclCategory::Connection,
+        AclCategory::PubSub,
+        AclCategory::Scripting,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AclCategory::Keyspace => "keyspace",
+            AclCategory::Read => "read",
+            AclCategory::Write => "write",
+            AclCategory::String => "string",
+            AclCategory::List => "list",
+            AclCategory::Admin => "admin",
+            AclCategory::Fast => "fast",
+            AclCategory::Slow => "slow",
+            AclCategory::Blocking => "blocking",
+            AclCategory::Dangerous => "dangerous",
+            AclCategory::Connection => "connection",
+            AclCategory::PubSub => "pubsub",
+            AclCategory::Scripting => "scripting",
+        }
+    }
+
+    /// The category named `name`, without the leading `@`, ignoring case.
+    pub fn from_name(name: &str) -> Option<AclCategory> {
+        AclCategory::ALL.iter().copied().find(|category| category.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// What `COMMAND DOCS` tells about a command.
+pub struct CommandDocs {
+    pub group: CommandGroup,
+    /// The Redis version that introduced the command.
+    pub since: &'static str,
+    pub complexity: &'static str,
+    pub summary: &'static str,
+}
+
+/// How a command uses its keys, as listed by the key specifications of `COMMAND INFO`. ACL
+/// key permissions follow from the kind of access: reading a value needs read permission,
+/// changing it needs write permission.
+#[derive(Clone, Copy, PartialEq)]
+pub enum KeyFlag {
+    /// Only reads the value.
+    Ro,
+    /// Reads and changes the value.
+    Rw,
+    /// Overwrites the value without reading it.
+    Ow,
+    /// Removes the key.
+    Rm,
+    /// Returns or exposes data of the value.
+    Access,
+    Update,
+    Insert,
+    Delete,
+}
+
+impl KeyFlag {
+    pub fn name(&self) -> &'static str {
+        match self {
+            KeyFlag::Ro => "RO",
+            KeyFlag::Rw => "RW",
+            KeyFlag::Ow => "OW",
+            KeyFlag::Rm => "RM",
+            KeyFlag::Access => "access",
+            KeyFlag::Update => "update",
+            KeyFlag::Insert => "insert",
+            KeyFlag::Delete => "delete",
+        }
+    }
+}
+
+/// Where the keys of a command are among its arguments, the command name being at 0. A
+/// negative `last` counts from the end, -1 being the last argument.
+pub struct KeySpec {
+    pub first: i32,
+    pub last: i32,
+    pub step: i32,
+    pub flags: &'static [KeyFlag],
+    /// Finds the keys of commands whose key positions depend on the arguments, like `MIGRATE`.
+    pub find: Option<KeyFinder>,
+}
+
+/// Lists the positions of the keys among the arguments of a call.
+pub type KeyFinder = fn(&[Vec<u8>]) -> Vec<usize>;
+
+impl KeySpec {
+    pub const NONE: KeySpec = KeySpec::range(0, 0, 0, &[]);
+
+    pub const fn range(first: i32, last: i32, step: i32, flags: &'static [KeyFlag]) -> Self {
+        KeySpec { first, last, step, flags, find: None }
+    }
+
+    /// Whether accessing the keys needs read and write permission respectively.
+    pub fn permissions(&self) -> (bool, bool) {
+        let read: bool = self.flags.contains(&KeyFlag::Access);
+        let write: bool = self.flags.iter()
+            .any(|flag| matches!(flag, KeyFlag::Update | KeyFlag::Insert | KeyFlag::Delete));
+        (read, write)
+    }
+
+    /// Positions of the keys within `arguments`.
+    fn positions(&self, arguments: &[Vec<u8>]) -> impl Iterator<Item = usize> {
+        let count: i32 = arguments.len() as i32;
+        let last: i32 = if self.last < 0 { count + self.last } else { self.last.min(count - 1) };
+        // With a key finder, the range only documents the usual key position for `COMMAND`.
+        let (first, last): (i32, i32) = match self.find {
+            None if self.first > 0 => (self.first, last),
+            _ => (1, 0),
+        };
+
+        (first..=last)
+            .step_by(self.step.max(1) as usize)
+            .map(|position| position as usize)
+            .chain(self.find.map(|find| find(arguments)).into_iter().flatten())
+    }
+}
+
+/// An entry of the command table.
+pub struct CommandSpec {
+    /// Lowercase name; subcommands are named after their container, e.g. `config|get`.
+    pub name: &'static str,
+    /// Number of arguments including the name, or its opposite when it is only a minimum.
+    pub arity: i32,
+    pub flags: &'static [CommandFlag],
+    pub keys: KeySpec,
+    pub subcommands: &'static [CommandSpec],
+    pub docs: CommandDocs,
+    /// Runs the command. Subcommands have none, their container's handler runs them.
+    pub handler: Option<Handler>,
+}
+
+impl CommandSpec {
+    pub fn has(&self, flag: CommandFlag) -> bool {
+        self.flags.contains(&flag)
+    }
+
+    pub fn is_write(&self) -> bool {
+        self.has(CommandFlag::Write)
+    }
+
+    pub fn accepts_argument_count(&self, count: usize) -> bool {
+        let arity: usize = self.arity.unsigned_abs() as usize;
+        if self.arity < 0 { count >= arity } else { count == arity }
+    }
+
+    /// The ACL categories of the command, following from its flags and group.
+    pub fn acl_categories(&self) -> Vec<AclCategory> {
+        let mut categories: Vec<AclCategory> = Vec::new();
+        if self.has(CommandFlag::Write) {
+            categories.push(AclCategory::Write);
+        }
+        if self.has(CommandFlag::ReadOnly) {
+            categories.push(AclCategory::Read);
+        }
+        if self.has(CommandFlag::Admin) {
+            categories.extend([AclCategory::Admin, AclCategory::Dangerous]);
+        }
+        if self.has(CommandFlag::Blocking) {
+            categories.push(AclCategory::Blocking);
+        }
+        if self.has(CommandFlag::PubSub) {
+            categories.push(AclCategory::PubSub);
+        }
+        categories.extend(self.docs.group.category());
+        categories.push(if self.has(CommandFlag::Fast) { AclCategory::Fast } else { AclCategory::Slow });
+        categories
+    }
+
+    /// The keys of a call with `arguments`, the command name included.
+    pub fn keys<'a>(&self, arguments: &'a [Vec<u8>]) -> impl Iterator<Item = &'a str> {
+        self.key_arguments(arguments).filter_map(|key| std::str::from_utf8(key).ok())
+    }
+
+    /// Like `keys`, including keys that are not valid UTF-8.
+    pub fn key_arguments<'a>(&self, arguments: &'a [Vec<u8>]) -> impl Iterator<Item = &'a [u8]> {
+        self.keys.positions(arguments)
+            .filter_map(|position| arguments.get(position))
+            .map(|key| key.as_slice())
+    }
 }
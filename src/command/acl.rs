@@ -0,0 +1,265 @@
+use std::io::{Error, ErrorKind};
+use std::sync::MutexGuard;
+use crate::acl::{Acl, LogEntry, Selector, User, DEFAULT_USER};
+use crate::cluster::now_millis;
+use crate::command::{AclCategory, Command, CommandSpec, Reply};
+use crate::key_value_store::KeyValueStore;
+use crate::parser::{self, LookupError, COMMAND_TABLE};
+use crate::reply::ReplyWriter;
+use crate::server::ServerState;
+
+/// Number of entries `ACL LOG` shows without a count.
+const DEFAULT_LOG_COUNT: usize = 10;
+
+const NO_ACL_FILE: &str = "ERR This Redis instance is not configured to use an ACL file. You may want to specify \
+    users via the ACL SETUSER command and then issue a CONFIG REWRITE (assuming you have a Redis configuration file \
+    set) in order to store users in the Redis configuration.";
+
+pub enum AclRequest<'a> {
+    /// Lists the categories, or the commands of one.
+    Cat(Option<AclCategory>),
+    DelUser(Vec<&'a str>),
+    DryRun(&'a str, Vec<&'a str>),
+    GetUser(&'a str),
+    List,
+    Load,
+    /// Shows the most recent entries, or clears the log when there is no count.
+    Log(Option<usize>),
+    Save,
+    SetUser(&'a str, Vec<&'a str>),
+    Users,
+    WhoAmI,
+}
+
+impl<'a> Command<'a> for AclRequest<'a> {
+    fn new(arguments: &[&'a str]) -> Result<Self, Error> {
+        let invalid = |message: String| Error::new(ErrorKind::InvalidInput, message);
+        let subcommand: &str = arguments.first().ok_or_else(|| invalid("Expected a subcommand".to_string()))?;
+        let arguments: &[&'a str] = &arguments[1..];
+
+        let request: AclRequest = match (subcommand.to_ascii_lowercase().as_str(), arguments) {
+            ("cat", []) => AclRequest::Cat(None),
+            ("cat", [category]) => AclRequest::Cat(Some(AclCategory::from_name(category)
+                .ok_or_else(|| invalid(format!("Unknown category '{}'", category)))?)),
+            ("deluser", names) if !names.is_empty() => AclRequest::DelUser(names.to_vec()),
+            ("dryrun", [username, command @ ..]) if !command.is_empty() => {
+                AclRequest::DryRun(username, command.to_vec())
+            }
+            ("getuser", [username]) => AclRequest::GetUser(username),
+            ("list", []) => AclRequest::List,
+            ("load", []) => AclRequest::Load,
+            ("log", []) => AclRequest::Log(Some(DEFAULT_LOG_COUNT)),
+            ("log", [reset]) if reset.eq_ignore_ascii_case("reset") => AclRequest::Log(None),
+            ("log", [count]) => {
+                let count: i64 = count.parse()
+                    .map_err(|_| invalid("value is not an integer or out of range".to_string()))?;
+                if count < 0 {
+                    return Err(invalid("value is out of range, must be positive".to_string()));
+                }
+                AclRequest::Log(Some(count as usize))
+            }
+            ("save", []) => AclRequest::Save,
+            ("setuser", [username, rules @ ..]) => AclRequest::SetUser(username, rules.to_vec()),
+            ("users", []) => AclRequest::Users,
+            ("whoami", []) => AclRequest::WhoAmI,
+            _ => return Err(invalid(format!(
+                "unknown subcommand or wrong number of arguments for '{}'. Try ACL HELP.", subcommand))),
+        };
+
+        Ok(request)
+    }
+
+    fn execute(
+        self,
+        _store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        let mut acl: MutexGuard<'_, Acl> = server.acl.lock();
+        let result: Result<(), String> = match self {
+            AclRequest::Cat(None) => {
+                reply.array(AclCategory::ALL.len());
+                for category in AclCategory::ALL {
+                    reply.bulk(category.name().as_bytes());
+                }
+                return Reply::Immediate;
+            }
+            AclRequest::Cat(Some(category)) => {
+                let names: Vec<&str> = COMMAND_TABLE.iter()
+                    .flat_map(|spec| std::iter::once(spec).chain(spec.subcommands))
+                    .filter(|spec| spec.acl_categories().contains(&category))
+                    .map(|spec| spec.name)
+                    .collect();
+                reply.array(names.len());
+                for name in names {
+                    reply.bulk(name.as_bytes());
+                }
+                return Reply::Immediate;
+            }
+            AclRequest::DelUser(names) => {
+                if names.contains(&DEFAULT_USER) {
+                    reply.error("ERR The 'default' user cannot be removed");
+                    return Reply::Immediate;
+                }
+                let deleted: usize = names.iter().filter(|name| acl.delete_user(name)).count();
+                disconnect_removed_users(&acl, server);
+                reply.integer(deleted as i64);
+                return Reply::Immediate;
+            }
+            AclRequest::DryRun(username, command) => {
+                dry_run(&acl, username, &command, reply);
+                return Reply::Immediate;
+            }
+            AclRequest::GetUser(username) => {
+                match acl.user(username) {
+                    Some(user) => write_user(user, reply),
+                    None => reply.null(),
+                }
+                return Reply::Immediate;
+            }
+            AclRequest::List => {
+                let lines: Vec<String> = acl.users().map(User::describe).collect();
+                reply.array(lines.len());
+                for line in lines {
+                    reply.bulk(line.as_bytes());
+                }
+                return Reply::Immediate;
+            }
+            AclRequest::Load => match server.config.aclfile.as_deref() {
+                Some(path) => acl.load_file(path)
+                    .map(|()| disconnect_removed_users(&acl, server))
+                    .map_err(|e| format!("ERR {}", e)),
+                None => Err(NO_ACL_FILE.to_string()),
+            },
+            AclRequest::Log(None) => {
+                acl.reset_log();
+                Ok(())
+            }
+            AclRequest::Log(Some(count)) => {
+                let entries: Vec<&LogEntry> = acl.log_entries().take(count).collect();
+                reply.array(entries.len());
+                let now: u64 = now_millis();
+                for entry in entries {
+                    write_log_entry(entry, now, reply);
+                }
+                return Reply::Immediate;
+            }
+            AclRequest::Save => match server.config.aclfile.as_deref() {
+                Some(path) => acl.save_file(path).map_err(|e| {
+                    println!("{}", e);
+                    "ERR There was an error trying to save the ACLs. Please check the server logs for more \
+                        information".to_string()
+                }),
+                None => Err(NO_ACL_FILE.to_string()),
+            },
+            AclRequest::SetUser(username, rules) => acl.set_user(username, &rules),
+            AclRequest::Users => {
+                let names: Vec<&str> = acl.users().map(|user| user.name.as_str()).collect();
+                reply.array(names.len());
+                for name in names {
+                    reply.bulk(name.as_bytes());
+                }
+                return Reply::Immediate;
+            }
+            AclRequest::WhoAmI => {
+                reply.bulk(server.session.user.as_bytes());
+                return Reply::Immediate;
+            }
+        };
+
+        match result {
+            Ok(()) => reply.ok(),
+            Err(message) => reply.error(&message),
+        }
+        Reply::Immediate
+    }
+}
+
+/// Disconnects the clients running as a user that no longer exists, once the replies they
+/// were sent are written. The current client may be one of them.
+fn disconnect_removed_users(acl: &Acl, server: &ServerState) {
+    for client in server.clients.values() {
+        let removed: bool = if server.current_client == Some(client.connection.id) {
+            acl.user(&server.session.user).is_none()
+        } else {
+            acl.user(&client.connection.activity().user).is_none()
+        };
+        if removed {
+            client.connection.kill();
+        }
+    }
+}
+
+/// Tells whether `username` could run `command`, without running it.
+fn dry_run(acl: &Acl, username: &str, command: &[&str], reply: &mut ReplyWriter) {
+    if acl.user(username).is_none() {
+        return reply.error(&format!("ERR User '{}' not found", username));
+    }
+    let arguments: Vec<Vec<u8>> = command.iter().map(|argument| argument.as_bytes().to_vec()).collect();
+    let spec: &CommandSpec = match parser::lookup_command(&arguments) {
+        Ok((spec, _)) => spec,
+        Err(LookupError::Unknown(_)) => return reply.error(&format!("ERR Command '{}' not found", command[0])),
+        Err(LookupError::Rejected(_, message)) => return reply.error(&message),
+    };
+
+    match acl.check(username, spec, &arguments) {
+        Ok(()) => reply.ok(),
+        Err(denial) => reply.bulk(denial.describe(spec).as_bytes()),
+    }
+}
+
+/// Writes the `ACL GETUSER` description of a user.
+fn write_user(user: &User, reply: &mut ReplyWriter) {
+    reply.map(6);
+    reply.bulk(b"flags");
+    let flags: Vec<&str> = user.flags();
+    reply.set(flags.len());
+    for flag in flags {
+        reply.bulk(flag.as_bytes());
+    }
+    reply.bulk(b"passwords");
+    reply.array(user.passwords.len());
+    for hash in &user.passwords {
+        reply.bulk(hash.as_bytes());
+    }
+    write_selector_fields(&user.root, reply);
+    reply.bulk(b"selectors");
+    reply.array(user.selectors.len());
+    for selector in &user.selectors {
+        reply.map(3);
+        write_selector_fields(selector, reply);
+    }
+}
+
+fn write_selector_fields(selector: &Selector, reply: &mut ReplyWriter) {
+    reply.bulk(b"commands");
+    reply.bulk(selector.describe_commands().as_bytes());
+    reply.bulk(b"keys");
+    reply.bulk(selector.describe_keys().as_bytes());
+    reply.bulk(b"channels");
+    reply.bulk(selector.describe_channels().as_bytes());
+}
+
+fn write_log_entry(entry: &LogEntry, now: u64, reply: &mut ReplyWriter) {
+    reply.map(10);
+    reply.bulk(b"count");
+    reply.integer(entry.count as i64);
+    reply.bulk(b"reason");
+    reply.bulk(entry.reason.as_bytes());
+    reply.bulk(b"context");
+    reply.bulk(entry.context.as_bytes());
+    reply.bulk(b"object");
+    reply.bulk(entry.object.as_bytes());
+    reply.bulk(b"username");
+    reply.bulk(entry.username.as_bytes());
+    reply.bulk(b"age-seconds");
+    reply.double(now.saturating_sub(entry.created) as f64 / 1000.0);
+    reply.bulk(b"client-info");
+    reply.bulk(entry.client_info.as_bytes());
+    reply.bulk(b"entry-id");
+    reply.integer(entry.entry_id as i64);
+    reply.bulk(b"timestamp-created");
+    reply.integer(entry.created as i64);
+    reply.bulk(b"timestamp-last-updated");
+    reply.integer(entry.updated as i64);
+}
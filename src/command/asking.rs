@@ -0,0 +1,35 @@
+use std::io::{Error, ErrorKind};
+use crate::command::{Command, Reply};
+use crate::key_value_store::KeyValueStore;
+use crate::reply::ReplyWriter;
+use crate::server::ServerState;
+
+pub struct AskingRequest {}
+
+impl Command<'_> for AskingRequest {
+    fn new(arguments: &[&str]) -> Result<Self, Error> {
+        if !arguments.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "Expected no arguments"));
+        }
+
+        Ok(AskingRequest {})
+    }
+
+    fn execute(
+        self,
+        _store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        if server.cluster.is_none() {
+            reply.error("ERR This instance has cluster support disabled");
+            return Reply::Immediate;
+        }
+
+        if let Some(client) = server.current_client {
+            server.asking.lock().insert(client);
+        }
+        reply.ok();
+        Reply::Immediate
+    }
+}
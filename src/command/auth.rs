@@ -0,0 +1,53 @@
+use std::io::{Error, ErrorKind};
+use crate::acl::{self, DEFAULT_USER};
+use crate::command::{Command, Reply};
+use crate::key_value_store::KeyValueStore;
+use crate::reply::ReplyWriter;
+use crate::server::ServerState;
+
+pub struct AuthRequest<'a> {
+    /// The user, absent when only a password is given, which is the default user's.
+    username: Option<&'a str>,
+    password: &'a str,
+}
+
+impl<'a> Command<'a> for AuthRequest<'a> {
+    fn new(arguments: &[&'a str]) -> Result<Self, Error> {
+        match arguments {
+            [password] => Ok(AuthRequest { username: None, password }),
+            [username, password] => Ok(AuthRequest { username: Some(username), password }),
+            _ => Err(Error::new(ErrorKind::InvalidInput, "syntax error")),
+        }
+    }
+
+    fn execute(
+        self,
+        _store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        if self.username.is_none() && !acl::password_required() {
+            reply.error("ERR AUTH <password> called without any password configured for the default user. \
+                Are you sure your configuration is correct?");
+            return Reply::Immediate;
+        }
+
+        match authenticate(server, self.username.unwrap_or(DEFAULT_USER), self.password) {
+            Ok(()) => reply.ok(),
+            Err(message) => reply.error(message),
+        }
+        Reply::Immediate
+    }
+}
+
+/// Checks the credentials of `AUTH` or `HELLO AUTH`, making the connection run as the user
+/// when they are right.
+pub fn authenticate(server: &mut ServerState, username: &str, password: &str) -> Result<(), &'static str> {
+    let client_info: String = acl::client_info(server.current_client.unwrap_or_default(), &server.session.user);
+    if !server.acl.lock().authenticate(username, password, client_info, server.config.acllog_max_len) {
+        return Err("WRONGPASS invalid username-password pair or user is disabled.");
+    }
+    server.session.user = username.to_string();
+    server.session.authenticated = true;
+    Ok(())
+}
@@ -1,130 +1,98 @@
-use std::io::Write;
 use std::io::{Error, ErrorKind};
 use std::time::Duration;
-use tokio::sync::oneshot;
-use crate::command::{CommandFactory, CommandRunner, DataRequester, Reply};
-use crate::key_value_store::{KeyValueStore, KeyValueStoreListEntry};
+use tokio::sync::{oneshot, watch};
+use crate::client::{self, Unblock};
+use crate::command::{Command, Reply};
+use crate::key_value_store::{BlpopWaiter, KeyValueStore, Served};
+use crate::notify::KeyspaceEvents;
+use crate::reply::{ReplyWriter, Value};
+use crate::server::ServerState;
 
+/// `BLPOP key [key ...] timeout`
 pub struct BLPopRequest {
-    key: String,
+    keys: Vec<String>,
     timeout: Option<Duration>,
 }
 
-struct BLPopResponseBody {
-    timeout: Option<Duration>,
-    rx: oneshot::Receiver<String>,
-}
-
-struct BLPopResponse {
-    key: String,
-    body: Result<BLPopResponseBody, &'static str>,
-}
-
-impl CommandFactory for BLPopRequest {
-    fn new(arguments: &[&str]) -> Result<Box<Self>, Error> {
-        if arguments.len() != 2 { 
-            return Err(Error::new(ErrorKind::InvalidInput, "Expected two arguments"));
+impl Command<'_> for BLPopRequest {
+    fn new(arguments: &[&str]) -> Result<Self, Error> {
+        let [keys @ .., timeout] = arguments else {
+            return Err(Error::new(ErrorKind::InvalidInput, "Expected keys and a timeout"));
+        };
+        if keys.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "Expected keys and a timeout"));
         }
-        
-        let key: String = arguments[0].to_string();
-        let timeout: f64 = arguments[1]
+
+        let keys: Vec<String> = keys.iter().map(|key| key.to_string()).collect();
+        let timeout: f64 = timeout
             .parse()
-            .map_err(|_| Error::new(
-                ErrorKind::InvalidInput, "Timeout must be a positive floating point number"))?;
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "timeout is not a float or out of range"))?;
+        if timeout < 0.0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "timeout is negative"));
+        }
         let timeout: Option<Duration> = if timeout == 0.0 {
             None
         } else {
-            Some(Duration::from_secs_f64(timeout))
+            Some(Duration::try_from_secs_f64(timeout)
+                .map_err(|_| Error::new(ErrorKind::InvalidInput, "timeout is out of range"))?)
         };
-        
-        Ok(Box::new(BLPopRequest { key, timeout }))
-    }
-}
 
-impl BLPopResponse {
-    fn new_err(key: String, err: &'static str) -> Self {
-        Self { key, body: Err(err) }
+        Ok(BLPopRequest { keys, timeout })
     }
-    
-    fn new(key: String, timeout: Option<Duration>, rx: oneshot::Receiver<String>) -> Self {
-        Self { key, body: Ok(BLPopResponseBody { timeout, rx }) }
-    }
-}
 
-impl DataRequester for BLPopRequest {
-    fn request(self: Box<Self>, store: &mut Box<dyn KeyValueStore>) -> Box<dyn CommandRunner> {
-        let entry = store
-            .ensure_exists_and_get_mut(self.key.clone(), KeyValueStoreListEntry::new_boxed);
-        
-        let response: Box<BLPopResponse> = match entry.generate_blpop_waiter() {
-            Ok(rx) => Box::new(BLPopResponse::new(self.key, self.timeout, rx)),
-            Err(err) => Box::new(BLPopResponse::new_err(self.key, err)),
-        };
-        
-        response
-    }
-}
+    fn execute(
+        self,
+        store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        // Replicas get the pop, as an `LPOP` of the list that served it, rather than the command.
+        server.prevent_propagation();
+
+        if self.keys.iter().any(|key| store.get(key).is_some_and(|entry| entry.type_name() != "list")) {
+            reply.null();
+            return Reply::Immediate;
+        }
+
+        let (waiter, mut rx): (BlpopWaiter, oneshot::Receiver<Served>) = BlpopWaiter::new();
+        for key in &self.keys {
+            store.add_blpop_waiter(key, waiter.on(key));
+            serve_waiters(store, server, key);
+
+            // Served by the first list holding an element, without blocking.
+            if let Ok((key, value)) = rx.try_recv() {
+                response(key, value).write(reply);
+                return Reply::Immediate;
+            }
+        }
 
-fn num_of_digits(number: usize) -> usize {
-    if number == 0 {
-        1
-    } else {
-        number.ilog10() as usize + 1
+        let timeout: Option<Duration> = self.timeout;
+        let unblocked: Option<watch::Receiver<Unblock>> = server.session.unblocked();
+        Reply::Deferred(Box::pin(async move {
+            tokio::select! {
+                served = rx => match served {
+                    Ok((key, value)) => response(key, value),
+                    Err(_canceled) => Value::Null,
+                },
+                error = client::expired(timeout, unblocked) => error.unwrap_or(Value::Null),
+            }
+        }))
     }
 }
 
-fn encode_response(key: String, value: String) -> Vec<u8> {
-    let key_length: usize = key.len();
-    let value_length: usize = value.len();
-    let output_capacity: usize = 
-        1 + 1 + 2
-        + 1 + num_of_digits(key_length) + 2
-        + key_length + 2
-        + 1 + num_of_digits(value_length) + 2
-        + value_length + 2;
-    
-    let mut response: Vec<u8> = Vec::with_capacity(output_capacity);
-    
-    response.extend_from_slice(b"*2\r\n");
-    
-    response.extend_from_slice(b"$");
-    write!(&mut response, "{}", key_length).unwrap();
-    response.extend_from_slice(b"\r\n");
-    response.extend_from_slice(key.as_bytes());
-    response.extend_from_slice(b"\r\n");
-    
-    response.extend_from_slice(b"$");
-    write!(&mut response, "{}", value_length).unwrap();
-    response.extend_from_slice(b"\r\n");
-    response.extend_from_slice(value.as_bytes());
-    response.extend_from_slice(b"\r\n");
-    
-    response
+/// Serves the clients blocked on the list at `key` with its first elements, publishing an
+/// `lpop` event and propagating an `LPOP` for each, and a `del` event if that emptied it.
+pub fn serve_waiters(store: &mut Box<dyn KeyValueStore>, server: &mut ServerState, key: &str) {
+    let served: usize = store.serve_blpop_waiters(key);
+    for _ in 0..served {
+        server.notify_keyspace_event(KeyspaceEvents::LIST, "lpop", key.as_bytes());
+        server.also_propagate(&[b"LPOP", key.as_bytes()]);
+    }
+    if served > 0 && store.get(key).is_none() {
+        server.notify_keyspace_event(KeyspaceEvents::GENERIC, "del", key.as_bytes());
+    }
 }
 
-fn nil() -> Vec<u8> { "$-1\r\n".as_bytes().to_vec() }
-
-impl CommandRunner for BLPopResponse {
-    fn run(self: Box<Self>) -> Reply {
-        match self.body {
-            Err(_) => Reply::Immediate(nil()),
-            Ok(BLPopResponseBody { rx, timeout }) => {
-                let key: String = self.key;
-                Reply::Deferred(Box::pin(async move {
-                    if let Some(timeout) = timeout {
-                        match tokio::time::timeout(timeout, rx).await {
-                            Err(_elapsed) => nil(),
-                            Ok(Err(_canceled)) => nil(),
-                            Ok(Ok(value)) => encode_response(key, value),
-                        }
-                    } else {
-                        match rx.await {
-                            Err(_canceled) => nil(),
-                            Ok(value) => encode_response(key, value),
-                        }
-                    }
-                }))
-            },
-        }
-    }
+fn response(key: String, value: String) -> Value {
+    Value::Array(vec![Value::Bulk(key.into_bytes()), Value::Bulk(value.into_bytes())])
 }
@@ -0,0 +1,493 @@
+use std::fmt::Write;
+use std::io::{Error, ErrorKind};
+use std::net::SocketAddr;
+use std::sync::{Arc, MutexGuard};
+use std::time::{Duration, Instant};
+use crate::client::{self, Activity, Connection, PauseMode, Unblock, READ_BUFFER_SIZE};
+use crate::command::{Command, Reply};
+use crate::key_value_store::KeyValueStore;
+use crate::reply::{Protocol, ReplyWriter};
+use crate::server::{ClientInfo, ServerState};
+use crate::tracking::{Tracking, TrackingInfo, TrackingMode};
+
+/// The kinds of connections `CLIENT LIST` and `CLIENT KILL` can select.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ClientType {
+    Normal,
+    Replica,
+    Master,
+    PubSub,
+}
+
+impl ClientType {
+    fn parse(name: &str) -> Result<ClientType, Error> {
+        match name.to_ascii_lowercase().as_str() {
+            "normal" => Ok(ClientType::Normal),
+            "replica" | "slave" => Ok(ClientType::Replica),
+            "master" => Ok(ClientType::Master),
+            "pubsub" => Ok(ClientType::PubSub),
+            _ => Err(Error::new(ErrorKind::InvalidInput, format!("Unknown client type '{}'", name))),
+        }
+    }
+
+    /// The kind of a registered client. The link to the master is not one of them.
+    fn of(client: &ClientInfo, server: &ServerState) -> ClientType {
+        if client.connection.activity().replica {
+            ClientType::Replica
+        } else if server.pubsub.lock().count(client.connection.id) > 0 {
+            ClientType::PubSub
+        } else {
+            ClientType::Normal
+        }
+    }
+}
+
+/// The connections `CLIENT KILL` closes: those matching every filter given.
+#[derive(Default)]
+pub struct KillFilter<'a> {
+    id: Option<u64>,
+    address: Option<&'a str>,
+    local_address: Option<&'a str>,
+    user: Option<&'a str>,
+    client_type: Option<ClientType>,
+    /// Minimum age in seconds.
+    max_age: Option<u64>,
+    /// Whether the connection sending `CLIENT KILL` is spared.
+    skip_me: bool,
+}
+
+/// `CLIENT TRACKING on|off` and its options.
+pub struct TrackingRequest<'a> {
+    enable: bool,
+    mode: TrackingMode,
+    redirect: Option<u64>,
+    prefixes: Vec<&'a str>,
+}
+
+pub enum ClientRequest<'a> {
+    Id,
+    Info,
+    List { client_type: Option<ClientType>, ids: Vec<u64> },
+    /// `CLIENT KILL <ip:port>`, the form preceding the filters, which replies OK or an error.
+    KillAddress(&'a str),
+    Kill(KillFilter<'a>),
+    SetName(&'a str),
+    GetName,
+    Pause(Duration, PauseMode),
+    Unpause,
+    NoEvict(bool),
+    Unblock(u64, Unblock),
+    Tracking(TrackingRequest<'a>),
+    /// `CLIENT CACHING yes` or `no`.
+    Caching(bool),
+    GetRedir,
+    TrackingInfo,
+}
+
+impl<'a> Command<'a> for ClientRequest<'a> {
+    fn new(arguments: &[&'a str]) -> Result<Self, Error> {
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidInput, message.to_string());
+        let subcommand: &str = arguments.first().ok_or_else(|| invalid("Expected a subcommand"))?;
+        let arguments: &[&'a str] = &arguments[1..];
+
+        let request: ClientRequest = match (subcommand.to_ascii_lowercase().as_str(), arguments) {
+            ("id", []) => ClientRequest::Id,
+            ("info", []) => ClientRequest::Info,
+            ("list", filters) => {
+                let (mut client_type, mut ids) = (None, Vec::new());
+                match filters {
+                    [] => {}
+                    [option, name] if option.eq_ignore_ascii_case("type") => client_type = Some(ClientType::parse(name)?),
+                    [option, values @ ..] if option.eq_ignore_ascii_case("id") && !values.is_empty() => {
+                        for value in values {
+                            ids.push(value.parse().ok().filter(|id| *id > 0).ok_or_else(|| invalid("Invalid client ID"))?);
+                        }
+                    }
+                    _ => return Err(invalid("syntax error")),
+                }
+                ClientRequest::List { client_type, ids }
+            }
+            ("kill", [address]) => ClientRequest::KillAddress(address),
+            ("kill", filters) if filters.len().is_multiple_of(2) => {
+                let mut filter: KillFilter = KillFilter { skip_me: true, ..KillFilter::default() };
+                for pair in filters.chunks_exact(2) {
+                    let value: &'a str = pair[1];
+                    match pair[0].to_ascii_lowercase().as_str() {
+                        "id" => filter.id = Some(value.parse().ok().filter(|id| *id > 0)
+                            .ok_or_else(|| invalid("client-id should be greater than 0"))?),
+                        "addr" => filter.address = Some(value),
+                        "laddr" => filter.local_address = Some(value),
+                        "user" => filter.user = Some(value),
+                        "type" => filter.client_type = Some(ClientType::parse(value)?),
+                        "maxage" => filter.max_age = Some(value.parse()
+                            .map_err(|_| invalid("value is not an integer or out of range"))?),
+                        "skipme" => filter.skip_me = match value.to_ascii_lowercase().as_str() {
+                            "yes" => true,
+                            "no" => false,
+                            _ => return Err(invalid("syntax error")),
+                        },
+                        _ => return Err(invalid("syntax error")),
+                    }
+                }
+                ClientRequest::Kill(filter)
+            }
+            ("setname", [name]) => ClientRequest::SetName(name),
+            ("getname", []) => ClientRequest::GetName,
+            ("pause", [timeout, mode @ ..]) if mode.len() <= 1 => {
+                let timeout: i64 = timeout.parse().map_err(|_| invalid("timeout is not an integer or out of range"))?;
+                if timeout < 0 {
+                    return Err(invalid("timeout is negative"));
+                }
+                let mode: PauseMode = match mode.first().map(|mode| mode.to_ascii_lowercase()).as_deref() {
+                    None | Some("all") => PauseMode::All,
+                    Some("write") => PauseMode::Write,
+                    Some(_) => return Err(invalid("syntax error")),
+                };
+                ClientRequest::Pause(Duration::from_millis(timeout as u64), mode)
+            }
+            ("unpause", []) => ClientRequest::Unpause,
+            ("no-evict", [mode]) => match mode.to_ascii_lowercase().as_str() {
+                "on" => ClientRequest::NoEvict(true),
+                "off" => ClientRequest::NoEvict(false),
+                _ => return Err(invalid("syntax error")),
+            },
+            ("unblock", [id, mode @ ..]) if mode.len() <= 1 => {
+                let id: u64 = id.parse().map_err(|_| invalid("value is not an integer or out of range"))?;
+                let unblock: Unblock = match mode.first().map(|mode| mode.to_ascii_lowercase()).as_deref() {
+                    None | Some("timeout") => Unblock::Timeout,
+                    Some("error") => Unblock::Error,
+                    Some(_) => return Err(invalid("CLIENT UNBLOCK reason should be TIMEOUT or ERROR")),
+                };
+                ClientRequest::Unblock(id, unblock)
+            }
+            ("tracking", [state, options @ ..]) => {
+                let enable: bool = match state.to_ascii_lowercase().as_str() {
+                    "on" => true,
+                    "off" => false,
+                    _ => return Err(invalid("syntax error")),
+                };
+                let mut request: TrackingRequest =
+                    TrackingRequest { enable, mode: TrackingMode::default(), redirect: None, prefixes: Vec::new() };
+                let mut options = options.iter();
+                while let Some(option) = options.next() {
+                    match option.to_ascii_lowercase().as_str() {
+                        "redirect" => {
+                            let id: &str = options.next().ok_or_else(|| invalid("syntax error"))?;
+                            if request.redirect.is_some() {
+                                return Err(invalid("A client can only redirect to a single other client"));
+                            }
+                            request.redirect = Some(id.parse()
+                                .map_err(|_| invalid("value is not an integer or out of range"))?);
+                        }
+                        "prefix" => request.prefixes.push(options.next().ok_or_else(|| invalid("syntax error"))?),
+                        "bcast" => request.mode.bcast = true,
+                        "optin" => request.mode.optin = true,
+                        "optout" => request.mode.optout = true,
+                        "noloop" => request.mode.noloop = true,
+                        _ => return Err(invalid("syntax error")),
+                    }
+                }
+                ClientRequest::Tracking(request)
+            }
+            ("caching", [mode]) => match mode.to_ascii_lowercase().as_str() {
+                "yes" => ClientRequest::Caching(true),
+                "no" => ClientRequest::Caching(false),
+                _ => return Err(invalid("syntax error")),
+            },
+            ("getredir", []) => ClientRequest::GetRedir,
+            ("trackinginfo", []) => ClientRequest::TrackingInfo,
+            _ => return Err(invalid(&format!(
+                "unknown subcommand or wrong number of arguments for '{}'. Try CLIENT HELP.", subcommand))),
+        };
+
+        Ok(request)
+    }
+
+    fn execute(
+        self,
+        _store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        let current: u64 = server.current_client.unwrap_or_default();
+        match self {
+            ClientRequest::Id => reply.integer(current as i64),
+            ClientRequest::Info => match server.clients.get(&current) {
+                Some(client) => reply.verbatim(&(describe(client, server) + "\n")),
+                None => reply.null(),
+            },
+            ClientRequest::List { client_type, ids } => {
+                let mut clients: Vec<&ClientInfo> = server.clients.values()
+                    .filter(|client| client_type.is_none_or(|client_type| ClientType::of(client, server) == client_type))
+                    .filter(|client| ids.is_empty() || ids.contains(&client.connection.id))
+                    .collect();
+                clients.sort_by_key(|client| client.connection.id);
+                let list: String = clients.iter().map(|client| describe(client, server) + "\n").collect();
+                reply.verbatim(&list);
+            }
+            ClientRequest::KillAddress(address) => {
+                let filter: KillFilter = KillFilter { address: Some(address), ..KillFilter::default() };
+                if kill(&filter, server) == 0 {
+                    reply.error("ERR No such client");
+                } else {
+                    reply.ok();
+                }
+            }
+            ClientRequest::Kill(filter) => {
+                if let Some(user) = filter.user {
+                    if server.acl.lock().user(user).is_none() {
+                        reply.error(&format!("ERR No such user '{}'", user));
+                        return Reply::Immediate;
+                    }
+                }
+                reply.integer(kill(&filter, server) as i64);
+            }
+            ClientRequest::SetName(name) => {
+                if !is_valid_name(name) {
+                    reply.error("ERR Client names cannot contain spaces, newlines or special characters.");
+                    return Reply::Immediate;
+                }
+                if let Some(client) = server.clients.get_mut(&current) {
+                    // An empty name removes the current one.
+                    client.name = Some(name.to_string()).filter(|name| !name.is_empty());
+                }
+                reply.ok();
+            }
+            ClientRequest::GetName => match server.clients.get(&current).and_then(|client| client.name.as_deref()) {
+                Some(name) => reply.bulk(name.as_bytes()),
+                None => reply.null(),
+            },
+            ClientRequest::Pause(timeout, mode) => {
+                client::pause(Instant::now() + timeout, mode);
+                reply.ok();
+            }
+            ClientRequest::Unpause => {
+                client::unpause();
+                reply.ok();
+            }
+            ClientRequest::NoEvict(no_evict) => {
+                if let Some(client) = server.clients.get_mut(&current) {
+                    client.no_evict = no_evict;
+                }
+                reply.ok();
+            }
+            ClientRequest::Unblock(id, unblock) => {
+                let unblocked: bool = server.clients.get(&id).is_some_and(|client| client.connection.unblock(unblock));
+                reply.integer(unblocked as i64);
+            }
+            ClientRequest::Tracking(request) => match track(request, server) {
+                Ok(()) => reply.ok(),
+                Err(message) => reply.error(&message),
+            },
+            ClientRequest::Caching(yes) => match server.session.tracking {
+                None => reply.error("ERR CLIENT CACHING can be called only when the client is in tracking mode with \
+                    OPTIN or OPTOUT mode enabled"),
+                Some(mode) if yes && !mode.optin => {
+                    reply.error("ERR CLIENT CACHING YES is only valid when tracking is enabled in OPTIN mode.")
+                }
+                Some(mode) if !yes && !mode.optout => {
+                    reply.error("ERR CLIENT CACHING NO is only valid when tracking is enabled in OPTOUT mode.")
+                }
+                Some(_) => {
+                    server.session.caching = true;
+                    reply.ok();
+                }
+            },
+            ClientRequest::GetRedir => match server.tracking.lock().info(current) {
+                Some(info) => reply.integer(info.redirect as i64),
+                None => reply.integer(-1),
+            },
+            ClientRequest::TrackingInfo => {
+                let info: Option<TrackingInfo> = server.tracking.lock().info(current);
+                let mut flags: Vec<&str> = Vec::new();
+                match info.as_ref() {
+                    None => flags.push("off"),
+                    Some(info) => {
+                        flags.push("on");
+                        let caching: bool = server.session.caching;
+                        let mode: TrackingMode = info.mode;
+                        let mode_flags = [
+                            (mode.bcast, "bcast"),
+                            (mode.optin, "optin"),
+                            (mode.optin && caching, "caching-yes"),
+                            (mode.optout, "optout"),
+                            (mode.optout && caching, "caching-no"),
+                            (mode.noloop, "noloop"),
+                            (info.broken_redirect, "broken_redirect"),
+                        ];
+                        flags.extend(mode_flags.iter().filter(|(set, _)| *set).map(|(_, name)| *name));
+                    }
+                }
+
+                reply.map(3);
+                reply.bulk(b"flags");
+                reply.set(flags.len());
+                flags.iter().for_each(|flag| reply.bulk(flag.as_bytes()));
+                reply.bulk(b"redirect");
+                reply.integer(info.as_ref().map_or(-1, |info| info.redirect as i64));
+                reply.bulk(b"prefixes");
+                let prefixes: &[Vec<u8>] = info.as_ref().map_or(&[], |info| &info.prefixes);
+                reply.array(prefixes.len());
+                prefixes.iter().for_each(|prefix| reply.bulk(prefix));
+            }
+        }
+        Reply::Immediate
+    }
+}
+
+/// Enables or disables tracking for the connection running `CLIENT TRACKING`, returning
+/// the error to reply with when the options conflict with each other or with the current ones.
+fn track(request: TrackingRequest, server: &mut ServerState) -> Result<(), String> {
+    let Some(connection) = server.session.connection.clone() else {
+        return Ok(());
+    };
+    let id: u64 = connection.id;
+    if !request.enable {
+        server.tracking.lock().disable(id);
+        server.session.tracking = None;
+        return Ok(());
+    }
+
+    let redirect: Option<Arc<Connection>> = match request.redirect {
+        Some(redirect) => Some(server.clients.get(&redirect).map(|client| client.connection.clone())
+            .ok_or("ERR The client ID you want redirect to does not exist")?),
+        None => None,
+    };
+    let mode: TrackingMode = request.mode;
+    if !mode.bcast && !request.prefixes.is_empty() {
+        return Err("ERR PREFIX option requires BCAST mode to be enabled".to_string());
+    }
+    if server.session.tracking.is_some_and(|current| current.bcast != mode.bcast) {
+        return Err("ERR You can't switch BCAST mode on/off before disabling tracking for this client, and then \
+            re-enabling it with a different mode.".to_string());
+    }
+    if mode.bcast && (mode.optin || mode.optout) {
+        return Err("ERR OPTIN and OPTOUT are not compatible with BCAST".to_string());
+    }
+    if mode.optin && mode.optout {
+        return Err("ERR You can't use both OPTIN and OPTOUT".to_string());
+    }
+    if server.session.tracking.is_some_and(|current| (mode.optin && current.optout) || (mode.optout && current.optin)) {
+        return Err("ERR You can't switch OPTIN/OPTOUT mode before disabling tracking for this client, and then \
+            re-enabling it with a different mode.".to_string());
+    }
+
+    let prefixes: Vec<&[u8]> = request.prefixes.iter().map(|prefix| prefix.as_bytes()).collect();
+    let mut tracking: MutexGuard<'_, Tracking> = server.tracking.lock();
+    for (position, prefix) in prefixes.iter().enumerate() {
+        if let Some((existing, other)) = tracking.overlapping_prefix(id, prefix, &prefixes[position + 1..]) {
+            return Err(format!(
+                "ERR Prefix '{}' overlaps with {} prefix '{}'. Prefixes for a single client must not overlap.",
+                String::from_utf8_lossy(prefix),
+                if existing { "an existing" } else { "another provided" },
+                String::from_utf8_lossy(other),
+            ));
+        }
+    }
+    tracking.enable(connection, mode, redirect, &prefixes);
+    server.session.tracking = Some(mode);
+    Ok(())
+}
+
+/// Whether `name` can name a connection: printable characters other than spaces.
+pub fn is_valid_name(name: &str) -> bool {
+    name.bytes().all(|byte| (b'!'..=b'~').contains(&byte))
+}
+
+/// Closes the connections matching `filter`, returning how many there were.
+fn kill(filter: &KillFilter, server: &ServerState) -> usize {
+    let current: Option<u64> = server.current_client;
+    let mut killed: usize = 0;
+    for client in server.clients.values() {
+        let connection_id: u64 = client.connection.id;
+        let matches: bool = filter.id.is_none_or(|id| id == connection_id)
+            && filter.address.is_none_or(|address| address == format_address(client.address, server))
+            && filter.local_address.is_none_or(|address| address == format_address(client.local_address, server))
+            && filter.user.is_none_or(|user| user == client.connection.activity().user)
+            && filter.client_type.is_none_or(|client_type| client_type == ClientType::of(client, server))
+            && filter.max_age.is_none_or(|max_age| client.connection.created.elapsed().as_secs() >= max_age)
+            && !(filter.skip_me && current == Some(connection_id));
+        if matches {
+            client.connection.kill();
+            killed += 1;
+        }
+    }
+    killed
+}
+
+/// An address as `CLIENT LIST` shows it, the Unix socket's path for connections on it.
+fn format_address(address: Option<SocketAddr>, server: &ServerState) -> String {
+    match address {
+        Some(address) => address.to_string(),
+        None => format!("{}:0", server.config.unixsocket.as_deref().unwrap_or_default()),
+    }
+}
+
+/// The line describing a client in `CLIENT LIST` and `CLIENT INFO`.
+fn describe(client: &ClientInfo, server: &ServerState) -> String {
+    let (channels, patterns): (usize, usize) = server.pubsub.lock().counts(client.connection.id);
+    let tracking: Option<TrackingInfo> = server.tracking.lock().info(client.connection.id);
+    let activity: MutexGuard<'_, Activity> = client.connection.activity();
+    let mut flags: String = String::new();
+    if activity.replica {
+        flags.push('S');
+    }
+    if channels + patterns > 0 {
+        flags.push('P');
+    }
+    if activity.blocked {
+        flags.push('b');
+    }
+    if let Some(tracking) = tracking.as_ref() {
+        flags.push('t');
+        if tracking.broken_redirect {
+            flags.push('R');
+        }
+        if tracking.mode.bcast {
+            flags.push('B');
+        }
+    }
+    if client.address.is_none() {
+        flags.push('U');
+    }
+    if client.no_evict {
+        flags.push('e');
+    }
+    if flags.is_empty() {
+        flags.push('N');
+    }
+
+    let total_memory: usize = READ_BUFFER_SIZE + activity.query_buffer + activity.query_buffer_free
+        + activity.arguments_memory + activity.output_memory;
+    let mut line: String = String::new();
+    write!(
+        line,
+        "id={} addr={} laddr={} fd={} name={} age={} idle={} flags={} db=0 sub={} psub={} ssub=0 multi=-1 \
+            qbuf={} qbuf-free={} argv-mem={} multi-mem=0 rbs={} rbp={} obl={} oll=0 omem={} tot-mem={} events={} \
+            cmd={} user={} redir={} resp={}",
+        client.connection.id,
+        format_address(client.address, server),
+        format_address(client.local_address, server),
+        client.connection.fd,
+        client.name.as_deref().unwrap_or_default(),
+        client.connection.created.elapsed().as_secs(),
+        activity.last_interaction.elapsed().as_secs(),
+        flags,
+        channels,
+        patterns,
+        activity.query_buffer,
+        activity.query_buffer_free,
+        activity.arguments_memory,
+        READ_BUFFER_SIZE,
+        activity.read_peak,
+        activity.output_buffer,
+        activity.output_memory,
+        total_memory,
+        if activity.output_buffer > 0 { "rw" } else { "r" },
+        activity.last_command,
+        activity.user,
+        tracking.as_ref().map_or(-1, |tracking| tracking.redirect as i64),
+        if activity.protocol == Protocol::Resp3 { 3 } else { 2 },
+    ).unwrap();
+    line
+}
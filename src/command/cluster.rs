@@ -0,0 +1,302 @@
+use std::io::{Error, ErrorKind};
+use std::sync::MutexGuard;
+use crate::cluster::{key_hash_slot, parse_slot, ClusterNode, ClusterState, FailureState, NodeRole};
+use crate::command::{Command, Reply};
+use crate::key_value_store::KeyValueStore;
+use crate::replication::ReplicationState;
+use crate::reply::ReplyWriter;
+use crate::server::ServerState;
+
+pub enum ClusterRequest {
+    Info,
+    MyId,
+    Nodes,
+    Slots,
+    Shards,
+    KeySlot(String),
+    CountKeysInSlot(u16),
+    GetKeysInSlot(u16, usize),
+    Meet(String, u16, Option<u16>),
+    AddSlots(Vec<u16>),
+    SetSlot(u16, SetSlotAction),
+    Failover { force: bool, takeover: bool },
+    Forget(String),
+    Reset { hard: bool },
+    Replicate(String),
+}
+
+pub enum SetSlotAction {
+    Migrating(String),
+    Importing(String),
+    Stable,
+    Node(String),
+}
+
+fn expect_arguments(arguments: &[&str], count: usize) -> Result<(), Error> {
+    if arguments.len() != count {
+        return Err(Error::new(ErrorKind::InvalidInput, "Wrong number of arguments for CLUSTER subcommand"));
+    }
+    Ok(())
+}
+
+fn parse_slot_argument(argument: &str) -> Result<u16, Error> {
+    parse_slot(argument).ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Invalid or out of range slot"))
+}
+
+fn parse_port_argument(argument: &str) -> Result<u16, Error> {
+    argument.parse().map_err(|_| Error::new(ErrorKind::InvalidInput, "Invalid TCP base port specified"))
+}
+
+fn parse_slot_ranges_arguments(arguments: &[&str]) -> Result<Vec<u16>, Error> {
+    if arguments.is_empty() || !arguments.len().is_multiple_of(2) {
+        return Err(Error::new(ErrorKind::InvalidInput, "Wrong number of arguments for CLUSTER ADDSLOTSRANGE"));
+    }
+
+    let mut slots: Vec<u16> = Vec::new();
+    for range in arguments.chunks_exact(2) {
+        let (start, end) = (parse_slot_argument(range[0])?, parse_slot_argument(range[1])?);
+        if start > end {
+            return Err(Error::new(ErrorKind::InvalidInput, "Start slot number is greater than end slot number"));
+        }
+        slots.extend(start..=end);
+    }
+    Ok(slots)
+}
+
+impl Command<'_> for ClusterRequest {
+    fn new(arguments: &[&str]) -> Result<Self, Error> {
+        let subcommand: &str = arguments.first()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Expected a subcommand"))?;
+        let arguments: &[&str] = &arguments[1..];
+
+        let request: ClusterRequest = match subcommand.to_ascii_lowercase().as_str() {
+            "info" => ClusterRequest::Info,
+            "myid" => ClusterRequest::MyId,
+            "nodes" => ClusterRequest::Nodes,
+            "slots" => ClusterRequest::Slots,
+            "shards" => ClusterRequest::Shards,
+            "keyslot" => {
+                expect_arguments(arguments, 1)?;
+                ClusterRequest::KeySlot(String::from(arguments[0]))
+            }
+            "countkeysinslot" => {
+                expect_arguments(arguments, 1)?;
+                ClusterRequest::CountKeysInSlot(parse_slot_argument(arguments[0])?)
+            }
+            "getkeysinslot" => {
+                expect_arguments(arguments, 2)?;
+                let count: usize = arguments[1].parse()
+                    .map_err(|_| Error::new(ErrorKind::InvalidInput, "Invalid number of keys"))?;
+                ClusterRequest::GetKeysInSlot(parse_slot_argument(arguments[0])?, count)
+            }
+            "meet" => {
+                if arguments.len() != 2 && arguments.len() != 3 {
+                    return Err(Error::new(ErrorKind::InvalidInput, "Wrong number of arguments for CLUSTER MEET"));
+                }
+                let bus_port: Option<u16> = arguments.get(2).map(|port| parse_port_argument(port)).transpose()?;
+                ClusterRequest::Meet(String::from(arguments[0]), parse_port_argument(arguments[1])?, bus_port)
+            }
+            "addslots" => {
+                if arguments.is_empty() {
+                    return Err(Error::new(ErrorKind::InvalidInput, "Wrong number of arguments for CLUSTER ADDSLOTS"));
+                }
+                let slots: Vec<u16> = arguments.iter().map(|slot| parse_slot_argument(slot)).collect::<Result<_, _>>()?;
+                ClusterRequest::AddSlots(slots)
+            }
+            "addslotsrange" => ClusterRequest::AddSlots(parse_slot_ranges_arguments(arguments)?),
+            "setslot" => {
+                let action: SetSlotAction = match arguments {
+                    [_, action, id] if action.eq_ignore_ascii_case("migrating") =>
+                        SetSlotAction::Migrating(String::from(*id)),
+                    [_, action, id] if action.eq_ignore_ascii_case("importing") =>
+                        SetSlotAction::Importing(String::from(*id)),
+                    [_, action, id] if action.eq_ignore_ascii_case("node") => SetSlotAction::Node(String::from(*id)),
+                    [_, action] if action.eq_ignore_ascii_case("stable") => SetSlotAction::Stable,
+                    _ => return Err(Error::new(
+                        ErrorKind::InvalidInput, "Invalid CLUSTER SETSLOT action or number of arguments")),
+                };
+                ClusterRequest::SetSlot(parse_slot_argument(arguments[0])?, action)
+            }
+            "failover" => match arguments.first().map(|mode| mode.to_ascii_lowercase()).as_deref() {
+                None => ClusterRequest::Failover { force: false, takeover: false },
+                Some("force") if arguments.len() == 1 => ClusterRequest::Failover { force: true, takeover: false },
+                Some("takeover") if arguments.len() == 1 => ClusterRequest::Failover { force: false, takeover: true },
+                _ => return Err(Error::new(ErrorKind::InvalidInput, "Invalid CLUSTER FAILOVER option")),
+            },
+            "forget" => {
+                expect_arguments(arguments, 1)?;
+                ClusterRequest::Forget(String::from(arguments[0]))
+            }
+            "reset" => match arguments.first().map(|mode| mode.to_ascii_lowercase()).as_deref() {
+                None | Some("soft") if arguments.len() <= 1 => ClusterRequest::Reset { hard: false },
+                Some("hard") if arguments.len() == 1 => ClusterRequest::Reset { hard: true },
+                _ => return Err(Error::new(ErrorKind::InvalidInput, "Invalid CLUSTER RESET option")),
+            },
+            "replicate" => {
+                expect_arguments(arguments, 1)?;
+                ClusterRequest::Replicate(String::from(arguments[0]))
+            }
+            _ => return Err(Error::new(ErrorKind::InvalidInput, "Unknown CLUSTER subcommand")),
+        };
+
+        Ok(request)
+    }
+
+    fn execute(
+        self,
+        store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        let mut cluster: MutexGuard<'_, ClusterState> = match server.cluster.as_ref() {
+            Some(cluster) => cluster.lock(),
+            None => {
+                reply.error("ERR This instance has cluster support disabled");
+                return Reply::Immediate;
+            }
+        };
+        let mut replication: MutexGuard<'_, ReplicationState> = server.replication.lock();
+
+        let updated: Result<(), String> = match self {
+            ClusterRequest::Meet(ip, port, bus_port) => {
+                cluster.meet(ip, port, bus_port);
+                Ok(())
+            }
+            ClusterRequest::AddSlots(slots) => cluster.add_slots(&slots),
+            ClusterRequest::SetSlot(slot, SetSlotAction::Migrating(id)) => cluster.set_slot_migrating(slot, &id),
+            ClusterRequest::SetSlot(slot, SetSlotAction::Importing(id)) => cluster.set_slot_importing(slot, &id),
+            ClusterRequest::SetSlot(slot, SetSlotAction::Stable) => {
+                cluster.set_slot_stable(slot);
+                Ok(())
+            }
+            ClusterRequest::SetSlot(slot, SetSlotAction::Node(id)) => cluster.set_slot_node(slot, &id, store.as_ref()),
+            ClusterRequest::Failover { force, takeover } => cluster.manual_failover(force, takeover, &mut replication),
+            ClusterRequest::Forget(id) => cluster.forget(&id),
+            ClusterRequest::Reset { hard } => cluster.reset(hard, store.as_ref(), &mut replication),
+            ClusterRequest::Replicate(id) => cluster.replicate(&id, store.as_ref(), &mut replication),
+            request => {
+                describe(request, &cluster, store.as_ref(), reply);
+                return Reply::Immediate;
+            }
+        };
+
+        match updated {
+            Ok(()) => reply.ok(),
+            Err(message) => reply.error(&message),
+        }
+        Reply::Immediate
+    }
+}
+
+fn replicas_of<'a>(cluster: &'a ClusterState, master_id: &'a str) -> impl Iterator<Item = &'a ClusterNode> {
+    cluster.nodes().filter(move |node| node.role == NodeRole::Replica(master_id.to_string()))
+}
+
+fn describe_info(cluster: &ClusterState) -> String {
+    let assigned: usize = cluster.assigned_slots();
+    let size: usize = cluster.nodes()
+        .filter(|node| node.role == NodeRole::Master && !cluster.slot_ranges(&node.id).is_empty())
+        .count();
+
+    format!(
+        "cluster_enabled:1\r\ncluster_state:{}\r\ncluster_slots_assigned:{}\r\ncluster_slots_ok:{}\r\n\
+         cluster_slots_pfail:{}\r\ncluster_slots_fail:{}\r\ncluster_known_nodes:{}\r\ncluster_size:{}\r\n\
+         cluster_current_epoch:{}\r\ncluster_my_epoch:{}\r\n",
+        if cluster.is_ok() { "ok" } else { "fail" },
+        assigned, assigned - cluster.slots_in_failure(FailureState::PFail) - cluster.slots_in_failure(FailureState::Fail),
+        cluster.slots_in_failure(FailureState::PFail), cluster.slots_in_failure(FailureState::Fail),
+        cluster.nodes().count(), size,
+        cluster.current_epoch(), cluster.myself().config_epoch,
+    )
+}
+
+fn describe_slot_node(node: &ClusterNode, reply: &mut ReplyWriter) {
+    reply.array(4);
+    reply.bulk(node.ip.as_bytes());
+    reply.integer(node.port as i64);
+    reply.bulk(node.id.as_bytes());
+    reply.map(0);
+}
+
+fn describe_slots(cluster: &ClusterState, reply: &mut ReplyWriter) {
+    let mut entries: Vec<(u16, u16, &ClusterNode, Vec<&ClusterNode>)> = Vec::new();
+    for master in cluster.nodes().filter(|node| node.role == NodeRole::Master) {
+        for (start, end) in cluster.slot_ranges(&master.id) {
+            entries.push((start, end, master, replicas_of(cluster, &master.id).collect()));
+        }
+    }
+
+    reply.array(entries.len());
+    for (start, end, master, replicas) in entries {
+        reply.array(3 + replicas.len());
+        reply.integer(start as i64);
+        reply.integer(end as i64);
+        describe_slot_node(master, reply);
+        replicas.iter().for_each(|replica| describe_slot_node(replica, reply));
+    }
+}
+
+fn describe_shard_node(node: &ClusterNode, reply: &mut ReplyWriter) {
+    let role: &str = if node.role == NodeRole::Master { "master" } else { "replica" };
+    let health: &str = if node.failure == FailureState::Ok { "online" } else { "failed" };
+
+    reply.map(7);
+    reply.bulk(b"id");
+    reply.bulk(node.id.as_bytes());
+    reply.bulk(b"port");
+    reply.integer(node.port as i64);
+    reply.bulk(b"ip");
+    reply.bulk(node.ip.as_bytes());
+    reply.bulk(b"endpoint");
+    reply.bulk(node.ip.as_bytes());
+    reply.bulk(b"role");
+    reply.bulk(role.as_bytes());
+    reply.bulk(b"replication-offset");
+    reply.integer(node.repl_offset as i64);
+    reply.bulk(b"health");
+    reply.bulk(health.as_bytes());
+}
+
+fn describe_shards(cluster: &ClusterState, reply: &mut ReplyWriter) {
+    let masters: Vec<&ClusterNode> = cluster.nodes().filter(|node| node.role == NodeRole::Master).collect();
+
+    reply.array(masters.len());
+    for master in masters {
+        let ranges: Vec<(u16, u16)> = cluster.slot_ranges(&master.id);
+        let nodes: Vec<&ClusterNode> = std::iter::once(master).chain(replicas_of(cluster, &master.id)).collect();
+
+        reply.map(2);
+        reply.bulk(b"slots");
+        reply.array(ranges.len() * 2);
+        for (start, end) in ranges {
+            reply.integer(start as i64);
+            reply.integer(end as i64);
+        }
+        reply.bulk(b"nodes");
+        reply.array(nodes.len());
+        nodes.iter().for_each(|node| describe_shard_node(node, reply));
+    }
+}
+
+fn keys_in_slot(store: &dyn KeyValueStore, slot: u16) -> impl Iterator<Item = &String> {
+    store.iter().map(|(key, _)| key).filter(move |key| key_hash_slot(key) == slot)
+}
+
+/// Answers the subcommands that only inspect the cluster.
+fn describe(request: ClusterRequest, cluster: &ClusterState, store: &dyn KeyValueStore, reply: &mut ReplyWriter) {
+    match request {
+        ClusterRequest::Info => reply.verbatim(&describe_info(cluster)),
+        ClusterRequest::MyId => reply.bulk(cluster.myself().id.as_bytes()),
+        ClusterRequest::Nodes => reply.verbatim(&cluster.describe_nodes()),
+        ClusterRequest::Slots => describe_slots(cluster, reply),
+        ClusterRequest::Shards => describe_shards(cluster, reply),
+        ClusterRequest::KeySlot(key) => reply.integer(key_hash_slot(&key) as i64),
+        ClusterRequest::CountKeysInSlot(slot) => reply.integer(keys_in_slot(store, slot).count() as i64),
+        ClusterRequest::GetKeysInSlot(slot, count) => {
+            let keys: Vec<&String> = keys_in_slot(store, slot).take(count).collect();
+            reply.array(keys.len());
+            keys.iter().for_each(|key| reply.bulk(key.as_bytes()));
+        }
+        _ => unreachable!("configuration subcommands are handled by the caller"),
+    }
+}
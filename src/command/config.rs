@@ -0,0 +1,91 @@
+use std::io::{Error, ErrorKind};
+use crate::command::{Command, Reply};
+use crate::config::Config;
+use crate::key_value_store::KeyValueStore;
+use crate::reply::ReplyWriter;
+use crate::server::ServerState;
+use crate::tls;
+
+pub enum ConfigRequest {
+    Get(Vec<String>),
+    Set(Vec<(String, String)>),
+    ResetStat,
+    Rewrite,
+}
+
+impl Command<'_> for ConfigRequest {
+    fn new(arguments: &[&str]) -> Result<Self, Error> {
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidInput, message.to_string());
+        let subcommand: &str = arguments.first().ok_or_else(|| invalid("Expected a subcommand"))?;
+        let arguments: &[&str] = &arguments[1..];
+
+        let request: ConfigRequest = match subcommand.to_ascii_lowercase().as_str() {
+            "get" if !arguments.is_empty() => {
+                ConfigRequest::Get(arguments.iter().map(|pattern| pattern.to_string()).collect())
+            }
+            "set" if !arguments.is_empty() && arguments.len().is_multiple_of(2) => ConfigRequest::Set(
+                arguments.chunks(2).map(|pair| (pair[0].to_string(), pair[1].to_string())).collect()),
+            "resetstat" if arguments.is_empty() => ConfigRequest::ResetStat,
+            "rewrite" if arguments.is_empty() => ConfigRequest::Rewrite,
+            _ => return Err(invalid("Unknown CONFIG subcommand or wrong number of arguments")),
+        };
+
+        Ok(request)
+    }
+
+    fn execute(
+        self,
+        store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        let result: Result<(), String> = match self {
+            ConfigRequest::Get(patterns) => {
+                let patterns: Vec<&str> = patterns.iter().map(|pattern| pattern.as_str()).collect();
+                let values: Vec<(&str, String)> = server.config.get(&patterns);
+                reply.map(values.len());
+                for (name, value) in values {
+                    reply.bulk(name.as_bytes());
+                    reply.bulk(value.as_bytes());
+                }
+                return Reply::Immediate;
+            }
+            ConfigRequest::Set(pairs) => {
+                let pairs: Vec<(&str, &str)> = pairs.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect();
+                let previous: Config = server.config.clone();
+                server.config.set(&pairs).and_then(|_| reload_tls(&pairs, previous, server)).map(|_| {
+                    server.apply_config(store.as_mut());
+                    // The password is the default user's, which ACL rules may have changed since.
+                    if pairs.iter().any(|(name, _)| name.eq_ignore_ascii_case("requirepass")) {
+                        server.acl.lock().set_default_password(server.config.requirepass.as_deref());
+                    }
+                })
+            }
+            ConfigRequest::ResetStat => {
+                server.stats.reset();
+                Ok(())
+            }
+            ConfigRequest::Rewrite => server.config.rewrite(),
+        };
+
+        match result {
+            Ok(()) => reply.ok(),
+            Err(message) => reply.error(&message),
+        }
+        Reply::Immediate
+    }
+}
+
+/// Loads the certificates again when a TLS parameter changed, going back to the previous
+/// configuration if they cannot be loaded.
+fn reload_tls(pairs: &[(&str, &str)], previous: Config, server: &mut ServerState) -> Result<(), String> {
+    let Some((name, _)) = pairs.iter().find(|(name, _)| name.to_ascii_lowercase().starts_with("tls-")) else {
+        return Ok(());
+    };
+    tls::configure(&server.config).map_err(|e| {
+        println!("Failed to update TLS configuration: {}", e);
+        server.config = previous;
+        format!("ERR CONFIG SET failed (possibly related to argument '{}') - Unable to update TLS configuration. \
+            Check server logs.", name)
+    })
+}
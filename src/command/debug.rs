@@ -0,0 +1,61 @@
+use std::io::{Error, ErrorKind};
+use crate::command::{Command, Reply};
+use crate::key_value_store::KeyValueStore;
+use crate::reply::{Protocol, ReplyWriter, Value};
+use crate::server::ServerState;
+
+/// Types `DEBUG PROTOCOL` can reply with, for clients to test their RESP3 support.
+const PROTOCOL_TYPES: [&str; 11] = [
+    "string", "integer", "double", "null", "array", "set", "map", "push", "verbatim", "true", "false",
+];
+
+pub enum DebugRequest {
+    Protocol(&'static str),
+}
+
+impl Command<'_> for DebugRequest {
+    fn new(arguments: &[&str]) -> Result<Self, Error> {
+        match arguments {
+            [subcommand, kind] if subcommand.eq_ignore_ascii_case("protocol") => {
+                let kind: &str = PROTOCOL_TYPES.iter()
+                    .find(|name| name.eq_ignore_ascii_case(kind))
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!(
+                        "Wrong protocol type name. Please use one of the following: {}", PROTOCOL_TYPES.join("|"))))?;
+                Ok(DebugRequest::Protocol(kind))
+            }
+            _ => Err(Error::new(ErrorKind::InvalidInput, "Unknown DEBUG subcommand or wrong number of arguments")),
+        }
+    }
+
+    fn execute(
+        self,
+        _store: &mut Box<dyn KeyValueStore>,
+        _server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        let DebugRequest::Protocol(kind) = self;
+        let value: Value = match kind {
+            "string" => Value::bulk("Hello World"),
+            "integer" => Value::Integer(12345),
+            "double" => Value::Double(1.5),
+            "null" => Value::Null,
+            "array" => Value::Array((0..3).map(Value::Integer).collect()),
+            "set" => Value::Set((0..3).map(Value::Integer).collect()),
+            "map" => Value::Map((0..3).map(|i| (Value::Integer(i), Value::Boolean(i == 1))).collect()),
+            "push" => {
+                if reply.protocol() == Protocol::Resp2 {
+                    reply.error("ERR RESP2 is not supported by this command");
+                    return Reply::Immediate;
+                }
+                Value::Push(vec![Value::bulk("server-cpu-usage"), Value::Integer(42)]).write(reply);
+                Value::bulk("Some real reply following the push reply")
+            }
+            "verbatim" => Value::Verbatim("This is a verbatim\nstring".to_string()),
+            "true" => Value::Boolean(true),
+            _ => Value::Boolean(false),
+        };
+
+        value.write(reply);
+        Reply::Immediate
+    }
+}
@@ -0,0 +1,48 @@
+use std::io::{Error, ErrorKind};
+use std::time::SystemTime;
+use crate::command::{Command, Reply};
+use crate::expire;
+use crate::key_value_store::KeyValueStore;
+use crate::notify::KeyspaceEvents;
+use crate::reply::ReplyWriter;
+use crate::server::ServerState;
+
+pub struct DelRequest<'a> {
+    keys: Vec<&'a str>,
+}
+
+impl<'a> Command<'a> for DelRequest<'a> {
+    fn new(arguments: &[&'a str]) -> Result<Self, Error> {
+        if arguments.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "Expected at least one key"));
+        }
+
+        Ok(DelRequest { keys: arguments.to_vec() })
+    }
+
+    fn execute(
+        self,
+        store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        let now: SystemTime = SystemTime::now();
+        let mut removed: usize = 0;
+        for key in &self.keys {
+            if expire::expire_if_needed(store, server, key, now) {
+                continue;
+            }
+            if store.remove(key).is_some() {
+                removed += 1;
+                server.notify_keyspace_event(KeyspaceEvents::GENERIC, "del", key.as_bytes());
+            }
+        }
+
+        if removed == 0 {
+            // Nothing was deleted, or only keys that expired, which are propagated on their own.
+            server.prevent_propagation();
+        }
+        reply.integer(removed as i64);
+        Reply::Immediate
+    }
+}
@@ -0,0 +1,44 @@
+use std::io::{Error, ErrorKind};
+use std::time::SystemTime;
+use crate::command::{Command, Reply};
+use crate::key_value_store::KeyValueStore;
+use crate::notify::KeyspaceEvents;
+use crate::rdb;
+use crate::reply::ReplyWriter;
+use crate::server::ServerState;
+
+pub struct DumpRequest<'a> {
+    key: &'a str,
+}
+
+impl<'a> Command<'a> for DumpRequest<'a> {
+    fn new(arguments: &[&'a str]) -> Result<Self, Error> {
+        if arguments.len() != 1 {
+            return Err(Error::new(ErrorKind::InvalidInput, "Expected a single argument"));
+        }
+
+        Ok(DumpRequest { key: arguments[0] })
+    }
+
+    fn execute(
+        self,
+        store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        let now: SystemTime = SystemTime::now();
+        let payload: Option<Vec<u8>> = store.get(self.key)
+            .filter(|entry| !entry.get_expiry().is_some_and(|expiry| expiry < now))
+            .map(rdb::dump);
+        server.stats.record_lookup(payload.is_some());
+        if payload.is_none() {
+            server.notify_keyspace_event(KeyspaceEvents::KEY_MISS, "keymiss", self.key.as_bytes());
+        }
+
+        match payload {
+            Some(payload) => reply.bulk(&payload),
+            None => reply.null(),
+        }
+        Reply::Immediate
+    }
+}
@@ -1,29 +1,29 @@
 use std::io::{Error, ErrorKind};
-use crate::command::{DataRequester, CommandFactory, CommandRunner, Reply};
+use crate::command::{Command, Reply};
 use crate::key_value_store::KeyValueStore;
+use crate::reply::ReplyWriter;
+use crate::server::ServerState;
 
-pub struct EchoCommand {
-    body: String,
+pub struct EchoCommand<'a> {
+    body: &'a str,
 }
 
-impl CommandFactory for EchoCommand {
-    fn new(arguments: &[&str]) -> Result<Box<Self>, Error> {
+impl<'a> Command<'a> for EchoCommand<'a> {
+    fn new(arguments: &[&'a str]) -> Result<Self, Error> {
         if arguments.len() != 1 {
             return Err(Error::new(ErrorKind::InvalidInput, "Expected a single argument"));
         }
 
-        Ok(Box::new(EchoCommand { body: String::from(arguments[0]) }))
+        Ok(EchoCommand { body: arguments[0] })
     }
-}
-
-impl DataRequester for EchoCommand {
-    fn request(self: Box<Self>, _store: &mut Box<dyn KeyValueStore>) -> Box<dyn CommandRunner> {
-        self
-    }
-}
 
-impl CommandRunner for EchoCommand {
-    fn run(self: Box<Self>) -> Reply {
-        Reply::Immediate(format!("${}\r\n{}\r\n", self.body.len(), self.body).into_bytes())
+    fn execute(
+        self,
+        _store: &mut Box<dyn KeyValueStore>,
+        _server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        reply.bulk(self.body.as_bytes());
+        Reply::Immediate
     }
 }
@@ -0,0 +1,73 @@
+use std::io::{Error, ErrorKind};
+use crate::command::{Command, Reply};
+use crate::key_value_store::KeyValueStore;
+use crate::reply::ReplyWriter;
+use crate::scripting::Scripting;
+use crate::server::{ServerState, Shared};
+
+/// `EVAL script numkeys [key ...] [arg ...]`, or `EVALSHA` with the SHA1 of a cached script
+/// when `SHA` is set. The `_RO` variants are `READ_ONLY` and refuse to run write commands.
+pub struct ScriptCallRequest<'a, const SHA: bool, const READ_ONLY: bool> {
+    script: &'a str,
+    keys: Vec<&'a str>,
+    arguments: Vec<&'a str>,
+}
+
+pub type EvalRequest<'a> = ScriptCallRequest<'a, false, false>;
+pub type EvalShaRequest<'a> = ScriptCallRequest<'a, true, false>;
+pub type EvalRoRequest<'a> = ScriptCallRequest<'a, false, true>;
+pub type EvalShaRoRequest<'a> = ScriptCallRequest<'a, true, true>;
+
+impl<'a, const SHA: bool, const READ_ONLY: bool> Command<'a> for ScriptCallRequest<'a, SHA, READ_ONLY> {
+    fn new(arguments: &[&'a str]) -> Result<Self, Error> {
+        let [script, count, rest @ ..] = arguments else {
+            return Err(Error::new(ErrorKind::InvalidInput, "Expected a script and the number of keys"));
+        };
+        let count: i64 = count.parse()
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "value is not an integer or out of range"))?;
+        if count < 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "Number of keys can't be negative"));
+        }
+        if count as usize > rest.len() {
+            return Err(Error::new(ErrorKind::InvalidInput, "Number of keys can't be greater than number of args"));
+        }
+        let (keys, arguments): (&[&'a str], &[&'a str]) = rest.split_at(count as usize);
+        Ok(ScriptCallRequest { script, keys: keys.to_vec(), arguments: arguments.to_vec() })
+    }
+
+    fn execute(
+        self,
+        store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        let scripting: Shared<Scripting> = server.scripting.clone();
+        let mut scripting = scripting.lock();
+        let sha: String = if SHA {
+            self.script.to_string()
+        } else {
+            match scripting.load(self.script) {
+                Ok(sha) => sha,
+                Err(message) => {
+                    reply.error(&message);
+                    return Reply::Immediate;
+                }
+            }
+        };
+
+        // The script may run for long, so the other tasks of this thread move elsewhere meanwhile.
+        tokio::task::block_in_place(|| {
+            scripting.run(&sha, &self.keys, &self.arguments, READ_ONLY, store, server, reply);
+        });
+        Reply::Immediate
+    }
+}
+
+/// Positions of the keys of an `EVAL` call: the `numkeys` arguments following the count.
+pub fn key_positions(arguments: &[Vec<u8>]) -> Vec<usize> {
+    let count: usize = arguments.get(2)
+        .and_then(|count| std::str::from_utf8(count).ok())
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(0);
+    (3..arguments.len()).take(count).collect()
+}
@@ -0,0 +1,236 @@
+use std::io::{Error, ErrorKind};
+use crate::command::{run, with_text_arguments, Command, Reply};
+use crate::glob;
+use crate::key_value_store::KeyValueStore;
+use crate::rdb;
+use crate::reply::ReplyWriter;
+use crate::scripting::{RegisteredFunction, RestorePolicy, Scripting};
+use crate::server::{ServerState, Shared};
+
+/// `FCALL function numkeys [key ...] [arg ...]`, or `FCALL_RO` when `READ_ONLY` is set,
+/// which only calls functions flagged `no-writes`.
+pub struct FunctionCallRequest<'a, const READ_ONLY: bool> {
+    function: &'a str,
+    keys: Vec<&'a str>,
+    arguments: Vec<&'a str>,
+}
+
+pub type FCallRequest<'a> = FunctionCallRequest<'a, false>;
+pub type FCallRoRequest<'a> = FunctionCallRequest<'a, true>;
+
+impl<'a, const READ_ONLY: bool> Command<'a> for FunctionCallRequest<'a, READ_ONLY> {
+    fn new(arguments: &[&'a str]) -> Result<Self, Error> {
+        let [function, count, rest @ ..] = arguments else {
+            return Err(Error::new(ErrorKind::InvalidInput, "Expected a function and the number of keys"));
+        };
+        let count: i64 = count.parse()
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "value is not an integer or out of range"))?;
+        if count < 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "Number of keys can't be negative"));
+        }
+        if count as usize > rest.len() {
+            return Err(Error::new(ErrorKind::InvalidInput, "Number of keys can't be greater than number of args"));
+        }
+        let (keys, arguments): (&[&'a str], &[&'a str]) = rest.split_at(count as usize);
+        Ok(FunctionCallRequest { function, keys: keys.to_vec(), arguments: arguments.to_vec() })
+    }
+
+    fn execute(
+        self,
+        store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        let scripting: Shared<Scripting> = server.scripting.clone();
+        let scripting = scripting.lock();
+        // The function may run for long, so the other tasks of this thread move elsewhere meanwhile.
+        tokio::task::block_in_place(|| {
+            scripting.call_function(self.function, &self.keys, &self.arguments, READ_ONLY, store, server, reply);
+        });
+        Reply::Immediate
+    }
+}
+
+/// Runs `FUNCTION`. `FUNCTION RESTORE` takes raw arguments since the payload is binary.
+pub fn handle(
+    arguments: &[Vec<u8>],
+    store: &mut Box<dyn KeyValueStore>,
+    server: &mut ServerState,
+    reply: &mut ReplyWriter
+) -> Reply {
+    if !arguments.get(1).is_some_and(|subcommand| subcommand.eq_ignore_ascii_case(b"restore")) {
+        return with_text_arguments(arguments, reply, |text, reply| run(FunctionRequest::new(text), store, server, reply));
+    }
+    match restore_policy(&arguments[3..]) {
+        Ok(policy) => {
+            let restored: Result<(), String> = rdb::restore_libraries(&arguments[2])
+                .map_err(|message| format!("ERR {}", message))
+                .and_then(|codes| server.scripting.lock().restore_libraries(codes, policy));
+            match restored {
+                Ok(()) => reply.ok(),
+                Err(message) => reply.error(&message),
+            }
+        }
+        Err(e) => reply.error(&format!("ERR {}", e)),
+    }
+    Reply::Immediate
+}
+
+/// Parses the policy following the payload of `FUNCTION RESTORE payload [FLUSH|APPEND|REPLACE]`.
+fn restore_policy(arguments: &[Vec<u8>]) -> Result<RestorePolicy, Error> {
+    let policy: RestorePolicy = match arguments {
+        [] => RestorePolicy::Append,
+        [policy] if policy.eq_ignore_ascii_case(b"append") => RestorePolicy::Append,
+        [policy] if policy.eq_ignore_ascii_case(b"replace") => RestorePolicy::Replace,
+        [policy] if policy.eq_ignore_ascii_case(b"flush") => RestorePolicy::Flush,
+        [_] => return Err(Error::new(
+            ErrorKind::InvalidInput, "Wrong restore policy given, value should be either FLUSH, APPEND or REPLACE."
+        )),
+        _ => return Err(Error::new(ErrorKind::InvalidInput, "syntax error")),
+    };
+    Ok(policy)
+}
+
+pub enum FunctionRequest<'a> {
+    Load { code: &'a str, replace: bool },
+    Delete(&'a str),
+    Flush,
+    /// `LIST [LIBRARYNAME pattern] [WITHCODE]`
+    List { pattern: Option<&'a str>, with_code: bool },
+    Dump,
+    Stats,
+    /// Only reaches the data manager when no function runs, like `SCRIPT KILL`.
+    Kill,
+}
+
+impl<'a> Command<'a> for FunctionRequest<'a> {
+    fn new(arguments: &[&'a str]) -> Result<Self, Error> {
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidInput, message.to_string());
+        let subcommand: String = arguments.first()
+            .ok_or_else(|| invalid("Expected a subcommand"))?
+            .to_ascii_lowercase();
+
+        let request: FunctionRequest = match (subcommand.as_str(), &arguments[1..]) {
+            ("load", [code]) => FunctionRequest::Load { code, replace: false },
+            ("load", [option, code]) if option.eq_ignore_ascii_case("replace") => {
+                FunctionRequest::Load { code, replace: true }
+            }
+            ("load", [option, _]) => return Err(invalid(&format!("Unknown option given: {}", option))),
+            ("delete", [library]) => FunctionRequest::Delete(library),
+            ("flush", []) => FunctionRequest::Flush,
+            ("flush", [mode]) if mode.eq_ignore_ascii_case("sync") || mode.eq_ignore_ascii_case("async") => {
+                FunctionRequest::Flush
+            }
+            ("flush", _) => return Err(invalid("FUNCTION FLUSH only supports SYNC|ASYNC option")),
+            ("list", options) => {
+                let mut pattern: Option<&str> = None;
+                let mut with_code: bool = false;
+                let mut options = options.iter();
+                while let Some(option) = options.next() {
+                    match option.to_ascii_lowercase().as_str() {
+                        "withcode" => with_code = true,
+                        "libraryname" if pattern.is_none() => {
+                            pattern = Some(options.next().ok_or_else(|| invalid("library name argument was not given"))?);
+                        }
+                        "libraryname" => return Err(invalid("library name can be given only once")),
+                        _ => return Err(invalid(&format!("Unknown argument {}", option))),
+                    }
+                }
+                FunctionRequest::List { pattern, with_code }
+            }
+            ("dump", []) => FunctionRequest::Dump,
+            ("stats", []) => FunctionRequest::Stats,
+            ("kill", []) => FunctionRequest::Kill,
+            _ => return Err(invalid("Unknown FUNCTION subcommand or wrong number of arguments")),
+        };
+
+        Ok(request)
+    }
+
+    fn execute(
+        self,
+        _store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        let mut scripting = server.scripting.lock();
+        match self {
+            FunctionRequest::Load { code, replace } => match scripting.load_library(code, replace) {
+                Ok(name) => reply.bulk(name.as_bytes()),
+                Err(message) => reply.error(&message),
+            },
+            FunctionRequest::Delete(library) => {
+                if scripting.delete_library(library) {
+                    reply.ok();
+                } else {
+                    reply.error("ERR Library not found");
+                }
+            }
+            FunctionRequest::Flush => {
+                scripting.flush_libraries();
+                reply.ok();
+            }
+            FunctionRequest::List { pattern, with_code } => write_libraries(&scripting, pattern, with_code, reply),
+            FunctionRequest::Dump => reply.bulk(&rdb::dump_libraries(&scripting.library_codes())),
+            FunctionRequest::Stats => {
+                reply.map(2);
+                reply.bulk(b"running_script");
+                reply.null();
+                reply.bulk(b"engines");
+                reply.map(1);
+                reply.bulk(b"LUA");
+                reply.map(2);
+                reply.bulk(b"libraries_count");
+                reply.integer(scripting.libraries().count() as i64);
+                reply.bulk(b"functions_count");
+                reply.integer(scripting.function_count() as i64);
+            }
+            FunctionRequest::Kill => reply.error("NOTBUSY No scripts in execution right now."),
+        }
+        Reply::Immediate
+    }
+}
+
+/// Writes the libraries whose name matches `pattern`, with their functions.
+fn write_libraries(scripting: &Scripting, pattern: Option<&str>, with_code: bool, reply: &mut ReplyWriter) {
+    let libraries: Vec<_> = scripting.libraries()
+        .filter(|(name, _)| pattern.is_none_or(|pattern| glob::matches(pattern.as_bytes(), name.as_bytes(), false)))
+        .collect();
+    reply.array(libraries.len());
+    for (name, library) in libraries {
+        reply.map(if with_code { 4 } else { 3 });
+        reply.bulk(b"library_name");
+        reply.bulk(name.as_bytes());
+        reply.bulk(b"engine");
+        reply.bulk(b"LUA");
+        reply.bulk(b"functions");
+        reply.array(library.functions.len());
+        for function in &library.functions {
+            let Some(registered) = scripting.function(function) else {
+                continue;
+            };
+            write_function(function, registered, reply);
+        }
+        if with_code {
+            reply.bulk(b"library_code");
+            reply.bulk(library.code.as_bytes());
+        }
+    }
+}
+
+fn write_function(name: &str, function: &RegisteredFunction, reply: &mut ReplyWriter) {
+    reply.map(3);
+    reply.bulk(b"name");
+    reply.bulk(name.as_bytes());
+    reply.bulk(b"description");
+    match &function.description {
+        Some(description) => reply.bulk(description.as_bytes()),
+        None => reply.null(),
+    }
+    reply.bulk(b"flags");
+    let flags: Vec<&str> = function.flags.names();
+    reply.set(flags.len());
+    for flag in flags {
+        reply.simple(flag);
+    }
+}
@@ -1,66 +1,49 @@
 use std::io::{Error, ErrorKind};
 use std::time::SystemTime;
-use crate::command::{DataRequester, CommandFactory, CommandRunner, Reply};
-use crate::key_value_store::KeyValueStore;
+use crate::command::{Command, Reply};
+use crate::expire;
+use crate::key_value_store::{KeyValueStore, KeyValueStoreEntry};
+use crate::notify::KeyspaceEvents;
+use crate::reply::ReplyWriter;
+use crate::server::ServerState;
 
-pub struct GetCommandRequest {
-    key: String,
+pub struct GetCommandRequest<'a> {
+    key: &'a str,
     current_time: SystemTime,
 }
 
-struct GetCommandResponse {
-    value: Option<String>,
-}
-
-impl CommandFactory for GetCommandRequest {
-    fn new(arguments: &[&str]) -> Result<Box<Self>, Error> {
+impl<'a> Command<'a> for GetCommandRequest<'a> {
+    fn new(arguments: &[&'a str]) -> Result<Self, Error> {
         if arguments.len() != 1 {
             return Err(Error::new(ErrorKind::InvalidInput, "Expected a single argument"));
         }
 
-        Ok(Box::new(GetCommandRequest {
-            key: String::from(arguments[0]),
+        Ok(GetCommandRequest {
+            key: arguments[0],
             current_time: SystemTime::now(),
-        }))
+        })
     }
-}
 
-impl GetCommandResponse {
-    fn new(value: Option<String>) -> Self {
-        Self { value }
-    }
-}
-impl DataRequester for GetCommandRequest {
-    fn request(self: Box<Self>, store: &mut Box<dyn KeyValueStore>) -> Box<dyn CommandRunner> {
-        let now: SystemTime = self.current_time;
-        
-        let (expired, value) = store.get(&self.key)
-            .map(|entity| { 
-                let expired = entity.get_expiry().is_some_and(|t| t < now);
-                let value = if expired { 
-                    None 
-                } else { 
-                    entity.get_value().cloned().ok() 
-                };
-                (expired, value) 
-            }).unwrap_or((false, None));
-        
-        if expired {
-            store.remove(&self.key);
+    fn execute(
+        self,
+        store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        let expired: bool = expire::expire_if_needed(store, server, self.key, self.current_time);
+        let entity: Option<&dyn KeyValueStoreEntry> = if expired { None } else { store.get(self.key) };
+        if let Some(entity) = entity {
+            server.stats.record_lookup(true);
+            match entity.get_value() {
+                Ok(value) => reply.bulk(value.as_bytes()),
+                Err(_) => reply.null(),
+            }
+            return Reply::Immediate;
         }
-        
-        Box::new(GetCommandResponse::new(value))
-    }
-}
+        server.stats.record_lookup(false);
+        server.notify_keyspace_event(KeyspaceEvents::KEY_MISS, "keymiss", self.key.as_bytes());
 
-impl CommandRunner for GetCommandResponse {
-    fn run(self: Box<Self>) -> Reply {
-        let reply: Vec<u8> = match self.value {
-            Some(value) => format!("${}\r\n{}\r\n", value.len(), value).into_bytes(),
-            None => "$-1\r\n".as_bytes().to_vec(),
-        };
-        
-        Reply::Immediate(reply)
+        reply.null();
+        Reply::Immediate
     }
 }
-
@@ -0,0 +1,113 @@
+use std::io::{Error, ErrorKind};
+use crate::acl;
+use crate::command::{Command, Reply};
+use crate::command::{auth, client};
+use crate::command::info::REDIS_VERSION;
+use crate::key_value_store::KeyValueStore;
+use crate::reply::{Protocol, ReplyWriter};
+use crate::server::ServerState;
+
+pub struct HelloRequest<'a> {
+    /// The protocol version to switch to, the current one being kept without it.
+    version: Option<i64>,
+    auth: Option<(&'a str, &'a str)>,
+    name: Option<&'a str>,
+}
+
+impl<'a> Command<'a> for HelloRequest<'a> {
+    fn new(arguments: &[&'a str]) -> Result<Self, Error> {
+        let Some((version, mut options)) = arguments.split_first() else {
+            return Ok(HelloRequest { version: None, auth: None, name: None });
+        };
+        let version: i64 = version.parse().map_err(|_| Error::new(
+            ErrorKind::InvalidInput, "Protocol version is not an integer or out of range"))?;
+
+        let mut request: HelloRequest = HelloRequest { version: Some(version), auth: None, name: None };
+        while let Some((option, rest)) = options.split_first() {
+            match (option.to_ascii_lowercase().as_str(), rest) {
+                ("auth", [username, password, rest @ ..]) => {
+                    request.auth = Some((username, password));
+                    options = rest;
+                }
+                ("setname", [name, rest @ ..]) => {
+                    request.name = Some(name);
+                    options = rest;
+                }
+                _ => return Err(Error::new(
+                    ErrorKind::InvalidInput, format!("Syntax error in HELLO option '{}'", option))),
+            }
+        }
+
+        Ok(request)
+    }
+
+    fn execute(
+        self,
+        _store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        let protocol: Protocol = match self.version {
+            None => reply.protocol(),
+            Some(2) => Protocol::Resp2,
+            Some(3) => Protocol::Resp3,
+            Some(_) => {
+                reply.error("NOPROTO unsupported protocol version");
+                return Reply::Immediate;
+            }
+        };
+
+        match self.auth {
+            Some((username, password)) => match auth::authenticate(server, username, password) {
+                Ok(()) => {}
+                Err(message) => {
+                    reply.error(message);
+                    return Reply::Immediate;
+                }
+            },
+            None if !server.session.authenticated && acl::password_required() => {
+                reply.error("NOAUTH HELLO must be called with the client already authenticated, otherwise the \
+                    HELLO <proto> AUTH <user> <pass> option can be used to authenticate the client and select \
+                    the RESP protocol version at the same time");
+                return Reply::Immediate;
+            }
+            None => {}
+        }
+
+        if let Some(name) = self.name {
+            if !client::is_valid_name(name) {
+                reply.error("ERR Client names cannot contain spaces, newlines or special characters.");
+                return Reply::Immediate;
+            }
+        }
+
+        let id: u64 = server.current_client.unwrap_or_default();
+        if let Some(client) = server.clients.get_mut(&id) {
+            if let Some(name) = self.name {
+                // An empty name removes the current one.
+                client.name = Some(name.to_string()).filter(|name| !name.is_empty());
+            }
+        }
+
+        let mode: &str = if server.cluster.is_some() { "cluster" } else { "standalone" };
+        let role: &str = if server.replication.lock().is_replica() { "replica" } else { "master" };
+
+        reply.set_protocol(protocol);
+        reply.map(7);
+        reply.bulk(b"server");
+        reply.bulk(b"redis");
+        reply.bulk(b"version");
+        reply.bulk(REDIS_VERSION.as_bytes());
+        reply.bulk(b"proto");
+        reply.integer(protocol.version());
+        reply.bulk(b"id");
+        reply.integer(id as i64);
+        reply.bulk(b"mode");
+        reply.bulk(mode.as_bytes());
+        reply.bulk(b"role");
+        reply.bulk(role.as_bytes());
+        reply.bulk(b"modules");
+        reply.array(0);
+        Reply::Immediate
+    }
+}
@@ -0,0 +1,198 @@
+use std::fmt::Write;
+use std::io::Error;
+use std::sync::MutexGuard;
+use std::sync::atomic::Ordering;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::allocator;
+use crate::command::{Command, Reply};
+use crate::key_value_store::{self, KeyValueStore};
+use crate::pubsub::PubSub;
+use crate::reply::ReplyWriter;
+use crate::server::ServerState;
+use crate::stats::{Stats, NET_INPUT_BYTES, NET_OUTPUT_BYTES};
+
+/// The Redis version whose behaviour the server follows, as reported to clients.
+pub const REDIS_VERSION: &str = "7.2.0";
+
+/// Every section, in the order Redis renders them.
+const SECTIONS: [&str; 9] = [
+    "server", "clients", "memory", "persistence", "stats", "replication", "commandstats", "cluster", "keyspace",
+];
+
+/// Sections that are only rendered when asked for by name or through `all`/`everything`.
+const NON_DEFAULT_SECTIONS: [&str; 1] = ["commandstats"];
+
+pub struct InfoRequest {
+    sections: Vec<&'static str>,
+}
+
+impl Command<'_> for InfoRequest {
+    fn new(arguments: &[&str]) -> Result<Self, Error> {
+        let requested: Vec<String> = if arguments.is_empty() {
+            vec!["default".to_string()]
+        } else {
+            arguments.iter().map(|argument| argument.to_ascii_lowercase()).collect()
+        };
+
+        let sections: Vec<&'static str> = SECTIONS.iter()
+            .copied()
+            .filter(|section| requested.iter().any(|requested| match requested.as_str() {
+                "all" | "everything" => true,
+                "default" => !NON_DEFAULT_SECTIONS.contains(section),
+                requested => requested == *section,
+            }))
+            .collect();
+
+        Ok(InfoRequest { sections })
+    }
+
+    fn execute(
+        self,
+        store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        let rendered: Vec<String> = self.sections.iter()
+            .map(|section| {
+                let mut info: String = String::new();
+                let mut title: String = section.to_string();
+                title[..1].make_ascii_uppercase();
+                writeln!(info, "# {}\r", title).unwrap();
+
+                match *section {
+                    "server" => write_server(&mut info, server),
+                    "clients" => write_clients(&mut info, server),
+                    "memory" => write_memory(&mut info, server),
+                    "persistence" => write_persistence(&mut info, server),
+                    "stats" => write_stats(&mut info, server),
+                    "replication" => info.push_str(&server.replication.lock().info()),
+                    "commandstats" => write_commandstats(&mut info, server),
+                    "cluster" => writeln!(info, "cluster_enabled:{}\r", server.cluster.is_some() as u8).unwrap(),
+                    _ => write_keyspace(&mut info, store.as_ref()),
+                }
+                info
+            })
+            .collect();
+
+        reply.verbatim(&rendered.join("\r\n"));
+        Reply::Immediate
+    }
+}
+
+fn unix_time(time: SystemTime) -> std::time::Duration {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+fn write_server(info: &mut String, server: &ServerState) {
+    let uptime: u64 = server.stats.started.elapsed().as_secs();
+    let executable: String = std::env::current_exe().map(|path| path.display().to_string()).unwrap_or_default();
+    let config_file: String = server.config.config_file().map(|path| path.display().to_string()).unwrap_or_default();
+
+    writeln!(info, "redis_version:{}\r", REDIS_VERSION).unwrap();
+    writeln!(info, "redis_mode:{}\r", if server.cluster.is_some() { "cluster" } else { "standalone" }).unwrap();
+    writeln!(info, "os:{} {}\r", std::env::consts::OS, std::env::consts::ARCH).unwrap();
+    writeln!(info, "arch_bits:{}\r", usize::BITS).unwrap();
+    writeln!(info, "process_id:{}\r", std::process::id()).unwrap();
+    writeln!(info, "run_id:{}\r", server.stats.run_id).unwrap();
+    writeln!(info, "tcp_port:{}\r", server.config.port).unwrap();
+    writeln!(info, "server_time_usec:{}\r", unix_time(SystemTime::now()).as_micros()).unwrap();
+    writeln!(info, "uptime_in_seconds:{}\r", uptime).unwrap();
+    writeln!(info, "uptime_in_days:{}\r", uptime / (24 * 60 * 60)).unwrap();
+    writeln!(info, "hz:10\r").unwrap();
+    writeln!(info, "lru_clock:{}\r", key_value_store::lru_clock()).unwrap();
+    writeln!(info, "executable:{}\r", executable).unwrap();
+    writeln!(info, "config_file:{}\r", config_file).unwrap();
+}
+
+fn write_clients(info: &mut String, server: &ServerState) {
+    // Like Redis, replicas are not counted as clients.
+    let replicas: usize = server.replication.lock().replica_client_ids()
+        .filter(|id| server.clients.contains_key(id))
+        .count();
+    writeln!(info, "connected_clients:{}\r", server.clients.len() - replicas).unwrap();
+    let blocked: usize = server.clients.values().filter(|client| client.connection.activity().blocked).count();
+    writeln!(info, "blocked_clients:{}\r", blocked).unwrap();
+    writeln!(info, "tracking_clients:{}\r", server.tracking.lock().client_count()).unwrap();
+}
+
+fn write_memory(info: &mut String, server: &ServerState) {
+    let used: usize = allocator::used_memory();
+    let peak: usize = allocator::peak_memory();
+    let resident: usize = allocator::resident_memory().unwrap_or(0);
+
+    writeln!(info, "used_memory:{}\r", used).unwrap();
+    writeln!(info, "used_memory_human:{}\r", allocator::format_bytes(used)).unwrap();
+    writeln!(info, "used_memory_rss:{}\r", resident).unwrap();
+    writeln!(info, "used_memory_rss_human:{}\r", allocator::format_bytes(resident)).unwrap();
+    writeln!(info, "used_memory_peak:{}\r", peak).unwrap();
+    writeln!(info, "used_memory_peak_human:{}\r", allocator::format_bytes(peak)).unwrap();
+    writeln!(info, "maxmemory:{}\r", server.config.maxmemory).unwrap();
+    writeln!(info, "maxmemory_human:{}\r", allocator::format_bytes(server.config.maxmemory)).unwrap();
+    writeln!(info, "maxmemory_policy:{}\r", server.config.maxmemory_policy.name()).unwrap();
+    writeln!(info, "mem_allocator:libc\r").unwrap();
+}
+
+fn write_persistence(info: &mut String, server: &ServerState) {
+    writeln!(info, "loading:0\r").unwrap();
+    writeln!(info, "rdb_changes_since_last_save:{}\r", server.stats.dirty).unwrap();
+    writeln!(info, "rdb_bgsave_in_progress:0\r").unwrap();
+    writeln!(info, "rdb_last_save_time:{}\r", unix_time(server.stats.last_save_time).as_secs()).unwrap();
+    writeln!(info, "aof_enabled:0\r").unwrap();
+}
+
+fn write_stats(info: &mut String, server: &ServerState) {
+    let stats: &Stats = &server.stats;
+
+    writeln!(info, "total_connections_received:{}\r", stats.total_connections_received).unwrap();
+    writeln!(info, "total_commands_processed:{}\r", stats.total_commands_processed).unwrap();
+    writeln!(info, "total_net_input_bytes:{}\r", NET_INPUT_BYTES.load(Ordering::Relaxed)).unwrap();
+    writeln!(info, "total_net_output_bytes:{}\r", NET_OUTPUT_BYTES.load(Ordering::Relaxed)).unwrap();
+    writeln!(info, "expired_keys:{}\r", stats.expired_keys).unwrap();
+    writeln!(info, "evicted_keys:{}\r", stats.evicted_keys).unwrap();
+    writeln!(info, "keyspace_hits:{}\r", stats.keyspace_hits).unwrap();
+    writeln!(info, "keyspace_misses:{}\r", stats.keyspace_misses).unwrap();
+    {
+        let pubsub: MutexGuard<'_, PubSub> = server.pubsub.lock();
+        writeln!(info, "pubsub_channels:{}\r", pubsub.channels().count()).unwrap();
+        writeln!(info, "pubsub_patterns:{}\r", pubsub.pattern_count()).unwrap();
+    }
+    writeln!(info, "sync_full:{}\r", stats.sync_full).unwrap();
+    writeln!(info, "sync_partial_ok:{}\r", stats.sync_partial_ok).unwrap();
+    writeln!(info, "sync_partial_err:{}\r", stats.sync_partial_err).unwrap();
+    let (tracked_keys, tracked_items, tracked_prefixes): (usize, usize, usize) = server.tracking.lock().totals();
+    writeln!(info, "tracking_total_keys:{}\r", tracked_keys).unwrap();
+    writeln!(info, "tracking_total_items:{}\r", tracked_items).unwrap();
+    writeln!(info, "tracking_total_prefixes:{}\r", tracked_prefixes).unwrap();
+    writeln!(info, "total_error_replies:{}\r", stats.total_error_replies).unwrap();
+}
+
+fn write_commandstats(info: &mut String, server: &ServerState) {
+    for (name, stats) in server.stats.commands() {
+        let usec_per_call: f64 = if stats.calls == 0 { 0.0 } else { stats.usec as f64 / stats.calls as f64 };
+        writeln!(
+            info,
+            "cmdstat_{}:calls={},usec={},usec_per_call={:.2},rejected_calls={},failed_calls={}\r",
+            name, stats.calls, stats.usec, usec_per_call, stats.rejected_calls, stats.failed_calls
+        ).unwrap();
+    }
+}
+
+fn write_keyspace(info: &mut String, store: &dyn KeyValueStore) {
+    let now: SystemTime = SystemTime::now();
+    let mut keys: usize = 0;
+    let mut expires: usize = 0;
+    let mut total_ttl: u128 = 0;
+
+    for (_, entry) in store.iter() {
+        keys += 1;
+        if let Some(expiry) = entry.get_expiry() {
+            expires += 1;
+            total_ttl += expiry.duration_since(now).unwrap_or_default().as_millis();
+        }
+    }
+
+    if keys > 0 {
+        let average_ttl: u128 = if expires == 0 { 0 } else { total_ttl / expires as u128 };
+        writeln!(info, "db0:keys={},expires={},avg_ttl={}\r", keys, expires, average_ttl).unwrap();
+    }
+}
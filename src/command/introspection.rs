@@ -0,0 +1,244 @@
+use std::io::{Error, ErrorKind};
+use crate::command::{AclCategory, Command, CommandSpec, Reply};
+use crate::glob;
+use crate::key_value_store::KeyValueStore;
+use crate::parser::{self, LookupError, COMMAND_TABLE};
+use crate::reply::ReplyWriter;
+use crate::server::ServerState;
+
+pub enum ListFilter<'a> {
+    Module,
+    AclCategory(AclCategory),
+    Pattern(&'a str),
+}
+
+pub enum CommandRequest<'a> {
+    Count,
+    /// Describes the named commands, or all of them when there are no names.
+    Info(Vec<&'a str>),
+    Docs(Vec<&'a str>),
+    GetKeys(Vec<&'a str>),
+    List(Option<ListFilter<'a>>),
+}
+
+impl<'a> Command<'a> for CommandRequest<'a> {
+    fn new(arguments: &[&'a str]) -> Result<Self, Error> {
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidInput, message.to_string());
+        let Some(subcommand) = arguments.first() else {
+            return Ok(CommandRequest::Info(Vec::new()));
+        };
+        let arguments: &[&'a str] = &arguments[1..];
+
+        let request: CommandRequest = match subcommand.to_ascii_lowercase().as_str() {
+            "count" => CommandRequest::Count,
+            "info" => CommandRequest::Info(arguments.to_vec()),
+            "docs" => CommandRequest::Docs(arguments.to_vec()),
+            "getkeys" => CommandRequest::GetKeys(arguments.to_vec()),
+            "list" => match arguments {
+                [] => CommandRequest::List(None),
+                [filterby, kind, value] if filterby.eq_ignore_ascii_case("filterby") => {
+                    let filter: ListFilter = match kind.to_ascii_lowercase().as_str() {
+                        "module" => ListFilter::Module,
+                        "aclcat" => ListFilter::AclCategory(
+                            AclCategory::from_name(value).ok_or_else(|| invalid("Unknown ACL category"))?),
+                        "pattern" => ListFilter::Pattern(value),
+                        _ => return Err(invalid("syntax error")),
+                    };
+                    CommandRequest::List(Some(filter))
+                }
+                _ => return Err(invalid("syntax error")),
+            },
+            _ => return Err(invalid("Unknown COMMAND subcommand")),
+        };
+
+        Ok(request)
+    }
+
+    fn execute(
+        self,
+        _store: &mut Box<dyn KeyValueStore>,
+        _server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        match self {
+            CommandRequest::Count => reply.integer(COMMAND_TABLE.len() as i64),
+            CommandRequest::Info(names) if names.is_empty() => {
+                reply.array(COMMAND_TABLE.len());
+                for spec in COMMAND_TABLE {
+                    write_info(spec, reply);
+                }
+            }
+            CommandRequest::Info(names) => {
+                reply.array(names.len());
+                for name in names {
+                    match parser::find_command(name) {
+                        Some(spec) => write_info(spec, reply),
+                        None => reply.null(),
+                    }
+                }
+            }
+            CommandRequest::Docs(names) => {
+                // Unknown names are left out rather than answered with a null.
+                let specs: Vec<&CommandSpec> = if names.is_empty() {
+                    COMMAND_TABLE.iter().collect()
+                } else {
+                    names.iter().filter_map(|name| parser::find_command(name)).collect()
+                };
+                reply.map(specs.len());
+                for spec in specs {
+                    reply.bulk(spec.name.as_bytes());
+                    write_docs(spec, reply);
+                }
+            }
+            CommandRequest::GetKeys(command) => get_keys(&command, reply),
+            CommandRequest::List(filter) => {
+                let names: Vec<&str> = COMMAND_TABLE.iter()
+                    .flat_map(|spec| std::iter::once(spec).chain(spec.subcommands))
+                    .filter(|spec| match &filter {
+                        None => true,
+                        Some(ListFilter::Module) => false,
+                        Some(ListFilter::AclCategory(category)) => spec.acl_categories().contains(category),
+                        Some(ListFilter::Pattern(pattern)) => glob::matches(pattern.as_bytes(), spec.name.as_bytes(), true),
+                    })
+                    .map(|spec| spec.name)
+                    .collect();
+                reply.array(names.len());
+                for name in names {
+                    reply.bulk(name.as_bytes());
+                }
+            }
+        }
+        Reply::Immediate
+    }
+}
+
+/// Writes the `COMMAND INFO` entry of a command, its subcommands nested in it.
+fn write_info(spec: &CommandSpec, reply: &mut ReplyWriter) {
+    reply.array(10);
+    reply.bulk(spec.name.as_bytes());
+    reply.integer(spec.arity as i64);
+
+    reply.set(spec.flags.len());
+    for flag in spec.flags {
+        reply.simple(flag.name());
+    }
+
+    reply.integer(spec.keys.first as i64);
+    reply.integer(spec.keys.last as i64);
+    reply.integer(spec.keys.step as i64);
+
+    let categories: Vec<AclCategory> = spec.acl_categories();
+    reply.set(categories.len());
+    for category in categories {
+        reply.simple(&format!("@{}", category.name()));
+    }
+
+    // Tips for clients of a cluster, none of which apply.
+    reply.array(0);
+    write_key_specs(spec, reply);
+
+    reply.array(spec.subcommands.len());
+    for subcommand in spec.subcommands {
+        write_info(subcommand, reply);
+    }
+}
+
+/// Writes the key specifications of a command: where the search for keys begins among the
+/// arguments, and how the keys are found from there.
+fn write_key_specs(spec: &CommandSpec, reply: &mut ReplyWriter) {
+    if spec.keys.first == 0 && spec.keys.find.is_none() {
+        reply.array(0);
+        return;
+    }
+
+    reply.array(1);
+    reply.map(3);
+    reply.bulk(b"flags");
+    reply.set(spec.keys.flags.len());
+    for flag in spec.keys.flags {
+        reply.simple(flag.name());
+    }
+
+    reply.bulk(b"begin_search");
+    reply.map(2);
+    reply.bulk(b"type");
+    if spec.keys.find.is_some() {
+        // The keys depend on the arguments, like the KEYS option of MIGRATE.
+        reply.bulk(b"unknown");
+        reply.bulk(b"spec");
+        reply.map(0);
+        reply.bulk(b"find_keys");
+        reply.map(2);
+        reply.bulk(b"type");
+        reply.bulk(b"unknown");
+        reply.bulk(b"spec");
+        reply.map(0);
+        return;
+    }
+    reply.bulk(b"index");
+    reply.bulk(b"spec");
+    reply.map(1);
+    reply.bulk(b"index");
+    reply.integer(spec.keys.first as i64);
+
+    reply.bulk(b"find_keys");
+    reply.map(2);
+    reply.bulk(b"type");
+    reply.bulk(b"range");
+    reply.bulk(b"spec");
+    reply.map(3);
+    // The last key is relative to the first, unless it counts from the end.
+    let last_key: i32 = if spec.keys.last < 0 { spec.keys.last } else { spec.keys.last - spec.keys.first };
+    reply.bulk(b"lastkey");
+    reply.integer(last_key as i64);
+    reply.bulk(b"keystep");
+    reply.integer(spec.keys.step as i64);
+    reply.bulk(b"limit");
+    reply.integer(0);
+}
+
+/// Writes the `COMMAND DOCS` map of a command, its subcommands nested in it.
+fn write_docs(spec: &CommandSpec, reply: &mut ReplyWriter) {
+    reply.map(if spec.subcommands.is_empty() { 4 } else { 5 });
+    reply.bulk(b"summary");
+    reply.bulk(spec.docs.summary.as_bytes());
+    reply.bulk(b"since");
+    reply.bulk(spec.docs.since.as_bytes());
+    reply.bulk(b"group");
+    reply.bulk(spec.docs.group.name().as_bytes());
+    reply.bulk(b"complexity");
+    reply.bulk(spec.docs.complexity.as_bytes());
+
+    if !spec.subcommands.is_empty() {
+        reply.bulk(b"subcommands");
+        reply.map(spec.subcommands.len());
+        for subcommand in spec.subcommands {
+            reply.bulk(subcommand.name.as_bytes());
+            write_docs(subcommand, reply);
+        }
+    }
+}
+
+/// Replies with the keys `command` would access, found the way the server routes it.
+fn get_keys(command: &[&str], reply: &mut ReplyWriter) {
+    let arguments: Vec<Vec<u8>> = command.iter().map(|argument| argument.as_bytes().to_vec()).collect();
+    let spec: &CommandSpec = match parser::lookup_command(&arguments) {
+        Ok((spec, _)) => spec,
+        Err(LookupError::Unknown(_)) => return reply.error("ERR Invalid command specified"),
+        Err(LookupError::Rejected(spec, _)) if spec.accepts_argument_count(arguments.len()) => {
+            return reply.error("ERR Invalid command specified");
+        }
+        Err(LookupError::Rejected(..)) => {
+            return reply.error("ERR Invalid number of arguments specified for command");
+        }
+    };
+
+    let keys: Vec<&str> = spec.keys(&arguments).collect();
+    if keys.is_empty() {
+        return reply.error("ERR The command has no key arguments");
+    }
+    reply.array(keys.len());
+    for key in keys {
+        reply.bulk(key.as_bytes());
+    }
+}
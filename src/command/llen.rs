@@ -1,44 +1,38 @@
 use std::io::{Error, ErrorKind};
-use crate::command::{DataRequester, CommandFactory, CommandRunner, Reply};
+use crate::command::{Command, Reply};
 use crate::key_value_store::KeyValueStore;
+use crate::notify::KeyspaceEvents;
+use crate::reply::ReplyWriter;
+use crate::server::ServerState;
 
-pub struct LLenCommand {
-    key: String,
+pub struct LLenCommand<'a> {
+    key: &'a str,
 }
 
-struct LLenResponse {
-    length: usize,
-}
-
-impl CommandFactory for LLenCommand {
-    fn new(arguments: &[&str]) -> Result<Box<Self>, Error> {
+impl<'a> Command<'a> for LLenCommand<'a> {
+    fn new(arguments: &[&'a str]) -> Result<Self, Error> {
         if arguments.len() != 1 {
             return Err(Error::new(ErrorKind::InvalidInput, "Expected a single argument"));
         }
 
-        Ok(Box::new(LLenCommand { key: String::from(arguments[0]) }))
+        Ok(LLenCommand { key: arguments[0] })
     }
-}
 
-impl LLenResponse {
-    fn new(length: usize) -> Self {
-        Self { length }
-    }
-}
-
-impl DataRequester for LLenCommand {
-    fn request(self: Box<Self>, store: &mut Box<dyn KeyValueStore>) -> Box<dyn CommandRunner> {
-        let length: usize = store
-            .get(&self.key)
-            .and_then(|entity| entity.len().ok())
-            .unwrap_or(0);
-        
-        Box::new(LLenResponse::new(length))
-    }
-}
+    fn execute(
+        self,
+        store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        let length: Option<usize> = store
+            .get(self.key)
+            .map(|entity| entity.len().unwrap_or(0));
+        server.stats.record_lookup(length.is_some());
+        if length.is_none() {
+            server.notify_keyspace_event(KeyspaceEvents::KEY_MISS, "keymiss", self.key.as_bytes());
+        }
 
-impl CommandRunner for LLenResponse {
-    fn run(self: Box<Self>) -> Reply { 
-        Reply::Immediate(format!(":{}\r\n", self.length).into_bytes())
+        reply.integer(length.unwrap_or(0) as i64);
+        Reply::Immediate
     }
 }
@@ -1,82 +1,78 @@
 use std::io::{Error, ErrorKind};
-use crate::command::{DataRequester, CommandFactory, CommandRunner, Reply};
+use crate::command::{Command, Reply};
 use crate::key_value_store::KeyValueStore;
+use crate::notify::KeyspaceEvents;
+use crate::reply::ReplyWriter;
+use crate::server::ServerState;
 
-pub struct LPopRequest {
-    key: String,
+pub struct LPopRequest<'a> {
+    key: &'a str,
     amount: Option<usize>,
 }
 
-enum OneOrMany {
-    One(String),
-    Many(Vec<String>),
-}
-
-struct LPopResponse {
-    response: Result<OneOrMany, &'static str>,
-}
-
-impl CommandFactory for LPopRequest {
-    fn new(arguments: &[&str]) -> Result<Box<Self>, Error> {
+impl<'a> Command<'a> for LPopRequest<'a> {
+    fn new(arguments: &[&'a str]) -> Result<Self, Error> {
         let argument_count = arguments.len();
         if argument_count == 1 {
-            return Ok(Box::new(
-                LPopRequest {
-                    key: String::from(arguments[0]),
-                    amount: None }));
+            return Ok(LPopRequest { key: arguments[0], amount: None });
         }
         if argument_count == 2 {
-            return Ok(Box::new(
+            return Ok(
                 LPopRequest {
-                    key: String::from(arguments[0]),
+                    key: arguments[0],
                     amount: Some(arguments[1].parse().map_err(|_| Error::new(
                         ErrorKind::InvalidInput, "COUNT must be an unsigned integer"
                     ))?)
                 }
-            ))
+            )
         }
         Err(Error::new(ErrorKind::InvalidInput, "Expected one or two arguments"))
     }
-}
-
-impl LPopResponse {
-    fn new(response: Result<OneOrMany, &'static str>) -> Self {
-        Self { response }
-    }
-}
 
-impl DataRequester for LPopRequest {
-    fn request(self: Box<Self>, store: &mut Box<dyn KeyValueStore>) -> Box<dyn CommandRunner> {
-        let popped_values = store.get_mut(&self.key)
-            .map(|entity| match self.amount {
-                None => entity.pop_front().map(|value| OneOrMany::One(value)),
-                Some(amount) => entity.pop_front_amount(amount).map(|values| OneOrMany::Many(values)),
-            }).unwrap_or(Err("No such key"));
-        
-        Box::new(LPopResponse::new(popped_values))
-    }
-}
+    fn execute(
+        self,
+        store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        let Some(entity) = store.get_mut(self.key) else {
+            reply.null();
+            return Reply::Immediate;
+        };
 
-impl CommandRunner for LPopResponse {
-    fn run(self: Box<Self>) -> Reply {
-        let reply: Vec<u8> = self.response.ok().filter(|entity| match entity {
-            OneOrMany::One(_) => true,
-            OneOrMany::Many(values) => !values.is_empty(),
-        }).map(|entity| {
-            match entity {
-                OneOrMany::One(value) => {
-                    format!("${}\r\n{}\r\n", value.len(), value).into_bytes()
+        let popped: bool = match self.amount {
+            None => match entity.pop_front() {
+                Ok(value) => {
+                    reply.bulk(value.as_bytes());
+                    true
                 }
-                OneOrMany::Many(values) => {
-                    let body: String = values
-                        .iter()
-                        .map(|value| format!("${}\r\n{}\r\n", value.len(), value))
-                        .collect();
-                    format!("*{}\r\n{}", values.len(), body).into_bytes()
+                Err(_) => {
+                    reply.null();
+                    false
                 }
+            },
+            Some(amount) => match entity.pop_front_amount(amount) {
+                Ok(values) if !values.is_empty() => {
+                    reply.array(values.len());
+                    for value in &values {
+                        reply.bulk(value.as_bytes());
+                    }
+                    true
+                }
+                _ => {
+                    reply.null();
+                    false
+                }
+            },
+        };
+        if popped {
+            let emptied: bool = entity.len() == Ok(0);
+            server.notify_keyspace_event(KeyspaceEvents::LIST, "lpop", self.key.as_bytes());
+            if emptied {
+                store.remove(self.key);
+                server.notify_keyspace_event(KeyspaceEvents::GENERIC, "del", self.key.as_bytes());
             }
-        }).unwrap_or(b"$-1\r\n".to_vec());
-        
-        Reply::Immediate(reply)
+        }
+        Reply::Immediate
     }
 }
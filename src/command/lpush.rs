@@ -1,33 +1,48 @@
 use std::io::{Error, ErrorKind};
-use crate::command::{DataRequester, CommandFactory, CommandRunner, Reply};
+use crate::command::{blpop, Command, Reply, WRONG_TYPE_ERROR};
 use crate::key_value_store::{KeyValueStore, KeyValueStoreEntry, KeyValueStoreListEntry};
+use crate::notify::KeyspaceEvents;
+use crate::reply::ReplyWriter;
+use crate::server::ServerState;
 
 pub struct LPushRequest {
     key: String,
     values: Vec<String>,
 }
 
-struct LPushResponse {
-    length: Result<usize, &'static str>,
-}
-
-impl CommandFactory for LPushRequest {
-    fn new(arguments: &[&str]) -> Result<Box<Self>, Error> {
+impl Command<'_> for LPushRequest {
+    fn new(arguments: &[&str]) -> Result<Self, Error> {
         if arguments.len() < 2 {
             return Err(Error::new(ErrorKind::InvalidInput, "Expected at least two arguments"));
         }
 
-        Ok(Box::new(
+        Ok(
             LPushRequest {
                 key: String::from(arguments[0]),
                 values: arguments[1..].iter().rev().map(|s| s.to_string()).collect(),
-            }))
+            })
     }
-}
 
-impl LPushResponse {
-    fn new(length: Result<usize, &'static str>) -> Self {
-        LPushResponse { length }
+    fn execute(
+        self,
+        store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        let key: String = self.key.clone();
+        let created: bool = store.get(&key).is_none();
+        match prepend(store, self.key, self.values) {
+            Ok(size) => {
+                if created {
+                    server.notify_keyspace_event(KeyspaceEvents::NEW, "new", key.as_bytes());
+                }
+                server.notify_keyspace_event(KeyspaceEvents::LIST, "lpush", key.as_bytes());
+                blpop::serve_waiters(store, server, &key);
+                reply.integer(size as i64)
+            }
+            Err(_) => reply.error(WRONG_TYPE_ERROR),
+        }
+        Reply::Immediate
     }
 }
 
@@ -41,19 +56,3 @@ fn prepend(store: &mut Box<dyn KeyValueStore>, key: String, other: Vec<String>)
     store.insert(key, Box::new(entry));
     return_value
 }
-
-impl DataRequester for LPushRequest {
-    fn request(self: Box<Self>, store: &mut Box<dyn KeyValueStore>) -> Box<dyn CommandRunner> {
-        Box::new(LPushResponse::new(prepend(store, self.key, self.values)))
-    }
-}
-
-impl CommandRunner for LPushResponse {
-    fn run(self: Box<Self>) -> Reply {
-        let reply: Vec<u8> = self.length.map_or_else(
-            |_| "$-1\r\n".as_bytes().to_vec(), 
-            |size| format!(":{}\r\n", size).into_bytes());
-        
-        Reply::Immediate(reply)
-    }
-}
@@ -1,73 +1,53 @@
 use std::io::{Error, ErrorKind};
-use crate::command::{DataRequester, CommandFactory, CommandRunner, Reply};
+use crate::command::{Command, Reply};
 use crate::key_value_store::KeyValueStore;
+use crate::notify::KeyspaceEvents;
+use crate::reply::ReplyWriter;
+use crate::server::ServerState;
 
-pub struct LRangeRequest {
-    key: String,
+pub struct LRangeRequest<'a> {
+    key: &'a str,
     start: isize,
     end: isize,
 }
 
-struct LRangeResponse {
-    subslice: Result<Vec<String>, &'static str>,
-}
-
-impl CommandFactory for LRangeRequest {
-    fn new(arguments: &[&str]) -> Result<Box<Self>, Error> {
+impl<'a> Command<'a> for LRangeRequest<'a> {
+    fn new(arguments: &[&'a str]) -> Result<Self, Error> {
         if arguments.len() != 3 {
             return Err(Error::new(ErrorKind::InvalidInput, "Expected exactly three arguments"));
         }
-        Ok(Box::new(
+        let index = |argument: &str| argument.parse().map_err(|_| Error::new(
+            ErrorKind::InvalidInput, "value is not an integer or out of range"));
+        Ok(
             LRangeRequest {
-                key: String::from(arguments[0]),
-                start: arguments[1].parse().unwrap(),
-                end: arguments[2].parse().unwrap(),
+                key: arguments[0],
+                start: index(arguments[1])?,
+                end: index(arguments[2])?,
             }
-        ))
+        )
     }
-}
-
-impl LRangeResponse {
-    fn new(subslice: Result<Vec<String>, &'static str>) -> Self {
-        LRangeResponse { subslice }
-    }
-}
-
-fn get_subslice<'a>(
-    store: &'a Box<dyn KeyValueStore>, key: &String, start: isize, end: isize
-) -> Result<&'a [String], &'static str> {
 
-    if let Some(entry) = store.get(&key) {
-        return match entry.get_subslice(start, end) {
-            Ok(slice) => Ok(slice.unwrap_or_else(|| &[])),
-            Err(s) => Err(s),
+    fn execute(
+        self,
+        store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        let entry = store.get(self.key);
+        server.stats.record_lookup(entry.is_some());
+        if entry.is_none() {
+            server.notify_keyspace_event(KeyspaceEvents::KEY_MISS, "keymiss", self.key.as_bytes());
         }
-    }
-    Ok(&[])
-}
 
-impl DataRequester for LRangeRequest {
-    fn request(self: Box<Self>, store: &mut Box<dyn KeyValueStore>) -> Box<dyn CommandRunner> {
-        let subslice = get_subslice(store, &self.key, self.start, self.end)
-            .map(|slice| slice.to_vec());
-        
-        Box::new(LRangeResponse::new(subslice))
-    }
-}
-
-impl CommandRunner for LRangeResponse {
-    fn run(self: Box<Self>) -> Reply {
-        let reply: Vec<u8> = match self.subslice {
+        match entry.map_or(Ok(Vec::new()), |entry| entry.get_subslice(self.start, self.end)) {
             Ok(slice) => {
-                let body: String = slice
-                    .iter()
-                    .map(|value: &String| format!("${}\r\n{}\r\n", value.len(), value))
-                    .collect();
-                format!("*{}\r\n{}", slice.len(), body).into_bytes()
-            },
-            Err(_) => "$-1\r\n".as_bytes().to_vec(),
-        };
-        
-        Reply::Immediate(reply)
+                reply.array(slice.len());
+                for value in &slice {
+                    reply.bulk(value.as_bytes());
+                }
+            }
+            Err(_) => reply.null(),
+        }
+        Reply::Immediate
     }
 }
@@ -0,0 +1,176 @@
+use std::fmt::Write;
+use std::io::{Error, ErrorKind};
+use std::time::SystemTime;
+use crate::allocator;
+use crate::command::{Command, Reply};
+use crate::key_value_store::{self, KeyValueStore, DEFAULT_MEMORY_SAMPLES, KEY_OVERHEAD};
+use crate::reply::{ReplyWriter, Value};
+use crate::server::ServerState;
+
+/// Below this much memory `MEMORY DOCTOR` has too little to go on, like in Redis.
+const DOCTOR_MINIMUM_MEMORY: usize = 5 * 1024 * 1024;
+
+pub enum MemoryRequest {
+    /// `USAGE key [SAMPLES count]`
+    Usage { key: String, samples: usize },
+    Stats,
+    Doctor,
+}
+
+impl Command<'_> for MemoryRequest {
+    fn new(arguments: &[&str]) -> Result<Self, Error> {
+        let subcommand: String = arguments.first()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Expected a subcommand"))?
+            .to_ascii_lowercase();
+
+        let request: MemoryRequest = match (subcommand.as_str(), &arguments[1..]) {
+            ("usage", [key]) => MemoryRequest::Usage { key: key.to_string(), samples: DEFAULT_MEMORY_SAMPLES },
+            ("usage", [key, option, count]) if option.eq_ignore_ascii_case("samples") => MemoryRequest::Usage {
+                key: key.to_string(),
+                samples: count.parse().map_err(|_| Error::new(ErrorKind::InvalidInput, "Invalid SAMPLES count"))?,
+            },
+            ("stats", []) => MemoryRequest::Stats,
+            ("doctor", []) => MemoryRequest::Doctor,
+            _ => return Err(Error::new(ErrorKind::InvalidInput, "Unknown MEMORY subcommand or wrong number of arguments")),
+        };
+
+        Ok(request)
+    }
+
+    fn execute(
+        self,
+        store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        match self {
+            MemoryRequest::Usage { key, samples } => {
+                // Like OBJECT, measuring a key does not count as an access to it.
+                let now: SystemTime = SystemTime::now();
+                match store.peek(&key) {
+                    Some((entry, _)) if !entry.get_expiry().is_some_and(|expiry| expiry < now) => {
+                        reply.integer(key_value_store::key_memory_usage(&key, entry, samples) as i64)
+                    }
+                    _ => reply.null(),
+                }
+            }
+            MemoryRequest::Stats => stats_reply(&MemoryStats::collect(store.as_ref(), server)).write(reply),
+            MemoryRequest::Doctor => reply.verbatim(&doctor(&MemoryStats::collect(store.as_ref(), server))),
+        }
+        Reply::Immediate
+    }
+}
+
+/// The breakdown reported by `MEMORY STATS`: what the server needs regardless of the data
+/// it holds, and what is left for the dataset itself.
+struct MemoryStats {
+    peak_allocated: usize,
+    total_allocated: usize,
+    startup_allocated: usize,
+    replication_backlog: usize,
+    keys: usize,
+    hashtable_main: usize,
+    hashtable_expires: usize,
+    resident: usize,
+}
+
+impl MemoryStats {
+    fn collect(store: &dyn KeyValueStore, server: &ServerState) -> Self {
+        MemoryStats {
+            peak_allocated: allocator::peak_memory(),
+            total_allocated: allocator::used_memory(),
+            startup_allocated: allocator::startup_memory(),
+            replication_backlog: server.replication.lock().backlog_size(),
+            keys: store.key_count(),
+            hashtable_main: store.key_count() * KEY_OVERHEAD,
+            hashtable_expires: store.volatile_key_count() * size_of::<String>(),
+            resident: allocator::resident_memory().unwrap_or(0),
+        }
+    }
+
+    fn overhead(&self) -> usize {
+        self.startup_allocated + self.replication_backlog + self.hashtable_main + self.hashtable_expires
+    }
+
+    fn dataset(&self) -> usize {
+        self.total_allocated.saturating_sub(self.overhead())
+    }
+
+    /// Memory used on top of what the server needed at startup.
+    fn net_usage(&self) -> usize {
+        self.total_allocated.saturating_sub(self.startup_allocated).max(1)
+    }
+
+    fn fragmentation(&self) -> f64 {
+        self.resident as f64 / self.total_allocated.max(1) as f64
+    }
+}
+
+fn stats_reply(stats: &MemoryStats) -> Value {
+    let integer = |name: &str, value: usize| (Value::bulk(name), Value::Integer(value as i64));
+    // Percentages and ratios are reported with two decimals.
+    let float = |name: &str, value: f64| (Value::bulk(name), Value::Double((value * 100.0).round() / 100.0));
+
+    let mut fields: Vec<(Value, Value)> = vec![
+        integer("peak.allocated", stats.peak_allocated),
+        integer("total.allocated", stats.total_allocated),
+        integer("startup.allocated", stats.startup_allocated),
+        integer("replication.backlog", stats.replication_backlog),
+    ];
+    if stats.keys > 0 {
+        fields.push((Value::bulk("db.0"), Value::Map(vec![
+            integer("overhead.hashtable.main", stats.hashtable_main),
+            integer("overhead.hashtable.expires", stats.hashtable_expires),
+        ])));
+    }
+    fields.extend([
+        integer("overhead.total", stats.overhead()),
+        integer("keys.count", stats.keys),
+        integer("keys.bytes-per-key", stats.net_usage() / stats.keys.max(1)),
+        integer("dataset.bytes", stats.dataset()),
+        float("dataset.percentage", stats.dataset() as f64 * 100.0 / stats.net_usage() as f64),
+        float("peak.percentage", stats.total_allocated as f64 * 100.0 / stats.peak_allocated.max(1) as f64),
+        integer("allocator.allocated", stats.total_allocated),
+        integer("allocator.resident", stats.resident),
+        float("fragmentation", stats.fragmentation()),
+    ]);
+
+    Value::Map(fields)
+}
+
+/// Explains what looks wrong in the memory usage, in the words of Redis' own report.
+fn doctor(stats: &MemoryStats) -> String {
+    if stats.total_allocated < DOCTOR_MINIMUM_MEMORY {
+        return "Hi Sam, this instance is empty or is using very little memory, my issues detector can't be used in \
+            these conditions. Please, leave for your mission on Earth and fill it with some data. The new Sam and I \
+            will be back to our programming as soon as I finished rebooting.\n".to_string();
+    }
+
+    let mut issues: Vec<&str> = Vec::new();
+    if stats.peak_allocated as f64 / stats.total_allocated as f64 > 1.5 {
+        issues.push(" * Peak memory: In the past this instance used more than 150% the memory that is currently \
+            using. The allocator is normally not able to release memory after a peak, so you can expect to see a big \
+            fragmentation ratio, however this is actually harmless and is only due to the memory peak, and if the \
+            Redis instance Resident Set Size (RSS) is currently bigger than expected, the memory will be used as \
+            soon as you fill the Redis instance with more data.");
+    }
+    if stats.fragmentation() > 1.4 {
+        issues.push(" * High total RSS: This instance has a memory fragmentation and RSS overhead greater than 1.4 \
+            (this means that the Resident Set Size of the Redis process is much larger than the sum of the logical \
+            allocations Redis performed). This problem is usually due either to a large peak memory (check if there \
+            is a peak memory entry above in the report) or may result from a workload that causes the allocator to \
+            fragment memory a lot.");
+    }
+
+    if issues.is_empty() {
+        return "Hi Sam, I can't find any memory issue in your instance. I can only account for what occurs on this \
+            base.\n".to_string();
+    }
+
+    let mut report: String = String::from("Sam, I detected a few issues in this Redis instance memory implants:\n\n");
+    for issue in issues {
+        writeln!(report, "{}\n", issue).unwrap();
+    }
+    report.push_str("I'm here to keep you safe, Sam. I want to help you.\n");
+    report
+}
@@ -0,0 +1,225 @@
+use std::io::{BufRead, BufReader, Error, ErrorKind, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::{Duration, SystemTime};
+use crate::command::{encode_command, Command, Reply};
+use crate::key_value_store::{KeyValueStore, KeyValueStoreEntry};
+use crate::notify::KeyspaceEvents;
+use crate::rdb;
+use crate::reply::ReplyWriter;
+use crate::server::ServerState;
+
+/// Used when `MIGRATE` is given a timeout of zero, in milliseconds.
+const DEFAULT_TIMEOUT: u64 = 1000;
+
+pub struct MigrateRequest {
+    host: String,
+    port: u16,
+    keys: Vec<String>,
+    database: u64,
+    timeout: Duration,
+    copy: bool,
+    replace: bool,
+    /// Optional username and the password sent with `AUTH` before restoring the keys.
+    auth: Option<(Option<String>, String)>,
+}
+
+impl Command<'_> for MigrateRequest {
+    fn new(arguments: &[&str]) -> Result<Self, Error> {
+        if arguments.len() < 5 {
+            return Err(Error::new(ErrorKind::InvalidInput, "Expected at least five arguments"));
+        }
+
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidInput, message.to_string());
+        let port: u16 = arguments[1].parse().map_err(|_| invalid("Invalid target port"))?;
+        let database: u64 = arguments[3].parse().map_err(|_| invalid("Invalid destination database"))?;
+        let timeout: u64 = arguments[4].parse().map_err(|_| invalid("Invalid timeout"))?;
+
+        let mut request: MigrateRequest = MigrateRequest {
+            host: String::from(arguments[0]),
+            port,
+            keys: vec![String::from(arguments[2])],
+            database,
+            timeout: Duration::from_millis(if timeout == 0 { DEFAULT_TIMEOUT } else { timeout }),
+            copy: false,
+            replace: false,
+            auth: None,
+        };
+
+        let mut options = arguments[5..].iter();
+        while let Some(option) = options.next() {
+            match option.to_ascii_lowercase().as_str() {
+                "copy" => request.copy = true,
+                "replace" => request.replace = true,
+                "auth" => {
+                    let password: &&str = options.next().ok_or_else(|| invalid("syntax error"))?;
+                    request.auth = Some((None, String::from(*password)));
+                }
+                "auth2" => {
+                    let username: &&str = options.next().ok_or_else(|| invalid("syntax error"))?;
+                    let password: &&str = options.next().ok_or_else(|| invalid("syntax error"))?;
+                    request.auth = Some((Some(String::from(*username)), String::from(*password)));
+                }
+                "keys" => {
+                    if !arguments[2].is_empty() {
+                        return Err(invalid(
+                            "When using MIGRATE KEYS option, the key argument must be set to the empty string"));
+                    }
+                    request.keys = options.by_ref().map(|key| String::from(*key)).collect();
+                }
+                _ => return Err(invalid("syntax error")),
+            }
+        }
+
+        if request.keys.is_empty() || request.keys.iter().any(|key| key.is_empty()) {
+            return Err(invalid("syntax error"));
+        }
+
+        Ok(request)
+    }
+
+    fn execute(
+        self,
+        store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        // Replicas get the deletion of the keys moved away, if any, rather than the command.
+        server.prevent_propagation();
+
+        let now: SystemTime = SystemTime::now();
+        let dumped: Vec<DumpedKey> = self.keys.iter()
+            .filter_map(|key| {
+                let entry: &dyn KeyValueStoreEntry = store.get(key)?;
+                let ttl: u64 = match entry.get_expiry() {
+                    Some(expiry) => expiry.duration_since(now).ok()?.as_millis().max(1) as u64,
+                    None => 0,
+                };
+                Some(DumpedKey { key, ttl, payload: rdb::dump(entry) })
+            })
+            .collect();
+
+        if dumped.is_empty() {
+            reply.simple("NOKEY");
+            return Reply::Immediate;
+        }
+
+        let asking: bool = server.cluster.is_some();
+        let results: Vec<Result<(), String>> = match tokio::task::block_in_place(|| self.transfer(&dumped, asking)) {
+            Ok(results) => results,
+            Err(message) => {
+                reply.error(&message);
+                return Reply::Immediate;
+            }
+        };
+
+        let mut first_error: Option<String> = None;
+        let mut migrated: Vec<&str> = Vec::new();
+        for (key, result) in dumped.iter().zip(results) {
+            match result {
+                Ok(()) => migrated.push(key.key),
+                Err(message) => {
+                    first_error.get_or_insert(message);
+                }
+            }
+        }
+
+        if !self.copy && !migrated.is_empty() {
+            migrated.iter().for_each(|key| {
+                store.remove(key);
+                server.notify_keyspace_event(KeyspaceEvents::GENERIC, "del", key.as_bytes());
+            });
+
+            let mut deletion: Vec<&[u8]> = vec![b"DEL"];
+            deletion.extend(migrated.iter().map(|key| key.as_bytes()));
+            server.also_propagate(&deletion);
+        }
+
+        match first_error {
+            Some(message) => reply.error(&message),
+            None => reply.ok(),
+        }
+        Reply::Immediate
+    }
+}
+
+/// Positions of the keys of a `MIGRATE` call: the key argument, or everything after `KEYS`
+/// when it is empty.
+pub fn key_positions(arguments: &[Vec<u8>]) -> Vec<usize> {
+    match arguments.get(3) {
+        Some(key) if key.is_empty() => arguments.iter()
+            .position(|argument| argument.eq_ignore_ascii_case(b"keys"))
+            .map_or(Vec::new(), |keys| (keys + 1..arguments.len()).collect()),
+        Some(_) => vec![3],
+        None => Vec::new(),
+    }
+}
+
+struct DumpedKey<'a> {
+    key: &'a str,
+    ttl: u64,
+    payload: Vec<u8>,
+}
+
+impl MigrateRequest {
+    /// Sends the dumped keys to the target and returns, for each of them, whether it was
+    /// restored. Like Redis, this blocks the data manager until the target answers or the
+    /// timeout expires, so the keys cannot change while they are in flight.
+    fn transfer(&self, dumped: &[DumpedKey], asking: bool) -> Result<Vec<Result<(), String>>, String> {
+        const CONNECT_ERROR: &str = "IOERR error or timeout connecting to the client";
+
+        let address: SocketAddr = (self.host.as_str(), self.port).to_socket_addrs().ok()
+            .and_then(|mut addresses| addresses.next())
+            .ok_or(CONNECT_ERROR)?;
+        let stream: TcpStream = TcpStream::connect_timeout(&address, self.timeout).map_err(|_| CONNECT_ERROR)?;
+        stream.set_read_timeout(Some(self.timeout)).map_err(|_| CONNECT_ERROR)?;
+        stream.set_write_timeout(Some(self.timeout)).map_err(|_| CONNECT_ERROR)?;
+
+        let mut commands: Vec<u8> = Vec::new();
+        let mut preamble: usize = 0;
+        match &self.auth {
+            Some((Some(username), password)) => {
+                commands.extend(encode_command(&[b"AUTH", username.as_bytes(), password.as_bytes()]));
+                preamble += 1;
+            }
+            Some((None, password)) => {
+                commands.extend(encode_command(&[b"AUTH", password.as_bytes()]));
+                preamble += 1;
+            }
+            None => {}
+        }
+        if self.database != 0 {
+            commands.extend(encode_command(&[b"SELECT", self.database.to_string().as_bytes()]));
+            preamble += 1;
+        }
+
+        let restore: &[u8] = if asking { b"RESTORE-ASKING" } else { b"RESTORE" };
+        for key in dumped {
+            let ttl: String = key.ttl.to_string();
+            let mut arguments: Vec<&[u8]> = vec![restore, key.key.as_bytes(), ttl.as_bytes(), &key.payload];
+            if self.replace {
+                arguments.push(b"REPLACE");
+            }
+            commands.extend(encode_command(&arguments));
+        }
+
+        (&stream).write_all(&commands).map_err(|_| "IOERR error or timeout writing to target instance")?;
+
+        let mut reader: BufReader<&TcpStream> = BufReader::new(&stream);
+        let mut read_reply = || -> Result<Result<(), String>, String> {
+            let mut line: String = String::new();
+            match reader.read_line(&mut line) {
+                Ok(length) if length > 0 => {}
+                _ => return Err("IOERR error or timeout reading to target instance".to_string()),
+            }
+            match line.trim_end().strip_prefix('-') {
+                Some(error) => Ok(Err(format!("ERR Target instance replied with error: {}", error))),
+                None => Ok(Ok(())),
+            }
+        };
+
+        for _ in 0..preamble {
+            read_reply()??;
+        }
+        dumped.iter().map(|_| read_reply()).collect()
+    }
+}
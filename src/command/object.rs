@@ -0,0 +1,69 @@
+use std::io::{Error, ErrorKind};
+use std::time::SystemTime;
+use crate::command::{Command, Reply};
+use crate::key_value_store::{AccessInfo, KeyValueStore, KeyValueStoreEntry};
+use crate::reply::ReplyWriter;
+use crate::server::ServerState;
+
+enum ObjectSubcommand {
+    Encoding,
+    RefCount,
+    IdleTime,
+    Freq,
+}
+
+pub struct ObjectRequest {
+    subcommand: ObjectSubcommand,
+    key: String,
+}
+
+impl Command<'_> for ObjectRequest {
+    fn new(arguments: &[&str]) -> Result<Self, Error> {
+        if arguments.len() != 2 {
+            return Err(Error::new(ErrorKind::InvalidInput, "Expected a subcommand and a key"));
+        }
+
+        let subcommand: ObjectSubcommand = match arguments[0].to_ascii_lowercase().as_str() {
+            "encoding" => ObjectSubcommand::Encoding,
+            "refcount" => ObjectSubcommand::RefCount,
+            "idletime" => ObjectSubcommand::IdleTime,
+            "freq" => ObjectSubcommand::Freq,
+            _ => return Err(Error::new(ErrorKind::InvalidInput, "Unknown OBJECT subcommand")),
+        };
+
+        Ok(ObjectRequest { subcommand, key: String::from(arguments[1]) })
+    }
+
+    fn execute(
+        self,
+        store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        // Inspecting a key must not count as an access to it.
+        let now: SystemTime = SystemTime::now();
+        let (entry, access): (&dyn KeyValueStoreEntry, &AccessInfo) = match store.peek(&self.key) {
+            Some((entry, access)) if !entry.get_expiry().is_some_and(|expiry| expiry < now) => (entry, access),
+            _ => {
+                reply.null();
+                return Reply::Immediate;
+            }
+        };
+
+        let lfu: bool = server.config.maxmemory_policy.is_lfu();
+        match self.subcommand {
+            ObjectSubcommand::Encoding => reply.bulk(entry.encoding().as_bytes()),
+            // Values are never shared between keys.
+            ObjectSubcommand::RefCount => reply.integer(1),
+            ObjectSubcommand::IdleTime if lfu => reply.error(
+                "ERR An LFU maxmemory policy is selected, idle time not tracked. \
+                 Please note that when switching between policies at runtime LRU and LFU data will take some time to adjust."),
+            ObjectSubcommand::IdleTime => reply.integer(access.idle_time().as_secs() as i64),
+            ObjectSubcommand::Freq if lfu => reply.integer(access.frequency(server.config.lfu_decay_time) as i64),
+            ObjectSubcommand::Freq => reply.error(
+                "ERR An LFU maxmemory policy is not selected, access frequency not tracked. \
+                 Please note that when switching between policies at runtime LRU and LFU data will take some time to adjust."),
+        }
+        Reply::Immediate
+    }
+}
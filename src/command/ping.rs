@@ -1,27 +1,39 @@
 use std::io::{Error, ErrorKind};
-use crate::command::{DataRequester, CommandFactory, CommandRunner, Reply};
+use crate::command::{Command, Reply};
 use crate::key_value_store::KeyValueStore;
+use crate::reply::{Protocol, ReplyWriter};
+use crate::server::ServerState;
 
-pub struct PingCommand {}
+pub struct PingCommand<'a> {
+    message: Option<&'a str>,
+}
 
-impl CommandFactory for PingCommand {
-    fn new(arguments: &[&str]) -> Result<Box<Self>, Error> {
-        if arguments.len() != 0 {
-            return Err(Error::new(ErrorKind::InvalidInput, "Expected no arguments"));
+impl<'a> Command<'a> for PingCommand<'a> {
+    fn new(arguments: &[&'a str]) -> Result<Self, Error> {
+        match arguments {
+            [] => Ok(PingCommand { message: None }),
+            [message] => Ok(PingCommand { message: Some(message) }),
+            _ => Err(Error::new(ErrorKind::InvalidInput, "wrong number of arguments for 'ping' command")),
         }
-
-        Ok(Box::new(PingCommand {}))
     }
-}
 
-impl DataRequester for PingCommand {
-    fn request(self: Box<Self>, _store: &mut Box<dyn KeyValueStore>) -> Box<dyn CommandRunner> {
-        self
+    fn execute(
+        self,
+        _store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        // A subscribed RESP2 connection reads everything as messages, so PING replies with one.
+        if server.session.subscriptions > 0 && reply.protocol() == Protocol::Resp2 {
+            reply.array(2);
+            reply.bulk(b"pong");
+            reply.bulk(self.message.unwrap_or_default().as_bytes());
+            return Reply::Immediate;
+        }
+        match self.message {
+            Some(message) => reply.bulk(message.as_bytes()),
+            None => reply.simple("PONG"),
+        }
+        Reply::Immediate
     }
 }
-
-impl CommandRunner for PingCommand {
-    fn run(self: Box<Self>) -> Reply {
-        Reply::Immediate(b"+PONG\r\n".to_vec())
-    }
-}
\ No newline at end of file
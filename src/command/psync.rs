@@ -0,0 +1,64 @@
+use std::io::{Error, ErrorKind};
+use std::sync::MutexGuard;
+use crate::command::{Command, Reply};
+use crate::key_value_store::KeyValueStore;
+use crate::rdb;
+use crate::replication::{ReplicationState, SyncKind};
+use crate::reply::ReplyWriter;
+use crate::server::ServerState;
+
+pub struct PSyncRequest {
+    replid: String,
+    offset: i64,
+}
+
+impl Command<'_> for PSyncRequest {
+    fn new(arguments: &[&str]) -> Result<Self, Error> {
+        if arguments.len() != 2 {
+            return Err(Error::new(ErrorKind::InvalidInput, "Expected two arguments"));
+        }
+
+        Ok(PSyncRequest {
+            replid: String::from(arguments[0]),
+            offset: arguments[1].parse().map_err(|_| Error::new(
+                ErrorKind::InvalidInput, "Offset must be an integer"))?,
+        })
+    }
+
+    fn execute(
+        self,
+        store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        let mut replication: MutexGuard<'_, ReplicationState> = server.replication.lock();
+
+        match replication.try_partial_resync(&self.replid, self.offset) {
+            SyncKind::Continue(missing) => {
+                server.stats.sync_partial_ok += 1;
+                reply.simple(&format!("CONTINUE {}", replication.replid()));
+                reply.raw(&missing);
+            }
+            SyncKind::Full => {
+                server.stats.sync_full += 1;
+                // A replica asking for a specific offset wanted to continue but couldn't.
+                if self.replid != "?" {
+                    server.stats.sync_partial_err += 1;
+                }
+                let snapshot: Vec<u8> = rdb::serialize(store.as_ref(), &server.scripting.lock().library_codes());
+                reply.simple(&format!("FULLRESYNC {} {}", replication.replid(), replication.master_repl_offset()));
+                // The snapshot is sent like a bulk string without the trailing CRLF.
+                reply.raw(format!("${}\r\n", snapshot.len()).as_bytes());
+                reply.raw(&snapshot);
+            }
+        }
+
+        let client_id: u64 = server.current_client.unwrap_or_default();
+        let address: (String, u16) = server.clients.get(&client_id)
+            .and_then(|client| client.address.map(|address| {
+                (address.ip().to_string(), client.listening_port.unwrap_or(address.port()))
+            }))
+            .unwrap_or_default();
+        Reply::Stream(replication.attach_replica(client_id, address))
+    }
+}
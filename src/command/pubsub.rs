@@ -0,0 +1,181 @@
+use std::io::{Error, ErrorKind};
+use std::sync::MutexGuard;
+use crate::command::{Command, CommandSpec, Reply};
+use crate::glob;
+use crate::key_value_store::KeyValueStore;
+use crate::pubsub::PubSub;
+use crate::reply::ReplyWriter;
+use crate::server::ServerState;
+
+/// The only commands a RESP2 connection may run once it subscribed to something, since
+/// messages could not be told apart from replies otherwise.
+pub fn allowed_while_subscribed(spec: &CommandSpec) -> bool {
+    matches!(spec.name, "subscribe" | "unsubscribe" | "psubscribe" | "punsubscribe" | "ping" | "quit" | "reset")
+}
+
+/// `SUBSCRIBE`, or `PSUBSCRIBE` when `PATTERN` is set.
+pub struct SubscriptionRequest<'a, const PATTERN: bool> {
+    channels: Vec<&'a str>,
+}
+
+pub type SubscribeRequest<'a> = SubscriptionRequest<'a, false>;
+pub type PSubscribeRequest<'a> = SubscriptionRequest<'a, true>;
+
+impl<'a, const PATTERN: bool> Command<'a> for SubscriptionRequest<'a, PATTERN> {
+    fn new(arguments: &[&'a str]) -> Result<Self, Error> {
+        if arguments.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "Expected at least one channel"));
+        }
+        Ok(SubscriptionRequest { channels: arguments.to_vec() })
+    }
+
+    fn execute(
+        self,
+        _store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        let Some(connection) = server.session.connection.clone() else {
+            reply.error("ERR Only client connections can subscribe");
+            return Reply::Immediate;
+        };
+        let kind: &str = if PATTERN { "psubscribe" } else { "subscribe" };
+
+        let mut pubsub: MutexGuard<'_, PubSub> = server.pubsub.lock();
+        for channel in self.channels {
+            pubsub.subscribe(&connection, channel.as_bytes(), PATTERN);
+            write_confirmation(reply, kind, Some(channel.as_bytes()), pubsub.count(connection.id));
+        }
+        server.session.subscriptions = pubsub.count(connection.id);
+        Reply::Immediate
+    }
+}
+
+/// `UNSUBSCRIBE`, or `PUNSUBSCRIBE` when `PATTERN` is set. Without channels, unsubscribes
+/// from all of them.
+pub struct UnsubscriptionRequest<'a, const PATTERN: bool> {
+    channels: Vec<&'a str>,
+}
+
+pub type UnsubscribeRequest<'a> = UnsubscriptionRequest<'a, false>;
+pub type PUnsubscribeRequest<'a> = UnsubscriptionRequest<'a, true>;
+
+impl<'a, const PATTERN: bool> Command<'a> for UnsubscriptionRequest<'a, PATTERN> {
+    fn new(arguments: &[&'a str]) -> Result<Self, Error> {
+        Ok(UnsubscriptionRequest { channels: arguments.to_vec() })
+    }
+
+    fn execute(
+        self,
+        _store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        let id: u64 = server.session.connection.as_ref().map_or(0, |connection| connection.id);
+        let kind: &str = if PATTERN { "punsubscribe" } else { "unsubscribe" };
+
+        let mut pubsub: MutexGuard<'_, PubSub> = server.pubsub.lock();
+        let channels: Vec<Vec<u8>> = if self.channels.is_empty() {
+            pubsub.subscriptions(id, PATTERN)
+        } else {
+            self.channels.iter().map(|channel| channel.as_bytes().to_vec()).collect()
+        };
+        if channels.is_empty() {
+            write_confirmation(reply, kind, None, pubsub.count(id));
+        }
+        for channel in channels {
+            pubsub.unsubscribe(id, &channel, PATTERN);
+            write_confirmation(reply, kind, Some(&channel), pubsub.count(id));
+        }
+        server.session.subscriptions = pubsub.count(id);
+        Reply::Immediate
+    }
+}
+
+/// Confirms a subscription change with the number of subscriptions left, as a message
+/// rather than a reply, so it is read like the messages that follow.
+fn write_confirmation(reply: &mut ReplyWriter, kind: &str, channel: Option<&[u8]>, count: usize) {
+    reply.push(3);
+    reply.bulk(kind.as_bytes());
+    match channel {
+        Some(channel) => reply.bulk(channel),
+        None => reply.null(),
+    }
+    reply.integer(count as i64);
+}
+
+pub struct PublishRequest<'a> {
+    channel: &'a str,
+    message: &'a str,
+}
+
+impl<'a> Command<'a> for PublishRequest<'a> {
+    fn new(arguments: &[&'a str]) -> Result<Self, Error> {
+        match arguments {
+            [channel, message] => Ok(PublishRequest { channel, message }),
+            _ => Err(Error::new(ErrorKind::InvalidInput, "Expected a channel and a message")),
+        }
+    }
+
+    fn execute(
+        self,
+        _store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        let receivers: usize = server.pubsub.lock().publish(self.channel.as_bytes(), self.message.as_bytes());
+        reply.integer(receivers as i64);
+        Reply::Immediate
+    }
+}
+
+pub enum PubSubRequest<'a> {
+    Channels(Option<&'a str>),
+    NumSub(Vec<&'a str>),
+    NumPat,
+}
+
+impl<'a> Command<'a> for PubSubRequest<'a> {
+    fn new(arguments: &[&'a str]) -> Result<Self, Error> {
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidInput, message.to_string());
+        let subcommand: &str = arguments.first().ok_or_else(|| invalid("Expected a subcommand"))?;
+        let arguments: &[&'a str] = &arguments[1..];
+
+        let request: PubSubRequest = match (subcommand.to_ascii_lowercase().as_str(), arguments) {
+            ("channels", pattern) if pattern.len() <= 1 => PubSubRequest::Channels(pattern.first().copied()),
+            ("numsub", channels) => PubSubRequest::NumSub(channels.to_vec()),
+            ("numpat", []) => PubSubRequest::NumPat,
+            _ => return Err(invalid(&format!(
+                "unknown subcommand or wrong number of arguments for '{}'. Try PUBSUB HELP.", subcommand))),
+        };
+
+        Ok(request)
+    }
+
+    fn execute(
+        self,
+        _store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        let pubsub: MutexGuard<'_, PubSub> = server.pubsub.lock();
+        match self {
+            PubSubRequest::Channels(pattern) => {
+                let channels: Vec<&[u8]> = pubsub.channels()
+                    .filter(|channel| pattern.is_none_or(|pattern| glob::matches(pattern.as_bytes(), channel, false)))
+                    .collect();
+                reply.array(channels.len());
+                channels.into_iter().for_each(|channel| reply.bulk(channel));
+            }
+            PubSubRequest::NumSub(channels) => {
+                reply.map(channels.len());
+                for channel in channels {
+                    reply.bulk(channel.as_bytes());
+                    reply.integer(pubsub.subscriber_count(channel.as_bytes()) as i64);
+                }
+            }
+            PubSubRequest::NumPat => reply.integer(pubsub.pattern_count() as i64),
+        }
+        Reply::Immediate
+    }
+}
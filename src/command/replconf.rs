@@ -0,0 +1,76 @@
+use std::io::{Error, ErrorKind};
+use crate::command::{Command, Reply};
+use crate::key_value_store::KeyValueStore;
+use crate::reply::ReplyWriter;
+use crate::server::{ClientInfo, ServerState};
+
+pub enum ReplConfRequest {
+    /// `listening-port <port>`, the port a replica serves clients on, reported by `INFO`.
+    ListeningPort(u16),
+    /// Other handshake options such as `capa`, which are only acknowledged.
+    Configure,
+    /// `ACK <offset> [FACK <aof offset>]`, sent by a replica over its replication link.
+    Ack { offset: u64, aof_offset: Option<u64> },
+    /// `GETACK *`, sent by the master to make the replica report its offset right away.
+    GetAck,
+}
+
+fn parse_offset(argument: &str) -> Result<u64, Error> {
+    argument.parse().map_err(|_| Error::new(ErrorKind::InvalidInput, "Offset must be an unsigned integer"))
+}
+
+impl Command<'_> for ReplConfRequest {
+    fn new(arguments: &[&str]) -> Result<Self, Error> {
+        if arguments.is_empty() || !arguments.len().is_multiple_of(2) {
+            return Err(Error::new(ErrorKind::InvalidInput, "Expected option-value pairs"));
+        }
+
+        let request: ReplConfRequest = match arguments[0].to_ascii_lowercase().as_str() {
+            "ack" => {
+                let aof_offset: Option<u64> = match arguments.get(2..4) {
+                    Some([option, value]) if option.eq_ignore_ascii_case("fack") => Some(parse_offset(value)?),
+                    _ => None,
+                };
+                ReplConfRequest::Ack { offset: parse_offset(arguments[1])?, aof_offset }
+            }
+            "getack" => ReplConfRequest::GetAck,
+            "listening-port" => ReplConfRequest::ListeningPort(arguments[1]
+                .parse()
+                .map_err(|_| Error::new(ErrorKind::InvalidInput, "Invalid listening port"))?),
+            _ => ReplConfRequest::Configure,
+        };
+
+        Ok(request)
+    }
+
+    fn execute(
+        self,
+        _store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        match self {
+            ReplConfRequest::ListeningPort(port) => {
+                let client: Option<&mut ClientInfo> = server.current_client.and_then(|id| server.clients.get_mut(&id));
+                if let Some(client) = client {
+                    client.listening_port = Some(port);
+                }
+                reply.ok();
+            }
+            ReplConfRequest::Configure => reply.ok(),
+            ReplConfRequest::Ack { offset, aof_offset } => {
+                if let Some(client_id) = server.current_client {
+                    server.replication.lock().acknowledge(client_id, offset, aof_offset);
+                }
+            }
+            ReplConfRequest::GetAck => {
+                let offset: String = server.replication.lock().master_repl_offset().to_string();
+                reply.array(3);
+                reply.bulk(b"REPLCONF");
+                reply.bulk(b"ACK");
+                reply.bulk(offset.as_bytes());
+            }
+        }
+        Reply::Immediate
+    }
+}
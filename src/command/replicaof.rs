@@ -0,0 +1,43 @@
+use std::io::{Error, ErrorKind};
+use crate::command::{Command, Reply};
+use crate::key_value_store::KeyValueStore;
+use crate::reply::ReplyWriter;
+use crate::server::ServerState;
+
+pub struct ReplicaOfRequest {
+    master: Option<(String, u16)>,
+}
+
+impl Command<'_> for ReplicaOfRequest {
+    fn new(arguments: &[&str]) -> Result<Self, Error> {
+        if arguments.len() != 2 {
+            return Err(Error::new(ErrorKind::InvalidInput, "Expected two arguments"));
+        }
+
+        if arguments[0].eq_ignore_ascii_case("no") && arguments[1].eq_ignore_ascii_case("one") {
+            return Ok(ReplicaOfRequest { master: None });
+        }
+
+        let port: u16 = arguments[1]
+            .parse()
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "Invalid master port"))?;
+
+        Ok(ReplicaOfRequest { master: Some((String::from(arguments[0]), port)) })
+    }
+
+    fn execute(
+        self,
+        _store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        server.config.replica_of = self.master.clone();
+        match self.master {
+            Some((host, port)) => server.replication.lock().replicate_from(host, port),
+            None => server.replication.lock().promote(),
+        }
+
+        reply.ok();
+        Reply::Immediate
+    }
+}
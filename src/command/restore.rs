@@ -0,0 +1,142 @@
+use std::io::{Error, ErrorKind};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::command::Reply;
+use crate::key_value_store::{KeyValueStore, KeyValueStoreEntry};
+use crate::notify::KeyspaceEvents;
+use crate::rdb;
+use crate::reply::ReplyWriter;
+use crate::server::ServerState;
+
+pub struct RestoreRequest {
+    key: String,
+    ttl: u64,
+    payload: Vec<u8>,
+    replace: bool,
+    /// Whether `ttl` is an absolute unix time in milliseconds rather than a relative one.
+    absolute_ttl: bool,
+    idle_time: Option<u64>,
+    frequency: Option<u8>,
+}
+
+/// Runs `RESTORE` and `RESTORE-ASKING`, the latter being sent by `MIGRATE` to a node
+/// importing the key's slot. They take raw arguments since the payload is binary.
+pub fn handle(
+    arguments: &[Vec<u8>],
+    store: &mut Box<dyn KeyValueStore>,
+    server: &mut ServerState,
+    reply: &mut ReplyWriter
+) -> Reply {
+    match RestoreRequest::from_raw(&arguments[1..]) {
+        Ok(request) => request.execute(store, server, reply),
+        Err(e) => {
+            reply.error(&format!("ERR {}", e));
+            Reply::Immediate
+        }
+    }
+}
+
+impl RestoreRequest {
+    /// Parses `RESTORE key ttl payload [REPLACE] [ABSTTL] [IDLETIME seconds] [FREQ frequency]`
+    /// from raw arguments, since the payload is binary.
+    fn from_raw(arguments: &[Vec<u8>]) -> Result<Self, Error> {
+        if arguments.len() < 3 {
+            return Err(Error::new(ErrorKind::InvalidInput, "Expected at least three arguments"));
+        }
+
+        let text = |argument: &[u8]| String::from_utf8(argument.to_vec())
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "Argument is not valid UTF-8"));
+        let ttl: u64 = text(&arguments[1])?
+            .parse()
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "Invalid TTL value, must be >= 0"))?;
+
+        let syntax_error = || Error::new(ErrorKind::InvalidInput, "syntax error");
+        let mut replace: bool = false;
+        let mut absolute_ttl: bool = false;
+        let mut idle_time: Option<u64> = None;
+        let mut frequency: Option<u8> = None;
+        let mut options = arguments[3..].iter();
+        while let Some(option) = options.next() {
+            match text(option)?.to_ascii_lowercase().as_str() {
+                "replace" => replace = true,
+                "absttl" => absolute_ttl = true,
+                "idletime" if frequency.is_none() => {
+                    let seconds: u64 = text(options.next().ok_or_else(syntax_error)?)?
+                        .parse()
+                        .map_err(|_| Error::new(ErrorKind::InvalidInput, "Invalid IDLETIME value, must be >= 0"))?;
+                    idle_time = Some(seconds);
+                }
+                "freq" if idle_time.is_none() => {
+                    let count: u8 = text(options.next().ok_or_else(syntax_error)?)?
+                        .parse()
+                        .map_err(|_| Error::new(ErrorKind::InvalidInput, "Invalid FREQ value, must be >= 0 and <= 255"))?;
+                    frequency = Some(count);
+                }
+                _ => return Err(syntax_error()),
+            }
+        }
+
+        Ok(RestoreRequest {
+            key: text(&arguments[0])?,
+            ttl,
+            payload: arguments[2].clone(),
+            replace,
+            absolute_ttl,
+            idle_time,
+            frequency,
+        })
+    }
+
+    fn execute(
+        self,
+        store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        let now: SystemTime = SystemTime::now();
+        let exists: bool = store.get(&self.key)
+            .is_some_and(|entry| !entry.get_expiry().is_some_and(|expiry| expiry < now));
+        if exists && !self.replace {
+            reply.error("BUSYKEY Target key name already exists.");
+            return Reply::Immediate;
+        }
+
+        let expiry: Option<SystemTime> = match (self.ttl, self.absolute_ttl) {
+            (0, _) => None,
+            (ttl, true) => Some(UNIX_EPOCH + Duration::from_millis(ttl)),
+            (ttl, false) => Some(now + Duration::from_millis(ttl)),
+        };
+        let entry: Box<dyn KeyValueStoreEntry> = match rdb::restore(&self.payload, expiry) {
+            Ok(entry) => entry,
+            Err(err) => {
+                reply.error(&format!("ERR {}", err));
+                return Reply::Immediate;
+            }
+        };
+
+        // A key restored with an absolute TTL in the past is accepted but never stored.
+        if expiry.is_some_and(|expiry| expiry <= now) {
+            if store.remove(&self.key).is_some() {
+                server.notify_keyspace_event(KeyspaceEvents::GENERIC, "del", self.key.as_bytes());
+            }
+            reply.ok();
+            return Reply::Immediate;
+        }
+
+        store.insert(self.key.clone(), entry);
+        if !exists {
+            server.notify_keyspace_event(KeyspaceEvents::NEW, "new", self.key.as_bytes());
+        }
+        server.notify_keyspace_event(KeyspaceEvents::GENERIC, "restore", self.key.as_bytes());
+        // Like Redis, each option only applies when the maxmemory policy tracks what it sets.
+        if let Some((_, access)) = store.peek(&self.key) {
+            let lfu: bool = server.config.maxmemory_policy.is_lfu();
+            match (self.idle_time, self.frequency) {
+                (Some(seconds), _) if !lfu => access.set_idle_time(Duration::from_secs(seconds)),
+                (_, Some(count)) if lfu => access.set_frequency(count),
+                _ => {}
+            }
+        }
+        reply.ok();
+        Reply::Immediate
+    }
+}
@@ -1,6 +1,9 @@
 use std::io::{Error, ErrorKind};
-use crate::command::{DataRequester, CommandFactory, CommandRunner, Reply};
+use crate::command::{blpop, Command, Reply, WRONG_TYPE_ERROR};
 use crate::key_value_store::{KeyValueStore, KeyValueStoreEntry, KeyValueStoreListEntry};
+use crate::notify::KeyspaceEvents;
+use crate::reply::ReplyWriter;
+use crate::server::ServerState;
 
 fn _push(store: &mut Box<dyn KeyValueStore>, key: String, value: String) -> Result<usize, &'static str> {
     if let Some(entry) = store.get_mut(&key) {
@@ -29,42 +32,38 @@ pub struct RPushRequest {
     values: Vec<String>,
 }
 
-struct RPushResult {
-    length: Result<usize, &'static str>,
-}
-
-impl CommandFactory for RPushRequest {
-    fn new(arguments: &[&str]) -> Result<Box<Self>, Error> {
+impl Command<'_> for RPushRequest {
+    fn new(arguments: &[&str]) -> Result<Self, Error> {
         if arguments.len() < 2 {
             return Err(Error::new(ErrorKind::InvalidInput, "Expected at least two arguments"));
         }
 
-        Ok(Box::new(
+        Ok(
             RPushRequest {
                 key: String::from(arguments[0]),
                 values: arguments[1..].iter().map(|s| s.to_string()).collect(),
-            }))
-    }
-}
-
-impl RPushResult {
-    fn new(length: Result<usize, &'static str>) -> Self {
-        RPushResult { length }
+            })
     }
-}
 
-impl DataRequester for RPushRequest {
-    fn request(mut self: Box<Self>, store: &mut Box<dyn KeyValueStore>) -> Box<dyn CommandRunner> {
-        Box::new(RPushResult::new(append(store, self.key, &mut self.values)))
-    }
-}
-
-impl CommandRunner for RPushResult {
-    fn run(self: Box<Self>) -> Reply {
-        let reply: Vec<u8> = self.length.map_or_else(
-            |_| "$-1\r\n".as_bytes().to_vec(), 
-            |size| format!(":{}\r\n", size).into_bytes());
-        
-        Reply::Immediate(reply)
+    fn execute(
+        mut self,
+        store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        let key: String = self.key.clone();
+        let created: bool = store.get(&key).is_none();
+        match append(store, self.key, &mut self.values) {
+            Ok(size) => {
+                if created {
+                    server.notify_keyspace_event(KeyspaceEvents::NEW, "new", key.as_bytes());
+                }
+                server.notify_keyspace_event(KeyspaceEvents::LIST, "rpush", key.as_bytes());
+                blpop::serve_waiters(store, server, &key);
+                reply.integer(size as i64)
+            }
+            Err(_) => reply.error(WRONG_TYPE_ERROR),
+        }
+        Reply::Immediate
     }
 }
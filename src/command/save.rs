@@ -0,0 +1,68 @@
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+use crate::command::{Command, Reply};
+use crate::key_value_store::KeyValueStore;
+use crate::rdb;
+use crate::reply::ReplyWriter;
+use crate::server::ServerState;
+
+/// Numbers the temporary files of the saves, so that background saves running at the same
+/// time do not write to the same one.
+static SAVES: AtomicU64 = AtomicU64::new(0);
+
+/// `SAVE`, or `BGSAVE [SCHEDULE]` when `BACKGROUND` is set, which writes the file on another
+/// thread once the snapshot is taken.
+pub struct SnapshotRequest<const BACKGROUND: bool>;
+
+pub type SaveRequest = SnapshotRequest<false>;
+pub type BgSaveRequest = SnapshotRequest<true>;
+
+impl<const BACKGROUND: bool> Command<'_> for SnapshotRequest<BACKGROUND> {
+    fn new(arguments: &[&str]) -> Result<Self, Error> {
+        match arguments {
+            [] => Ok(SnapshotRequest),
+            // No save ever runs for long, so there is nothing to schedule after.
+            [option] if BACKGROUND && option.eq_ignore_ascii_case("schedule") => Ok(SnapshotRequest),
+            _ => Err(Error::new(ErrorKind::InvalidInput, "syntax error")),
+        }
+    }
+
+    fn execute(
+        self,
+        store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        let snapshot: Vec<u8> = rdb::serialize(store.as_ref(), &server.scripting.lock().library_codes());
+        let path: PathBuf = PathBuf::from(&server.config.dbfilename);
+        server.stats.dirty = 0;
+        server.stats.last_save_time = SystemTime::now();
+
+        if BACKGROUND {
+            tokio::task::spawn_blocking(move || {
+                if let Err(e) = write_snapshot(&path, &snapshot) {
+                    println!("could not save {}: {}", path.display(), e);
+                }
+            });
+            reply.simple("Background saving started");
+            return Reply::Immediate;
+        }
+
+        match write_snapshot(&path, &snapshot) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(&format!("ERR {}", e)),
+        }
+        Reply::Immediate
+    }
+}
+
+/// Writes `snapshot` to a temporary file moved over `path` once complete, so a crash while
+/// saving leaves the previous snapshot in place.
+fn write_snapshot(path: &Path, snapshot: &[u8]) -> Result<(), std::io::Error> {
+    let save: u64 = SAVES.fetch_add(1, Ordering::Relaxed);
+    let temporary: PathBuf = PathBuf::from(format!("temp-{}-{}.rdb", std::process::id(), save));
+    std::fs::write(&temporary, snapshot)?;
+    std::fs::rename(&temporary, path)
+}
@@ -0,0 +1,63 @@
+use std::io::{Error, ErrorKind};
+use crate::command::{Command, Reply};
+use crate::key_value_store::KeyValueStore;
+use crate::reply::ReplyWriter;
+use crate::server::ServerState;
+
+pub enum ScriptRequest<'a> {
+    Load(&'a str),
+    Exists(Vec<&'a str>),
+    Flush,
+    /// Only reaches the data manager when no script runs; connection tasks kill running
+    /// scripts themselves, since the data manager is busy with them.
+    Kill,
+}
+
+impl<'a> Command<'a> for ScriptRequest<'a> {
+    fn new(arguments: &[&'a str]) -> Result<Self, Error> {
+        let subcommand: String = arguments.first()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Expected a subcommand"))?
+            .to_ascii_lowercase();
+
+        let request: ScriptRequest = match (subcommand.as_str(), &arguments[1..]) {
+            ("load", [body]) => ScriptRequest::Load(body),
+            ("exists", shas) if !shas.is_empty() => ScriptRequest::Exists(shas.to_vec()),
+            ("flush", []) => ScriptRequest::Flush,
+            ("flush", [mode]) if mode.eq_ignore_ascii_case("sync") || mode.eq_ignore_ascii_case("async") => {
+                ScriptRequest::Flush
+            }
+            ("flush", _) => return Err(Error::new(ErrorKind::InvalidInput, "SCRIPT FLUSH only support SYNC|ASYNC option")),
+            ("kill", []) => ScriptRequest::Kill,
+            _ => return Err(Error::new(ErrorKind::InvalidInput, "Unknown SCRIPT subcommand or wrong number of arguments")),
+        };
+
+        Ok(request)
+    }
+
+    fn execute(
+        self,
+        _store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        match self {
+            ScriptRequest::Load(body) => match server.scripting.lock().load(body) {
+                Ok(sha) => reply.bulk(sha.as_bytes()),
+                Err(message) => reply.error(&message),
+            },
+            ScriptRequest::Exists(shas) => {
+                let scripting = server.scripting.lock();
+                reply.array(shas.len());
+                for sha in shas {
+                    reply.integer(scripting.exists(sha) as i64);
+                }
+            }
+            ScriptRequest::Flush => {
+                server.scripting.lock().flush();
+                reply.ok();
+            }
+            ScriptRequest::Kill => reply.error("NOTBUSY No scripts in execution right now."),
+        }
+        Reply::Immediate
+    }
+}
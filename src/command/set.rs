@@ -1,8 +1,11 @@
 use std::io::{Error, ErrorKind};
 use std::time::{Duration, SystemTime};
-use crate::command::{DataRequester, CommandFactory, CommandRunner, Reply};
+use crate::command::{Command, Reply};
 use crate::key_value_store::KeyValueStore;
 use crate::KeyValueStoreStringEntry;
+use crate::notify::KeyspaceEvents;
+use crate::reply::ReplyWriter;
+use crate::server::ServerState;
 
 pub struct SetCommandRequest {
     key: String,
@@ -10,55 +13,58 @@ pub struct SetCommandRequest {
     calculated_expiry: Option<SystemTime>,
 }
 
-struct SetCommandResponse {}
-
-impl CommandFactory for SetCommandRequest {
-    fn new(arguments: &[&str]) -> Result<Box<Self>, Error> {
+impl Command<'_> for SetCommandRequest {
+    fn new(arguments: &[&str]) -> Result<Self, Error> {
         if arguments.len() < 2 {
             return Err(Error::new(ErrorKind::InvalidInput, "Expected at least two arguments"));
         }
 
         if arguments.len() == 4 && arguments[2].eq_ignore_ascii_case("px") {
-            let expiry_time: Duration = Duration::from_millis(arguments[3].parse().unwrap());
+            let expiry_time: Duration = arguments[3].parse()
+                .map(Duration::from_millis)
+                .map_err(|_| Error::new(ErrorKind::InvalidInput, "value is not an integer or out of range"))?;
             let calculated_expiry: SystemTime = SystemTime::now() + expiry_time;
 
-            return Ok(Box::new(
+            return Ok(
                 SetCommandRequest {
                     key: String::from(arguments[0]),
                     value: String::from(arguments[1]),
                     calculated_expiry: Some(calculated_expiry),
-                }));
+                });
         }
 
-        Ok(Box::new(SetCommandRequest {
+        Ok(SetCommandRequest {
             key: String::from(arguments[0]),
             value: String::from(arguments[1]),
             calculated_expiry: None
-        }))
+        })
     }
-}
-
-impl SetCommandResponse {
-    fn new() -> Self {
-        SetCommandResponse {}
-    }
-}
 
-impl DataRequester for SetCommandRequest {
-    fn request(self: Box<Self>, store: &mut Box<dyn KeyValueStore>) -> Box<dyn CommandRunner> {
+    fn execute(
+        self,
+        store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        let now: SystemTime = SystemTime::now();
+        let existed: bool = store.get(&self.key)
+            .is_some_and(|entry| !entry.get_expiry().is_some_and(|expiry| expiry < now));
+        let expires: bool = self.calculated_expiry.is_some();
         store.insert(
-            self.key,
+            self.key.clone(),
             Box::new(KeyValueStoreStringEntry {
                 value: self.value,
                 expiry: self.calculated_expiry
             }));
 
-        Box::new(SetCommandResponse::new())
-    }
-}
-
-impl CommandRunner for SetCommandResponse {
-    fn run(self: Box<Self>) -> Reply {
-        Reply::Immediate("+OK\r\n".as_bytes().to_vec())
+        if !existed {
+            server.notify_keyspace_event(KeyspaceEvents::NEW, "new", self.key.as_bytes());
+        }
+        server.notify_keyspace_event(KeyspaceEvents::STRING, "set", self.key.as_bytes());
+        if expires {
+            server.notify_keyspace_event(KeyspaceEvents::GENERIC, "expire", self.key.as_bytes());
+        }
+        reply.ok();
+        Reply::Immediate
     }
 }
@@ -0,0 +1,129 @@
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+use tokio::sync::watch;
+use crate::client::{self, Unblock};
+use crate::command::{Command, Reply};
+use crate::key_value_store::KeyValueStore;
+use crate::replication::AckKind;
+use crate::reply::{ReplyWriter, Value};
+use crate::server::ServerState;
+
+pub struct WaitRequest {
+    replicas: usize,
+    timeout: Option<Duration>,
+}
+
+pub struct WaitAofRequest {
+    local: usize,
+    replicas: usize,
+    timeout: Option<Duration>,
+}
+
+fn parse_timeout(argument: &str) -> Result<Option<Duration>, Error> {
+    let timeout: u64 = argument
+        .parse()
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "Timeout must be a non-negative number of milliseconds"))?;
+
+    Ok(if timeout == 0 { None } else { Some(Duration::from_millis(timeout)) })
+}
+
+fn parse_count(argument: &str) -> Result<usize, Error> {
+    argument.parse().map_err(|_| Error::new(ErrorKind::InvalidInput, "Count must be an unsigned integer"))
+}
+
+impl Command<'_> for WaitRequest {
+    fn new(arguments: &[&str]) -> Result<Self, Error> {
+        if arguments.len() != 2 {
+            return Err(Error::new(ErrorKind::InvalidInput, "Expected two arguments"));
+        }
+
+        Ok(WaitRequest {
+            replicas: parse_count(arguments[0])?,
+            timeout: parse_timeout(arguments[1])?,
+        })
+    }
+
+    fn execute(
+        self,
+        _store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        if server.replication.lock().is_replica() {
+            reply.error("ERR WAIT cannot be used with replica instances.");
+            return Reply::Immediate;
+        }
+
+        let acked: Result<usize, watch::Receiver<usize>> =
+            server.replication.lock().wait_for_acks(AckKind::Replicated, self.replicas);
+        wait(AckKind::Replicated, self.replicas, self.timeout, acked, server.session.unblocked(), reply)
+    }
+}
+
+impl Command<'_> for WaitAofRequest {
+    fn new(arguments: &[&str]) -> Result<Self, Error> {
+        if arguments.len() != 3 {
+            return Err(Error::new(ErrorKind::InvalidInput, "Expected three arguments"));
+        }
+
+        Ok(WaitAofRequest {
+            local: parse_count(arguments[0])?,
+            replicas: parse_count(arguments[1])?,
+            timeout: parse_timeout(arguments[2])?,
+        })
+    }
+
+    fn execute(
+        self,
+        _store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) -> Reply {
+        if server.replication.lock().is_replica() {
+            reply.error("ERR WAITAOF cannot be used with replica instances.");
+            return Reply::Immediate;
+        }
+
+        if self.local > 0 {
+            reply.error("ERR WAITAOF cannot be used when numlocal is set but appendonly is disabled.");
+            return Reply::Immediate;
+        }
+
+        let acked: Result<usize, watch::Receiver<usize>> =
+            server.replication.lock().wait_for_acks(AckKind::Fsynced, self.replicas);
+        wait(AckKind::Fsynced, self.replicas, self.timeout, acked, server.session.unblocked(), reply)
+    }
+}
+
+fn response(kind: AckKind, acked: usize) -> Value {
+    match kind {
+        AckKind::Replicated => Value::Integer(acked as i64),
+        AckKind::Fsynced => Value::Array(vec![Value::Integer(0), Value::Integer(acked as i64)]),
+    }
+}
+
+/// Replies right away when enough replicas acknowledged already, or once `needed` have or
+/// the timeout expires.
+fn wait(
+    kind: AckKind,
+    needed: usize,
+    timeout: Option<Duration>,
+    acked: Result<usize, watch::Receiver<usize>>,
+    unblocked: Option<watch::Receiver<Unblock>>,
+    reply: &mut ReplyWriter
+) -> Reply {
+    match acked {
+        Ok(acked) => {
+            response(kind, acked).write(reply);
+            Reply::Immediate
+        }
+        Err(mut rx) => Reply::Deferred(Box::pin(async move {
+            let error: Option<Value> = tokio::select! {
+                _ = rx.wait_for(|acked| *acked >= needed) => None,
+                error = client::expired(timeout, unblocked) => error,
+            };
+            let acked: usize = *rx.borrow();
+            error.unwrap_or_else(|| response(kind, acked))
+        })),
+    }
+}
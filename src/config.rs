@@ -0,0 +1,872 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::{cluster, glob, replication};
+use crate::eviction::EvictionPolicy;
+use crate::key_value_store::AccessTracking;
+use crate::notify::KeyspaceEvents;
+use crate::tls::ClientAuth;
+
+/// Comment introducing the parameters `CONFIG REWRITE` had to append to the file.
+const REWRITE_SIGNATURE: &str = "# Generated by CONFIG REWRITE";
+/// Directives of a stock redis.conf for features this server does not have. They are
+/// accepted and ignored, so such a file loads as it is.
+const IGNORED_DIRECTIVES: &[&str] = &[
+    "protected-mode", "tcp-backlog", "timeout", "tcp-keepalive", "daemonize", "supervised", "pidfile", "loglevel",
+    "logfile", "databases", "always-show-logo", "set-proc-title", "proc-title-template", "locale-collate",
+    "stop-writes-on-bgsave-error", "rdbcompression", "rdbchecksum", "rdb-del-sync-files",
+    "replica-serve-stale-data", "slave-serve-stale-data", "replica-read-only", "slave-read-only",
+    "repl-diskless-sync", "repl-diskless-sync-delay", "repl-diskless-sync-max-replicas", "repl-diskless-load",
+    "repl-disable-tcp-nodelay", "replica-priority", "slave-priority", "lazyfree-lazy-eviction",
+    "lazyfree-lazy-expire", "lazyfree-lazy-server-del", "replica-lazy-flush", "slave-lazy-flush",
+    "lazyfree-lazy-user-del", "lazyfree-lazy-user-flush", "oom-score-adj", "oom-score-adj-values", "disable-thp",
+    "appendfilename", "appenddirname", "appendfsync", "no-appendfsync-on-rewrite", "auto-aof-rewrite-percentage",
+    "auto-aof-rewrite-min-size", "aof-load-truncated", "aof-use-rdb-preamble", "aof-timestamp-enabled",
+    "aof-rewrite-incremental-fsync", "rdb-save-incremental-fsync", "slowlog-log-slower-than", "slowlog-max-len",
+    "latency-monitor-threshold", "hash-max-listpack-entries", "hash-max-listpack-value",
+    "hash-max-ziplist-entries", "hash-max-ziplist-value", "set-max-intset-entries", "set-max-listpack-entries",
+    "set-max-listpack-value", "zset-max-listpack-entries", "zset-max-listpack-value", "zset-max-ziplist-entries",
+    "zset-max-ziplist-value", "hll-sparse-max-bytes", "stream-node-max-bytes", "stream-node-max-entries",
+    "activerehashing", "client-output-buffer-limit", "hz", "dynamic-hz", "jemalloc-bg-thread",
+];
+/// Upper bound of `keyspace-shards`; more shards than cores only adds coordination.
+const MAX_KEYSPACE_SHARDS: usize = 1024;
+
+/// Server parameters, set from the configuration file and the command line at startup and
+/// through `CONFIG SET` at runtime.
+#[derive(Clone)]
+pub struct Config {
+    pub port: u16,
+    pub bind: Vec<String>,
+    /// Absolute path of the working directory, where the RDB file and nodes.conf live.
+    pub dir: PathBuf,
+    pub dbfilename: String,
+    /// Snapshot points as `(seconds, changes)` pairs. They are accepted for compatibility
+    /// with redis.conf; the server does not write snapshots on its own.
+    pub save: Vec<(u64, u64)>,
+    pub replica_of: Option<(String, u16)>,
+    pub repl_backlog_size: usize,
+    pub cluster_enabled: bool,
+    pub cluster_config_file: String,
+    pub cluster_node_timeout: u64,
+    /// Memory limit in bytes enforced by evicting keys; 0 means no limit.
+    pub maxmemory: usize,
+    pub maxmemory_policy: EvictionPolicy,
+    /// Number of keys sampled every time the eviction pool is refilled.
+    pub maxmemory_samples: usize,
+    pub lfu_log_factor: u32,
+    /// Minutes of inactivity after which a key's access counter is decremented.
+    pub lfu_decay_time: u64,
+    /// Largest list kept in a single listpack: elements if positive, a size class if negative.
+    pub list_max_listpack_size: isize,
+    /// Number of quicklist nodes at each end left uncompressed; 0 disables compression.
+    pub list_compress_depth: usize,
+    /// Number of shards the keyspace is split into, each served by its own task.
+    pub keyspace_shards: usize,
+    /// Password of the default user, which clients must authenticate with before running
+    /// commands.
+    pub requirepass: Option<String>,
+    /// File the ACL users are loaded from at startup and by `ACL LOAD`, saved by `ACL SAVE`.
+    pub aclfile: Option<String>,
+    /// Number of entries the ACL LOG keeps.
+    pub acllog_max_len: usize,
+    /// Path of the Unix socket accepting connections, in addition to the TCP ports.
+    pub unixsocket: Option<String>,
+    /// Permissions of the Unix socket; 0 leaves those given by the umask.
+    pub unixsocketperm: u32,
+    /// Port accepting TLS connections; 0 disables it.
+    pub tls_port: u16,
+    pub tls_cert_file: Option<String>,
+    pub tls_key_file: Option<String>,
+    /// CA certificates that client and master certificates are verified against.
+    pub tls_ca_cert_file: Option<String>,
+    pub tls_auth_clients: ClientAuth,
+    /// Whether a replica connects to its master over TLS.
+    pub tls_replication: bool,
+    /// Classes of keyspace events published to the notification channels.
+    pub notify_keyspace_events: KeyspaceEvents,
+    /// Milliseconds a script runs before other clients get BUSY errors and it may be killed.
+    pub busy_reply_threshold: u64,
+    /// Absolute path of the configuration file, which `CONFIG REWRITE` updates.
+    config_file: Option<PathBuf>,
+    /// Bumped by every `CONFIG SET`, so the keyspace shards only apply the configuration
+    /// the coordinator hands back when it changed.
+    pub generation: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            port: 6379,
+            bind: vec!["127.0.0.1".to_string(), "-::1".to_string()],
+            dir: PathBuf::new(),
+            dbfilename: "dump.rdb".to_string(),
+            save: Vec::new(),
+            replica_of: None,
+            repl_backlog_size: replication::DEFAULT_BACKLOG_SIZE,
+            cluster_enabled: false,
+            cluster_config_file: cluster::DEFAULT_CONFIG_FILE.to_string(),
+            cluster_node_timeout: cluster::DEFAULT_NODE_TIMEOUT,
+            maxmemory: 0,
+            maxmemory_policy: EvictionPolicy::NoEviction,
+            maxmemory_samples: 5,
+            lfu_log_factor: 10,
+            lfu_decay_time: 1,
+            list_max_listpack_size: -2,
+            list_compress_depth: 0,
+            keyspace_shards: 1,
+            requirepass: None,
+            aclfile: None,
+            acllog_max_len: 128,
+            unixsocket: None,
+            unixsocketperm: 0,
+            tls_port: 0,
+            tls_cert_file: None,
+            tls_key_file: None,
+            tls_ca_cert_file: None,
+            tls_auth_clients: ClientAuth::Required,
+            tls_replication: false,
+            notify_keyspace_events: KeyspaceEvents::default(),
+            busy_reply_threshold: 5000,
+            config_file: None,
+            generation: 0,
+        }
+    }
+}
+
+type Getter = fn(&Config) -> String;
+type Setter = fn(&mut Config, &str) -> Result<(), String>;
+
+/// One entry of the parameter table. Values are exchanged as strings; parameters taking
+/// several arguments, like `replicaof host port`, join them with spaces.
+struct Parameter {
+    name: &'static str,
+    alias: Option<&'static str>,
+    /// Whether `CONFIG SET` may change the parameter at runtime.
+    mutable: bool,
+    /// Whether the value is a list of arguments rather than a single, possibly quoted, one.
+    multiple_arguments: bool,
+    get: Getter,
+    set: Setter,
+}
+
+const PARAMETERS: &[Parameter] = &[
+    Parameter {
+        name: "port",
+        alias: None,
+        mutable: false,
+        multiple_arguments: false,
+        get: |config| config.port.to_string(),
+        set: |config, value| {
+            config.port = parse_integer(value)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "bind",
+        alias: None,
+        mutable: false,
+        multiple_arguments: true,
+        get: |config| config.bind.join(" "),
+        set: |config, value| {
+            config.bind = value.split_whitespace().map(String::from).collect();
+            if config.bind.is_empty() {
+                return Err("bind requires at least one address".to_string());
+            }
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "dir",
+        alias: None,
+        mutable: true,
+        multiple_arguments: false,
+        get: |config| config.dir.display().to_string(),
+        set: |config, value| {
+            // Like Redis, the server works from inside `dir`, so relative file names resolve there.
+            std::env::set_current_dir(value).map_err(|e| e.to_string())?;
+            config.dir = std::env::current_dir().map_err(|e| e.to_string())?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "dbfilename",
+        alias: None,
+        mutable: true,
+        multiple_arguments: false,
+        get: |config| config.dbfilename.clone(),
+        set: |config, value| {
+            if value.is_empty() || Path::new(value).components().count() != 1 {
+                return Err("dbfilename can't be a path, just a filename".to_string());
+            }
+            config.dbfilename = value.to_string();
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "save",
+        alias: None,
+        mutable: true,
+        multiple_arguments: false,
+        get: |config| config.save.iter()
+            .map(|(seconds, changes)| format!("{} {}", seconds, changes))
+            .collect::<Vec<String>>()
+            .join(" "),
+        set: |config, value| {
+            let numbers: Vec<u64> = value.split_whitespace().map(parse_integer).collect::<Result<_, _>>()?;
+            if !numbers.len().is_multiple_of(2) {
+                return Err("Invalid save parameters".to_string());
+            }
+            config.save = numbers.chunks(2).map(|pair| (pair[0], pair[1])).collect();
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "appendonly",
+        alias: None,
+        mutable: true,
+        multiple_arguments: false,
+        get: |_| format_bool(false),
+        set: |_, value| {
+            if parse_bool(value)? {
+                return Err("append only files are not supported".to_string());
+            }
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "replicaof",
+        alias: Some("slaveof"),
+        mutable: false,
+        multiple_arguments: true,
+        get: |config| config.replica_of.as_ref()
+            .map(|(host, port)| format!("{} {}", host, port))
+            .unwrap_or_default(),
+        set: |config, value| {
+            let parts: Vec<&str> = value.split_whitespace().collect();
+            config.replica_of = match parts.as_slice() {
+                [] => None,
+                [no, one] if no.eq_ignore_ascii_case("no") && one.eq_ignore_ascii_case("one") => None,
+                [host, port] => Some((host.to_string(), parse_integer(port)?)),
+                _ => return Err("replicaof requires a host and a port".to_string()),
+            };
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "repl-backlog-size",
+        alias: None,
+        mutable: true,
+        multiple_arguments: false,
+        get: |config| config.repl_backlog_size.to_string(),
+        set: |config, value| {
+            config.repl_backlog_size = parse_memory(value)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "cluster-enabled",
+        alias: None,
+        mutable: false,
+        multiple_arguments: false,
+        get: |config| format_bool(config.cluster_enabled),
+        set: |config, value| {
+            config.cluster_enabled = parse_bool(value)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "cluster-config-file",
+        alias: None,
+        mutable: false,
+        multiple_arguments: false,
+        get: |config| config.cluster_config_file.clone(),
+        set: |config, value| {
+            config.cluster_config_file = value.to_string();
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "cluster-node-timeout",
+        alias: None,
+        mutable: true,
+        multiple_arguments: false,
+        get: |config| config.cluster_node_timeout.to_string(),
+        set: |config, value| {
+            config.cluster_node_timeout = parse_integer(value)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "maxmemory",
+        alias: None,
+        mutable: true,
+        multiple_arguments: false,
+        get: |config| config.maxmemory.to_string(),
+        set: |config, value| {
+            config.maxmemory = parse_memory(value)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "maxmemory-policy",
+        alias: None,
+        mutable: true,
+        multiple_arguments: false,
+        get: |config| config.maxmemory_policy.name().to_string(),
+        set: |config, value| {
+            config.maxmemory_policy = EvictionPolicy::parse(value).ok_or("argument(s) must be one of the following: \
+                volatile-lru, allkeys-lru, volatile-lfu, allkeys-lfu, volatile-random, allkeys-random, volatile-ttl, noeviction")?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "maxmemory-samples",
+        alias: None,
+        mutable: true,
+        multiple_arguments: false,
+        get: |config| config.maxmemory_samples.to_string(),
+        set: |config, value| {
+            config.maxmemory_samples = parse_integer(value)?;
+            if !(1..=64).contains(&config.maxmemory_samples) {
+                return Err("argument must be between 1 and 64 inclusive".to_string());
+            }
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "lfu-log-factor",
+        alias: None,
+        mutable: true,
+        multiple_arguments: false,
+        get: |config| config.lfu_log_factor.to_string(),
+        set: |config, value| {
+            config.lfu_log_factor = parse_integer(value)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "lfu-decay-time",
+        alias: None,
+        mutable: true,
+        multiple_arguments: false,
+        get: |config| config.lfu_decay_time.to_string(),
+        set: |config, value| {
+            config.lfu_decay_time = parse_integer(value)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "list-max-listpack-size",
+        alias: Some("list-max-ziplist-size"),
+        mutable: true,
+        multiple_arguments: false,
+        get: |config| config.list_max_listpack_size.to_string(),
+        set: |config, value| {
+            config.list_max_listpack_size = parse_integer(value)?;
+            if config.list_max_listpack_size == 0 || config.list_max_listpack_size < -5 {
+                return Err("argument must be between -5 and -1, or a positive count".to_string());
+            }
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "list-compress-depth",
+        alias: None,
+        mutable: true,
+        multiple_arguments: false,
+        get: |config| config.list_compress_depth.to_string(),
+        set: |config, value| {
+            config.list_compress_depth = parse_integer(value)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "keyspace-shards",
+        alias: None,
+        mutable: false,
+        multiple_arguments: false,
+        get: |config| config.keyspace_shards.to_string(),
+        set: |config, value| {
+            config.keyspace_shards = parse_integer(value)?;
+            if !(1..=MAX_KEYSPACE_SHARDS).contains(&config.keyspace_shards) {
+                return Err(format!("argument must be between 1 and {}", MAX_KEYSPACE_SHARDS));
+            }
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "requirepass",
+        alias: None,
+        mutable: true,
+        multiple_arguments: false,
+        get: |config| config.requirepass.clone().unwrap_or_default(),
+        set: |config, value| {
+            config.requirepass = Some(value.to_string()).filter(|password| !password.is_empty());
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "aclfile",
+        alias: None,
+        mutable: false,
+        multiple_arguments: false,
+        get: |config| config.aclfile.clone().unwrap_or_default(),
+        set: |config, value| {
+            config.aclfile = Some(value.to_string()).filter(|path| !path.is_empty());
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "acllog-max-len",
+        alias: None,
+        mutable: true,
+        multiple_arguments: false,
+        get: |config| config.acllog_max_len.to_string(),
+        set: |config, value| {
+            config.acllog_max_len = parse_integer(value)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "unixsocket",
+        alias: None,
+        mutable: false,
+        multiple_arguments: false,
+        get: |config| config.unixsocket.clone().unwrap_or_default(),
+        set: |config, value| {
+            config.unixsocket = Some(value.to_string()).filter(|path| !path.is_empty());
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "unixsocketperm",
+        alias: None,
+        mutable: false,
+        multiple_arguments: false,
+        get: |config| format!("{:o}", config.unixsocketperm),
+        set: |config, value| {
+            config.unixsocketperm = u32::from_str_radix(value, 8)
+                .ok().filter(|permissions| *permissions <= 0o777)
+                .ok_or("argument must be an octal number between 0 and 777")?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "tls-port",
+        alias: None,
+        mutable: false,
+        multiple_arguments: false,
+        get: |config| config.tls_port.to_string(),
+        set: |config, value| {
+            config.tls_port = parse_integer(value)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "tls-cert-file",
+        alias: None,
+        mutable: true,
+        multiple_arguments: false,
+        get: |config| config.tls_cert_file.clone().unwrap_or_default(),
+        set: |config, value| {
+            config.tls_cert_file = Some(value.to_string()).filter(|path| !path.is_empty());
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "tls-key-file",
+        alias: None,
+        mutable: true,
+        multiple_arguments: false,
+        get: |config| config.tls_key_file.clone().unwrap_or_default(),
+        set: |config, value| {
+            config.tls_key_file = Some(value.to_string()).filter(|path| !path.is_empty());
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "tls-ca-cert-file",
+        alias: None,
+        mutable: true,
+        multiple_arguments: false,
+        get: |config| config.tls_ca_cert_file.clone().unwrap_or_default(),
+        set: |config, value| {
+            config.tls_ca_cert_file = Some(value.to_string()).filter(|path| !path.is_empty());
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "tls-auth-clients",
+        alias: None,
+        mutable: true,
+        multiple_arguments: false,
+        get: |config| config.tls_auth_clients.name().to_string(),
+        set: |config, value| {
+            config.tls_auth_clients = ClientAuth::parse(value).ok_or("argument(s) must be one of the following: \
+                yes, no, optional")?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "tls-replication",
+        alias: None,
+        mutable: true,
+        multiple_arguments: false,
+        get: |config| format_bool(config.tls_replication),
+        set: |config, value| {
+            config.tls_replication = parse_bool(value)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "notify-keyspace-events",
+        alias: None,
+        mutable: true,
+        multiple_arguments: false,
+        get: |config| config.notify_keyspace_events.name(),
+        set: |config, value| {
+            config.notify_keyspace_events = KeyspaceEvents::parse(value)
+                .ok_or("Invalid event class character. Use 'Ag$lshzxeKEtmn'.")?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "busy-reply-threshold",
+        alias: Some("lua-time-limit"),
+        mutable: true,
+        multiple_arguments: false,
+        get: |config| config.busy_reply_threshold.to_string(),
+        set: |config, value| {
+            config.busy_reply_threshold = parse_integer(value)?;
+            Ok(())
+        },
+    },
+];
+
+fn find_parameter(name: &str) -> Option<&'static Parameter> {
+    PARAMETERS.iter().find(|parameter| {
+        parameter.name.eq_ignore_ascii_case(name)
+            || parameter.alias.is_some_and(|alias| alias.eq_ignore_ascii_case(name))
+    })
+}
+
+impl Config {
+    /// How key accesses are recorded, which depends on whether the policy is LFU based.
+    pub fn access_tracking(&self) -> AccessTracking {
+        if self.maxmemory_policy.is_lfu() {
+            AccessTracking::Lfu { log_factor: self.lfu_log_factor, decay_time: self.lfu_decay_time }
+        } else {
+            AccessTracking::Lru
+        }
+    }
+
+    /// Builds the configuration from `[config-file] [--name value ...]`. Options given on
+    /// the command line are applied after the file, so they take precedence.
+    pub fn from_args(arguments: &[String]) -> Result<Config, String> {
+        let mut config: Config = Config::default();
+        let mut arguments: &[String] = arguments;
+
+        if let Some(file) = arguments.first().filter(|argument| !argument.starts_with("--")) {
+            let path: PathBuf = std::path::absolute(file).map_err(|e| format!("{}: {}", file, e))?;
+            let contents: String = fs::read_to_string(&path)
+                .map_err(|e| format!("Fatal error, can't open config file '{}': {}", file, e))?;
+            for (number, line) in contents.lines().enumerate() {
+                config.apply_line(line)
+                    .map_err(|e| format!("Reading the configuration file, at line {}\n>>> '{}'\n{}", number + 1, line, e))?;
+            }
+            config.config_file = Some(path);
+            arguments = &arguments[1..];
+        }
+
+        while let Some((flag, rest)) = arguments.split_first() {
+            let name: &str = flag.strip_prefix("--").ok_or_else(|| format!("Invalid argument '{}'", flag))?;
+            let values: usize = rest.iter().take_while(|argument| !argument.starts_with("--")).count();
+            config.apply(name, &rest[..values].join(" "))
+                .map_err(|e| format!("Invalid command line option '--{}'\n{}", name, e))?;
+            arguments = &rest[values..];
+        }
+
+        if config.dir.as_os_str().is_empty() {
+            config.dir = std::env::current_dir().map_err(|e| e.to_string())?;
+        }
+        Ok(config)
+    }
+
+    pub fn config_file(&self) -> Option<&Path> {
+        self.config_file.as_deref()
+    }
+
+    fn apply_line(&mut self, line: &str) -> Result<(), String> {
+        if is_comment(line) {
+            return Ok(());
+        }
+        let arguments: Vec<String> = split_arguments(line)?;
+        match arguments.split_first() {
+            Some((name, values)) => self.apply(name, &values.join(" ")),
+            None => Ok(()),
+        }
+    }
+
+    fn apply(&mut self, name: &str, value: &str) -> Result<(), String> {
+        if IGNORED_DIRECTIVES.iter().any(|ignored| ignored.eq_ignore_ascii_case(name)) {
+            return Ok(());
+        }
+        let parameter: &Parameter = find_parameter(name)
+            .ok_or_else(|| "Bad directive or wrong number of arguments".to_string())?;
+        (parameter.set)(self, value)
+    }
+
+    /// `CONFIG GET`: the parameters matching any of the glob patterns, with their values.
+    /// Aliases are only reported when asked for by their exact name.
+    pub fn get(&self, patterns: &[&str]) -> Vec<(&'static str, String)> {
+        let mut seen: HashSet<&str> = HashSet::new();
+        let mut values: Vec<(&'static str, String)> = Vec::new();
+
+        for pattern in patterns {
+            for parameter in PARAMETERS {
+                let name: &'static str = if glob::matches(pattern.as_bytes(), parameter.name.as_bytes(), true) {
+                    parameter.name
+                } else {
+                    match parameter.alias.filter(|alias| alias.eq_ignore_ascii_case(pattern)) {
+                        Some(alias) => alias,
+                        None => continue,
+                    }
+                };
+                if seen.insert(name) {
+                    values.push((name, (parameter.get)(self)));
+                }
+            }
+        }
+
+        values
+    }
+
+    /// `CONFIG SET`: applies every pair or none of them. Parameters already changed when a
+    /// later one fails are restored to their previous values.
+    pub fn set(&mut self, pairs: &[(&str, &str)]) -> Result<(), String> {
+        let mut parameters: Vec<&Parameter> = Vec::with_capacity(pairs.len());
+        for (name, _) in pairs {
+            let parameter: &Parameter = find_parameter(name)
+                .ok_or_else(|| format!("ERR Unknown option or number of arguments for CONFIG SET - '{}'", name))?;
+            if !parameter.mutable {
+                return Err(format!(
+                    "ERR CONFIG SET failed (possibly related to argument '{}') - can't set immutable config", name));
+            }
+            if parameters.iter().any(|other| other.name == parameter.name) {
+                return Err(format!("ERR CONFIG SET failed (possibly related to argument '{}') - duplicate parameter", name));
+            }
+            parameters.push(parameter);
+        }
+
+        let previous: Vec<String> = parameters.iter().map(|parameter| (parameter.get)(self)).collect();
+        for (index, (parameter, (name, value))) in parameters.iter().zip(pairs).enumerate() {
+            if let Err(e) = (parameter.set)(self, value) {
+                for (parameter, value) in parameters.iter().zip(&previous).take(index) {
+                    let _ = (parameter.set)(self, value);
+                }
+                return Err(format!("ERR CONFIG SET failed (possibly related to argument '{}') - {}", name, e));
+            }
+        }
+
+        self.generation += 1;
+        Ok(())
+    }
+
+    /// `CONFIG REWRITE`: updates the configuration file in place. Comments and unknown
+    /// lines are kept, known directives are replaced by their current value, and parameters
+    /// that differ from their default are appended.
+    pub fn rewrite(&self) -> Result<(), String> {
+        let path: &Path = self.config_file.as_deref()
+            .ok_or_else(|| "ERR The server is running without a config file".to_string())?;
+        let contents: String = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(format!("ERR Rewriting config file: {}", e)),
+        };
+
+        let mut written: HashSet<&str> = HashSet::new();
+        let mut lines: Vec<String> = Vec::new();
+        for line in contents.lines() {
+            if line.trim() == REWRITE_SIGNATURE {
+                continue;
+            }
+            let name: Option<String> = Some(line)
+                .filter(|line| !is_comment(line))
+                .and_then(|line| split_arguments(line).ok())
+                .and_then(|arguments| arguments.into_iter().next());
+            match name.as_deref().and_then(find_parameter) {
+                Some(parameter) => {
+                    if written.insert(parameter.name) {
+                        lines.push(self.format_directive(parameter));
+                    }
+                }
+                None => lines.push(line.to_string()),
+            }
+        }
+
+        let defaults: Config = Config::default();
+        let mut appended: Vec<String> = PARAMETERS.iter()
+            .filter(|parameter| !written.contains(parameter.name))
+            .filter(|parameter| (parameter.get)(self) != (parameter.get)(&defaults))
+            .map(|parameter| self.format_directive(parameter))
+            .collect();
+        if !appended.is_empty() {
+            lines.push(REWRITE_SIGNATURE.to_string());
+            lines.append(&mut appended);
+        }
+
+        let temporary: PathBuf = path.with_extension(format!("tmp-{}", std::process::id()));
+        let mut output: String = lines.join("\n");
+        output.push('\n');
+        fs::write(&temporary, output)
+            .and_then(|_| fs::rename(&temporary, path))
+            .map_err(|e| {
+                let _ = fs::remove_file(&temporary);
+                format!("ERR Rewriting config file: {}", e)
+            })
+    }
+
+    fn format_directive(&self, parameter: &Parameter) -> String {
+        let value: String = (parameter.get)(self);
+        if parameter.multiple_arguments {
+            format!("{} {}", parameter.name, value)
+        } else {
+            format!("{} {}", parameter.name, quote(&value))
+        }
+    }
+}
+
+fn is_comment(line: &str) -> bool {
+    let line: &str = line.trim_start();
+    line.is_empty() || line.starts_with('#')
+}
+
+fn parse_integer<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| "argument couldn't be parsed into an integer".to_string())
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err("argument must be 'yes' or 'no'".to_string()),
+    }
+}
+
+fn format_bool(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_string()
+}
+
+/// Parses sizes such as `1mb` or `512k`: `k`, `m` and `g` are powers of 1000, `kb`, `mb`
+/// and `gb` powers of 1024.
+fn parse_memory(value: &str) -> Result<usize, String> {
+    let value: String = value.to_ascii_lowercase();
+    let digits: usize = value.find(|character: char| !character.is_ascii_digit()).unwrap_or(value.len());
+    let multiplier: usize = match &value[digits..] {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return Err("argument must be a memory value".to_string()),
+    };
+    value[..digits].parse::<usize>().ok()
+        .and_then(|amount| amount.checked_mul(multiplier))
+        .ok_or_else(|| "argument must be a memory value".to_string())
+}
+
+/// Splits a configuration or ACL file line into arguments. Arguments may be wrapped in double quotes,
+/// which understand backslash escapes, or in single quotes, which only escape `'`.
+pub fn split_arguments(line: &str) -> Result<Vec<String>, String> {
+    const UNBALANCED: &str = "Unbalanced quotes in configuration line";
+
+    let mut arguments: Vec<String> = Vec::new();
+    let mut characters = line.trim().chars().peekable();
+
+    while let Some(&first) = characters.peek() {
+        if first.is_whitespace() {
+            characters.next();
+            continue;
+        }
+
+        let mut argument: String = String::new();
+        match first {
+            '"' => {
+                characters.next();
+                loop {
+                    match characters.next().ok_or(UNBALANCED)? {
+                        '"' => break,
+                        '\\' => match characters.next().ok_or(UNBALANCED)? {
+                            'n' => argument.push('\n'),
+                            'r' => argument.push('\r'),
+                            't' => argument.push('\t'),
+                            'x' => {
+                                let hex: String = characters.by_ref().take(2).collect();
+                                let byte: u8 = u8::from_str_radix(&hex, 16).map_err(|_| UNBALANCED)?;
+                                argument.push(byte as char);
+                            }
+                            escaped => argument.push(escaped),
+                        },
+                        character => argument.push(character),
+                    }
+                }
+            }
+            '\'' => {
+                characters.next();
+                loop {
+                    match characters.next().ok_or(UNBALANCED)? {
+                        '\'' => break,
+                        '\\' if characters.peek() == Some(&'\'') => argument.push(characters.next().unwrap()),
+                        character => argument.push(character),
+                    }
+                }
+            }
+            _ => {
+                while let Some(&character) = characters.peek() {
+                    if character.is_whitespace() {
+                        break;
+                    }
+                    argument.push(character);
+                    characters.next();
+                }
+            }
+        }
+
+        if characters.peek().is_some_and(|character| !character.is_whitespace()) {
+            return Err(UNBALANCED.to_string());
+        }
+        arguments.push(argument);
+    }
+
+    Ok(arguments)
+}
+
+/// Quotes a value for the configuration file when it would not survive `split_arguments`
+/// as a single bare word.
+fn quote(value: &str) -> String {
+    let bare: bool = !value.is_empty()
+        && !value.starts_with(['"', '\''])
+        && !value.contains(|character: char| character.is_whitespace() || character == '\\');
+    if bare {
+        return value.to_string();
+    }
+
+    let mut quoted: String = String::from("\"");
+    for character in value.chars() {
+        match character {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(character);
+            }
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            _ => quoted.push(character),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
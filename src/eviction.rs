@@ -0,0 +1,179 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::allocator;
+use crate::command::encode_command;
+use crate::key_value_store::{self, KeyValueStore, Sample, DEFAULT_MEMORY_SAMPLES};
+use crate::notify::KeyspaceEvents;
+use crate::server::ServerState;
+
+/// Number of candidates the eviction pool keeps between evictions.
+const EVICTION_POOL_SIZE: usize = 16;
+
+/// The `maxmemory-policy` values: which keys may be evicted and how they are ranked.
+#[derive(Clone, Copy, PartialEq)]
+pub enum EvictionPolicy {
+    VolatileLru,
+    AllKeysLru,
+    VolatileLfu,
+    AllKeysLfu,
+    VolatileRandom,
+    AllKeysRandom,
+    VolatileTtl,
+    NoEviction,
+}
+
+impl EvictionPolicy {
+    const ALL: [EvictionPolicy; 8] = [
+        EvictionPolicy::VolatileLru,
+        EvictionPolicy::AllKeysLru,
+        EvictionPolicy::VolatileLfu,
+        EvictionPolicy::AllKeysLfu,
+        EvictionPolicy::VolatileRandom,
+        EvictionPolicy::AllKeysRandom,
+        EvictionPolicy::VolatileTtl,
+        EvictionPolicy::NoEviction,
+    ];
+
+    pub fn parse(name: &str) -> Option<EvictionPolicy> {
+        Self::ALL.into_iter().find(|policy| policy.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EvictionPolicy::VolatileLru => "volatile-lru",
+            EvictionPolicy::AllKeysLru => "allkeys-lru",
+            EvictionPolicy::VolatileLfu => "volatile-lfu",
+            EvictionPolicy::AllKeysLfu => "allkeys-lfu",
+            EvictionPolicy::VolatileRandom => "volatile-random",
+            EvictionPolicy::AllKeysRandom => "allkeys-random",
+            EvictionPolicy::VolatileTtl => "volatile-ttl",
+            EvictionPolicy::NoEviction => "noeviction",
+        }
+    }
+
+    pub fn is_lfu(&self) -> bool {
+        matches!(self, EvictionPolicy::VolatileLfu | EvictionPolicy::AllKeysLfu)
+    }
+
+    /// Whether only keys with an expiry may be evicted.
+    fn is_volatile(&self) -> bool {
+        matches!(
+            self,
+            EvictionPolicy::VolatileLru | EvictionPolicy::VolatileLfu | EvictionPolicy::VolatileRandom | EvictionPolicy::VolatileTtl
+        )
+    }
+
+    fn is_random(&self) -> bool {
+        matches!(self, EvictionPolicy::VolatileRandom | EvictionPolicy::AllKeysRandom)
+    }
+}
+
+/// The best eviction candidates seen so far, sorted by ascending score so the best one is
+/// last. Like in Redis, it survives between evictions, so every refill only has to sample
+/// a few keys to keep approximating the ideal choice.
+pub struct EvictionPool {
+    candidates: Vec<(u64, String)>,
+}
+
+impl EvictionPool {
+    pub fn new() -> Self {
+        EvictionPool { candidates: Vec::with_capacity(EVICTION_POOL_SIZE) }
+    }
+
+    pub fn clear(&mut self) {
+        self.candidates.clear();
+    }
+
+    /// Samples keys and keeps those ranking better than the current candidates.
+    fn populate(&mut self, store: &dyn KeyValueStore, policy: EvictionPolicy, samples: usize, decay_time: u64) {
+        let now: u64 = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+
+        for (key, entry, access) in store.sample(samples, policy.is_volatile()) {
+            let score: u64 = match policy {
+                EvictionPolicy::VolatileLfu | EvictionPolicy::AllKeysLfu => (u8::MAX - access.frequency(decay_time)) as u64,
+                EvictionPolicy::VolatileTtl => {
+                    let expiry: u64 = entry.get_expiry()
+                        .map(|expiry| expiry.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64)
+                        .unwrap_or(now);
+                    u64::MAX - expiry
+                }
+                _ => access.idle_time().as_millis() as u64,
+            };
+            self.offer(score, key);
+        }
+    }
+
+    fn offer(&mut self, score: u64, key: &str) {
+        if self.candidates.iter().any(|(_, candidate)| candidate == key) {
+            return;
+        }
+        if self.candidates.len() == EVICTION_POOL_SIZE {
+            if score <= self.candidates[0].0 {
+                return;
+            }
+            self.candidates.remove(0);
+        }
+        let position: usize = self.candidates.partition_point(|(candidate, _)| *candidate < score);
+        self.candidates.insert(position, (score, key.to_string()));
+    }
+
+    /// Takes the best candidate that still exists.
+    fn pop(&mut self, store: &dyn KeyValueStore) -> Option<String> {
+        while let Some((_, key)) = self.candidates.pop() {
+            if store.peek(&key).is_some() {
+                return Some(key);
+            }
+        }
+        None
+    }
+}
+
+/// Evicts keys following `maxmemory-policy` until the used memory is back under
+/// `maxmemory`. Returns false if it cannot get there, either because of the `noeviction`
+/// policy or because no key is left to evict. A keyspace shard only frees its share of
+/// the excess, the other shards freeing theirs as they run commands.
+pub fn perform_evictions(store: &mut Box<dyn KeyValueStore>, server: &mut ServerState) -> bool {
+    let maxmemory: usize = server.config.maxmemory;
+    if maxmemory == 0 {
+        return true;
+    }
+    let used: usize = allocator::used_memory();
+    if used <= maxmemory {
+        return true;
+    }
+    // Replicas follow the evictions of their master.
+    if server.replication.lock().is_replica() {
+        return true;
+    }
+
+    let policy: EvictionPolicy = server.config.maxmemory_policy;
+    if policy == EvictionPolicy::NoEviction {
+        return false;
+    }
+
+    let to_free: usize = ((used - maxmemory) / server.eviction_share).max(1);
+    let mut freed: usize = 0;
+    while freed < to_free {
+        let key: Option<String> = if policy.is_random() {
+            let sample: Vec<Sample<'_>> = store.sample(1, policy.is_volatile());
+            sample.first().map(|(key, _, _)| key.to_string())
+        } else {
+            server.eviction_pool.populate(
+                store.as_ref(), policy, server.config.maxmemory_samples, server.config.lfu_decay_time);
+            server.eviction_pool.pop(store.as_ref())
+        };
+
+        let key: String = match key {
+            Some(key) => key,
+            None => return false,
+        };
+        if let Some(entry) = store.remove(&key) {
+            freed += key_value_store::key_memory_usage(&key, entry.as_ref(), DEFAULT_MEMORY_SAMPLES);
+            server.stats.evicted_keys += 1;
+            server.invalidate_key(key.as_bytes(), None);
+            server.notify_keyspace_event(KeyspaceEvents::EVICTED, "evicted", key.as_bytes());
+            server.replication.lock().feed(&encode_command(&[b"DEL", key.as_bytes()]));
+        }
+    }
+
+    true
+}
@@ -0,0 +1,131 @@
+use std::sync::MutexGuard;
+use std::time::{Duration, Instant, SystemTime};
+use crate::key_value_store::{KeyValueStore, Sample};
+use crate::notify::KeyspaceEvents;
+use crate::replication::ReplicationState;
+use crate::server::{Propagation, ServerState};
+
+/// How often every store looks for expired keys nobody accessed.
+pub const ACTIVE_EXPIRE_PERIOD: Duration = Duration::from_millis(100);
+
+/// Keys with an expiry sampled at a time by the active expire cycle.
+const ACTIVE_EXPIRE_SAMPLES: usize = 20;
+
+/// Share of the sampled keys, in percent, above which the cycle samples again, as many
+/// more keys are then likely to have expired too.
+const ACTIVE_EXPIRE_ACCEPTABLE_STALE: usize = 10;
+
+/// Time a cycle may take, a quarter of the period like Redis, so commands keep going
+/// even when many keys expire at once.
+const ACTIVE_EXPIRE_TIME_LIMIT: Duration = Duration::from_millis(25);
+
+/// Whether `key` expired before `now`, in which case it is deleted. Replicas keep their
+/// keys until the master deletes them: their clients see the key gone, while the commands
+/// of the master still find it.
+pub fn expire_if_needed(store: &mut Box<dyn KeyValueStore>, server: &mut ServerState, key: &str, now: SystemTime) -> bool {
+    let expired: bool = store.peek(key)
+        .is_some_and(|(entry, _)| entry.get_expiry().is_some_and(|expiry| expiry < now));
+    if !expired {
+        return false;
+    }
+    if server.replication.lock().is_replica() {
+        return server.current_client.is_some();
+    }
+    store.remove(key);
+    deleted_expired_key(server, key);
+    true
+}
+
+/// Accounts for `key`, which expired, having been deleted, and has replicas delete it too.
+pub fn deleted_expired_key(server: &mut ServerState, key: &str) {
+    server.stats.expired_keys += 1;
+    server.invalidate_key(key.as_bytes(), None);
+    server.notify_keyspace_event(KeyspaceEvents::EXPIRED, "expired", key.as_bytes());
+    server.also_propagate(&[b"DEL", key.as_bytes()]);
+}
+
+/// Deletes expired keys of `store` that are not accessed, which lazy expiry never finds,
+/// like Redis' `activeExpireCycle`: it samples keys with an expiry, deleting those that
+/// expired, and samples again while many of them had.
+pub fn active_expire_cycle(store: &mut Box<dyn KeyValueStore>, server: &mut ServerState) {
+    if store.volatile_key_count() == 0 || server.replication.lock().is_replica() {
+        return;
+    }
+
+    let started: Instant = Instant::now();
+    loop {
+        let now: SystemTime = SystemTime::now();
+        let samples: Vec<Sample<'_>> = store.sample(ACTIVE_EXPIRE_SAMPLES, true);
+        let sampled: usize = samples.len();
+        let mut expired: Vec<String> = samples.into_iter()
+            .filter(|(_, entry, _)| entry.get_expiry().is_some_and(|expiry| expiry < now))
+            .map(|(key, _, _)| key.to_string())
+            .collect();
+        // Sampling picks keys with replacement, so the same key may come up twice.
+        expired.sort_unstable();
+        expired.dedup();
+
+        for key in &expired {
+            if store.remove(key).is_some() {
+                deleted_expired_key(server, key);
+            }
+        }
+
+        if sampled == 0
+            || expired.len() * 100 <= sampled * ACTIVE_EXPIRE_ACCEPTABLE_STALE
+            || started.elapsed() >= ACTIVE_EXPIRE_TIME_LIMIT {
+            break;
+        }
+    }
+
+    // No command runs the deletions through the usual propagation, so they are fed here.
+    let Propagation { also, .. } = std::mem::take(&mut server.propagation);
+    if !also.is_empty() {
+        let mut replication: MutexGuard<'_, ReplicationState> = server.replication.lock();
+        for command in &also {
+            replication.feed_command(command);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+    use tokio::sync::mpsc;
+    use super::active_expire_cycle;
+    use crate::Msg;
+    use crate::acl::Acl;
+    use crate::config::Config;
+    use crate::key_value_store::{InMemoryKeyValueStore, KeyValueStore, KeyValueStoreStringEntry};
+    use crate::replication::ReplicationState;
+    use crate::server::ServerState;
+
+    fn string(expiry: Option<SystemTime>) -> Box<KeyValueStoreStringEntry> {
+        Box::new(KeyValueStoreStringEntry { value: "value".to_string(), expiry })
+    }
+
+    #[test]
+    fn the_cycle_deletes_expired_keys_and_propagates_their_deletion() {
+        let (store_tx, _store_rx): (mpsc::Sender<Msg>, mpsc::Receiver<Msg>) = mpsc::channel(1);
+        let replication: ReplicationState = ReplicationState::new(1024 * 1024, 6379, store_tx);
+        let mut server: ServerState = ServerState::new(Config::default(), replication, None, Acl::new(None));
+        let mut store: Box<dyn KeyValueStore> = Box::new(InMemoryKeyValueStore::new());
+
+        let past: SystemTime = SystemTime::now() - Duration::from_secs(1);
+        let future: SystemTime = SystemTime::now() + Duration::from_secs(3600);
+        for index in 0..100 {
+            store.insert(format!("expired:{}", index), string(Some(past)));
+        }
+        store.insert(String::from("later"), string(Some(future)));
+        store.insert(String::from("persistent"), string(None));
+
+        active_expire_cycle(&mut store, &mut server);
+        // The cycle keeps sampling while most samples expired, so few expired keys remain.
+        assert!(store.key_count() < 2 + 10);
+        assert!(store.peek("later").is_some());
+        assert!(store.peek("persistent").is_some());
+        assert_eq!(server.stats.expired_keys, 102 - store.key_count() as u64);
+
+        assert!(server.replication.lock().master_repl_offset() > 0);
+    }
+}
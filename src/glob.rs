@@ -0,0 +1,76 @@
+/// Glob-style matching as used by `CONFIG GET` and other pattern arguments: `*`, `?`,
+/// `[abc]`, `[^a-z]` and `\` to escape the next character.
+pub fn matches(pattern: &[u8], text: &[u8], ignore_case: bool) -> bool {
+    let equal = |a: u8, b: u8| if ignore_case { a.eq_ignore_ascii_case(&b) } else { a == b };
+
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => {
+            let rest: &[u8] = trim_leading_stars(rest);
+            rest.is_empty() || (0..=text.len()).any(|start| matches(rest, &text[start..], ignore_case))
+        }
+        Some((b'?', rest)) => !text.is_empty() && matches(rest, &text[1..], ignore_case),
+        Some((b'[', rest)) => match text.split_first() {
+            Some((&character, text_rest)) => {
+                let (matched, pattern_rest) = match_class(rest, character, ignore_case);
+                matched && matches(pattern_rest, text_rest, ignore_case)
+            }
+            None => false,
+        },
+        Some((b'\\', rest)) if !rest.is_empty() => {
+            !text.is_empty() && equal(rest[0], text[0]) && matches(&rest[1..], &text[1..], ignore_case)
+        }
+        Some((&literal, rest)) => {
+            !text.is_empty() && equal(literal, text[0]) && matches(rest, &text[1..], ignore_case)
+        }
+    }
+}
+
+fn trim_leading_stars(mut pattern: &[u8]) -> &[u8] {
+    while let Some((b'*', rest)) = pattern.split_first() {
+        pattern = rest;
+    }
+    pattern
+}
+
+/// Matches `character` against the class following a `[`, returning whether it matched and
+/// the pattern after the closing `]`. An unterminated class runs to the end of the pattern.
+fn match_class(mut pattern: &[u8], character: u8, ignore_case: bool) -> (bool, &[u8]) {
+    let fold = |byte: u8| if ignore_case { byte.to_ascii_lowercase() } else { byte };
+    let character: u8 = fold(character);
+
+    let negate: bool = pattern.first() == Some(&b'^');
+    if negate {
+        pattern = &pattern[1..];
+    }
+
+    let mut matched: bool = false;
+    loop {
+        match pattern {
+            [] => break,
+            [b']', rest @ ..] => {
+                pattern = rest;
+                break;
+            }
+            [b'\\', escaped, rest @ ..] => {
+                matched |= fold(*escaped) == character;
+                pattern = rest;
+            }
+            [start, b'-', end, rest @ ..] if *end != b']' => {
+                let (low, high) = if fold(*start) <= fold(*end) {
+                    (fold(*start), fold(*end))
+                } else {
+                    (fold(*end), fold(*start))
+                };
+                matched |= (low..=high).contains(&character);
+                pattern = rest;
+            }
+            [single, rest @ ..] => {
+                matched |= fold(*single) == character;
+                pattern = rest;
+            }
+        }
+    }
+
+    (matched != negate, pattern)
+}
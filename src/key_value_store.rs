@@ -1,8 +1,129 @@
+use std::cell::Cell;
 use std::cmp::min;
 use std::collections::{HashMap, VecDeque};
 use std::collections::hash_map::Entry;
-use std::time::SystemTime;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::sync::oneshot;
+use crate::listpack::Listpack;
+use crate::quicklist::{ListLimits, Quicklist};
+use crate::random;
+
+/// The LRU clock has a resolution of one second and wraps around after 24 bits, like the
+/// per-object clock Redis keeps.
+const LRU_CLOCK_MAX: u32 = (1 << 24) - 1;
+
+/// Access counter given to new keys under an LFU policy, so they are not evicted before
+/// they had a chance to be accessed.
+const LFU_INIT_VAL: u8 = 5;
+
+fn unix_seconds() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0)
+}
+
+pub fn lru_clock() -> u32 {
+    (unix_seconds() & LRU_CLOCK_MAX as u64) as u32
+}
+
+/// The LFU decay clock: minutes, wrapping around after 16 bits.
+fn lfu_minutes() -> u32 {
+    ((unix_seconds() / 60) & 0xFFFF) as u32
+}
+
+/// How lookups update the access bookkeeping, which depends on the maxmemory policy.
+#[derive(Clone, Copy)]
+pub enum AccessTracking {
+    Lru,
+    Lfu { log_factor: u32, decay_time: u64 },
+}
+
+/// Access bookkeeping kept next to every value. Lookups update it through a shared
+/// reference, so reading a key does not need mutable access to the store.
+///
+/// Like Redis' `robj->lru`, the 24 bits hold either the LRU clock of the last access, or
+/// the minutes of the last decrement in the upper 16 bits and a logarithmic access counter
+/// in the lower 8 bits.
+pub struct AccessInfo {
+    lru: Cell<u32>,
+}
+
+impl AccessInfo {
+    fn new(tracking: AccessTracking) -> Self {
+        let lru: u32 = match tracking {
+            AccessTracking::Lru => lru_clock(),
+            AccessTracking::Lfu { .. } => (lfu_minutes() << 8) | LFU_INIT_VAL as u32,
+        };
+        AccessInfo { lru: Cell::new(lru) }
+    }
+
+    fn touch(&self, tracking: AccessTracking) {
+        match tracking {
+            AccessTracking::Lru => self.lru.set(lru_clock()),
+            AccessTracking::Lfu { log_factor, decay_time } => {
+                let counter: u8 = log_increment(self.frequency(decay_time), log_factor);
+                self.set_frequency(counter);
+            }
+        }
+    }
+
+    pub fn idle_time(&self) -> Duration {
+        let now: u32 = lru_clock();
+        let lru: u32 = self.lru.get();
+        let idle: u32 = if now >= lru { now - lru } else { LRU_CLOCK_MAX - lru + now };
+        Duration::from_secs(idle as u64)
+    }
+
+    pub fn set_idle_time(&self, idle: Duration) {
+        let idle: u32 = (idle.as_secs() & LRU_CLOCK_MAX as u64) as u32;
+        let now: u32 = lru_clock();
+        self.lru.set(if now >= idle { now - idle } else { LRU_CLOCK_MAX - (idle - now) });
+    }
+
+    /// The access counter, decremented by one for every `decay_time` minutes since it was
+    /// last decremented. Only meaningful under an LFU policy.
+    pub fn frequency(&self, decay_time: u64) -> u8 {
+        let lru: u32 = self.lru.get();
+        let last_decrement: u32 = lru >> 8;
+        let counter: u8 = (lru & 0xFF) as u8;
+
+        let now: u32 = lfu_minutes();
+        let elapsed: u32 = if now >= last_decrement { now - last_decrement } else { 0xFFFF - last_decrement + now };
+        let periods: u64 = (elapsed as u64).checked_div(decay_time).unwrap_or(0);
+        counter.saturating_sub(periods.min(u8::MAX as u64) as u8)
+    }
+
+    pub fn set_frequency(&self, counter: u8) {
+        self.lru.set((lfu_minutes() << 8) | counter as u32);
+    }
+}
+
+/// Morris counter increment: the higher the counter, the less likely an access bumps it,
+/// so eight bits can tell apart keys accessed a handful or a million times.
+fn log_increment(counter: u8, log_factor: u32) -> u8 {
+    if counter == u8::MAX {
+        return counter;
+    }
+    let base: f64 = counter.saturating_sub(LFU_INIT_VAL) as f64;
+    let probability: f64 = 1.0 / (base * log_factor as f64 + 1.0);
+    if random::random_unit() < probability { counter + 1 } else { counter }
+}
+
+/// Longest string Redis stores in the same allocation as its object header.
+const EMBSTR_SIZE_LIMIT: usize = 44;
+
+/// Bookkeeping the store keeps per key besides the key and the value.
+pub const KEY_OVERHEAD: usize = size_of::<StoredEntry>() + size_of::<String>();
+
+/// Number of collection elements `MEMORY USAGE` samples by default.
+pub const DEFAULT_MEMORY_SAMPLES: usize = 5;
+
+/// Estimate of the bytes a key takes in the store, including its value.
+pub fn key_memory_usage(key: &str, entry: &dyn KeyValueStoreEntry, samples: usize) -> usize {
+    KEY_OVERHEAD + key.len() + entry.memory_usage(samples)
+}
+
+/// A key picked at random by `KeyValueStore::sample`.
+pub type Sample<'a> = (&'a String, &'a dyn KeyValueStoreEntry, &'a AccessInfo);
 
 pub trait KeyValueStore: Send {
     fn insert(
@@ -11,7 +132,7 @@ pub trait KeyValueStore: Send {
         entry: Box<dyn KeyValueStoreEntry>
     ) -> Option<Box<dyn KeyValueStoreEntry>>;
     
-    fn get(&self, key: &str) -> Option<&Box<dyn KeyValueStoreEntry>>;
+    fn get(&self, key: &str) -> Option<&dyn KeyValueStoreEntry>;
     fn get_mut(&mut self, key: &str) -> Option<&mut Box<dyn KeyValueStoreEntry>>;
     fn remove(&mut self, key: &str) -> Option<Box<dyn KeyValueStoreEntry>>;
     fn ensure_exists_and_get_mut(
@@ -19,16 +140,73 @@ pub trait KeyValueStore: Send {
         key: String, 
         factory_fn: fn() -> Box<dyn KeyValueStoreEntry>
     ) -> &mut Box<dyn KeyValueStoreEntry>;
+    fn iter(&self) -> Box<dyn Iterator<Item = (&String, &dyn KeyValueStoreEntry)> + '_>;
+    /// Looks up `key` together with its access bookkeeping, without counting as an access.
+    fn peek(&self, key: &str) -> Option<(&dyn KeyValueStoreEntry, &AccessInfo)>;
+    /// Picks up to `count` random keys, only among keys with an expiry if `volatile_only`.
+    fn sample(&self, count: usize, volatile_only: bool) -> Vec<Sample<'_>>;
+    fn set_access_tracking(&mut self, tracking: AccessTracking);
+    fn key_count(&self) -> usize;
+    /// Number of keys with an expiry.
+    fn volatile_key_count(&self) -> usize;
+    fn clear(&mut self);
+    /// Makes a client blocked by `BLPOP` wait for an element of the list at `key`.
+    fn add_blpop_waiter(&mut self, key: &str, waiter: BlpopWaiter);
+    /// Hands the first elements of the list at `key` to the clients waiting on it, as long as
+    /// it has some, and deletes the list if that empties it. Returns how many were served.
+    fn serve_blpop_waiters(&mut self, key: &str) -> usize;
+    /// Splits the store into the stores it is made of, a single one unless it is sharded.
+    fn into_shards(self: Box<Self>) -> Vec<Box<dyn KeyValueStore>>;
+}
+
+struct StoredEntry {
+    value: Box<dyn KeyValueStoreEntry>,
+    access: AccessInfo,
+    /// Position of the key in `InMemoryKeyValueStore::keys`.
+    key_index: usize,
+    /// Position of the key in `InMemoryKeyValueStore::volatile_keys`, if it has an expiry.
+    volatile_index: Option<usize>,
 }
 
 pub struct InMemoryKeyValueStore {
-    store: HashMap<String, Box<dyn KeyValueStoreEntry>>,
+    store: HashMap<String, StoredEntry>,
+    /// Every key, and the keys with an expiry, in no particular order so that eviction can
+    /// sample them at random.
+    keys: Vec<String>,
+    volatile_keys: Vec<String>,
+    tracking: AccessTracking,
+    /// Clients blocked by `BLPOP`, by the key of the list they wait on, in the order they came.
+    blpop_waiters: HashMap<String, VecDeque<BlpopWaiter>>,
 }
 
 impl InMemoryKeyValueStore {
     pub fn new() -> Self {
         InMemoryKeyValueStore {
             store: HashMap::new(),
+            keys: Vec::new(),
+            volatile_keys: Vec::new(),
+            tracking: AccessTracking::Lru,
+            blpop_waiters: HashMap::new(),
+        }
+    }
+
+    fn add_volatile(&mut self, key: &str) -> usize {
+        self.volatile_keys.push(key.to_string());
+        self.volatile_keys.len() - 1
+    }
+
+    /// Removes `keys[index]` by moving the last key into its place.
+    fn unlink(
+        keys: &mut Vec<String>,
+        store: &mut HashMap<String, StoredEntry>,
+        index: usize,
+        position: fn(&mut StoredEntry) -> &mut usize
+    ) {
+        keys.swap_remove(index);
+        if let Some(moved) = keys.get(index) {
+            if let Some(entry) = store.get_mut(moved) {
+                *position(entry) = index;
+            }
         }
     }
 }
@@ -39,19 +217,42 @@ impl KeyValueStore for InMemoryKeyValueStore {
         key: String, 
         entry: Box<dyn KeyValueStoreEntry>
     ) -> Option<Box<dyn KeyValueStoreEntry>> {
-        self.store.insert(key, entry)
+        let replaced: Option<Box<dyn KeyValueStoreEntry>> = self.remove(&key);
+        let volatile_index: Option<usize> = entry.get_expiry().is_some().then(|| self.add_volatile(&key));
+        self.keys.push(key.clone());
+        self.store.insert(key, StoredEntry {
+            value: entry,
+            access: AccessInfo::new(self.tracking),
+            key_index: self.keys.len() - 1,
+            volatile_index,
+        });
+        replaced
     }
     
-    fn get(&self, key: &str) -> Option<&Box<dyn KeyValueStoreEntry>> {
-        self.store.get(key)
+    fn get(&self, key: &str) -> Option<&dyn KeyValueStoreEntry> {
+        self.store.get(key).map(|entry| {
+            entry.access.touch(self.tracking);
+            entry.value.as_ref()
+        })
     }
     
     fn get_mut(&mut self, key: &str) -> Option<&mut Box<dyn KeyValueStoreEntry>> {
-        self.store.get_mut(key)
+        let tracking: AccessTracking = self.tracking;
+        self.store.get_mut(key).map(|entry| {
+            entry.access.touch(tracking);
+            &mut entry.value
+        })
     }
     
     fn remove(&mut self, key: &str) -> Option<Box<dyn KeyValueStoreEntry>> {
-        self.store.remove(key)
+        let entry: StoredEntry = self.store.remove(key)?;
+        Self::unlink(&mut self.keys, &mut self.store, entry.key_index, |entry| &mut entry.key_index);
+        if let Some(index) = entry.volatile_index {
+            Self::unlink(&mut self.volatile_keys, &mut self.store, index, |entry| {
+                entry.volatile_index.as_mut().expect("keys in the volatile list have an index")
+            });
+        }
+        Some(entry.value)
     }
     
     fn ensure_exists_and_get_mut(
@@ -59,14 +260,106 @@ impl KeyValueStore for InMemoryKeyValueStore {
         key: String, 
         factory_fn: fn() -> Box<dyn KeyValueStoreEntry> 
     ) -> &mut Box<dyn KeyValueStoreEntry> { 
-        match self.store.entry(key) { 
+        let entry: &mut StoredEntry = match self.store.entry(key) { 
             Entry::Occupied(o) => o.into_mut(), 
-            Entry::Vacant(v) => v.insert(factory_fn()), 
-        } 
+            Entry::Vacant(v) => {
+                self.keys.push(v.key().clone());
+                v.insert(StoredEntry {
+                    value: factory_fn(),
+                    access: AccessInfo::new(self.tracking),
+                    key_index: self.keys.len() - 1,
+                    volatile_index: None,
+                })
+            }
+        };
+        entry.access.touch(self.tracking);
+        &mut entry.value
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (&String, &dyn KeyValueStoreEntry)> + '_> {
+        Box::new(self.store.iter().map(|(key, entry)| (key, entry.value.as_ref())))
+    }
+
+    fn peek(&self, key: &str) -> Option<(&dyn KeyValueStoreEntry, &AccessInfo)> {
+        self.store.get(key).map(|entry| (entry.value.as_ref(), &entry.access))
+    }
+
+    fn sample(&self, count: usize, volatile_only: bool) -> Vec<Sample<'_>> {
+        let keys: &[String] = if volatile_only { &self.volatile_keys } else { &self.keys };
+        if keys.is_empty() {
+            return Vec::new();
+        }
+
+        (0..count)
+            .map(|_| &keys[random::random_below(keys.len() as u64) as usize])
+            .filter_map(|key| self.store.get_key_value(key))
+            .map(|(key, entry)| (key, entry.value.as_ref(), &entry.access))
+            .collect()
+    }
+
+    fn set_access_tracking(&mut self, tracking: AccessTracking) {
+        self.tracking = tracking;
+    }
+
+    fn key_count(&self) -> usize {
+        self.keys.len()
+    }
+
+    fn volatile_key_count(&self) -> usize {
+        self.volatile_keys.len()
+    }
+
+    fn clear(&mut self) {
+        self.store.clear();
+        self.keys.clear();
+        self.volatile_keys.clear();
+    }
+
+    fn add_blpop_waiter(&mut self, key: &str, waiter: BlpopWaiter) {
+        let waiters: &mut VecDeque<BlpopWaiter> = self.blpop_waiters.entry(key.to_string()).or_default();
+        // Clients served by another list or gone are only dropped here or when served.
+        waiters.retain(BlpopWaiter::waiting);
+        waiters.push_back(waiter);
+    }
+
+    fn serve_blpop_waiters(&mut self, key: &str) -> usize {
+        let Some(waiters) = self.blpop_waiters.get_mut(key) else {
+            return 0;
+        };
+        let Some(entry) = self.store.get_mut(key) else {
+            return 0;
+        };
+
+        let mut served: usize = 0;
+        while let Some(waiter) = waiters.pop_front() {
+            let Ok(value) = entry.value.pop_front() else {
+                waiters.push_front(waiter);
+                break;
+            };
+            match waiter.serve(value) {
+                Ok(()) => served += 1,
+                Err(value) => {
+                    let _ = entry.value.prepend(vec![value]);
+                }
+            }
+        }
+
+        if waiters.is_empty() {
+            self.blpop_waiters.remove(key);
+        }
+        if served > 0 && entry.value.len() == Ok(0) {
+            self.remove(key);
+        }
+        served
+    }
+
+    fn into_shards(self: Box<Self>) -> Vec<Box<dyn KeyValueStore>> {
+        vec![self]
     }
 }
 
 pub trait KeyValueStoreEntry: Send {
+    fn type_name(&self) -> &'static str;
     fn get_value(&self) -> Result<&String, &'static str>;
     fn get_expiry(&self) -> &Option<SystemTime>;
     fn _push(&mut self, value: String) -> Result<usize, &'static str>;
@@ -74,9 +367,13 @@ pub trait KeyValueStoreEntry: Send {
     fn prepend(&mut self, other: Vec<String>) -> Result<usize, &'static str>;
     fn pop_front(&mut self) -> Result<String, &'static str>;
     fn pop_front_amount(&mut self, amount: usize) -> Result<Vec<String>, &'static str>;
-    fn get_subslice(&self, start: isize, end: isize) -> Result<Option<&[String]>, &'static str>;
+    fn get_subslice(&self, start: isize, end: isize) -> Result<Vec<String>, &'static str>;
     fn len(&self) -> Result<usize, &'static str>;
-    fn generate_blpop_waiter(&mut self) -> Result<oneshot::Receiver<String>, &'static str>;
+    /// Name of the representation, as reported by `OBJECT ENCODING`.
+    fn encoding(&self) -> &'static str;
+    /// Estimate of the bytes the value takes, including the entry itself. Collections
+    /// extrapolate from their first `samples` elements, or look at all of them if it is 0.
+    fn memory_usage(&self, samples: usize) -> usize;
 }
 
 pub struct KeyValueStoreStringEntry {
@@ -85,6 +382,10 @@ pub struct KeyValueStoreStringEntry {
 }
 
 impl KeyValueStoreEntry for KeyValueStoreStringEntry {
+    fn type_name(&self) -> &'static str {
+        "string"
+    }
+
     fn get_value(&self) -> Result<&String, &'static str> {
         Ok(&self.value)
     }
@@ -113,7 +414,7 @@ impl KeyValueStoreEntry for KeyValueStoreStringEntry {
         Err("String value, not list - pop to a value is not allowed")
     }
 
-    fn get_subslice(&self, _start: isize, _end: isize) -> Result<Option<&[String]>, &'static str> {
+    fn get_subslice(&self, _start: isize, _end: isize) -> Result<Vec<String>, &'static str> {
         Err("String value, not list - getting a subslice is not allowed")
     }
 
@@ -121,52 +422,160 @@ impl KeyValueStoreEntry for KeyValueStoreStringEntry {
         Ok(self.value.len())
     }
 
-    fn generate_blpop_waiter(&mut self) -> Result<oneshot::Receiver<String>, &'static str> {
-        Err("String value, not list - adding a pop waiter to a value is not allowed")
+    fn encoding(&self) -> &'static str {
+        // Like Redis, strings that are the canonical form of a 64 bit integer count as `int`,
+        // and short strings as `embstr`.
+        if self.value.len() <= 20 && self.value.parse::<i64>().is_ok_and(|number| number.to_string() == self.value) {
+            "int"
+        } else if self.value.len() <= EMBSTR_SIZE_LIMIT {
+            "embstr"
+        } else {
+            "raw"
+        }
+    }
+
+    fn memory_usage(&self, _samples: usize) -> usize {
+        size_of::<Self>() + self.value.capacity()
     }
 }
 
+/// The key of the list that served a client blocked by `BLPOP`, and the element it got.
+pub type Served = (String, String);
+
+/// A client blocked by `BLPOP` on one of its lists. The client waits on all of them at once,
+/// and is served by the first one getting an element; the others then pass it over.
+pub struct BlpopWaiter {
+    key: String,
+    tx: Arc<Mutex<Option<oneshot::Sender<Served>>>>,
+}
+
+impl BlpopWaiter {
+    /// A waiter on no list yet, and the receiver getting the key and element it is served.
+    pub fn new() -> (Self, oneshot::Receiver<Served>) {
+        let (tx, rx): (oneshot::Sender<Served>, oneshot::Receiver<Served>) = oneshot::channel();
+        (BlpopWaiter { key: String::new(), tx: Arc::new(Mutex::new(Some(tx))) }, rx)
+    }
+
+    /// The same client, waiting on the list at `key`.
+    pub fn on(&self, key: &str) -> Self {
+        BlpopWaiter { key: key.to_string(), tx: self.tx.clone() }
+    }
+
+    /// Whether the client still waits, neither served nor gone.
+    fn waiting(&self) -> bool {
+        self.tx.lock().unwrap_or_else(PoisonError::into_inner).as_ref().is_some_and(|tx| !tx.is_closed())
+    }
+
+    /// Hands `value`, popped from the list, to the client, or gives it back when the client
+    /// was served already or stopped waiting.
+    fn serve(&self, value: String) -> Result<(), String> {
+        match self.tx.lock().unwrap_or_else(PoisonError::into_inner).take() {
+            Some(tx) => tx.send((self.key.clone(), value)).map_err(|(_, value)| value),
+            None => Err(value),
+        }
+    }
+}
+
+/// How a list is stored: a single listpack while it is small, a quicklist once it outgrows
+/// `list-max-listpack-size`.
+enum ListEncoding {
+    Listpack(Listpack),
+    Quicklist(Quicklist),
+}
+
 pub struct KeyValueStoreListEntry {
-    list: Vec<String>,
+    list: ListEncoding,
     expiry: Option<SystemTime>,
-    blpop_waiting_channels: VecDeque<oneshot::Sender<String>>
 }
 
 impl KeyValueStoreListEntry {
     pub fn new() -> Self {
         KeyValueStoreListEntry {
-            list: Vec::new(),
+            list: ListEncoding::Listpack(Listpack::new()),
             expiry: None,
-            blpop_waiting_channels: VecDeque::new(),
         }
     }
     
-    pub fn new_boxed() -> Box<dyn KeyValueStoreEntry> {
-        Box::new(KeyValueStoreListEntry::new())
-    }
-    
     pub fn _new_with_expiry(expiry: Option<SystemTime>) -> Self {
         KeyValueStoreListEntry {
-            list: Vec::new(),
             expiry,
-            blpop_waiting_channels: VecDeque::new(),
+            ..KeyValueStoreListEntry::new()
         }
     }
     
-    fn check_for_blpop_waiters(&mut self) {
-        while !self.list.is_empty() {
-            if let Some(tx) = self.blpop_waiting_channels.pop_front() {
-                if tx.send(self.list[0].clone()).is_ok() {
-                    let _ = self.list.remove(0);
+    pub fn with_values(list: Vec<String>, expiry: Option<SystemTime>) -> Self {
+        let mut entry: KeyValueStoreListEntry = KeyValueStoreListEntry::_new_with_expiry(expiry);
+        for value in list {
+            entry.push_back(value);
+        }
+        entry
+    }
+
+    fn length(&self) -> usize {
+        match &self.list {
+            ListEncoding::Listpack(listpack) => listpack.len(),
+            ListEncoding::Quicklist(quicklist) => quicklist.len(),
+        }
+    }
+
+    fn push_back(&mut self, value: String) {
+        let limits: ListLimits = ListLimits::current();
+        match &mut self.list {
+            ListEncoding::Listpack(listpack) => listpack.push_back(value.as_bytes()),
+            ListEncoding::Quicklist(quicklist) => quicklist.push_back(value.as_bytes(), limits),
+        }
+        self.convert_if_exceeded(limits);
+    }
+
+    fn push_front(&mut self, value: String) {
+        let limits: ListLimits = ListLimits::current();
+        match &mut self.list {
+            ListEncoding::Listpack(listpack) => listpack.push_front(value.as_bytes()),
+            ListEncoding::Quicklist(quicklist) => quicklist.push_front(value.as_bytes(), limits),
+        }
+        self.convert_if_exceeded(limits);
+    }
+
+    fn pop_front_value(&mut self) -> Option<String> {
+        let limits: ListLimits = ListLimits::current();
+        let value: Option<Vec<u8>> = match &mut self.list {
+            ListEncoding::Listpack(listpack) => listpack.pop_front(),
+            ListEncoding::Quicklist(quicklist) => quicklist.pop_front(limits),
+        };
+        self.convert_if_shrunk(limits);
+        value.map(into_string)
+    }
+
+    /// Moves a listpack that grew over the limit into a quicklist.
+    fn convert_if_exceeded(&mut self, limits: ListLimits) {
+        if let ListEncoding::Listpack(listpack) = &self.list {
+            if limits.exceeded(listpack.size(), listpack.len()) {
+                let mut quicklist: Quicklist = Quicklist::new();
+                for value in listpack.iter() {
+                    quicklist.push_back(&value, limits);
                 }
-            } else {
-                break;
+                self.list = ListEncoding::Quicklist(quicklist);
+            }
+        }
+    }
+
+    /// Moves a quicklist back into a single listpack once it shrank to half the limit, so a
+    /// list hovering around the limit does not keep converting back and forth.
+    fn convert_if_shrunk(&mut self, limits: ListLimits) {
+        if let ListEncoding::Quicklist(quicklist) = &mut self.list {
+            if quicklist.fits_listpack(limits) {
+                let quicklist: Quicklist = std::mem::replace(quicklist, Quicklist::new());
+                self.list = ListEncoding::Listpack(quicklist.into_listpack());
             }
         }
     }
 }
 
 impl KeyValueStoreEntry for KeyValueStoreListEntry {
+    fn type_name(&self) -> &'static str {
+        "list"
+    }
+
     fn get_value(&self) -> Result<&String, &'static str> {
         Err("Not yet implemented")
     }
@@ -176,38 +585,41 @@ impl KeyValueStoreEntry for KeyValueStoreListEntry {
     }
 
     fn _push(&mut self, value: String) -> Result<usize, &'static str> {
-        self.list.push(value);
-        let length: usize = self.list.len();
-        self.check_for_blpop_waiters();
+        self.push_back(value);
+        let length: usize = self.length();
         Ok(length)
     }
 
     fn append(&mut self, other: &mut Vec<String>) -> Result<usize, &'static str> {
-        self.list.append(other);
-        let length: usize = self.list.len();
-        self.check_for_blpop_waiters();
+        for value in other.drain(..) {
+            self.push_back(value);
+        }
+        let length: usize = self.length();
         Ok(length)
     }
 
-    fn prepend(&mut self, mut other: Vec<String>) -> Result<usize, &'static str> {
-        other.append(&mut self.list);
-        self.list = other;
-        let length: usize = self.list.len();
-        self.check_for_blpop_waiters();
+    fn prepend(&mut self, other: Vec<String>) -> Result<usize, &'static str> {
+        for value in other.into_iter().rev() {
+            self.push_front(value);
+        }
+        let length: usize = self.length();
         Ok(length)
     }
 
     fn pop_front(&mut self) -> Result<String, &'static str> {
-        Ok(self.list.remove(0))
+        self.pop_front_value().ok_or("List is empty")
     }
 
-    fn pop_front_amount(&mut self, mut amount: usize) -> Result<Vec<String>, &'static str> {
-        amount = min(amount, self.list.len());
-        Ok(self.list.drain(..amount).collect())
+    fn pop_front_amount(&mut self, amount: usize) -> Result<Vec<String>, &'static str> {
+        let amount: usize = min(amount, self.length());
+        Ok((0..amount).filter_map(|_| self.pop_front_value()).collect())
     }
 
-    fn get_subslice(&self, start: isize, end: isize) -> Result<Option<&[String]>, &'static str> {
-        let list_length: usize = self.list.len();
+    fn get_subslice(&self, start: isize, end: isize) -> Result<Vec<String>, &'static str> {
+        let list_length: usize = self.length();
+        if list_length == 0 {
+            return Ok(Vec::new());
+        }
 
         let start: usize = normalize_index(start, list_length);
         let mut end: usize = normalize_index(end, list_length);
@@ -215,25 +627,45 @@ impl KeyValueStoreEntry for KeyValueStoreListEntry {
         if list_length <= end {
             end = list_length - 1;
         }
+        if start > end {
+            return Ok(Vec::new());
+        }
 
-        Ok(self.list.get(start..=end))
+        let count: usize = end - start + 1;
+        let values: Vec<Vec<u8>> = match &self.list {
+            ListEncoding::Listpack(listpack) => listpack.iter().skip(start).take(count).collect(),
+            ListEncoding::Quicklist(quicklist) => quicklist.range(start, count),
+        };
+        Ok(values.into_iter().map(into_string).collect())
     }
 
     fn len(&self) -> Result<usize, &'static str> {
-        Ok(self.list.len())
+        Ok(self.length())
     }
 
-    fn generate_blpop_waiter(&mut self) -> Result<oneshot::Receiver<String>, &'static str> {
-        let (tx, rx): (oneshot::Sender<String>, oneshot::Receiver<String>) = oneshot::channel();
-        self.blpop_waiting_channels.push_back(tx);
-        self.check_for_blpop_waiters();
-        Ok(rx)
+    fn encoding(&self) -> &'static str {
+        match self.list {
+            ListEncoding::Listpack(_) => "listpack",
+            ListEncoding::Quicklist(_) => "quicklist",
+        }
     }
+
+    fn memory_usage(&self, samples: usize) -> usize {
+        size_of::<Self>() + match &self.list {
+            ListEncoding::Listpack(listpack) => listpack.memory_usage(),
+            ListEncoding::Quicklist(quicklist) => quicklist.memory_usage(samples),
+        }
+    }
+}
+
+/// List elements are only ever created from strings, so they are valid UTF-8.
+fn into_string(bytes: Vec<u8>) -> String {
+    String::from_utf8(bytes).unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned())
 }
 
 fn normalize_index(index: isize, list_length: usize) -> usize {
     if index < 0 {
-        let normalized_index: usize = (-1 * index) as usize;
+        let normalized_index: usize = (-index) as usize;
         if list_length < normalized_index {
             return 0;
         }
@@ -241,3 +673,41 @@ fn normalize_index(index: isize, list_length: usize) -> usize {
     }
     index as usize
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::oneshot;
+    use super::{BlpopWaiter, InMemoryKeyValueStore, KeyValueStore, KeyValueStoreListEntry, Served};
+
+    fn list(values: &[&str]) -> Box<KeyValueStoreListEntry> {
+        Box::new(KeyValueStoreListEntry::with_values(values.iter().map(|value| value.to_string()).collect(), None))
+    }
+
+    #[test]
+    fn a_client_blocked_on_several_lists_is_served_once() {
+        let mut store: InMemoryKeyValueStore = InMemoryKeyValueStore::new();
+        let (waiter, mut rx): (BlpopWaiter, oneshot::Receiver<Served>) = BlpopWaiter::new();
+        store.add_blpop_waiter("first", waiter.on("first"));
+        store.add_blpop_waiter("second", waiter.on("second"));
+
+        store.insert(String::from("second"), list(&["a"]));
+        store.insert(String::from("first"), list(&["b"]));
+        assert_eq!(store.serve_blpop_waiters("second"), 1);
+        assert_eq!(store.serve_blpop_waiters("first"), 0);
+        assert_eq!(rx.try_recv().unwrap(), (String::from("second"), String::from("a")));
+        assert_eq!(store.get("first").unwrap().get_subslice(0, -1).unwrap(), vec![String::from("b")]);
+        assert!(store.get("second").is_none());
+    }
+
+    #[test]
+    fn a_waiter_that_gave_up_leaves_the_element() {
+        let mut store: InMemoryKeyValueStore = InMemoryKeyValueStore::new();
+        let (waiter, rx): (BlpopWaiter, oneshot::Receiver<Served>) = BlpopWaiter::new();
+        store.add_blpop_waiter("list", waiter.on("list"));
+        drop(rx);
+
+        store.insert(String::from("list"), list(&["a"]));
+        assert_eq!(store.serve_blpop_waiters("list"), 0);
+        assert_eq!(store.get("list").unwrap().get_subslice(0, -1).unwrap(), vec![String::from("a")]);
+    }
+}
@@ -0,0 +1,210 @@
+use crate::rdb::read_le_int;
+
+const HEADER_LENGTH: usize = 6;
+const END: u8 = 0xFF;
+/// Element count stored in the header once the real count no longer fits in 16 bits.
+const UNKNOWN_COUNT: u16 = u16::MAX;
+const CORRUPT: &str = "Invalid listpack";
+
+/// A listpack, the compact encoding Redis 7 uses for small lists and for the nodes of
+/// quicklists: a single buffer holding the total size, the element count and the elements
+/// one after the other, with integers in their shortest binary form.
+pub struct Listpack {
+    bytes: Vec<u8>,
+}
+
+impl Listpack {
+    pub fn new() -> Self {
+        let mut listpack: Listpack = Listpack { bytes: vec![0; HEADER_LENGTH] };
+        listpack.bytes.push(END);
+        listpack.update_header(0);
+        listpack
+    }
+
+    /// Wraps a listpack read from an RDB file or a `DUMP` payload, checking its structure.
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, &'static str> {
+        let total_length: usize = u32::from_le_bytes(bytes.get(..4).ok_or(CORRUPT)?.try_into().unwrap()) as usize;
+        if total_length != bytes.len() || total_length <= HEADER_LENGTH || bytes.last() != Some(&END) {
+            return Err(CORRUPT);
+        }
+
+        let mut position: usize = HEADER_LENGTH;
+        while bytes[position] != END {
+            position += decode(&bytes, position)?.1;
+            if position >= bytes.len() {
+                return Err(CORRUPT);
+            }
+        }
+        if position != bytes.len() - 1 {
+            return Err(CORRUPT);
+        }
+
+        Ok(Listpack { bytes })
+    }
+
+    pub fn len(&self) -> usize {
+        match u16::from_le_bytes([self.bytes[4], self.bytes[5]]) {
+            UNKNOWN_COUNT => self.iter().count(),
+            count => count as usize,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes[HEADER_LENGTH] == END
+    }
+
+    /// Size of the encoded listpack in bytes.
+    pub fn size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        self.bytes.capacity()
+    }
+
+    /// The elements from the first to the last. Integers are returned in their decimal form.
+    pub fn iter(&self) -> impl Iterator<Item = Vec<u8>> + '_ {
+        let mut position: usize = HEADER_LENGTH;
+        std::iter::from_fn(move || {
+            if self.bytes[position] == END {
+                return None;
+            }
+            let (element, length): (Vec<u8>, usize) = decode(&self.bytes, position).expect("listpacks are validated");
+            position += length;
+            Some(element)
+        })
+    }
+
+    pub fn push_back(&mut self, value: &[u8]) {
+        let count: usize = self.len();
+        let end: usize = self.bytes.len() - 1;
+        self.bytes.splice(end..end, encode(value));
+        self.update_header(count + 1);
+    }
+
+    pub fn push_front(&mut self, value: &[u8]) {
+        let count: usize = self.len();
+        self.bytes.splice(HEADER_LENGTH..HEADER_LENGTH, encode(value));
+        self.update_header(count + 1);
+    }
+
+    pub fn pop_front(&mut self) -> Option<Vec<u8>> {
+        if self.is_empty() {
+            return None;
+        }
+        let count: usize = self.len();
+        let (element, length): (Vec<u8>, usize) = decode(&self.bytes, HEADER_LENGTH).expect("listpacks are validated");
+        self.bytes.drain(HEADER_LENGTH..HEADER_LENGTH + length);
+        self.update_header(count - 1);
+        Some(element)
+    }
+
+    fn update_header(&mut self, count: usize) {
+        let total_length: u32 = self.bytes.len() as u32;
+        let count: u16 = if count < UNKNOWN_COUNT as usize { count as u16 } else { UNKNOWN_COUNT };
+        self.bytes[..4].copy_from_slice(&total_length.to_le_bytes());
+        self.bytes[4..HEADER_LENGTH].copy_from_slice(&count.to_le_bytes());
+    }
+}
+
+/// Decodes the element at `position`, returning it with the length of its whole entry.
+fn decode(bytes: &[u8], position: usize) -> Result<(Vec<u8>, usize), &'static str> {
+    let encoding: u8 = *bytes.get(position).ok_or(CORRUPT)?;
+    let data = |offset: usize, length: usize| bytes.get(position + offset..position + offset + length).ok_or(CORRUPT);
+
+    let (element, entry_length): (Vec<u8>, usize) = if encoding & 0x80 == 0 {
+        ((encoding & 0x7F).to_string().into_bytes(), 1)
+    } else if encoding & 0xC0 == 0x80 {
+        let length: usize = (encoding & 0x3F) as usize;
+        (data(1, length)?.to_vec(), 1 + length)
+    } else if encoding & 0xE0 == 0xC0 {
+        let raw: i64 = (((encoding & 0x1F) as i64) << 8) | data(1, 1)?[0] as i64;
+        let value: i64 = if raw >= 1 << 12 { raw - (1 << 13) } else { raw };
+        (value.to_string().into_bytes(), 2)
+    } else if encoding & 0xF0 == 0xE0 {
+        let length: usize = (((encoding & 0x0F) as usize) << 8) | data(1, 1)?[0] as usize;
+        (data(2, length)?.to_vec(), 2 + length)
+    } else {
+        match encoding {
+            0xF0 => {
+                let length: usize = u32::from_le_bytes(data(1, 4)?.try_into().unwrap()) as usize;
+                (data(5, length)?.to_vec(), 5 + length)
+            }
+            0xF1 => (read_le_int(data(1, 2)?).to_string().into_bytes(), 3),
+            0xF2 => (read_le_int(data(1, 3)?).to_string().into_bytes(), 4),
+            0xF3 => (read_le_int(data(1, 4)?).to_string().into_bytes(), 5),
+            0xF4 => (read_le_int(data(1, 8)?).to_string().into_bytes(), 9),
+            _ => return Err(CORRUPT),
+        }
+    };
+
+    Ok((element, entry_length + backlen_size(entry_length)))
+}
+
+/// Encodes an element together with its back length. Like Redis, strings holding the
+/// canonical form of a 64 bit integer are stored as integers.
+fn encode(value: &[u8]) -> Vec<u8> {
+    let integer: Option<i64> = std::str::from_utf8(value).ok()
+        .filter(|text| text.len() <= 20)
+        .and_then(|text| text.parse::<i64>().ok().filter(|number| number.to_string() == text));
+
+    let mut entry: Vec<u8> = match integer {
+        Some(number @ 0..=127) => vec![number as u8],
+        Some(number @ -4096..=4095) => {
+            let raw: u16 = (number as u16) & 0x1FFF;
+            vec![0xC0 | (raw >> 8) as u8, raw as u8]
+        }
+        Some(number) => {
+            let (encoding, width): (u8, usize) = if i16::try_from(number).is_ok() {
+                (0xF1, 2)
+            } else if (-(1 << 23)..1 << 23).contains(&number) {
+                (0xF2, 3)
+            } else if i32::try_from(number).is_ok() {
+                (0xF3, 4)
+            } else {
+                (0xF4, 8)
+            };
+            let mut entry: Vec<u8> = vec![encoding];
+            entry.extend_from_slice(&number.to_le_bytes()[..width]);
+            entry
+        }
+        None => {
+            let length: usize = value.len();
+            let mut entry: Vec<u8> = if length < 64 {
+                vec![0x80 | length as u8]
+            } else if length < 4096 {
+                vec![0xE0 | (length >> 8) as u8, length as u8]
+            } else {
+                let mut header: Vec<u8> = vec![0xF0];
+                header.extend_from_slice(&(length as u32).to_le_bytes());
+                header
+            };
+            entry.extend_from_slice(value);
+            entry
+        }
+    };
+
+    // Every entry ends with its own length, in 7-bit groups read from the right, so the
+    // listpack can also be walked backwards.
+    let length: usize = entry.len();
+    let groups: usize = backlen_size(length);
+    for group in (0..groups).rev() {
+        let bits: u8 = ((length >> (7 * group)) & 0x7F) as u8;
+        entry.push(if group == groups - 1 { bits } else { bits | 0x80 });
+    }
+    entry
+}
+
+fn backlen_size(entry_length: usize) -> usize {
+    match entry_length {
+        0..=127 => 1,
+        128..16383 => 2,
+        16383..2097151 => 3,
+        2097151..268435455 => 4,
+        _ => 5,
+    }
+}
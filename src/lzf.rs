@@ -0,0 +1,156 @@
+/// Size of the window back references can reach into.
+const MAX_DISTANCE: usize = 1 << 13;
+/// Longest back reference the format can express.
+const MAX_REFERENCE: usize = (1 << 8) + (1 << 3);
+/// Longest run of literal bytes behind a single control byte.
+const MAX_LITERALS: usize = 1 << 5;
+const HASH_LOG: u32 = 13;
+
+/// Compresses a block with LZF, as Redis does for quicklist nodes. Returns `None` if the
+/// result would not be smaller than the input.
+pub fn compress(input: &[u8]) -> Option<Vec<u8>> {
+    let mut output: Vec<u8> = Vec::with_capacity(input.len());
+    let mut literals: Vec<u8> = Vec::with_capacity(MAX_LITERALS);
+    // Last position each three-byte sequence was seen at.
+    let mut table: Vec<Option<usize>> = vec![None; 1 << HASH_LOG];
+    let mut position: usize = 0;
+
+    while position + 2 < input.len() {
+        let hash: usize = (u32::from_le_bytes([input[position], input[position + 1], input[position + 2], 0])
+            .wrapping_mul(2654435761) >> (32 - HASH_LOG)) as usize;
+        let candidate: Option<usize> = table[hash].replace(position);
+
+        let length: usize = match candidate {
+            Some(candidate) if position - candidate <= MAX_DISTANCE
+                && input[candidate..candidate + 3] == input[position..position + 3] => {
+                let limit: usize = MAX_REFERENCE.min(input.len() - position);
+                let mut length: usize = 3;
+                while length < limit && input[candidate + length] == input[position + length] {
+                    length += 1;
+                }
+                flush_literals(&mut output, &mut literals);
+
+                let offset: usize = position - candidate - 1;
+                let encoded_length: usize = length - 2;
+                if encoded_length < 7 {
+                    output.push(((encoded_length << 5) | (offset >> 8)) as u8);
+                } else {
+                    output.push(((7 << 5) | (offset >> 8)) as u8);
+                    output.push((encoded_length - 7) as u8);
+                }
+                output.push(offset as u8);
+                length
+            }
+            _ => {
+                literals.push(input[position]);
+                if literals.len() == MAX_LITERALS {
+                    flush_literals(&mut output, &mut literals);
+                }
+                1
+            }
+        };
+
+        position += length;
+        if output.len() >= input.len() {
+            return None;
+        }
+    }
+
+    for &byte in &input[position..] {
+        literals.push(byte);
+        if literals.len() == MAX_LITERALS {
+            flush_literals(&mut output, &mut literals);
+        }
+    }
+    flush_literals(&mut output, &mut literals);
+
+    (output.len() < input.len()).then_some(output)
+}
+
+fn flush_literals(output: &mut Vec<u8>, literals: &mut Vec<u8>) {
+    if !literals.is_empty() {
+        output.push((literals.len() - 1) as u8);
+        output.append(literals);
+    }
+}
+
+/// Decompresses an LZF block, the compression Redis applies to long strings in RDB files and
+/// `DUMP` payloads. `length` is the uncompressed size recorded next to the block.
+pub fn decompress(input: &[u8], length: usize) -> Result<Vec<u8>, &'static str> {
+    const CORRUPT: &str = "Invalid LZF compressed string";
+
+    // `length` is read from untrusted input, so no more is reserved than the block can expand to.
+    let mut output: Vec<u8> = Vec::with_capacity(length.min(input.len().saturating_mul(MAX_REFERENCE)));
+    let mut position: usize = 0;
+
+    while position < input.len() {
+        let control: usize = input[position] as usize;
+        position += 1;
+
+        if control < 1 << 5 {
+            let literal: &[u8] = input.get(position..position + control + 1).ok_or(CORRUPT)?;
+            output.extend_from_slice(literal);
+            position += control + 1;
+            continue;
+        }
+
+        let mut run: usize = control >> 5;
+        if run == 7 {
+            run += *input.get(position).ok_or(CORRUPT)? as usize;
+            position += 1;
+        }
+        let distance: usize = ((control & 0x1F) << 8) + *input.get(position).ok_or(CORRUPT)? as usize + 1;
+        position += 1;
+
+        let start: usize = output.len().checked_sub(distance).ok_or(CORRUPT)?;
+        // Back references may overlap the bytes they produce, so copy one byte at a time.
+        for index in start..start + run + 2 {
+            output.push(output[index]);
+        }
+    }
+
+    if output.len() != length {
+        return Err(CORRUPT);
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compress, decompress};
+
+    fn round_trip(input: &[u8]) {
+        let compressed: Vec<u8> = compress(input).expect("input is compressible");
+        assert!(compressed.len() < input.len());
+        assert_eq!(decompress(&compressed, input.len()).unwrap(), input);
+    }
+
+    #[test]
+    fn round_trips_compressible_input() {
+        round_trip(&[b'a'; 1000]);
+        round_trip(b"abcabcabcabcabcabcabcabcabcabcabcabcabcabc");
+        round_trip("the quick brown fox jumps over the lazy dog. ".repeat(50).as_bytes());
+        let mixed: Vec<u8> = (0..5000u32).map(|index| (index % 7 * 31 + index / 100) as u8).collect();
+        round_trip(&mixed);
+    }
+
+    #[test]
+    fn leaves_incompressible_input() {
+        assert_eq!(compress(b"abcdefgh"), None);
+        assert_eq!(compress(b""), None);
+    }
+
+    #[test]
+    fn decompresses_literals_and_overlapping_references() {
+        // Three literals, then a reference one byte back repeating the last byte four times.
+        assert_eq!(decompress(b"\x02abc\x40\x00", 7).unwrap(), b"abccccc");
+    }
+
+    #[test]
+    fn rejects_corrupt_blocks() {
+        assert!(decompress(b"\x02abc", 4).is_err());
+        assert!(decompress(b"\x05ab", 6).is_err());
+        assert!(decompress(b"\x00a\x20\x05", 4).is_err());
+        assert!(decompress(b"\x00a", 1 << 40).is_err());
+    }
+}
@@ -1,35 +1,313 @@
+mod acl;
+mod allocator;
+mod client;
+mod cluster;
 mod command;
+mod config;
+mod eviction;
+mod expire;
+mod glob;
 mod key_value_store;
+mod listpack;
+mod notify;
+mod lzf;
 mod parser;
+mod pubsub;
+mod quicklist;
+mod random;
+mod rdb;
+mod replication;
+mod reply;
+mod scripting;
+mod server;
+mod sha1;
+mod sha256;
+mod shard;
+mod stats;
+mod tls;
+mod tracking;
 
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::{mpsc, oneshot};
-use crate::command::{CommandRunner, DataRequester, Reply};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::os::fd::AsRawFd;
+use std::sync::{Arc, MutexGuard};
+use std::time::{Instant, SystemTime};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, UnixListener};
+use tokio::sync::mpsc;
+use crate::command::{CommandFlag, CommandSpec, DataRequester, Handler, Reply};
+use crate::acl::{Acl, Denial};
+use crate::client::{Activity, Connection, READ_BUFFER_SIZE};
+use crate::cluster::ClusterState;
+use crate::config::Config;
 use crate::key_value_store::{InMemoryKeyValueStore, KeyValueStore, KeyValueStoreStringEntry};
-use crate::parser::redis_parser;
+use crate::parser::{lookup_command, parse_frame_into, Arguments, LookupError};
+use crate::quicklist::ListLimits;
+use crate::replication::ReplicationState;
+use crate::reply::{Protocol, ReplyWriter, Value};
+use crate::scripting::RestorePolicy;
+use crate::server::{ClientInfo, Propagation, ServerState, Session};
+use crate::shard::{Router, ShardedKeyValueStore};
+use crate::stats::{NET_INPUT_BYTES, NET_OUTPUT_BYTES};
+
+#[global_allocator]
+static ALLOCATOR: allocator::CountingAllocator = allocator::CountingAllocator;
 
 #[tokio::main]
 async fn main() {
     println!("Logs from your program will appear here!");
 
-    let listener: TcpListener = TcpListener::bind("127.0.0.1:6379").await.unwrap();
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    let config: Config = match Config::from_args(&arguments) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("\n*** FATAL CONFIG FILE ERROR ***\n{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let acl: Acl = match Acl::load(&config) {
+        Ok(acl) => acl,
+        Err(e) => {
+            println!("\n*** FATAL ACL FILE ERROR ***\n{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(e) = tls::configure(&config) {
+        println!("\n*** FATAL TLS CONFIGURATION ERROR ***\n{}", e);
+        std::process::exit(1);
+    }
+
+    let mut listeners: Vec<Listener> = Vec::new();
+    if config.port != 0 {
+        listeners.extend(bind_listeners(&config, config.port).await.into_iter().map(Listener::Tcp));
+    }
+    if config.tls_port != 0 {
+        listeners.extend(bind_listeners(&config, config.tls_port).await.into_iter().map(Listener::Tls));
+    }
+    if let Some(path) = config.unixsocket.as_deref() {
+        listeners.push(Listener::Unix(bind_unix_socket(path, config.unixsocketperm)));
+    }
+    if listeners.is_empty() {
+        println!("Configured to not listen anywhere, exiting.");
+        std::process::exit(1);
+    }
     let (tx, rx) = mpsc::channel::<Msg>(100);
+    // The backlog is accounted separately, like Redis which only creates it later on.
+    allocator::mark_startup();
 
-    tokio::spawn(async move {
-        data_manager(rx).await;
+    // A replica connecting over TLS tells its master to reach it on the TLS port too.
+    let announced_port: u16 = if config.tls_replication { config.tls_port } else { config.port };
+    let mut replication: ReplicationState =
+        ReplicationState::new(config.repl_backlog_size, announced_port, tx.clone());
+    let cluster: Option<ClusterState> = config.cluster_enabled.then(|| {
+        ClusterState::load_or_create(
+            config.cluster_config_file.clone(), config.cluster_node_timeout, "127.0.0.1", config.port)
+            .expect("could not load the cluster configuration")
     });
 
-    loop {
-        let stream = listener.accept().await;
+    let replica_of: Option<(String, u16)> = match cluster.as_ref().and_then(|cluster| cluster.replicated_master()) {
+        Some(master) => Some((master.ip.clone(), master.port)),
+        None => config.replica_of.clone(),
+    };
+    if let Some((host, port)) = replica_of {
+        replication.replicate_from(host, port);
+    }
+    if let Some(cluster) = cluster.as_ref() {
+        tokio::spawn(cluster::bus::run(cluster.myself().bus_port, tx.clone()));
+    }
+
+    let (store, libraries): (Box<dyn KeyValueStore>, Vec<String>) = load_dataset(&config);
+    let mut shards: Vec<Box<dyn KeyValueStore>> = store.into_shards();
+    let server: ServerState = ServerState::new(config, replication, cluster, acl);
+    if let Err(e) = server.scripting.lock().restore_libraries(libraries, RestorePolicy::Append) {
+        println!("could not load the function libraries: {}", e);
+        std::process::exit(1);
+    }
+    let router: Router = if shards.len() == 1 {
+        let key_value_store: Box<dyn KeyValueStore> = shards.remove(0);
+        tokio::spawn(data_manager(rx, key_value_store, server));
+        Router::unsharded(tx)
+    } else {
+        shard::spawn(rx, tx, shards, server)
+    };
+
+    let accept_loops: Vec<tokio::task::JoinHandle<()>> = listeners.into_iter()
+        .map(|listener| tokio::spawn(accept_clients(listener, router.clone())))
+        .collect();
+    for accept_loop in accept_loops {
+        let _ = accept_loop.await;
+    }
+}
+
+/// Binds `port` on every address of the `bind` parameter. Addresses prefixed with `-` are
+/// optional: failing to bind them, e.g. `::1` on a host without IPv6, is not fatal.
+async fn bind_listeners(config: &Config, port: u16) -> Vec<TcpListener> {
+    let mut listeners: Vec<TcpListener> = Vec::new();
+
+    for address in &config.bind {
+        let (address, optional) = match address.strip_prefix('-') {
+            Some(address) => (address, true),
+            None => (address.as_str(), false),
+        };
+        let host: &str = match address {
+            "*" => "0.0.0.0",
+            "::*" => "::",
+            host => host,
+        };
 
-        match stream {
-            Ok((socket, _addr)) => {
+        match TcpListener::bind((host, port)).await {
+            Ok(listener) => listeners.push(listener),
+            Err(e) if optional => println!("skipping optional bind address {}: {}", address, e),
+            Err(e) => {
+                println!("could not bind {}:{}: {}", address, port, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    listeners
+}
+
+/// Binds the Unix socket at `path`, replacing the socket a previous run may have left
+/// behind, and gives it `permissions` unless they are 0.
+fn bind_unix_socket(path: &str, permissions: u32) -> UnixListener {
+    match std::fs::remove_file(path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => {
+            println!("could not remove the existing unix socket {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+    let listener: UnixListener = match UnixListener::bind(path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("could not bind unix socket {}: {}", path, e);
+            std::process::exit(1);
+        }
+    };
+    if permissions != 0 {
+        if let Err(e) = std::fs::set_permissions(path, std::fs::Permissions::from_mode(permissions)) {
+            println!("could not set the permissions of unix socket {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+    listener
+}
+
+/// Loads the RDB file in the working directory, if there is one, dropping expired keys.
+/// The keys are spread over `keyspace-shards` stores. Returns the function libraries too.
+fn load_dataset(config: &Config) -> (Box<dyn KeyValueStore>, Vec<String>) {
+    let dbfilename: &str = &config.dbfilename;
+    ListLimits::set(config.list_max_listpack_size, config.list_compress_depth);
+    let mut store: Box<dyn KeyValueStore> = if config.keyspace_shards > 1 {
+        Box::new(ShardedKeyValueStore::new(config.keyspace_shards))
+    } else {
+        Box::new(InMemoryKeyValueStore::new())
+    };
+    store.set_access_tracking(config.access_tracking());
+
+    let bytes: Vec<u8> = match std::fs::read(dbfilename) {
+        Ok(bytes) => bytes,
+        Err(_) => return (store, Vec::new()),
+    };
+    match rdb::deserialize(&bytes) {
+        Ok(snapshot) => {
+            let now: SystemTime = SystemTime::now();
+            for (key, entry) in snapshot.entries {
+                if !entry.get_expiry().is_some_and(|expiry| expiry < now) {
+                    store.insert(key, entry);
+                }
+            }
+            (store, snapshot.libraries)
+        }
+        Err(e) => {
+            println!("could not load {}: {}", dbfilename, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Where clients connect.
+enum Listener {
+    Tcp(TcpListener),
+    /// A TCP port whose connections start with a TLS handshake.
+    Tls(TcpListener),
+    Unix(UnixListener),
+}
+
+/// A connection just accepted by a listener.
+struct Accepted {
+    stream: Box<dyn tls::Stream>,
+    fd: i32,
+    /// Addresses of the peer and of the server, absent on the Unix socket.
+    address: Option<SocketAddr>,
+    local_address: Option<SocketAddr>,
+}
+
+impl Listener {
+    async fn accept(&self) -> Result<Accepted, std::io::Error> {
+        match self {
+            Listener::Tcp(listener) | Listener::Tls(listener) => {
+                let (socket, address) = listener.accept().await?;
+                // Like Redis, reply without waiting to coalesce small writes.
+                let _ = socket.set_nodelay(true);
+                Ok(Accepted {
+                    fd: socket.as_raw_fd(),
+                    address: Some(address),
+                    local_address: socket.local_addr().ok(),
+                    stream: Box::new(socket),
+                })
+            }
+            Listener::Unix(listener) => {
+                let (socket, _) = listener.accept().await?;
+                Ok(Accepted { fd: socket.as_raw_fd(), address: None, local_address: None, stream: Box::new(socket) })
+            }
+        }
+    }
+}
+
+async fn accept_clients(listener: Listener, router: Router) {
+    let tls: bool = matches!(listener, Listener::Tls(_));
+
+    loop {
+        match listener.accept().await {
+            Ok(accepted) => {
                 println!("accepted new connection");
-                let tx_clone: mpsc::Sender<Msg> = tx.clone();
+                let router_clone: Router = router.clone();
+                let client_id: u64 = NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed);
 
                 tokio::spawn(async move {
-                    handle_client(socket, tx_clone).await;
+                    let stream: Box<dyn tls::Stream> = if tls {
+                        // The certificates may have been reloaded, the acceptor is the latest one.
+                        let Some(acceptor) = tls::acceptor() else {
+                            return;
+                        };
+                        match acceptor.accept(accepted.stream).await {
+                            Ok(stream) => Box::new(stream),
+                            Err(e) => {
+                                println!("error accepting TLS connection: {}", e);
+                                return;
+                            }
+                        }
+                    } else {
+                        accepted.stream
+                    };
+
+                    let (push_tx, push_rx) = mpsc::unbounded_channel::<Value>();
+                    let connection: Arc<Connection> =
+                        Arc::new(Connection::new(client_id, accepted.fd, acl::DEFAULT_USER, push_tx));
+                    send_internal(router_clone.coordinator(), ClientConnectedRequest {
+                        connection: connection.clone(),
+                        address: accepted.address,
+                        local_address: accepted.local_address,
+                    }).await;
+                    handle_client(stream, connection, push_rx, &router_clone).await;
+                    send_internal(router_clone.coordinator(), ClientDisconnectedRequest { id: client_id }).await;
                 });
             }
             Err(e) => {
@@ -37,57 +315,567 @@ async fn main() {
             }
         }
     }
+}
+
+/// Where a command came from: a regular client, or the master this server replicates from.
+pub enum CommandSource {
+    Client { id: u64 },
+    Master,
+}
+
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// A command on its way to the data manager. It carries the buffers of its connection,
+/// which come back with the reply written to `output`, so they are reused by the next
+/// command instead of being allocated again.
+pub struct CommandMsg {
+    /// The table entry of the command, the subcommand's for container commands.
+    pub spec: &'static CommandSpec,
+    pub handler: Handler,
+    pub arguments: Arguments,
+    pub output: Vec<u8>,
+    /// The protocol of the connection, which the command may switch like `HELLO` does.
+    pub protocol: Protocol,
+    pub session: Session,
+    pub source: CommandSource,
+    pub reply_tx: mpsc::Sender<(CommandMsg, Reply)>,
+}
+
+pub enum Msg {
+    Command(CommandMsg),
+    /// Bookkeeping of the server itself, like registering a new connection.
+    Internal(Box<dyn DataRequester + Send + 'static>),
+}
+
+async fn data_manager(
+    mut rx: mpsc::Receiver<Msg>,
+    mut key_value_store: Box<dyn KeyValueStore>,
+    mut server: ServerState
+) {
+    let mut expire_interval: tokio::time::Interval = tokio::time::interval(expire::ACTIVE_EXPIRE_PERIOD);
+    loop {
+        let message: Msg = tokio::select! {
+            message = rx.recv() => match message {
+                Some(message) => message,
+                None => return,
+            },
+            _ = expire_interval.tick() => {
+                expire::active_expire_cycle(&mut key_value_store, &mut server);
+                continue;
+            }
+        };
+        match message {
+            Msg::Command(command) => process(command, &mut key_value_store, &mut server),
+            Msg::Internal(request) => request.request(&mut key_value_store, &mut server),
+        }
+    }
+}
+
+/// Runs a command against `store`, which is either the whole keyspace or the shard holding
+/// the command's keys, and hands it back to its connection with the reply.
+fn process(mut command: CommandMsg, store: &mut Box<dyn KeyValueStore>, server: &mut ServerState) {
+    let mut reply: ReplyWriter = ReplyWriter::new(&mut command.output, command.protocol);
+    server.session = std::mem::take(&mut command.session);
+    let outcome: Reply = dispatch(command.spec, command.handler, &command.arguments, &command.source, store, server, &mut reply);
+    // `CLIENT CACHING` only applies to the command following it.
+    if command.spec.name != "client|caching" {
+        server.session.caching = false;
+    }
+    command.protocol = reply.protocol();
+    command.session = std::mem::take(&mut server.session);
+    server.current_client = None;
+
+    // Connections wait for each reply before sending their next command, so there is room.
+    let _ = command.reply_tx.clone().try_send((command, outcome));
+}
+
+/// Runs a command after the checks that may refuse it.
+fn dispatch(
+    spec: &'static CommandSpec,
+    handler: Handler,
+    arguments: &[Vec<u8>],
+    source: &CommandSource,
+    store: &mut Box<dyn KeyValueStore>,
+    server: &mut ServerState,
+    reply: &mut ReplyWriter
+) -> Reply {
+    match source {
+        CommandSource::Client { id } => {
+            server.current_client = Some(*id);
+            if let Err(message) = authorize(spec, arguments, *id, server) {
+                server.stats.record_rejection(spec.name);
+                reply.error(&message);
+                return Reply::Immediate;
+            }
+            let subscribed: bool = server.session.subscriptions > 0 && reply.protocol() == Protocol::Resp2;
+            if subscribed && !command::pubsub::allowed_while_subscribed(spec) {
+                server.stats.record_rejection(spec.name);
+                reply.error(&format!(
+                    "ERR Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed \
+                        in this context",
+                    spec.name
+                ));
+                return Reply::Immediate;
+            }
+
+            let is_write: bool = spec.is_write();
+            // Only cluster nodes accept ASKING, so shards need not contend for the set otherwise.
+            let asking: bool = spec.has(CommandFlag::Asking) || (server.cluster.is_some() && server.asking.lock().remove(id));
+            let redirection: Option<String> = server.cluster.as_ref().and_then(|cluster| {
+                let keys: Vec<&str> = spec.keys(arguments).collect();
+                cluster.lock().redirect(&keys, store.as_ref(), asking)
+            });
+
+            if let Some(redirection) = redirection {
+                server.stats.record_rejection(spec.name);
+                reply.error(&redirection);
+                Reply::Immediate
+            } else if is_write && server.replication.lock().is_replica() {
+                server.stats.record_rejection(spec.name);
+                reply.error("READONLY You can't write against a read only replica.");
+                Reply::Immediate
+            } else if !eviction::perform_evictions(store, server) && spec.has(CommandFlag::DenyOom) {
+                server.stats.record_rejection(spec.name);
+                reply.error("OOM command not allowed when used memory > 'maxmemory'.");
+                Reply::Immediate
+            } else {
+                let outcome: Reply = execute(spec, handler, arguments, store, server, reply);
+                // Replicas only get the writes that went through, but get what any command
+                // propagated besides, like the keys it found expired.
+                if !is_write || reply.is_error() {
+                    server.prevent_propagation();
+                }
+                propagate(arguments, server);
+                outcome
+            }
+        }
+        CommandSource::Master => {
+            let outcome: Reply = execute(spec, handler, arguments, store, server, reply);
+            // The master already sent what its own command propagated.
+            server.propagation = Propagation::default();
+            server.replication.lock().feed_command(arguments);
+            outcome
+        }
+    }
+}
 
+/// Feeds a write command to the replicas, with the changes the command made to how it
+/// propagates.
+fn propagate(arguments: &[Vec<u8>], server: &mut ServerState) {
+    let Propagation { prevented, also } = std::mem::take(&mut server.propagation);
+    if prevented && also.is_empty() {
+        return;
+    }
+    let mut replication: MutexGuard<'_, ReplicationState> = server.replication.lock();
+    if !prevented {
+        replication.feed_command(arguments);
+    }
+    for command in &also {
+        replication.feed_command(command);
+    }
 }
 
-type Msg = (Box<dyn DataRequester + Send + 'static>, 
-            oneshot::Sender<Box<dyn CommandRunner + Send + 'static>>);
+/// Checks a client command against the ACL rules of the connection's user, logging the
+/// refusals, and returns the error to reply with when it is refused.
+fn authorize(spec: &CommandSpec, arguments: &[Vec<u8>], client_id: u64, server: &mut ServerState) -> Result<(), String> {
+    let mut acl: MutexGuard<'_, Acl> = server.acl.lock();
+    if acl.user(&server.session.user).is_none() {
+        // `ACL DELUSER` and `ACL LOAD` disconnect the clients of the users they remove; one
+        // that sent a command meanwhile is refused it and disconnected as well.
+        if let Some(connection) = &server.session.connection {
+            connection.kill();
+        }
+        return Err(format!("ERR User '{}' no longer exists", server.session.user));
+    }
+
+    acl.check(&server.session.user, spec, arguments).map_err(|denial: Denial| {
+        let message: String = denial.message(&server.session.user, spec);
+        let client_info: String = acl::client_info(client_id, &server.session.user);
+        acl.log_denial(&denial, spec, &server.session.user, client_info, server.config.acllog_max_len);
+        message
+    })
+}
 
-async fn data_manager(mut rx: mpsc::Receiver<Msg>) {
-    let mut key_value_store: Box<dyn KeyValueStore> = Box::new(InMemoryKeyValueStore::new());
+/// Runs a client or master command, accounting for it in the statistics.
+fn execute(
+    spec: &'static CommandSpec,
+    handler: Handler,
+    arguments: &[Vec<u8>],
+    store: &mut Box<dyn KeyValueStore>,
+    server: &mut ServerState,
+    reply: &mut ReplyWriter
+) -> Reply {
+    let started: Instant = Instant::now();
+    let outcome: Reply = handler(arguments, store, server, reply);
 
-    while let Some((command, tx)) = rx.recv().await {
-        let _ = tx.send(command.request(&mut key_value_store));
+    let failed: bool = reply.is_error();
+    server.stats.record_call(spec.name, started.elapsed(), failed);
+    if spec.is_write() && !failed {
+        server.stats.dirty += 1;
+        for key in spec.key_arguments(arguments) {
+            server.invalidate_key(key, server.current_client);
+        }
+    }
+    if let (Some(mode), Some(id)) = (server.session.tracking, server.current_client) {
+        if spec.has(CommandFlag::ReadOnly) && mode.remembers_keys(server.session.caching) {
+            server.tracking.lock().remember_keys(id, spec.key_arguments(arguments));
+        }
+    }
+    outcome
+}
+
+async fn next_streamed(stream: &mut Option<mpsc::UnboundedReceiver<Vec<u8>>>) -> Option<Vec<u8>> {
+    match stream {
+        Some(rx) => rx.recv().await,
+        None => std::future::pending().await,
     }
 }
 
-async fn handle_client(mut stream: TcpStream, store_tx: mpsc::Sender<Msg>) {
-    let mut buffer: [u8; 512] = [0; 512];
+/// Writes the replies gathered in `output` to the connection.
+async fn flush<S: AsyncWrite + Unpin>(stream: &mut S, output: &mut Vec<u8>) -> Result<(), std::io::Error> {
+    if output.is_empty() {
+        return Ok(());
+    }
+    stream.write_all(output).await?;
+    NET_OUTPUT_BYTES.fetch_add(output.len() as u64, Ordering::Relaxed);
+    output.clear();
+    Ok(())
+}
+
+async fn handle_client<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    connection: Arc<Connection>,
+    mut pushes: mpsc::UnboundedReceiver<Value>,
+    router: &Router
+) {
+    let client_id: u64 = connection.id;
+    let mut buffer: Vec<u8> = vec![0; READ_BUFFER_SIZE];
+    let mut pending: Vec<u8> = Vec::new();
+    let mut arguments: Arguments = Arguments::new();
+    let mut output: Vec<u8> = Vec::new();
+    let mut protocol: Protocol = Protocol::Resp2;
+    let mut session: Session = Session { connection: Some(connection.clone()), ..Session::new() };
+    let (reply_tx, mut reply_rx) = mpsc::channel::<(CommandMsg, Reply)>(1);
+    let mut outgoing_stream: Option<mpsc::UnboundedReceiver<Vec<u8>>> = None;
+    // Set once the connection turned into a replica receiving the replication stream.
+    let mut is_replica: bool = false;
+
+    loop {
+        let buffer_length: usize = tokio::select! {
+            _ = connection.killed() => break,
+            read = stream.read(&mut buffer) => match read {
+                Ok(buffer_length) => buffer_length,
+                Err(_) => break,
+            },
+            Some(pushed) = pushes.recv() => {
+                pushed.write(&mut ReplyWriter::new(&mut output, protocol));
+                if flush(&mut stream, &mut output).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+            streamed = next_streamed(&mut outgoing_stream) => {
+                match streamed {
+                    Some(bytes) => {
+                        if stream.write_all(&bytes).await.is_err() {
+                            break;
+                        }
+                        NET_OUTPUT_BYTES.fetch_add(bytes.len() as u64, Ordering::Relaxed);
+                        continue;
+                    }
+                    None => break,
+                }
+            }
+        };
 
-    while let Ok(buffer_length) = stream.read(&mut buffer).await {
         if buffer_length == 0 {
             break;
         }
-        
-        let input = match std::str::from_utf8(&buffer[..buffer_length]) {
-            Ok(input) => input,
-            Err(_) => {
-                println!("received invalid UTF-8");
+        pending.extend_from_slice(&buffer[..buffer_length]);
+        NET_INPUT_BYTES.fetch_add(buffer_length as u64, Ordering::Relaxed);
+
+        // Every complete frame is run before the replies are written back in a single write.
+        let mut consumed: usize = 0;
+        loop {
+            match parse_frame_into(&pending[consumed..], &mut arguments) {
+                Ok(Some(frame_length)) => consumed += frame_length,
+                Ok(None) => break,
+                Err(e) => {
+                    // The rest of the input cannot be told apart from garbage, so the connection is closed.
+                    ReplyWriter::new(&mut output, protocol).error(&format!("ERR Protocol error: {}", e));
+                    let _ = flush(&mut stream, &mut output).await;
+                    return;
+                }
+            }
+            if arguments.is_empty() {
                 continue;
             }
-        };
-        
-        let command = match redis_parser(input) {
-            Ok(parsed_command) => parsed_command,
-            Err(e) => {
-                println!("error: {}", e);
+
+            let (spec, handler) = match lookup_command(&arguments) {
+                Ok(command) => command,
+                Err(LookupError::Unknown(message)) => {
+                    ReplyWriter::new(&mut output, protocol).error(&message);
+                    continue;
+                }
+                Err(LookupError::Rejected(spec, message)) => {
+                    ReplyWriter::new(&mut output, protocol).error(&message);
+                    send_internal(router.coordinator(), RejectedCommandRequest { name: spec.name }).await;
+                    continue;
+                }
+            };
+            {
+                let mut activity: MutexGuard<'_, Activity> = connection.activity();
+                activity.last_command = spec.name;
+                activity.last_interaction = Instant::now();
+                activity.query_buffer = pending.len() - consumed;
+                activity.query_buffer_free = pending.capacity() - pending.len();
+                activity.read_peak = activity.read_peak.max(buffer_length);
+                activity.arguments_memory = arguments.iter().map(|argument| argument.len()).sum();
+                activity.output_buffer = output.len();
+                activity.output_memory = output.capacity();
+            }
+            if !session.authenticated && !spec.has(CommandFlag::NoAuth) && acl::password_required() {
+                ReplyWriter::new(&mut output, protocol).error("NOAUTH Authentication required.");
                 continue;
             }
-        };
+            // The data manager cannot answer while a script runs, so `SCRIPT KILL` is handled here.
+            match scripting::busy_reply(spec) {
+                Some(Ok(())) => {
+                    ReplyWriter::new(&mut output, protocol).ok();
+                    continue;
+                }
+                Some(Err(message)) => {
+                    ReplyWriter::new(&mut output, protocol).error(&message);
+                    continue;
+                }
+                None => {}
+            }
+            // Replicas are not paused, so they keep acknowledging the replication stream.
+            if !is_replica {
+                tokio::select! {
+                    _ = client::wait_while_paused(spec) => {}
+                    _ = connection.killed() => return,
+                }
+            }
 
-        let (oneshot_data_tx, data_rx): 
-            (oneshot::Sender<Box<dyn CommandRunner + Send + 'static>>, 
-             oneshot::Receiver<Box<dyn CommandRunner + Send + 'static>>) 
-            = oneshot::channel();
-        
-        store_tx.send((command, oneshot_data_tx)).await.unwrap();
-        
-        let runner: Box<dyn CommandRunner + Send> = data_rx.await.unwrap();
-        let response: Vec<u8> = match runner.run() {
-            Reply::Immediate(bytes) => bytes,
-            Reply::Deferred(future) => future.await,
-        };
+            let command: CommandMsg = CommandMsg {
+                spec,
+                handler,
+                arguments,
+                output,
+                protocol,
+                session,
+                source: CommandSource::Client { id: client_id },
+                reply_tx: reply_tx.clone(),
+            };
+            if router.send(command).await.is_err() {
+                return;
+            }
+            let Some((command, reply)) = reply_rx.recv().await else {
+                return;
+            };
+            arguments = command.arguments;
+            output = command.output;
+            protocol = command.protocol;
+            session = command.session;
+            is_replica |= matches!(reply, Reply::Stream(_));
+            {
+                let mut activity: MutexGuard<'_, Activity> = connection.activity();
+                activity.arguments_memory = 0;
+                activity.blocked = matches!(reply, Reply::Deferred(_));
+                activity.replica = is_replica;
+                activity.protocol = protocol;
+                if activity.user != session.user {
+                    activity.user.clone_from(&session.user);
+                }
+            }
+
+            match reply {
+                Reply::Immediate => {}
+                Reply::Deferred(future) => {
+                    // Earlier replies are not held back while the command blocks.
+                    if flush(&mut stream, &mut output).await.is_err() {
+                        return;
+                    }
+                    let mut future = future;
+                    let value: Value = loop {
+                        tokio::select! {
+                            value = &mut future => break value,
+                            Some(pushed) = pushes.recv() => {
+                                pushed.write(&mut ReplyWriter::new(&mut output, protocol));
+                                if flush(&mut stream, &mut output).await.is_err() {
+                                    return;
+                                }
+                            }
+                            _ = connection.killed() => return,
+                        }
+                    };
+                    connection.activity().blocked = false;
+                    value.write(&mut ReplyWriter::new(&mut output, protocol));
+                }
+                Reply::Stream(rx) => outgoing_stream = Some(rx),
+            }
+        }
+        pending.drain(..consumed);
+
+        if flush(&mut stream, &mut output).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Hands a bookkeeping request to the data manager without waiting for it to run.
+async fn send_internal<R: DataRequester + Send + 'static>(store_tx: &mpsc::Sender<Msg>, request: R) {
+    let _ = store_tx.send(Msg::Internal(Box::new(request))).await;
+}
+
+struct ClientConnectedRequest {
+    connection: Arc<Connection>,
+    address: Option<SocketAddr>,
+    local_address: Option<SocketAddr>,
+}
+
+impl DataRequester for ClientConnectedRequest {
+    fn request(
+        self: Box<Self>,
+        _store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState
+    ) {
+        server.stats.total_connections_received += 1;
+        server.clients.insert(self.connection.id, ClientInfo {
+            connection: self.connection,
+            address: self.address,
+            local_address: self.local_address,
+            listening_port: None,
+            name: None,
+            no_evict: false,
+        });
+    }
+
+    fn uses_keyspace(&self) -> bool {
+        false
+    }
+}
+
+struct ClientDisconnectedRequest {
+    id: u64,
+}
+
+impl DataRequester for ClientDisconnectedRequest {
+    fn request(
+        self: Box<Self>,
+        _store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState
+    ) {
+        server.clients.remove(&self.id);
+        server.asking.lock().remove(&self.id);
+        server.pubsub.lock().remove_client(self.id);
+        server.tracking.lock().disable(self.id);
+    }
+
+    fn uses_keyspace(&self) -> bool {
+        false
+    }
+}
+
+/// Accounts for a known command called with an unknown subcommand or the wrong number of
+/// arguments.
+struct RejectedCommandRequest {
+    name: &'static str,
+}
+
+impl DataRequester for RejectedCommandRequest {
+    fn request(
+        self: Box<Self>,
+        _store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState
+    ) {
+        server.stats.record_rejection(self.name);
+    }
+
+    fn uses_keyspace(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+    use tokio::sync::mpsc;
+    use super::{dispatch, CommandSource, Msg};
+    use crate::acl::Acl;
+    use crate::command::{encode_command, CommandSpec, Handler};
+    use crate::config::Config;
+    use crate::key_value_store::{InMemoryKeyValueStore, KeyValueStore, KeyValueStoreStringEntry};
+    use crate::parser::lookup_command;
+    use crate::replication::ReplicationState;
+    use crate::reply::{Protocol, ReplyWriter};
+    use crate::server::{ServerState, Session};
+
+    /// Runs a client command as the data manager would, returning its reply.
+    fn run(command: &[&str], store: &mut Box<dyn KeyValueStore>, server: &mut ServerState) -> Vec<u8> {
+        let arguments: Vec<Vec<u8>> = command.iter().map(|argument| argument.as_bytes().to_vec()).collect();
+        let (spec, handler): (&'static CommandSpec, Handler) = lookup_command(&arguments).ok().unwrap();
+        let mut output: Vec<u8> = Vec::new();
+        let mut reply: ReplyWriter = ReplyWriter::new(&mut output, Protocol::Resp2);
+        dispatch(spec, handler, &arguments, &CommandSource::Client { id: 1 }, store, server, &mut reply);
+        output
+    }
+
+    /// A master without replicas, running commands for the default user.
+    fn server() -> ServerState {
+        let (store_tx, _store_rx): (mpsc::Sender<Msg>, mpsc::Receiver<Msg>) = mpsc::channel(1);
+        let replication: ReplicationState = ReplicationState::new(1024 * 1024, 6379, store_tx);
+        let mut server: ServerState = ServerState::new(Config::default(), replication, None, Acl::new(None));
+        server.session = Session::new();
+        server
+    }
+
+    #[test]
+    fn only_writes_that_changed_something_are_propagated() {
+        let mut server: ServerState = server();
+        let mut store: Box<dyn KeyValueStore> = Box::new(InMemoryKeyValueStore::new());
+
+        assert_eq!(run(&["SET", "key", "value"], &mut store, &mut server), b"+OK\r\n");
+        let offset: u64 = server.replication.lock().master_repl_offset();
+        assert!(offset > 0);
+
+        assert!(run(&["RESTORE", "key", "0", "garbage", "REPLACE"], &mut store, &mut server).starts_with(b"-ERR"));
+        assert!(run(&["SET", "key", "value", "PX", "soon"], &mut store, &mut server).starts_with(b"-ERR"));
+        assert_eq!(run(&["DEL", "missing"], &mut store, &mut server), b":0\r\n");
+        assert_eq!(server.replication.lock().master_repl_offset(), offset);
+
+        assert_eq!(run(&["DEL", "key"], &mut store, &mut server), b":1\r\n");
+        assert!(server.replication.lock().master_repl_offset() > offset);
+    }
+
+    #[test]
+    fn pushing_to_a_string_is_a_failed_write() {
+        let mut server: ServerState = server();
+        let mut store: Box<dyn KeyValueStore> = Box::new(InMemoryKeyValueStore::new());
+        run(&["SET", "key", "value"], &mut store, &mut server);
+        let offset: u64 = server.replication.lock().master_repl_offset();
+        let dirty: u64 = server.stats.dirty;
+
+        let wrong_type: &[u8] = b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        assert_eq!(run(&["LPUSH", "key", "a"], &mut store, &mut server), wrong_type);
+        assert_eq!(run(&["RPUSH", "key", "a", "b"], &mut store, &mut server), wrong_type);
+        assert_eq!(store.get("key").unwrap().get_value().unwrap(), "value");
+        assert_eq!(server.stats.dirty, dirty);
+        assert_eq!(server.replication.lock().master_repl_offset(), offset);
+    }
+
+    #[test]
+    fn keys_found_expired_are_deleted_on_replicas_too() {
+        let mut server: ServerState = server();
+        let mut store: Box<dyn KeyValueStore> = Box::new(InMemoryKeyValueStore::new());
+        let expiry: Option<SystemTime> = Some(SystemTime::now() - Duration::from_secs(1));
+        store.insert(String::from("key"), Box::new(KeyValueStoreStringEntry { value: String::from("value"), expiry }));
 
-        stream.write_all(&response).await.unwrap();
+        assert_eq!(run(&["GET", "key"], &mut store, &mut server), b"$-1\r\n");
+        assert!(store.peek("key").is_none());
+        assert_eq!(server.stats.expired_keys, 1);
+        let deletion: Vec<u8> = encode_command(&[b"DEL", b"key"]);
+        assert_eq!(server.replication.lock().master_repl_offset(), deletion.len() as u64);
     }
 }
@@ -0,0 +1,106 @@
+use std::ops::BitOr;
+
+/// Classes of keyspace events, as selected by `notify-keyspace-events`. Besides the classes
+/// of events, `K` and `E` select the channels they are published to.
+#[derive(Clone, Copy, PartialEq, Default)]
+pub struct KeyspaceEvents(u16);
+
+impl KeyspaceEvents {
+    /// Published to `__keyspace@0__:<key>`, with the event as the message.
+    pub const KEYSPACE: KeyspaceEvents = KeyspaceEvents(1 << 0);
+    /// Published to `__keyevent@0__:<event>`, with the key as the message.
+    pub const KEYEVENT: KeyspaceEvents = KeyspaceEvents(1 << 1);
+    /// Commands that work on keys of any type, like `DEL` and `RESTORE`.
+    pub const GENERIC: KeyspaceEvents = KeyspaceEvents(1 << 2);
+    pub const STRING: KeyspaceEvents = KeyspaceEvents(1 << 3);
+    pub const LIST: KeyspaceEvents = KeyspaceEvents(1 << 4);
+    pub const SET: KeyspaceEvents = KeyspaceEvents(1 << 5);
+    pub const HASH: KeyspaceEvents = KeyspaceEvents(1 << 6);
+    pub const ZSET: KeyspaceEvents = KeyspaceEvents(1 << 7);
+    pub const EXPIRED: KeyspaceEvents = KeyspaceEvents(1 << 8);
+    pub const EVICTED: KeyspaceEvents = KeyspaceEvents(1 << 9);
+    pub const STREAM: KeyspaceEvents = KeyspaceEvents(1 << 10);
+    /// Lookups of keys that do not exist.
+    pub const KEY_MISS: KeyspaceEvents = KeyspaceEvents(1 << 11);
+    /// Keys added to the dataset.
+    pub const NEW: KeyspaceEvents = KeyspaceEvents(1 << 12);
+    /// The classes `A` stands for, which leave out key misses and new keys.
+    pub const ALL: KeyspaceEvents = KeyspaceEvents(
+        Self::GENERIC.0 | Self::STRING.0 | Self::LIST.0 | Self::SET.0 | Self::HASH.0 | Self::ZSET.0
+            | Self::EXPIRED.0 | Self::EVICTED.0 | Self::STREAM.0
+    );
+
+    /// The character of each class `A` stands for, in the order they are written back.
+    const CLASSES: [(char, KeyspaceEvents); 9] = [
+        ('g', Self::GENERIC),
+        ('$', Self::STRING),
+        ('l', Self::LIST),
+        ('s', Self::SET),
+        ('h', Self::HASH),
+        ('z', Self::ZSET),
+        ('x', Self::EXPIRED),
+        ('e', Self::EVICTED),
+        ('t', Self::STREAM),
+    ];
+    /// The characters written back after the classes.
+    const FLAGS: [(char, KeyspaceEvents); 4] = [
+        ('K', Self::KEYSPACE),
+        ('E', Self::KEYEVENT),
+        ('m', Self::KEY_MISS),
+        ('n', Self::NEW),
+    ];
+
+    pub fn parse(classes: &str) -> Option<KeyspaceEvents> {
+        classes.chars().try_fold(KeyspaceEvents::default(), |events, class| {
+            let added: KeyspaceEvents = match class {
+                'A' => Self::ALL,
+                _ => Self::CLASSES.iter().chain(&Self::FLAGS).find(|(character, _)| *character == class)?.1,
+            };
+            Some(events | added)
+        })
+    }
+
+    /// The flags as `CONFIG GET` shows them, with `A` standing for the classes it covers.
+    pub fn name(&self) -> String {
+        let mut name: String = String::new();
+        let classes: &[(char, KeyspaceEvents)] = if self.contains(Self::ALL) {
+            name.push('A');
+            &[]
+        } else {
+            &Self::CLASSES
+        };
+        for (character, class) in classes.iter().chain(&Self::FLAGS) {
+            if self.contains(*class) {
+                name.push(*character);
+            }
+        }
+        name
+    }
+
+    pub fn contains(&self, other: KeyspaceEvents) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Whether events of `class` are published to any channel.
+    pub fn publishes(&self, class: KeyspaceEvents) -> bool {
+        self.0 & class.0 != 0 && self.0 & (Self::KEYSPACE.0 | Self::KEYEVENT.0) != 0
+    }
+}
+
+impl BitOr for KeyspaceEvents {
+    type Output = KeyspaceEvents;
+
+    fn bitor(self, other: KeyspaceEvents) -> KeyspaceEvents {
+        KeyspaceEvents(self.0 | other.0)
+    }
+}
+
+/// Channel the events of `key` are published to.
+pub fn keyspace_channel(key: &[u8]) -> Vec<u8> {
+    [b"__keyspace@0__:".as_slice(), key].concat()
+}
+
+/// Channel the keys an `event` happened to are published to.
+pub fn keyevent_channel(event: &str) -> Vec<u8> {
+    format!("__keyevent@0__:{}", event).into_bytes()
+}
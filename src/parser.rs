@@ -1,77 +1,1222 @@
 use std::io::{Error, ErrorKind};
-use crate::command::{DataRequester, CommandFactory};
+use std::ops::Deref;
+use crate::command::{handler, Command, CommandDocs, CommandFlag, CommandGroup, CommandSpec, Handler, KeySpec};
+use crate::command::CommandGroup::{Cluster, Connection, Generic, List, Scripting, Server};
+use crate::command::CommandFlag::{
+    Admin, AllowBusy, Asking, Blocking, DenyOom, Fast, Loading, MayReplicate, NoAuth, NoScript, PubSub, ReadOnly, Stale,
+    Write,
+};
+use crate::command::KeyFlag::{Access, Delete, Insert, Ow, Rm, Ro, Rw, Update};
+use crate::command::acl::AclRequest;
+use crate::command::asking::AskingRequest;
+use crate::command::auth::AuthRequest;
+use crate::command::client::ClientRequest;
 use crate::command::blpop::BLPopRequest;
+use crate::command::cluster::ClusterRequest;
+use crate::command::config::ConfigRequest;
+use crate::command::introspection::CommandRequest;
+use crate::command::debug::DebugRequest;
+use crate::command::del::DelRequest;
+use crate::command::dump::DumpRequest;
 use crate::command::echo::EchoCommand;
+use crate::command::function::{self, FCallRequest, FCallRoRequest};
+use crate::command::eval::{self, EvalRequest, EvalRoRequest, EvalShaRequest, EvalShaRoRequest};
 use crate::command::get::GetCommandRequest;
+use crate::command::hello::HelloRequest;
+use crate::command::info::InfoRequest;
 use crate::command::llen::LLenCommand;
 use crate::command::lpop::LPopRequest;
 use crate::command::lpush::LPushRequest;
 use crate::command::lrange::LRangeRequest;
+use crate::command::memory::MemoryRequest;
+use crate::command::migrate::{self, MigrateRequest};
+use crate::command::object::ObjectRequest;
 use crate::command::ping::PingCommand;
+use crate::command::pubsub::{
+    PSubscribeRequest, PUnsubscribeRequest, PubSubRequest, PublishRequest, SubscribeRequest, UnsubscribeRequest,
+};
 use crate::command::rpush::RPushRequest;
+use crate::command::psync::PSyncRequest;
+use crate::command::replconf::ReplConfRequest;
+use crate::command::replicaof::ReplicaOfRequest;
+use crate::command::restore;
+use crate::command::save::{BgSaveRequest, SaveRequest};
+use crate::command::script::ScriptRequest;
 use crate::command::set::SetCommandRequest;
+use crate::command::wait::{WaitAofRequest, WaitRequest};
 
-fn parse_bulk_string<'a>(length_line: &str, content: &'a str) -> Result<&'a str, &'static str> {
+/// The raw arguments of a RESP frame and the number of bytes the frame spans.
+pub type Frame = (Vec<Vec<u8>>, usize);
+
+/// The longest bulk string accepted, Redis' default `proto-max-bulk-len` of 512MB.
+const PROTO_MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+/// The most arguments a frame may have, so a client cannot make the connection wait on an
+/// unbounded number of them.
+const MULTIBULK_MAX_LEN: usize = 1024 * 1024;
+
+fn parse_bulk_length(length_line: &str) -> Result<usize, &'static str> {
     if !length_line.starts_with('$') {
-        return Err("Bulk string length line must start with '$'");
+        return Err("expected '$' at the start of a bulk string");
     }
 
-    let declared_len: usize = length_line[1..]
+    length_line[1..]
         .parse()
-        .map_err(|_| "Invalid length value")?;
+        .ok()
+        .filter(|&length| length <= PROTO_MAX_BULK_LEN)
+        .ok_or("invalid bulk length")
+}
+
+/// Reads the `\r\n`-terminated line starting at `start`, returning it together with the
+/// position right after its terminator.
+pub fn read_line(buffer: &[u8], start: usize) -> Option<(&str, usize)> {
+    let remaining: &[u8] = buffer.get(start..)?;
+    let line_length: usize = remaining.windows(2).position(|window| window == b"\r\n")?;
+    let line: &str = std::str::from_utf8(&remaining[..line_length]).ok()?;
+    Some((line, start + line_length + 2))
+}
+
+/// The arguments of a frame, command name first. The buffers are kept from one frame to the
+/// next, so parsing the usual commands of a connection does not allocate.
+#[derive(Default)]
+pub struct Arguments {
+    buffers: Vec<Vec<u8>>,
+    count: usize,
+}
+
+impl Arguments {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-    if declared_len != content.len() {
-        return Err("Bulk string declared length does not match content length");
+    fn push(&mut self, argument: &[u8]) {
+        self.push_empty().extend_from_slice(argument);
     }
 
-    Ok(content)
+    /// Adds an empty argument, returning its buffer to be filled.
+    fn push_empty(&mut self) -> &mut Vec<u8> {
+        if self.count == self.buffers.len() {
+            self.buffers.push(Vec::new());
+        }
+        let buffer: &mut Vec<u8> = &mut self.buffers[self.count];
+        buffer.clear();
+        self.count += 1;
+        buffer
+    }
 }
 
-pub fn redis_parser(command: &str) -> Result<Box<dyn DataRequester + 'static>, Error> {
-    let lines: Vec<&str> = command.split("\r\n").collect();
+impl Deref for Arguments {
+    type Target = [Vec<u8>];
 
-    let argument_count_line: &str = lines.get(0).ok_or_else(|| {
-        Error::new(ErrorKind::InvalidInput, "Missing argument count line")
-    })?;
+    fn deref(&self) -> &[Vec<u8>] {
+        &self.buffers[..self.count]
+    }
+}
 
-    if !argument_count_line.starts_with('*') {
-        return Err(Error::new(ErrorKind::InvalidInput, "First line should start with '*'"));
+/// Splits one RESP array of bulk strings off the front of `buffer`, returning its raw
+/// arguments and the number of bytes it spans, or `None` if the frame has not been fully
+/// received yet.
+pub fn parse_frame(buffer: &[u8]) -> Result<Option<Frame>, Error> {
+    let mut arguments: Arguments = Arguments::new();
+    Ok(parse_frame_into(buffer, &mut arguments)?.map(|length| (arguments.to_vec(), length)))
+}
+
+/// Like `parse_frame`, reading the arguments into `arguments` and returning the length of
+/// the frame. Frames that do not start with `*` are inline commands, typed at a terminal.
+/// A frame may have no arguments, e.g. an empty line, in which case there is nothing to run.
+pub fn parse_frame_into(buffer: &[u8], arguments: &mut Arguments) -> Result<Option<usize>, Error> {
+    if buffer.first().is_some_and(|&first| first != b'*') {
+        return parse_inline(buffer, arguments);
     }
 
-    let total_parts: usize = argument_count_line[1..]
+    let (argument_count_line, mut position) = match read_line(buffer, 0) {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+
+    let total_parts: i64 = argument_count_line[1..]
         .parse()
-        .map_err(|_| Error::new(ErrorKind::InvalidInput, "Invalid argument count line"))?;
+        .ok()
+        .filter(|&count| count <= MULTIBULK_MAX_LEN as i64)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "invalid multibulk length"))?;
+
+    // Like Redis, an empty or null array such as `*0` or `*-1` is skipped.
+    arguments.count = 0;
+    for _ in 0..total_parts {
+        let (length_line, content_start) = match read_line(buffer, position) {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+
+        let content_end: usize = parse_bulk_length(length_line)
+            .and_then(|length| content_start.checked_add(length).ok_or("invalid bulk length"))
+            .map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+
+        if buffer.len() < content_end + 2 {
+            return Ok(None);
+        }
+
+        if &buffer[content_end..content_end + 2] != b"\r\n" {
+            return Err(Error::new(
+                ErrorKind::InvalidInput, "Bulk string declared length does not match content length"));
+        }
 
-    if lines.len() < 1 + total_parts * 2 {
-        return Err(Error::new(ErrorKind::InvalidInput, "Incomplete command input"));
+        arguments.push(&buffer[content_start..content_end]);
+        position = content_end + 2;
     }
 
-    let command = parse_bulk_string(
-        lines.get(1).ok_or(Error::new(ErrorKind::InvalidInput, "Missing command length line"))?,
-        lines.get(2).ok_or(Error::new(ErrorKind::InvalidInput, "Missing command line"))?,
-    ).map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+    Ok(Some(position))
+}
 
-    let arguments: &[&str] = &lines.as_slice()[3..];
-    let argument_count: usize = total_parts - 1;
-    let mut verified_arguments: Vec<&str> = Vec::with_capacity(argument_count);
+/// The longest inline command accepted, its terminating newline not received yet.
+const INLINE_MAX_SIZE: usize = 64 * 1024;
 
-    for pair in arguments.chunks_exact(2) {
-        let argument = parse_bulk_string(pair[0], pair[1])
-            .map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
-        verified_arguments.push(argument);
-    }
-
-    match command.to_ascii_lowercase().as_str() {
-        "ping" => PingCommand::new_command(&verified_arguments),
-        "echo" => EchoCommand::new_command(&verified_arguments),
-        "set" => SetCommandRequest::new_command(&verified_arguments),
-        "get" => GetCommandRequest::new_command(&verified_arguments),
-        "rpush" => RPushRequest::new_command(&verified_arguments),
-        "lpush" => LPushRequest::new_command(&verified_arguments),
-        "lrange" => LRangeRequest::new_command(&verified_arguments),
-        "llen" => LLenCommand::new_command(&verified_arguments),
-        "lpop" => LPopRequest::new_command(&verified_arguments),
-        "blpop" => BLPopRequest::new_command(&verified_arguments),
-        _ => Err(Error::new(ErrorKind::InvalidInput, "Unknown command")),
+/// Parses an inline command: a line of arguments separated by spaces.
+fn parse_inline(buffer: &[u8], arguments: &mut Arguments) -> Result<Option<usize>, Error> {
+    let Some(line_length) = buffer.iter().position(|&byte| byte == b'\n') else {
+        if buffer.len() > INLINE_MAX_SIZE {
+            return Err(Error::new(ErrorKind::InvalidInput, "too big inline request"));
+        }
+        return Ok(None);
+    };
+    let line: &[u8] = buffer[..line_length].strip_suffix(b"\r").unwrap_or(&buffer[..line_length]);
+
+    split_arguments(line, arguments)
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "unbalanced quotes in request"))?;
+    Ok(Some(line_length + 1))
+}
+
+/// Splits `line` into arguments the way `redis-cli` does. Arguments are separated by
+/// whitespace and may be quoted: double quotes allow escapes like `\n` or `\x41`, single
+/// quotes only `\'`. A closing quote must end the argument.
+fn split_arguments(line: &[u8], arguments: &mut Arguments) -> Result<(), ()> {
+    let mut position: usize = 0;
+    arguments.count = 0;
+
+    loop {
+        while line.get(position).is_some_and(|byte| byte.is_ascii_whitespace()) {
+            position += 1;
+        }
+        if position == line.len() {
+            return Ok(());
+        }
+
+        let argument: &mut Vec<u8> = arguments.push_empty();
+        let mut quote: Option<u8> = None;
+        while let Some(&byte) = line.get(position) {
+            position += 1;
+            match (quote, byte) {
+                (None, byte) if byte.is_ascii_whitespace() => break,
+                (None, b'"' | b'\'') => quote = Some(byte),
+                (None, byte) => argument.push(byte),
+                (Some(b'"'), b'\\') => {
+                    let hex = |offset: usize| line.get(position + offset).and_then(|digit| (*digit as char).to_digit(16));
+                    match (line.get(position), hex(1), hex(2)) {
+                        (Some(b'x'), Some(high), Some(low)) => {
+                            argument.push((high * 16 + low) as u8);
+                            position += 3;
+                        }
+                        (Some(&escaped), _, _) => {
+                            argument.push(match escaped {
+                                b'n' => b'\n',
+                                b'r' => b'\r',
+                                b't' => b'\t',
+                                b'b' => 0x08,
+                                b'a' => 0x07,
+                                other => other,
+                            });
+                            position += 1;
+                        }
+                        (None, _, _) => return Err(()),
+                    }
+                }
+                (Some(b'\''), b'\\') if line.get(position) == Some(&b'\'') => {
+                    argument.push(b'\'');
+                    position += 1;
+                }
+                (Some(open), byte) if byte == open => {
+                    if line.get(position).is_some_and(|next| !next.is_ascii_whitespace()) {
+                        return Err(());
+                    }
+                    quote = None;
+                    break;
+                }
+                (Some(_), byte) => argument.push(byte),
+            }
+        }
+        if quote.is_some() {
+            return Err(());
+        }
+    }
+}
+
+const fn command(
+    name: &'static str,
+    arity: i32,
+    flags: &'static [CommandFlag],
+    keys: KeySpec,
+    docs: CommandDocs,
+    handler: Handler
+) -> CommandSpec {
+    CommandSpec { name, arity, flags, keys, subcommands: &[], docs, handler: Some(handler) }
+}
+
+/// A command whose first argument names a subcommand, run by `handler` whatever the
+/// subcommand.
+const fn container(
+    name: &'static str,
+    arity: i32,
+    docs: CommandDocs,
+    handler: Handler,
+    subcommands: &'static [CommandSpec]
+) -> CommandSpec {
+    CommandSpec { name, arity, flags: &[], keys: KeySpec::NONE, subcommands, docs, handler: Some(handler) }
+}
+
+const fn subcommand(
+    name: &'static str,
+    arity: i32,
+    flags: &'static [CommandFlag],
+    keys: KeySpec,
+    docs: CommandDocs
+) -> CommandSpec {
+    CommandSpec { name, arity, flags, keys, subcommands: &[], docs, handler: None }
+}
+
+const fn docs(group: CommandGroup, since: &'static str, complexity: &'static str, summary: &'static str) -> CommandDocs {
+    CommandDocs { group, since, complexity, summary }
+}
+
+/// Flags of the subcommands managing the server rather than serving data.
+const ADMIN: &[CommandFlag] = &[Admin, NoScript, Loading, Stale];
+const CLUSTER_ADMIN: &[CommandFlag] = &[Admin, Stale, NoScript];
+
+const O1: &str = "O(1)";
+/// Where the keys of a script call are: the given number of arguments after the count.
+const SCRIPT_KEYS: KeySpec = KeySpec { first: 3, last: 3, step: 1, flags: &[Rw, Access, Update], find: Some(eval::key_positions) };
+const SCRIPT_COMPLEXITY: &str = "Depends on the script that is executed.";
+const FUNCTION_COMPLEXITY: &str = "Depends on the function that is executed.";
+
+/// Every command the server knows, looked up by name. `COMMAND` describes the commands
+/// from this table too, so an entry is all a new command needs.
+pub static COMMAND_TABLE: &[CommandSpec] = &[
+    command(
+        "ping", -1, &[Fast, Stale], KeySpec::NONE,
+        docs(Connection, "1.0.0", O1, "Returns the server's liveliness response."),
+        handler!(PingCommand)
+    ),
+    command(
+        "echo", 2, &[Fast], KeySpec::NONE,
+        docs(Connection, "1.0.0", O1, "Returns the given string."),
+        handler!(EchoCommand)
+    ),
+    command(
+        "auth", -2, &[NoScript, Loading, Stale, Fast, NoAuth], KeySpec::NONE,
+        docs(
+            Connection, "1.0.0", "O(N) where N is the number of passwords defined for the user",
+            "Authenticates the connection."
+        ),
+        handler!(AuthRequest)
+    ),
+    command(
+        "hello", -1, &[NoScript, Loading, Stale, Fast, NoAuth], KeySpec::NONE,
+        docs(Connection, "6.0.0", O1, "Handshakes with the Redis server."),
+        handler!(HelloRequest)
+    ),
+    command(
+        "set", -3, &[Write, DenyOom], KeySpec::range(1, 1, 1, &[Ow, Update]),
+        docs(
+            CommandGroup::String, "1.0.0", O1,
+            "Sets the string value of a key, ignoring its type. The key is created if it doesn't exist."
+        ),
+        handler!(SetCommandRequest)
+    ),
+    command(
+        "get", 2, &[ReadOnly, Fast], KeySpec::range(1, 1, 1, &[Ro, Access]),
+        docs(CommandGroup::String, "1.0.0", O1, "Returns the string value of a key."),
+        handler!(GetCommandRequest)
+    ),
+    command(
+        "rpush", -3, &[Write, DenyOom, Fast], KeySpec::range(1, 1, 1, &[Rw, Insert]),
+        docs(
+            List, "1.0.0",
+            "O(1) for each element added, so O(N) to add N elements when the command is called with multiple arguments.",
+            "Appends one or more elements to a list. Creates the key if it doesn't exist."
+        ),
+        handler!(RPushRequest)
+    ),
+    command(
+        "lpush", -3, &[Write, DenyOom, Fast], KeySpec::range(1, 1, 1, &[Rw, Insert]),
+        docs(
+            List, "1.0.0",
+            "O(1) for each element added, so O(N) to add N elements when the command is called with multiple arguments.",
+            "Prepends one or more elements to a list. Creates the key if it doesn't exist."
+        ),
+        handler!(LPushRequest)
+    ),
+    command(
+        "lrange", 4, &[ReadOnly], KeySpec::range(1, 1, 1, &[Ro, Access]),
+        docs(
+            List, "1.0.0",
+            "O(S+N) where S is the distance of start offset from HEAD for small lists, from nearest end (HEAD or TAIL) \
+             for large lists; and N is the number of elements in the specified range.",
+            "Returns a range of elements from a list."
+        ),
+        handler!(LRangeRequest)
+    ),
+    command(
+        "llen", 2, &[ReadOnly, Fast], KeySpec::range(1, 1, 1, &[Ro]),
+        docs(List, "1.0.0", O1, "Returns the length of a list."),
+        handler!(LLenCommand)
+    ),
+    command(
+        "lpop", -2, &[Write, Fast], KeySpec::range(1, 1, 1, &[Rw, Access, Delete]),
+        docs(
+            List, "1.0.0", "O(N) where N is the number of elements returned",
+            "Returns the first elements in a list after removing it. Deletes the list if the last element was popped."
+        ),
+        handler!(LPopRequest)
+    ),
+    // Replicas get the pops BLPOP makes as LPOP, whether served at once or later by a push.
+    command(
+        "blpop", -3, &[Write, Blocking], KeySpec::range(1, -2, 1, &[Rw, Access, Delete]),
+        docs(
+            List, "2.0.0", "O(N) where N is the number of provided keys.",
+            "Removes and returns the first element in a list. Blocks until an element is available otherwise. \
+             Deletes the list if the last element was popped."
+        ),
+        handler!(BLPopRequest)
+    ),
+    command(
+        "replconf", -1, &[Admin, NoScript, Loading, Stale], KeySpec::NONE,
+        docs(Server, "3.0.0", O1, "An internal command for configuring the replication stream."),
+        handler!(ReplConfRequest)
+    ),
+    command(
+        "psync", -3, &[Admin, NoScript], KeySpec::NONE,
+        docs(Server, "2.8.0", "", "An internal command used in replication."),
+        handler!(PSyncRequest)
+    ),
+    command(
+        "replicaof", 3, &[Admin, NoScript, Stale], KeySpec::NONE,
+        docs(
+            Server, "5.0.0", O1,
+            "Configures a server as replica of another, or promotes it to a master."
+        ),
+        handler!(ReplicaOfRequest)
+    ),
+    command(
+        "slaveof", 3, &[Admin, NoScript, Stale], KeySpec::NONE,
+        docs(
+            Server, "1.0.0", O1,
+            "Sets a Redis server as a replica of another, or promotes it to being a master."
+        ),
+        handler!(ReplicaOfRequest)
+    ),
+    command(
+        "wait", 3, &[Blocking], KeySpec::NONE,
+        docs(
+            Generic, "3.0.0", O1,
+            "Blocks until the asynchronous replication of all preceding write commands sent by the connection is completed."
+        ),
+        handler!(WaitRequest)
+    ),
+    command(
+        "waitaof", 4, &[Blocking], KeySpec::NONE,
+        docs(
+            Generic, "7.2.0", O1,
+            "Blocks until all of the preceding write commands sent by the connection are written to the append-only file \
+             of the master and/or replicas."
+        ),
+        handler!(WaitAofRequest)
+    ),
+    command(
+        "asking", 1, &[Fast], KeySpec::NONE,
+        docs(Cluster, "3.0.0", O1, "Signals that a cluster client is following an -ASK redirect."),
+        handler!(AskingRequest)
+    ),
+    command(
+        "del", -2, &[Write], KeySpec::range(1, -1, 1, &[Rm, Delete]),
+        docs(
+            Generic, "1.0.0",
+            "O(N) where N is the number of keys that will be removed. When a key to remove holds a value other than a \
+             string, the individual complexity for this key is O(M) where M is the number of elements in the list, set, \
+             sorted set or hash. Removing a single key that holds a string value is O(1).",
+            "Deletes one or more keys."
+        ),
+        handler!(DelRequest)
+    ),
+    // MIGRATE propagates the deletion of the keys it moved rather than itself.
+    command(
+        "migrate", -6, &[Write],
+        KeySpec { first: 3, last: 3, step: 1, flags: &[Rw, Access, Delete], find: Some(migrate::key_positions) },
+        docs(
+            Generic, "2.6.0",
+            "This command actually executes a DUMP+DEL in the source instance, and a RESTORE in the target instance. \
+             See the pages of these commands for time complexity. Also an O(N) data transfer between the two instances \
+             is performed.",
+            "Atomically transfers a key from one Redis instance to another."
+        ),
+        handler!(MigrateRequest)
+    ),
+    command(
+        "dump", 2, &[ReadOnly], KeySpec::range(1, 1, 1, &[Ro, Access]),
+        docs(
+            Generic, "2.6.0",
+            "O(1) to access the key and additional O(N*M) to serialize it, where N is the number of Redis objects \
+             composing the value and M their average size.",
+            "Returns a serialized representation of the value stored at a key."
+        ),
+        handler!(DumpRequest)
+    ),
+    command(
+        "restore", -4, &[Write, DenyOom], KeySpec::range(1, 1, 1, &[Ow, Update]),
+        docs(
+            Generic, "2.6.0",
+            "O(1) to create the new key and additional O(N*M) to reconstruct the serialized value, where N is the number \
+             of Redis objects composing the value and M their average size.",
+            "Creates a key from the serialized representation of a value."
+        ),
+        restore::handle
+    ),
+    command(
+        "restore-asking", -4, &[Write, DenyOom, Asking], KeySpec::range(1, 1, 1, &[Ow, Update]),
+        docs(
+            Server, "3.0.0",
+            "O(1) to create the new key and additional O(N*M) to reconstruct the serialized value, where N is the number \
+             of Redis objects composing the value and M their average size.",
+            "An internal command for migrating keys in a cluster."
+        ),
+        restore::handle
+    ),
+    command(
+        "info", -1, &[Loading, Stale], KeySpec::NONE,
+        docs(Server, "1.0.0", O1, "Returns information and statistics about the server."),
+        handler!(InfoRequest)
+    ),
+    command(
+        "save", 1, &[Admin, NoScript], KeySpec::NONE,
+        docs(
+            Server, "1.0.0", "O(N) where N is the total number of keys in all databases",
+            "Synchronously saves the database(s) to disk."
+        ),
+        handler!(SaveRequest)
+    ),
+    command(
+        "bgsave", -1, &[Admin, NoScript], KeySpec::NONE,
+        docs(
+            Server, "1.0.0", "O(N) where N is the total number of keys in all databases",
+            "Asynchronously saves the database(s) to disk."
+        ),
+        handler!(BgSaveRequest)
+    ),
+    command(
+        "debug", -2, &[Admin, NoScript, Loading, Stale], KeySpec::NONE,
+        docs(Server, "1.0.0", "Depends on subcommand.", "A container for debugging commands."),
+        handler!(DebugRequest)
+    ),
+    container(
+        "object", -2,
+        docs(Generic, "2.2.3", "Depends on subcommand.", "A container for object introspection commands."),
+        handler!(ObjectRequest),
+        &[
+            subcommand(
+                "object|encoding", 3, &[ReadOnly], KeySpec::range(2, 2, 1, &[Ro]),
+                docs(Generic, "2.2.3", O1, "Returns the internal encoding of a Redis object.")
+            ),
+            subcommand(
+                "object|freq", 3, &[ReadOnly], KeySpec::range(2, 2, 1, &[Ro]),
+                docs(Generic, "4.0.0", O1, "Returns the logarithmic access frequency counter of a Redis object.")
+            ),
+            subcommand(
+                "object|idletime", 3, &[ReadOnly], KeySpec::range(2, 2, 1, &[Ro]),
+                docs(Generic, "2.2.3", O1, "Returns the time since the last access to a Redis object.")
+            ),
+            subcommand(
+                "object|refcount", 3, &[ReadOnly], KeySpec::range(2, 2, 1, &[Ro]),
+                docs(Generic, "2.2.3", O1, "Returns the reference count of a value of a key.")
+            ),
+        ]
+    ),
+    container(
+        "config", -2,
+        docs(Server, "2.0.0", "Depends on subcommand.", "A container for server configuration commands."),
+        handler!(ConfigRequest),
+        &[
+            subcommand(
+                "config|get", -3, ADMIN, KeySpec::NONE,
+                docs(
+                    Server, "2.0.0", "O(N) when N is the number of configuration parameters provided",
+                    "Returns the effective values of configuration parameters."
+                )
+            ),
+            subcommand(
+                "config|set", -4, ADMIN, KeySpec::NONE,
+                docs(
+                    Server, "2.0.0", "O(N) when N is the number of configuration parameters provided",
+                    "Sets configuration parameters in-flight."
+                )
+            ),
+            subcommand(
+                "config|resetstat", 2, ADMIN, KeySpec::NONE,
+                docs(Server, "2.0.0", O1, "Resets the server's statistics.")
+            ),
+            subcommand(
+                "config|rewrite", 2, ADMIN, KeySpec::NONE,
+                docs(Server, "2.8.0", O1, "Persists the effective configuration to file.")
+            ),
+        ]
+    ),
+    container(
+        "memory", -2,
+        docs(Server, "4.0.0", "Depends on subcommand.", "A container for memory diagnostics commands."),
+        handler!(MemoryRequest),
+        &[
+            subcommand(
+                "memory|usage", -3, &[ReadOnly], KeySpec::range(2, 2, 1, &[Ro]),
+                docs(
+                    Server, "4.0.0", "O(N) where N is the number of samples.",
+                    "Estimates the memory usage of a key."
+                )
+            ),
+            subcommand(
+                "memory|stats", 2, &[], KeySpec::NONE,
+                docs(Server, "4.0.0", O1, "Returns details about memory usage.")
+            ),
+            subcommand(
+                "memory|doctor", 2, &[], KeySpec::NONE,
+                docs(Server, "4.0.0", O1, "Outputs a memory problems report.")
+            ),
+        ]
+    ),
+    container(
+        "cluster", -2,
+        docs(Cluster, "3.0.0", "Depends on subcommand.", "A container for Redis Cluster commands."),
+        handler!(ClusterRequest),
+        &[
+            subcommand(
+                "cluster|info", 2, &[Stale], KeySpec::NONE,
+                docs(Cluster, "3.0.0", O1, "Returns information about the state of a node.")
+            ),
+            subcommand(
+                "cluster|myid", 2, &[Stale], KeySpec::NONE,
+                docs(Cluster, "3.0.0", O1, "Returns the ID of a node.")
+            ),
+            subcommand(
+                "cluster|nodes", 2, &[Stale], KeySpec::NONE,
+                docs(
+                    Cluster, "3.0.0", "O(N) where N is the total number of Cluster nodes",
+                    "Returns the cluster configuration for a node."
+                )
+            ),
+            subcommand(
+                "cluster|slots", 2, &[Stale], KeySpec::NONE,
+                docs(
+                    Cluster, "3.0.0", "O(N) where N is the total number of Cluster nodes",
+                    "Returns the mapping of cluster slots to nodes."
+                )
+            ),
+            subcommand(
+                "cluster|shards", 2, &[Stale], KeySpec::NONE,
+                docs(
+                    Cluster, "7.0.0", "O(N) where N is the total number of cluster nodes",
+                    "Returns the mapping of cluster slots to shards."
+                )
+            ),
+            subcommand(
+                "cluster|keyslot", 3, &[Stale], KeySpec::NONE,
+                docs(
+                    Cluster, "3.0.0", "O(N) where N is the number of bytes in the key",
+                    "Returns the hash slot for a key."
+                )
+            ),
+            subcommand(
+                "cluster|countkeysinslot", 3, &[Stale], KeySpec::NONE,
+                docs(Cluster, "3.0.0", O1, "Returns the number of keys in a hash slot.")
+            ),
+            subcommand(
+                "cluster|getkeysinslot", 4, &[Stale], KeySpec::NONE,
+                docs(
+                    Cluster, "3.0.0", "O(N) where N is the number of requested keys",
+                    "Returns the key names in a hash slot."
+                )
+            ),
+            subcommand(
+                "cluster|meet", -4, CLUSTER_ADMIN, KeySpec::NONE,
+                docs(Cluster, "3.0.0", O1, "Forces a node to handshake with another node.")
+            ),
+            subcommand(
+                "cluster|addslots", -3, CLUSTER_ADMIN, KeySpec::NONE,
+                docs(
+                    Cluster, "3.0.0", "O(N) where N is the total number of hash slot arguments",
+                    "Assigns new hash slots to a node."
+                )
+            ),
+            subcommand(
+                "cluster|addslotsrange", -4, CLUSTER_ADMIN, KeySpec::NONE,
+                docs(
+                    Cluster, "7.0.0",
+                    "O(N) where N is the total number of the slots between the start slot and end slot arguments.",
+                    "Assigns new hash slot ranges to a node."
+                )
+            ),
+            subcommand(
+                "cluster|setslot", -4, CLUSTER_ADMIN, KeySpec::NONE,
+                docs(Cluster, "3.0.0", O1, "Binds a hash slot to a node.")
+            ),
+            subcommand(
+                "cluster|failover", -2, CLUSTER_ADMIN, KeySpec::NONE,
+                docs(Cluster, "3.0.0", O1, "Forces a replica to perform a manual failover of its master.")
+            ),
+            subcommand(
+                "cluster|forget", 3, CLUSTER_ADMIN, KeySpec::NONE,
+                docs(Cluster, "3.0.0", O1, "Removes a node from the nodes table.")
+            ),
+            subcommand(
+                "cluster|reset", -2, CLUSTER_ADMIN, KeySpec::NONE,
+                docs(
+                    Cluster, "3.0.0",
+                    "O(N) where N is the number of known nodes. The command may execute a FLUSHALL as a side effect.",
+                    "Resets a node."
+                )
+            ),
+            subcommand(
+                "cluster|replicate", 3, CLUSTER_ADMIN, KeySpec::NONE,
+                docs(Cluster, "3.0.0", O1, "Configure a node as replica of a master node.")
+            ),
+        ]
+    ),
+    container(
+        "command", -1,
+        docs(
+            Server, "2.8.13", "O(N) where N is the total number of Redis commands",
+            "Returns detailed information about all commands."
+        ),
+        handler!(CommandRequest),
+        &[
+            subcommand(
+                "command|count", 2, &[Loading, Stale], KeySpec::NONE,
+                docs(Server, "2.8.13", O1, "Returns a count of commands.")
+            ),
+            subcommand(
+                "command|info", -2, &[Loading, Stale], KeySpec::NONE,
+                docs(
+                    Server, "2.8.13", "O(N) where N is the number of commands to look up",
+                    "Returns information about one, multiple or all commands."
+                )
+            ),
+            subcommand(
+                "command|docs", -2, &[Loading, Stale], KeySpec::NONE,
+                docs(
+                    Server, "7.0.0", "O(N) where N is the number of commands to look up",
+                    "Returns documentary information about one, multiple or all commands."
+                )
+            ),
+            subcommand(
+                "command|getkeys", -3, &[Loading, Stale], KeySpec::NONE,
+                docs(
+                    Server, "2.8.13", "O(N) where N is the number of arguments to the command",
+                    "Extracts the key names from an arbitrary command."
+                )
+            ),
+            subcommand(
+                "command|list", -2, &[Loading, Stale], KeySpec::NONE,
+                docs(
+                    Server, "7.0.0", "O(N) where N is the total number of Redis commands",
+                    "Returns a list of command names."
+                )
+            ),
+        ]
+    ),
+    container(
+        "client", -2,
+        docs(Connection, "2.4.0", "Depends on subcommand.", "A container for client connection commands."),
+        handler!(ClientRequest),
+        &[
+            subcommand(
+                "client|caching", 3, &[NoScript, Loading, Stale], KeySpec::NONE,
+                docs(Connection, "6.0.0", O1, "Instructs the server whether to track the keys in the next request.")
+            ),
+            subcommand(
+                "client|getname", 2, &[NoScript, Loading, Stale], KeySpec::NONE,
+                docs(Connection, "2.6.9", O1, "Returns the name of the connection.")
+            ),
+            subcommand(
+                "client|getredir", 2, &[NoScript, Loading, Stale], KeySpec::NONE,
+                docs(
+                    Connection, "6.0.0", O1,
+                    "Returns the client ID to which the connection's tracking notifications are redirected."
+                )
+            ),
+            subcommand(
+                "client|id", 2, &[NoScript, Loading, Stale], KeySpec::NONE,
+                docs(Connection, "5.0.0", O1, "Returns the unique client ID of the connection.")
+            ),
+            subcommand(
+                "client|info", 2, &[NoScript, Loading, Stale], KeySpec::NONE,
+                docs(Connection, "6.2.0", O1, "Returns information about the connection.")
+            ),
+            subcommand(
+                "client|kill", -3, ADMIN, KeySpec::NONE,
+                docs(
+                    Connection, "2.4.0", "O(N) where N is the number of client connections",
+                    "Terminates open connections."
+                )
+            ),
+            subcommand(
+                "client|list", -2, ADMIN, KeySpec::NONE,
+                docs(
+                    Connection, "2.4.0", "O(N) where N is the number of client connections",
+                    "Lists open connections."
+                )
+            ),
+            subcommand(
+                "client|no-evict", 3, ADMIN, KeySpec::NONE,
+                docs(Connection, "7.0.0", O1, "Sets the client eviction mode of the connection.")
+            ),
+            subcommand(
+                "client|pause", -3, ADMIN, KeySpec::NONE,
+                docs(Connection, "3.0.0", O1, "Suspends commands processing.")
+            ),
+            subcommand(
+                "client|setname", 3, &[NoScript, Loading, Stale], KeySpec::NONE,
+                docs(Connection, "2.6.9", O1, "Sets the connection name.")
+            ),
+            subcommand(
+                "client|tracking", -3, &[NoScript, Loading, Stale], KeySpec::NONE,
+                docs(
+                    Connection, "6.0.0", "O(1). Some options may introduce additional complexity.",
+                    "Controls server-assisted client-side caching for the connection."
+                )
+            ),
+            subcommand(
+                "client|trackinginfo", 2, &[NoScript, Loading, Stale], KeySpec::NONE,
+                docs(
+                    Connection, "6.2.0", O1,
+                    "Returns information about server-assisted client-side caching for the connection."
+                )
+            ),
+            subcommand(
+                "client|unblock", -3, ADMIN, KeySpec::NONE,
+                docs(
+                    Connection, "5.0.0", "O(log N) where N is the number of client connections",
+                    "Unblocks a client blocked by a blocking command from a different connection."
+                )
+            ),
+            subcommand(
+                "client|unpause", 2, ADMIN, KeySpec::NONE,
+                docs(
+                    Connection, "6.2.0", "O(N) Where N is the number of paused clients",
+                    "Resumes processing commands from paused clients."
+                )
+            ),
+        ]
+    ),
+    container(
+        "acl", -2,
+        docs(Server, "6.0.0", "Depends on subcommand.", "A container for Access List Control commands."),
+        handler!(AclRequest),
+        &[
+            subcommand(
+                "acl|cat", -2, &[NoScript, Loading, Stale], KeySpec::NONE,
+                docs(
+                    Server, "6.0.0", "O(1) since the categories and commands are a fixed set.",
+                    "Lists the ACL categories, or the commands inside a category."
+                )
+            ),
+            subcommand(
+                "acl|deluser", -3, ADMIN, KeySpec::NONE,
+                docs(
+                    Server, "6.0.0", "O(1) amortized time considering the typical user.",
+                    "Deletes ACL users, and terminates their connections."
+                )
+            ),
+            subcommand(
+                "acl|dryrun", -4, ADMIN, KeySpec::NONE,
+                docs(
+                    Server, "7.0.0", O1,
+                    "Simulates the execution of a command by a user, without executing the command."
+                )
+            ),
+            subcommand(
+                "acl|getuser", 3, ADMIN, KeySpec::NONE,
+                docs(
+                    Server, "6.0.0", "O(N). Where N is the number of password, command and pattern rules that the user has.",
+                    "Lists the ACL rules of a user."
+                )
+            ),
+            subcommand(
+                "acl|list", 2, ADMIN, KeySpec::NONE,
+                docs(
+                    Server, "6.0.0", "O(N). Where N is the number of configured users.",
+                    "Dumps the effective rules in ACL file format."
+                )
+            ),
+            subcommand(
+                "acl|load", 2, ADMIN, KeySpec::NONE,
+                docs(
+                    Server, "6.0.0", "O(N). Where N is the number of configured users.",
+                    "Reloads the rules from the configured ACL file."
+                )
+            ),
+            subcommand(
+                "acl|log", -2, ADMIN, KeySpec::NONE,
+                docs(
+                    Server, "6.0.0", "O(N) with N being the number of entries shown.",
+                    "Lists recent security events generated due to ACL rules."
+                )
+            ),
+            subcommand(
+                "acl|save", 2, ADMIN, KeySpec::NONE,
+                docs(
+                    Server, "6.0.0", "O(N). Where N is the number of configured users.",
+                    "Saves the effective ACL rules in the configured ACL file."
+                )
+            ),
+            subcommand(
+                "acl|setuser", -3, ADMIN, KeySpec::NONE,
+                docs(
+                    Server, "6.0.0", "O(N). Where N is the number of rules provided.",
+                    "Creates and modifies an ACL user and its rules."
+                )
+            ),
+            subcommand(
+                "acl|users", 2, ADMIN, KeySpec::NONE,
+                docs(Server, "6.0.0", "O(N). Where N is the number of configured users.", "Lists all ACL users.")
+            ),
+            subcommand(
+                "acl|whoami", 2, &[NoScript, Loading, Stale], KeySpec::NONE,
+                docs(Server, "6.0.0", O1, "Returns the authenticated username of the current connection.")
+            ),
+        ]
+    ),
+    command(
+        "subscribe", -2, &[PubSub, NoScript, Loading, Stale], KeySpec::NONE,
+        docs(
+            CommandGroup::PubSub, "2.0.0", "O(N) where N is the number of channels to subscribe to.",
+            "Listens for messages published to channels."
+        ),
+        handler!(SubscribeRequest)
+    ),
+    command(
+        "unsubscribe", -1, &[PubSub, NoScript, Loading, Stale], KeySpec::NONE,
+        docs(
+            CommandGroup::PubSub, "2.0.0", "O(N) where N is the number of channels to unsubscribe.",
+            "Stops listening to messages posted to channels."
+        ),
+        handler!(UnsubscribeRequest)
+    ),
+    command(
+        "psubscribe", -2, &[PubSub, NoScript, Loading, Stale], KeySpec::NONE,
+        docs(
+            CommandGroup::PubSub, "2.0.0", "O(N) where N is the number of patterns to subscribe to.",
+            "Listens for messages published to channels that match one or more patterns."
+        ),
+        handler!(PSubscribeRequest)
+    ),
+    command(
+        "punsubscribe", -1, &[PubSub, NoScript, Loading, Stale], KeySpec::NONE,
+        docs(
+            CommandGroup::PubSub, "2.0.0", "O(N) where N is the number of patterns to unsubscribe.",
+            "Stops listening to messages published to channels that match one or more patterns."
+        ),
+        handler!(PUnsubscribeRequest)
+    ),
+    command(
+        "publish", 3, &[PubSub, Loading, Stale, Fast], KeySpec::NONE,
+        docs(
+            CommandGroup::PubSub, "2.0.0",
+            "O(N+M) where N is the number of clients subscribed to the receiving channel and M is the total number \
+                of subscribed patterns (by any client).",
+            "Posts a message to a channel."
+        ),
+        handler!(PublishRequest)
+    ),
+    container(
+        "pubsub", -2,
+        docs(CommandGroup::PubSub, "2.8.0", "Depends on subcommand.", "A container for Pub/Sub commands."),
+        handler!(PubSubRequest),
+        &[
+            subcommand(
+                "pubsub|channels", -2, &[PubSub, Loading, Stale], KeySpec::NONE,
+                docs(
+                    CommandGroup::PubSub, "2.8.0",
+                    "O(N) where N is the number of active channels, and assuming constant time pattern matching \
+                        (relatively short channels and patterns)",
+                    "Returns the active channels."
+                )
+            ),
+            subcommand(
+                "pubsub|numpat", 2, &[PubSub, Loading, Stale], KeySpec::NONE,
+                docs(CommandGroup::PubSub, "2.8.0", O1, "Returns a count of unique pattern subscriptions.")
+            ),
+            subcommand(
+                "pubsub|numsub", -2, &[PubSub, Loading, Stale], KeySpec::NONE,
+                docs(
+                    CommandGroup::PubSub, "2.8.0", "O(N) for the NUMSUB subcommand, where N is the number of requested channels",
+                    "Returns a count of subscribers to channels."
+                )
+            ),
+        ]
+    ),
+    command(
+        "eval", -3, &[NoScript, Stale, MayReplicate], SCRIPT_KEYS,
+        docs(Scripting, "2.6.0", SCRIPT_COMPLEXITY, "Executes a server-side Lua script."),
+        handler!(EvalRequest)
+    ),
+    command(
+        "evalsha", -3, &[NoScript, Stale, MayReplicate], SCRIPT_KEYS,
+        docs(Scripting, "2.6.0", SCRIPT_COMPLEXITY, "Executes a server-side Lua script by SHA1 digest."),
+        handler!(EvalShaRequest)
+    ),
+    command(
+        "eval_ro", -3, &[NoScript, Stale, ReadOnly], SCRIPT_KEYS,
+        docs(Scripting, "7.0.0", SCRIPT_COMPLEXITY, "Executes a read-only server-side Lua script."),
+        handler!(EvalRoRequest)
+    ),
+    command(
+        "evalsha_ro", -3, &[NoScript, Stale, ReadOnly], SCRIPT_KEYS,
+        docs(Scripting, "7.0.0", SCRIPT_COMPLEXITY, "Executes a read-only server-side Lua script by SHA1 digest."),
+        handler!(EvalShaRoRequest)
+    ),
+    container(
+        "script", -2,
+        docs(Scripting, "2.6.0", "Depends on subcommand.", "A container for Lua scripts management commands."),
+        handler!(ScriptRequest),
+        &[
+            subcommand(
+                "script|exists", -3, &[NoScript], KeySpec::NONE,
+                docs(
+                    Scripting, "2.6.0",
+                    "O(N) with N being the number of scripts to check (so checking a single script is an O(1) operation).",
+                    "Determines whether server-side Lua scripts exist in the script cache."
+                )
+            ),
+            subcommand(
+                "script|flush", -2, &[NoScript], KeySpec::NONE,
+                docs(
+                    Scripting, "2.6.0", "O(N) with N being the number of scripts in cache",
+                    "Removes all server-side Lua scripts from the script cache."
+                )
+            ),
+            subcommand(
+                "script|kill", 2, &[NoScript, AllowBusy], KeySpec::NONE,
+                docs(Scripting, "2.6.0", O1, "Terminates a server-side Lua script during execution.")
+            ),
+            subcommand(
+                "script|load", 3, &[NoScript, Stale], KeySpec::NONE,
+                docs(
+                    Scripting, "2.6.0", "O(N) with N being the length in bytes of the script body.",
+                    "Loads a server-side Lua script to the script cache."
+                )
+            ),
+        ]
+    ),
+    command(
+        "fcall", -3, &[NoScript, Stale, MayReplicate], SCRIPT_KEYS,
+        docs(Scripting, "7.0.0", FUNCTION_COMPLEXITY, "Invokes a function."),
+        handler!(FCallRequest)
+    ),
+    command(
+        "fcall_ro", -3, &[NoScript, Stale, ReadOnly], SCRIPT_KEYS,
+        docs(Scripting, "7.0.0", FUNCTION_COMPLEXITY, "Invokes a read-only function."),
+        handler!(FCallRoRequest)
+    ),
+    container(
+        "function", -2,
+        docs(Scripting, "7.0.0", "Depends on subcommand.", "A container for function commands."),
+        function::handle,
+        &[
+            subcommand(
+                "function|delete", 3, &[Write, NoScript], KeySpec::NONE,
+                docs(Scripting, "7.0.0", O1, "Deletes a library and its functions.")
+            ),
+            subcommand(
+                "function|dump", 2, &[NoScript], KeySpec::NONE,
+                docs(
+                    Scripting, "7.0.0", "O(N) where N is the number of functions",
+                    "Dumps all libraries into a serialized binary payload."
+                )
+            ),
+            subcommand(
+                "function|flush", -2, &[Write, NoScript], KeySpec::NONE,
+                docs(
+                    Scripting, "7.0.0", "O(N) where N is the number of functions deleted",
+                    "Deletes all libraries and functions."
+                )
+            ),
+            subcommand(
+                "function|kill", 2, &[NoScript, AllowBusy], KeySpec::NONE,
+                docs(Scripting, "7.0.0", O1, "Terminates a function during execution.")
+            ),
+            subcommand(
+                "function|list", -2, &[NoScript], KeySpec::NONE,
+                docs(
+                    Scripting, "7.0.0", "O(N) where N is the number of functions",
+                    "Returns information about all libraries."
+                )
+            ),
+            subcommand(
+                "function|load", -3, &[Write, DenyOom, NoScript], KeySpec::NONE,
+                docs(
+                    Scripting, "7.0.0", "O(1) (considering compilation time is redundant)",
+                    "Creates a library."
+                )
+            ),
+            subcommand(
+                "function|restore", -3, &[Write, DenyOom, NoScript], KeySpec::NONE,
+                docs(
+                    Scripting, "7.0.0", "O(N) where N is the number of functions on the payload",
+                    "Restores all libraries from a payload."
+                )
+            ),
+            subcommand(
+                "function|stats", 2, &[NoScript], KeySpec::NONE,
+                docs(
+                    Scripting, "7.0.0", O1,
+                    "Returns information about a function during execution."
+                )
+            ),
+        ]
+    ),
+];
+
+/// Why a frame could not be matched to a runnable command.
+pub enum LookupError {
+    /// The command does not exist; no statistics are kept for it.
+    Unknown(String),
+    /// The command exists but was called with an unknown subcommand or the wrong number of
+    /// arguments, counted as a rejected call.
+    Rejected(&'static CommandSpec, String),
+}
+
+/// Finds the table entry of a frame, the subcommand's for container commands, together
+/// with the handler running it.
+pub fn lookup_command(arguments: &[Vec<u8>]) -> Result<(&'static CommandSpec, Handler), LookupError> {
+    let name: &[u8] = arguments.first().map(|name| name.as_slice()).unwrap_or_default();
+    let Some(spec) = find(COMMAND_TABLE, name, None) else {
+        let mut message: String = format!(
+            "ERR unknown command '{}', with args beginning with: ", String::from_utf8_lossy(name));
+        for argument in arguments.iter().skip(1) {
+            message.push_str(&format!("'{}' ", String::from_utf8_lossy(argument)));
+        }
+        return Err(LookupError::Unknown(message));
+    };
+    let handler: Handler = spec.handler.expect("top-level commands have a handler");
+
+    let spec: &'static CommandSpec = match (spec.subcommands.is_empty(), arguments.get(1)) {
+        (true, _) | (false, None) => spec,
+        (false, Some(subcommand)) => match find(spec.subcommands, subcommand, Some(spec.name)) {
+            Some(subcommand) => subcommand,
+            None => return Err(LookupError::Rejected(spec, format!(
+                "ERR unknown subcommand '{}'. Try {} HELP.",
+                String::from_utf8_lossy(subcommand),
+                spec.name.to_ascii_uppercase()
+            ))),
+        },
+    };
+
+    if !spec.accepts_argument_count(arguments.len()) {
+        return Err(LookupError::Rejected(
+            spec, format!("ERR wrong number of arguments for '{}' command", spec.name)));
+    }
+    Ok((spec, handler))
+}
+
+/// The entry of a command or subcommand by name, ignoring case; subcommands are named
+/// after their container, e.g. `config|get`.
+pub fn find_command(name: &str) -> Option<&'static CommandSpec> {
+    match name.split_once('|') {
+        None => find(COMMAND_TABLE, name.as_bytes(), None),
+        Some((container, subcommand)) => {
+            let spec: &'static CommandSpec = find(COMMAND_TABLE, container.as_bytes(), None)?;
+            find(spec.subcommands, subcommand.as_bytes(), Some(spec.name))
+        }
+    }
+}
+
+/// The entry of `table` named `name`, ignoring case. Subcommand entries are prefixed with
+/// the name of their `container`.
+fn find(table: &'static [CommandSpec], name: &[u8], container: Option<&str>) -> Option<&'static CommandSpec> {
+    table.iter().find(|spec| {
+        let own_name: &str = match container {
+            Some(container) => &spec.name[container.len() + 1..],
+            None => spec.name,
+        };
+        own_name.as_bytes().eq_ignore_ascii_case(name)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_frame, Frame};
+
+    fn inline(line: &str) -> Vec<Vec<u8>> {
+        let (arguments, length): Frame = parse_frame(line.as_bytes()).unwrap().unwrap();
+        assert_eq!(length, line.len());
+        arguments
+    }
+
+    /// The reply a connection gets before being closed for sending `line`.
+    fn protocol_error(line: &str) -> String {
+        format!("ERR Protocol error: {}", parse_frame(line.as_bytes()).unwrap_err())
+    }
+
+    #[test]
+    fn parses_a_frame_of_bulk_strings() {
+        let frame: Option<Frame> = parse_frame(b"*2\r\n$4\r\nECHO\r\n$2\r\nhi\r\n").unwrap();
+        assert_eq!(frame, Some((vec![b"ECHO".to_vec(), b"hi".to_vec()], 22)));
+        assert_eq!(parse_frame(b"*2\r\n$4\r\nECHO\r\n$2\r\nh").unwrap(), None);
+    }
+
+    #[test]
+    fn skips_empty_and_null_arrays() {
+        assert_eq!(parse_frame(b"*0\r\n").unwrap(), Some((vec![], 4)));
+        assert_eq!(parse_frame(b"*-1\r\n").unwrap(), Some((vec![], 5)));
+    }
+
+    #[test]
+    fn rejects_oversized_lengths() {
+        let error = |input: &[u8]| parse_frame(input).unwrap_err().to_string();
+        assert_eq!(error(b"*1\r\n$18446744073709551615\r\n"), "invalid bulk length");
+        assert_eq!(error(b"*1\r\n$536870913\r\n"), "invalid bulk length");
+        assert_eq!(error(b"*1\r\n$-1\r\n"), "invalid bulk length");
+        assert_eq!(error(b"*1048577\r\n"), "invalid multibulk length");
+        assert_eq!(error(b"*x\r\n"), "invalid multibulk length");
+        assert_eq!(parse_frame(b"*1\r\n$536870912\r\n").unwrap(), None);
+    }
+
+    #[test]
+    fn splits_inline_commands_on_whitespace() {
+        assert_eq!(inline("SET  key\tvalue\r\n"), vec![b"SET".to_vec(), b"key".to_vec(), b"value".to_vec()]);
+        assert_eq!(inline("PING\n"), vec![b"PING".to_vec()]);
+        assert_eq!(parse_frame(b"PING").unwrap(), None);
+    }
+
+    #[test]
+    fn unquotes_inline_arguments() {
+        assert_eq!(inline("ECHO \"a key\"\r\n")[1], b"a key");
+        assert_eq!(inline("ECHO 'it\\'s \"quoted\"'\r\n")[1], b"it's \"quoted\"");
+        assert_eq!(inline("ECHO 'no \\n escapes'\r\n")[1], b"no \\n escapes");
+        assert_eq!(inline("ECHO \"\\x41\\x4a\\n\\t\\\"\"\r\n")[1], b"AJ\n\t\"");
+        assert_eq!(inline("ECHO \"\\xZZ\"\r\n")[1], b"xZZ");
+        assert_eq!(inline("ECHO \"\" ''\r\n"), vec![b"ECHO".to_vec(), Vec::new(), Vec::new()]);
+    }
+
+    #[test]
+    fn rejects_unbalanced_inline_quotes() {
+        let unbalanced: &str = "ERR Protocol error: unbalanced quotes in request";
+        assert_eq!(protocol_error("ECHO \"open\r\n"), unbalanced);
+        assert_eq!(protocol_error("ECHO 'open\r\n"), unbalanced);
+        assert_eq!(protocol_error("ECHO \"trailing escape\\\r\n"), unbalanced);
+        // A closing quote must be followed by whitespace or the end of the line.
+        assert_eq!(protocol_error("ECHO \"closed\"next\r\n"), unbalanced);
+        assert_eq!(protocol_error("ECHO 'closed'next\r\n"), unbalanced);
+    }
+
+    #[test]
+    fn skips_empty_inline_lines() {
+        assert_eq!(parse_frame(b"\r\n").unwrap(), Some((vec![], 2)));
+        assert_eq!(parse_frame(b"   \n").unwrap(), Some((vec![], 4)));
     }
 }
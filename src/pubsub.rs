@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use crate::client::Connection;
+use crate::glob;
+use crate::reply::Value;
+
+/// The connections subscribed to each channel or pattern, by client id.
+type Subscribers = HashMap<Vec<u8>, HashMap<u64, Arc<Connection>>>;
+
+/// The channels and patterns connections subscribed to, shared by the data manager and
+/// every keyspace shard so any of them can publish.
+#[derive(Default)]
+pub struct PubSub {
+    channels: Subscribers,
+    patterns: Subscribers,
+    /// What each subscribed client subscribed to, in the order it did.
+    clients: HashMap<u64, Subscriptions>,
+}
+
+#[derive(Default)]
+struct Subscriptions {
+    channels: Vec<Vec<u8>>,
+    patterns: Vec<Vec<u8>>,
+}
+
+impl PubSub {
+    pub fn new() -> Self {
+        PubSub::default()
+    }
+
+    /// Subscribes the connection to `channel`, or to the channels matching it as a pattern.
+    /// Returns false if it already was.
+    pub fn subscribe(&mut self, connection: &Arc<Connection>, channel: &[u8], pattern: bool) -> bool {
+        let (subscribers, subscriptions) = self.lists(connection.id, pattern);
+        if subscriptions.iter().any(|subscribed| subscribed == channel) {
+            return false;
+        }
+        subscriptions.push(channel.to_vec());
+        subscribers.entry(channel.to_vec()).or_default().insert(connection.id, connection.clone());
+        true
+    }
+
+    /// Unsubscribes a client from `channel`, or from the pattern. Returns false if it was not
+    /// subscribed.
+    pub fn unsubscribe(&mut self, id: u64, channel: &[u8], pattern: bool) -> bool {
+        if !self.clients.contains_key(&id) {
+            return false;
+        }
+        let (subscribers, subscriptions) = self.lists(id, pattern);
+        let Some(position) = subscriptions.iter().position(|subscribed| subscribed == channel) else {
+            return false;
+        };
+        subscriptions.remove(position);
+        if let Some(channel_subscribers) = subscribers.get_mut(channel) {
+            channel_subscribers.remove(&id);
+            if channel_subscribers.is_empty() {
+                subscribers.remove(channel);
+            }
+        }
+        if self.count(id) == 0 {
+            self.clients.remove(&id);
+        }
+        true
+    }
+
+    /// The channels, or the patterns, a client subscribed to.
+    pub fn subscriptions(&self, id: u64, pattern: bool) -> Vec<Vec<u8>> {
+        self.clients.get(&id)
+            .map(|subscriptions| if pattern { &subscriptions.patterns } else { &subscriptions.channels })
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Number of channels and patterns a client subscribed to.
+    pub fn count(&self, id: u64) -> usize {
+        self.clients.get(&id).map_or(0, |subscriptions| subscriptions.channels.len() + subscriptions.patterns.len())
+    }
+
+    /// Number of channels, then of patterns, a client subscribed to.
+    pub fn counts(&self, id: u64) -> (usize, usize) {
+        self.clients.get(&id).map_or((0, 0), |subscriptions| (subscriptions.channels.len(), subscriptions.patterns.len()))
+    }
+
+    /// Drops the subscriptions of a client that disconnected.
+    pub fn remove_client(&mut self, id: u64) {
+        for channel in self.subscriptions(id, false) {
+            self.unsubscribe(id, &channel, false);
+        }
+        for pattern in self.subscriptions(id, true) {
+            self.unsubscribe(id, &pattern, true);
+        }
+    }
+
+    /// Sends `message` to the subscribers of `channel` and of the patterns matching it,
+    /// returning how many received it.
+    pub fn publish(&self, channel: &[u8], message: &[u8]) -> usize {
+        let mut receivers: usize = 0;
+        for connection in self.channels.get(channel).into_iter().flat_map(HashMap::values) {
+            let sent: bool = connection.push(Value::Push(vec![
+                Value::bulk("message"),
+                Value::Bulk(channel.to_vec()),
+                Value::Bulk(message.to_vec()),
+            ]));
+            receivers += sent as usize;
+        }
+        for (pattern, subscribers) in &self.patterns {
+            if !glob::matches(pattern, channel, false) {
+                continue;
+            }
+            for connection in subscribers.values() {
+                let sent: bool = connection.push(Value::Push(vec![
+                    Value::bulk("pmessage"),
+                    Value::Bulk(pattern.clone()),
+                    Value::Bulk(channel.to_vec()),
+                    Value::Bulk(message.to_vec()),
+                ]));
+                receivers += sent as usize;
+            }
+        }
+        receivers
+    }
+
+    /// The channels with at least one subscriber.
+    pub fn channels(&self) -> impl Iterator<Item = &[u8]> {
+        self.channels.keys().map(Vec::as_slice)
+    }
+
+    pub fn subscriber_count(&self, channel: &[u8]) -> usize {
+        self.channels.get(channel).map_or(0, HashMap::len)
+    }
+
+    /// Number of distinct patterns subscribed to.
+    pub fn pattern_count(&self) -> usize {
+        self.patterns.len()
+    }
+
+    fn lists(&mut self, id: u64, pattern: bool) -> (&mut Subscribers, &mut Vec<Vec<u8>>) {
+        let subscriptions: &mut Subscriptions = self.clients.entry(id).or_default();
+        if pattern {
+            (&mut self.patterns, &mut subscriptions.patterns)
+        } else {
+            (&mut self.channels, &mut subscriptions.channels)
+        }
+    }
+}
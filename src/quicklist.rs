@@ -0,0 +1,244 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
+use crate::listpack::Listpack;
+use crate::lzf;
+
+/// Node sizes allowed by the negative `list-max-listpack-size` values, from -1 to -5.
+const SIZE_LIMITS: [usize; 5] = [4096, 8192, 16384, 32768, 65536];
+/// Largest node a positive `list-max-listpack-size` still allows growing, whatever its count.
+const SIZE_SAFETY_LIMIT: usize = 8192;
+/// Nodes smaller than this are not worth compressing.
+const MIN_COMPRESS_BYTES: usize = 48;
+/// Compression is only kept if it saves at least this many bytes.
+const MIN_COMPRESS_IMPROVE: usize = 8;
+/// Upper bound of the bytes an element adds to a listpack besides its value.
+const ENTRY_OVERHEAD: usize = 11;
+
+static LIST_MAX_LISTPACK_SIZE: AtomicIsize = AtomicIsize::new(-2);
+static LIST_COMPRESS_DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+/// The `list-max-listpack-size` and `list-compress-depth` parameters, which every list
+/// consults when it grows or shrinks.
+#[derive(Clone, Copy)]
+pub struct ListLimits {
+    /// Positive: maximum elements per listpack. Negative: maximum bytes, see `SIZE_LIMITS`.
+    fill: isize,
+    /// Number of nodes at each end of a quicklist left uncompressed; 0 disables compression.
+    compress_depth: usize,
+}
+
+impl ListLimits {
+    pub fn current() -> Self {
+        ListLimits {
+            fill: LIST_MAX_LISTPACK_SIZE.load(Ordering::Relaxed),
+            compress_depth: LIST_COMPRESS_DEPTH.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn set(fill: isize, compress_depth: usize) {
+        LIST_MAX_LISTPACK_SIZE.store(fill, Ordering::Relaxed);
+        LIST_COMPRESS_DEPTH.store(compress_depth, Ordering::Relaxed);
+    }
+
+    /// Whether a listpack of `size` bytes holding `count` elements is over the limit.
+    pub fn exceeded(&self, size: usize, count: usize) -> bool {
+        if self.fill >= 0 {
+            count > self.fill as usize || size > SIZE_SAFETY_LIMIT
+        } else {
+            size > SIZE_LIMITS[(self.fill.unsigned_abs() - 1).min(SIZE_LIMITS.len() - 1)]
+        }
+    }
+}
+
+/// A node of a quicklist: a listpack, compressed with LZF when it is in the middle of the
+/// list, where it is rarely accessed.
+struct Node {
+    count: usize,
+    data: NodeData,
+}
+
+enum NodeData {
+    Packed(Listpack),
+    Compressed { compressed: Vec<u8>, size: usize },
+}
+
+impl Node {
+    fn new(value: &[u8]) -> Self {
+        let mut listpack: Listpack = Listpack::new();
+        listpack.push_back(value);
+        Node { count: 1, data: NodeData::Packed(listpack) }
+    }
+
+    fn size(&self) -> usize {
+        match &self.data {
+            NodeData::Packed(listpack) => listpack.size(),
+            NodeData::Compressed { size, .. } => *size,
+        }
+    }
+
+    fn allows_insert(&self, value: &[u8], limits: ListLimits) -> bool {
+        !limits.exceeded(self.size() + value.len() + ENTRY_OVERHEAD, self.count + 1)
+    }
+
+    fn listpack_mut(&mut self) -> &mut Listpack {
+        self.decompress();
+        match &mut self.data {
+            NodeData::Packed(listpack) => listpack,
+            NodeData::Compressed { .. } => unreachable!("the node was just decompressed"),
+        }
+    }
+
+    fn elements(&self) -> Vec<Vec<u8>> {
+        match &self.data {
+            NodeData::Packed(listpack) => listpack.iter().collect(),
+            NodeData::Compressed { compressed, size } => Self::inflate(compressed, *size).iter().collect(),
+        }
+    }
+
+    fn compress(&mut self) {
+        if let NodeData::Packed(listpack) = &self.data {
+            if listpack.size() < MIN_COMPRESS_BYTES {
+                return;
+            }
+            if let Some(compressed) = lzf::compress(listpack.as_bytes()) {
+                if compressed.len() + MIN_COMPRESS_IMPROVE < listpack.size() {
+                    self.data = NodeData::Compressed { compressed, size: listpack.size() };
+                }
+            }
+        }
+    }
+
+    fn decompress(&mut self) {
+        if let NodeData::Compressed { compressed, size } = &self.data {
+            self.data = NodeData::Packed(Self::inflate(compressed, *size));
+        }
+    }
+
+    fn inflate(compressed: &[u8], size: usize) -> Listpack {
+        lzf::decompress(compressed, size)
+            .and_then(Listpack::from_bytes)
+            .expect("quicklist nodes are compressed from valid listpacks")
+    }
+
+    fn memory_usage(&self) -> usize {
+        size_of::<Node>() + match &self.data {
+            NodeData::Packed(listpack) => listpack.memory_usage(),
+            NodeData::Compressed { compressed, .. } => compressed.capacity(),
+        }
+    }
+}
+
+/// The encoding of lists too large for a single listpack: a deque of listpacks, each kept
+/// under `list-max-listpack-size`, so pushing and popping at either end stays cheap.
+pub struct Quicklist {
+    nodes: VecDeque<Node>,
+    count: usize,
+}
+
+impl Quicklist {
+    pub fn new() -> Self {
+        Quicklist { nodes: VecDeque::new(), count: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn push_back(&mut self, value: &[u8], limits: ListLimits) {
+        match self.nodes.back_mut() {
+            Some(node) if node.allows_insert(value, limits) => {
+                node.listpack_mut().push_back(value);
+                node.count += 1;
+            }
+            _ => self.nodes.push_back(Node::new(value)),
+        }
+        self.count += 1;
+        self.update_compression(limits);
+    }
+
+    pub fn push_front(&mut self, value: &[u8], limits: ListLimits) {
+        match self.nodes.front_mut() {
+            Some(node) if node.allows_insert(value, limits) => {
+                node.listpack_mut().push_front(value);
+                node.count += 1;
+            }
+            _ => self.nodes.push_front(Node::new(value)),
+        }
+        self.count += 1;
+        self.update_compression(limits);
+    }
+
+    pub fn pop_front(&mut self, limits: ListLimits) -> Option<Vec<u8>> {
+        let node: &mut Node = self.nodes.front_mut()?;
+        let value: Vec<u8> = node.listpack_mut().pop_front()?;
+        node.count -= 1;
+        if node.count == 0 {
+            self.nodes.pop_front();
+        }
+        self.count -= 1;
+        self.update_compression(limits);
+        Some(value)
+    }
+
+    /// Up to `count` elements starting at index `start`. Nodes before the range are skipped
+    /// by their element count, without decompressing them.
+    pub fn range(&self, mut start: usize, count: usize) -> Vec<Vec<u8>> {
+        let mut elements: Vec<Vec<u8>> = Vec::with_capacity(count.min(self.count));
+        for node in &self.nodes {
+            if elements.len() == count {
+                break;
+            }
+            if start >= node.count {
+                start -= node.count;
+                continue;
+            }
+            elements.extend(node.elements().into_iter().skip(start).take(count - elements.len()));
+            start = 0;
+        }
+        elements
+    }
+
+    /// Whether the whole list fits in one listpack of half the limit, so it can go back to
+    /// the plain listpack encoding without converting again on the next push.
+    pub fn fits_listpack(&self, limits: ListLimits) -> bool {
+        match self.nodes.front() {
+            Some(node) => self.nodes.len() == 1 && !limits.exceeded(node.size() * 2, node.count * 2),
+            None => true,
+        }
+    }
+
+    pub fn into_listpack(mut self) -> Listpack {
+        match self.nodes.pop_front() {
+            Some(mut node) => std::mem::replace(node.listpack_mut(), Listpack::new()),
+            None => Listpack::new(),
+        }
+    }
+
+    /// Estimate of the bytes taken, extrapolated from the first `samples` nodes, or from
+    /// all of them if it is 0.
+    pub fn memory_usage(&self, samples: usize) -> usize {
+        let sampled: usize = if samples == 0 { self.nodes.len() } else { samples.min(self.nodes.len()) };
+        let sampled_bytes: usize = self.nodes.iter().take(sampled).map(Node::memory_usage).sum();
+        size_of::<Self>() + (sampled_bytes * self.nodes.len()).checked_div(sampled).unwrap_or(0)
+    }
+
+    /// Keeps the `compress_depth` nodes at each end uncompressed and compresses the ones
+    /// they push towards the middle. Nodes further in were compressed when they got there.
+    fn update_compression(&mut self, limits: ListLimits) {
+        let depth: usize = limits.compress_depth;
+        if depth == 0 {
+            return;
+        }
+
+        let length: usize = self.nodes.len();
+        let edges = (0..length.min(depth + 1)).chain(length.saturating_sub(depth + 1)..length);
+        for index in edges {
+            let node: &mut Node = &mut self.nodes[index];
+            if index < depth || index >= length - depth {
+                node.decompress();
+            } else {
+                node.compress();
+            }
+        }
+    }
+}
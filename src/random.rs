@@ -0,0 +1,34 @@
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+thread_local! {
+    static STATE: Cell<u64> = Cell::new(seed());
+}
+
+/// Seeds each thread from the random keys the standard library generates for hash maps.
+fn seed() -> u64 {
+    RandomState::new().build_hasher().finish() | 1
+}
+
+/// Non-cryptographic xorshift64* generator, cheap enough for sampling keys and for the
+/// probabilistic access counters.
+pub fn next_u64() -> u64 {
+    STATE.with(|state| {
+        let mut x: u64 = state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(x);
+        x.wrapping_mul(0x2545F4914F6CDD1D)
+    })
+}
+
+pub fn random_below(bound: u64) -> u64 {
+    next_u64() % bound.max(1)
+}
+
+/// Uniformly distributed in `[0, 1)`.
+pub fn random_unit() -> f64 {
+    (next_u64() >> 11) as f64 / (1u64 << 53) as f64
+}
@@ -0,0 +1,490 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::key_value_store::{
+    KeyValueStore, KeyValueStoreEntry, KeyValueStoreListEntry, KeyValueStoreStringEntry
+};
+use crate::listpack::Listpack;
+
+mod ziplist;
+
+const RDB_VERSION: u16 = 11;
+/// Newest format accepted when loading; Redis 7.4 writes version 12 with the same value encodings.
+const RDB_MAX_VERSION: u16 = 12;
+
+/// A function library, as its code.
+const OPCODE_FUNCTION2: u8 = 0xF5;
+const OPCODE_AUX: u8 = 0xFA;
+const OPCODE_RESIZE_DB: u8 = 0xFB;
+const OPCODE_EXPIRE_TIME_MS: u8 = 0xFC;
+const OPCODE_EXPIRE_TIME: u8 = 0xFD;
+const OPCODE_SELECT_DB: u8 = 0xFE;
+const OPCODE_EOF: u8 = 0xFF;
+
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_LIST_ZIPLIST: u8 = 10;
+const TYPE_LIST_QUICKLIST: u8 = 14;
+const TYPE_LIST_QUICKLIST_2: u8 = 18;
+
+const QUICKLIST_NODE_PLAIN: usize = 1;
+const QUICKLIST_NODE_PACKED: usize = 2;
+
+const ENCODING_INT8: u8 = 0;
+const ENCODING_INT16: u8 = 1;
+const ENCODING_INT32: u8 = 2;
+const ENCODING_LZF: u8 = 3;
+
+pub type LoadedEntry = (String, Box<dyn KeyValueStoreEntry>);
+
+/// What an RDB snapshot holds: the entries of database 0 and the function libraries.
+pub struct Snapshot {
+    pub entries: Vec<LoadedEntry>,
+    /// The code of each library, which recreates it when loaded.
+    pub libraries: Vec<String>,
+}
+
+const fn crc64_table() -> [u64; 256] {
+    let mut table: [u64; 256] = [0; 256];
+    let mut index: usize = 0;
+    while index < 256 {
+        let mut crc: u64 = index as u64;
+        let mut bit: usize = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0x95AC9329AC4BC9B5 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[index] = crc;
+        index += 1;
+    }
+    table
+}
+
+/// Reflected CRC-64/Jones, the checksum Redis appends to RDB files and DUMP payloads.
+const CRC64_TABLE: [u64; 256] = crc64_table();
+
+pub fn crc64(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |crc: u64, byte| CRC64_TABLE[((crc ^ *byte as u64) & 0xFF) as usize] ^ (crc >> 8))
+}
+
+/// Serializes a single value the way `DUMP` does: its RDB type and encoding, followed by the
+/// RDB version and a CRC64 of everything before it.
+pub fn dump(entry: &dyn KeyValueStoreEntry) -> Vec<u8> {
+    let mut payload: Vec<u8> = vec![value_type(entry)];
+    write_value(&mut payload, entry);
+    payload.extend_from_slice(&RDB_VERSION.to_le_bytes());
+    let checksum: u64 = crc64(&payload);
+    payload.extend_from_slice(&checksum.to_le_bytes());
+    payload
+}
+
+/// Validates the footer of a `DUMP` payload and rebuilds the value it holds.
+pub fn restore(payload: &[u8], expiry: Option<SystemTime>) -> Result<Box<dyn KeyValueStoreEntry>, &'static str> {
+    let body: &[u8] = payload_body(payload).ok_or("DUMP payload version or checksum are wrong")?;
+    let mut reader: RdbReader = RdbReader::new(body);
+    let value_type: u8 = reader.read_u8()?;
+    let entry: Box<dyn KeyValueStoreEntry> = read_entry(&mut reader, value_type, expiry)
+        .map_err(|_| "Bad data format")?;
+    if reader.position != reader.bytes.len() {
+        return Err("Bad data format");
+    }
+    Ok(entry)
+}
+
+/// Serializes function libraries the way `FUNCTION DUMP` does: each library's code, with
+/// the same footer as `DUMP`.
+pub fn dump_libraries(libraries: &[&str]) -> Vec<u8> {
+    let mut payload: Vec<u8> = Vec::new();
+    write_libraries(&mut payload, libraries);
+    payload.extend_from_slice(&RDB_VERSION.to_le_bytes());
+    let checksum: u64 = crc64(&payload);
+    payload.extend_from_slice(&checksum.to_le_bytes());
+    payload
+}
+
+/// Validates the footer of a `FUNCTION DUMP` payload and returns the code of its libraries.
+pub fn restore_libraries(payload: &[u8]) -> Result<Vec<String>, &'static str> {
+    let body: &[u8] = payload_body(payload).ok_or("payload version or checksum are wrong")?;
+    let mut reader: RdbReader = RdbReader::new(body);
+    let mut libraries: Vec<String> = Vec::new();
+    while reader.position != reader.bytes.len() {
+        if reader.read_u8()? != OPCODE_FUNCTION2 {
+            return Err("given type is not a function");
+        }
+        libraries.push(into_string(reader.read_string()?)?);
+    }
+    Ok(libraries)
+}
+
+/// The body of a `DUMP` or `FUNCTION DUMP` payload, if its version and checksum are valid.
+fn payload_body(payload: &[u8]) -> Option<&[u8]> {
+    const FOOTER_LENGTH: usize = 10;
+
+    if payload.len() < FOOTER_LENGTH + 1 {
+        return None;
+    }
+    let (body, checksum) = payload.split_at(payload.len() - 8);
+    let version: u16 = u16::from_le_bytes([body[body.len() - 2], body[body.len() - 1]]);
+    if version > RDB_MAX_VERSION || crc64(body) != u64::from_le_bytes(checksum.try_into().unwrap()) {
+        return None;
+    }
+    Some(&body[..body.len() - 2])
+}
+
+/// Serializes the whole keyspace and the function libraries into an RDB snapshot, as sent
+/// to replicas on a full resync.
+pub fn serialize(store: &dyn KeyValueStore, libraries: &[&str]) -> Vec<u8> {
+    let mut output: Vec<u8> = Vec::new();
+    output.extend_from_slice(format!("REDIS{:04}", RDB_VERSION).as_bytes());
+
+    write_aux(&mut output, "redis-ver", "7.2.0");
+    write_aux(&mut output, "redis-bits", "64");
+    write_libraries(&mut output, libraries);
+
+    let entries: Vec<(&String, &dyn KeyValueStoreEntry)> = store
+        .iter()
+        .filter(|(_, entry)| entry.type_name() != "list" || entry.len().unwrap_or(0) > 0)
+        .collect();
+    let expires: usize = entries.iter().filter(|(_, entry)| entry.get_expiry().is_some()).count();
+
+    output.push(OPCODE_SELECT_DB);
+    write_length(&mut output, 0);
+    output.push(OPCODE_RESIZE_DB);
+    write_length(&mut output, entries.len());
+    write_length(&mut output, expires);
+
+    for (key, entry) in entries {
+        if let Some(expiry) = entry.get_expiry() {
+            output.push(OPCODE_EXPIRE_TIME_MS);
+            output.extend_from_slice(&to_unix_millis(*expiry).to_le_bytes());
+        }
+        write_entry(&mut output, key, entry);
+    }
+
+    output.push(OPCODE_EOF);
+    let checksum: u64 = crc64(&output);
+    output.extend_from_slice(&checksum.to_le_bytes());
+    output
+}
+
+/// Parses an RDB snapshot into the entries of database 0 and the function libraries.
+pub fn deserialize(bytes: &[u8]) -> Result<Snapshot, &'static str> {
+    let mut reader: RdbReader = RdbReader::new(bytes);
+
+    if reader.take(5)? != b"REDIS" {
+        return Err("Missing RDB magic string");
+    }
+    let version: u16 = std::str::from_utf8(reader.take(4)?)
+        .ok()
+        .and_then(|version| version.parse().ok())
+        .ok_or("Invalid RDB version")?;
+    if version > RDB_MAX_VERSION {
+        return Err("Unsupported RDB version");
+    }
+
+    let mut entries: Vec<LoadedEntry> = Vec::new();
+    let mut libraries: Vec<String> = Vec::new();
+    let mut expiry: Option<SystemTime> = None;
+    let mut database: usize = 0;
+
+    loop {
+        match reader.read_u8()? {
+            OPCODE_EOF => {
+                // A zero checksum means the writer had checksums disabled.
+                let body_length: usize = reader.position;
+                if let Ok(checksum) = reader.take(8) {
+                    let checksum: u64 = u64::from_le_bytes(checksum.try_into().unwrap());
+                    if checksum != 0 && checksum != crc64(&bytes[..body_length]) {
+                        return Err("Wrong RDB checksum");
+                    }
+                }
+                break;
+            }
+            OPCODE_AUX => {
+                reader.read_string()?;
+                reader.read_string()?;
+            }
+            OPCODE_FUNCTION2 => libraries.push(into_string(reader.read_string()?)?),
+            OPCODE_RESIZE_DB => {
+                reader.read_length()?;
+                reader.read_length()?;
+            }
+            OPCODE_SELECT_DB => database = reader.read_length()?,
+            OPCODE_EXPIRE_TIME_MS => {
+                let millis: u64 = u64::from_le_bytes(reader.take(8)?.try_into().unwrap());
+                expiry = Some(UNIX_EPOCH + Duration::from_millis(millis));
+            }
+            OPCODE_EXPIRE_TIME => {
+                let seconds: u32 = u32::from_le_bytes(reader.take(4)?.try_into().unwrap());
+                expiry = Some(UNIX_EPOCH + Duration::from_secs(seconds as u64));
+            }
+            value_type => {
+                let key: String = into_string(reader.read_string()?)?;
+                let entry: Box<dyn KeyValueStoreEntry> = read_entry(&mut reader, value_type, expiry.take())?;
+                if database == 0 {
+                    entries.push((key, entry));
+                }
+            }
+        }
+    }
+
+    Ok(Snapshot { entries, libraries })
+}
+
+fn write_entry(output: &mut Vec<u8>, key: &str, entry: &dyn KeyValueStoreEntry) {
+    output.push(value_type(entry));
+    write_string(output, key.as_bytes());
+    write_value(output, entry);
+}
+
+fn value_type(entry: &dyn KeyValueStoreEntry) -> u8 {
+    match entry.type_name() {
+        "list" => TYPE_LIST,
+        _ => TYPE_STRING,
+    }
+}
+
+fn write_value(output: &mut Vec<u8>, entry: &dyn KeyValueStoreEntry) {
+    match entry.type_name() {
+        "list" => {
+            let values: Vec<String> = entry.get_subslice(0, -1).unwrap_or_default();
+            write_length(output, values.len());
+            for value in values {
+                write_string(output, value.as_bytes());
+            }
+        }
+        _ => write_string(output, entry.get_value().map(|value| value.as_bytes()).unwrap_or(b"")),
+    }
+}
+
+fn read_entry(
+    reader: &mut RdbReader,
+    value_type: u8,
+    expiry: Option<SystemTime>
+) -> Result<Box<dyn KeyValueStoreEntry>, &'static str> {
+    match value_type {
+        TYPE_STRING => {
+            let value: String = into_string(reader.read_string()?)?;
+            Ok(Box::new(KeyValueStoreStringEntry { value, expiry }))
+        }
+        TYPE_LIST => {
+            let length: usize = reader.read_length()?;
+            // Every element takes at least a byte, which bounds what a forged length can reserve.
+            let mut values: Vec<String> = Vec::with_capacity(length.min(reader.remaining()));
+            for _ in 0..length {
+                values.push(into_string(reader.read_string()?)?);
+            }
+            Ok(Box::new(KeyValueStoreListEntry::with_values(values, expiry)))
+        }
+        TYPE_LIST_ZIPLIST => {
+            let values: Vec<Vec<u8>> = ziplist::entries(&reader.read_string()?)?;
+            list_entry(values, expiry)
+        }
+        TYPE_LIST_QUICKLIST => {
+            let nodes: usize = reader.read_length()?;
+            let mut values: Vec<Vec<u8>> = Vec::new();
+            for _ in 0..nodes {
+                values.extend(ziplist::entries(&reader.read_string()?)?);
+            }
+            list_entry(values, expiry)
+        }
+        TYPE_LIST_QUICKLIST_2 => {
+            let nodes: usize = reader.read_length()?;
+            let mut values: Vec<Vec<u8>> = Vec::new();
+            for _ in 0..nodes {
+                let container: usize = reader.read_length()?;
+                let node: Vec<u8> = reader.read_string()?;
+                match container {
+                    QUICKLIST_NODE_PLAIN => values.push(node),
+                    QUICKLIST_NODE_PACKED => values.extend(Listpack::from_bytes(node)?.iter()),
+                    _ => return Err("Invalid quicklist node container"),
+                }
+            }
+            list_entry(values, expiry)
+        }
+        _ => Err("Unsupported RDB value type"),
+    }
+}
+
+fn list_entry(values: Vec<Vec<u8>>, expiry: Option<SystemTime>) -> Result<Box<dyn KeyValueStoreEntry>, &'static str> {
+    let values: Vec<String> = values.into_iter().map(into_string).collect::<Result<_, _>>()?;
+    Ok(Box::new(KeyValueStoreListEntry::with_values(values, expiry)))
+}
+
+/// Reads a signed little-endian integer of one to eight bytes, as found in ziplists and listpacks.
+pub fn read_le_int(bytes: &[u8]) -> i64 {
+    let mut buffer: [u8; 8] = [0; 8];
+    buffer[..bytes.len()].copy_from_slice(bytes);
+    let shift: u32 = 64 - 8 * bytes.len() as u32;
+    (i64::from_le_bytes(buffer) << shift) >> shift
+}
+
+fn write_libraries(output: &mut Vec<u8>, libraries: &[&str]) {
+    for library in libraries {
+        output.push(OPCODE_FUNCTION2);
+        write_string(output, library.as_bytes());
+    }
+}
+
+fn write_aux(output: &mut Vec<u8>, key: &str, value: &str) {
+    output.push(OPCODE_AUX);
+    write_string(output, key.as_bytes());
+    write_string(output, value.as_bytes());
+}
+
+fn write_length(output: &mut Vec<u8>, length: usize) {
+    if length < 1 << 6 {
+        output.push(length as u8);
+    } else if length < 1 << 14 {
+        output.push(0x40 | (length >> 8) as u8);
+        output.push(length as u8);
+    } else if length <= u32::MAX as usize {
+        output.push(0x80);
+        output.extend_from_slice(&(length as u32).to_be_bytes());
+    } else {
+        output.push(0x81);
+        output.extend_from_slice(&(length as u64).to_be_bytes());
+    }
+}
+
+fn write_string(output: &mut Vec<u8>, value: &[u8]) {
+    write_length(output, value.len());
+    output.extend_from_slice(value);
+}
+
+fn to_unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|duration| duration.as_millis() as u64).unwrap_or(0)
+}
+
+fn into_string(bytes: Vec<u8>) -> Result<String, &'static str> {
+    String::from_utf8(bytes).map_err(|_| "RDB string is not valid UTF-8")
+}
+
+enum Length {
+    Plain(usize),
+    Encoded(u8),
+}
+
+struct RdbReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> RdbReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        RdbReader { bytes, position: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.position
+    }
+
+    fn take(&mut self, amount: usize) -> Result<&'a [u8], &'static str> {
+        let end: usize = self.position.checked_add(amount).ok_or("Unexpected end of RDB")?;
+        let slice: &[u8] = self.bytes.get(self.position..end).ok_or("Unexpected end of RDB")?;
+        self.position = end;
+        Ok(slice)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, &'static str> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn read_length_or_encoding(&mut self) -> Result<Length, &'static str> {
+        let first: u8 = self.read_u8()?;
+        match first >> 6 {
+            0 => Ok(Length::Plain((first & 0x3F) as usize)),
+            1 => Ok(Length::Plain(((first as usize & 0x3F) << 8) | self.read_u8()? as usize)),
+            2 => match first {
+                0x80 => Ok(Length::Plain(u32::from_be_bytes(self.take(4)?.try_into().unwrap()) as usize)),
+                0x81 => Ok(Length::Plain(u64::from_be_bytes(self.take(8)?.try_into().unwrap()) as usize)),
+                _ => Err("Invalid RDB length encoding"),
+            },
+            _ => Ok(Length::Encoded(first & 0x3F)),
+        }
+    }
+
+    fn read_length(&mut self) -> Result<usize, &'static str> {
+        match self.read_length_or_encoding()? {
+            Length::Plain(length) => Ok(length),
+            Length::Encoded(_) => Err("Expected a plain RDB length"),
+        }
+    }
+
+    fn read_string(&mut self) -> Result<Vec<u8>, &'static str> {
+        match self.read_length_or_encoding()? {
+            Length::Plain(length) => Ok(self.take(length)?.to_vec()),
+            Length::Encoded(ENCODING_INT8) => Ok((self.read_u8()? as i8).to_string().into_bytes()),
+            Length::Encoded(ENCODING_INT16) => {
+                let value: i16 = i16::from_le_bytes(self.take(2)?.try_into().unwrap());
+                Ok(value.to_string().into_bytes())
+            }
+            Length::Encoded(ENCODING_INT32) => {
+                let value: i32 = i32::from_le_bytes(self.take(4)?.try_into().unwrap());
+                Ok(value.to_string().into_bytes())
+            }
+            Length::Encoded(ENCODING_LZF) => {
+                let compressed_length: usize = self.read_length()?;
+                let length: usize = self.read_length()?;
+                crate::lzf::decompress(self.take(compressed_length)?, length)
+            }
+            Length::Encoded(_) => Err("Unsupported RDB string encoding"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc64, dump, restore, RDB_VERSION};
+    use crate::key_value_store::{KeyValueStoreEntry, KeyValueStoreListEntry, KeyValueStoreStringEntry};
+
+    /// Appends the version and checksum footer to a payload body.
+    fn payload(body: &[u8]) -> Vec<u8> {
+        let mut payload: Vec<u8> = body.to_vec();
+        payload.extend_from_slice(&RDB_VERSION.to_le_bytes());
+        let checksum: u64 = crc64(&payload);
+        payload.extend_from_slice(&checksum.to_le_bytes());
+        payload
+    }
+
+    #[test]
+    fn crc64_matches_redis() {
+        assert_eq!(crc64(b"123456789"), 0xe9c6d914c4b8d9ca);
+        assert_eq!(crc64(b""), 0);
+    }
+
+    #[test]
+    fn restores_what_it_dumps() {
+        let string: KeyValueStoreStringEntry = KeyValueStoreStringEntry { value: "hello".to_string(), expiry: None };
+        let restored: Box<dyn KeyValueStoreEntry> = restore(&dump(&string), None).unwrap();
+        assert_eq!(restored.get_value().unwrap(), "hello");
+
+        let values: Vec<String> = vec!["a".to_string(), "12".to_string(), "x".repeat(100)];
+        let list: KeyValueStoreListEntry = KeyValueStoreListEntry::with_values(values.clone(), None);
+        let restored: Box<dyn KeyValueStoreEntry> = restore(&dump(&list), None).unwrap();
+        assert_eq!(restored.get_subslice(0, -1).unwrap(), values);
+    }
+
+    #[test]
+    fn rejects_a_wrong_checksum() {
+        let mut payload: Vec<u8> = payload(b"\x00\x05hello");
+        let last: usize = payload.len() - 1;
+        payload[last] ^= 1;
+        assert!(restore(&payload, None).is_err());
+    }
+
+    #[test]
+    fn rejects_forged_lengths_without_reserving_them() {
+        // A list claiming 2^40 elements.
+        let list: Vec<u8> = payload(b"\x01\x81\x00\x00\x01\x00\x00\x00\x00\x00");
+        assert_eq!(restore(&list, None).err(), Some("Bad data format"));
+
+        // An LZF string of one compressed byte claiming to expand to 2^40 bytes.
+        let lzf: Vec<u8> = payload(b"\x00\xC3\x01\x81\x00\x00\x01\x00\x00\x00\x00\x00\x00");
+        assert_eq!(restore(&lzf, None).err(), Some("Bad data format"));
+
+        // A string longer than the payload, and a listpack shorter than its header.
+        let string: Vec<u8> = payload(b"\x00\x81\xFF\xFF\xFF\xFF\xFF\xFF\xFF\xF0");
+        assert_eq!(restore(&string, None).err(), Some("Bad data format"));
+        let listpack: Vec<u8> = payload(b"\x12\x01\x02\x05\x05\x00\x00\x00\xFF");
+        assert_eq!(restore(&listpack, None).err(), Some("Bad data format"));
+    }
+}
@@ -0,0 +1,58 @@
+use crate::rdb::read_le_int;
+
+const END: u8 = 0xFF;
+const HEADER_LENGTH: usize = 10;
+
+/// Decodes the elements of a ziplist, the compact list encoding used by RDB files written
+/// before Redis 7. Integers are returned in their decimal form.
+pub fn entries(bytes: &[u8]) -> Result<Vec<Vec<u8>>, &'static str> {
+    const CORRUPT: &str = "Invalid ziplist";
+
+    let total_length: usize = u32::from_le_bytes(bytes.get(..4).ok_or(CORRUPT)?.try_into().unwrap()) as usize;
+    if total_length != bytes.len() {
+        return Err(CORRUPT);
+    }
+
+    let mut elements: Vec<Vec<u8>> = Vec::new();
+    let mut position: usize = HEADER_LENGTH;
+
+    loop {
+        let first: u8 = *bytes.get(position).ok_or(CORRUPT)?;
+        if first == END {
+            break;
+        }
+        // Skip the length of the previous entry.
+        position += if first == 0xFE { 5 } else { 1 };
+
+        let encoding: u8 = *bytes.get(position).ok_or(CORRUPT)?;
+        let data = |offset: usize, length: usize| bytes.get(position + offset..position + offset + length).ok_or(CORRUPT);
+        let (element, entry_length): (Vec<u8>, usize) = match encoding >> 6 {
+            0 => {
+                let length: usize = (encoding & 0x3F) as usize;
+                (data(1, length)?.to_vec(), 1 + length)
+            }
+            1 => {
+                let length: usize = (((encoding & 0x3F) as usize) << 8) | data(1, 1)?[0] as usize;
+                (data(2, length)?.to_vec(), 2 + length)
+            }
+            2 => {
+                let length: usize = u32::from_be_bytes(data(1, 4)?.try_into().unwrap()) as usize;
+                (data(5, length)?.to_vec(), 5 + length)
+            }
+            _ => match encoding {
+                0xC0 => (read_le_int(data(1, 2)?).to_string().into_bytes(), 3),
+                0xD0 => (read_le_int(data(1, 4)?).to_string().into_bytes(), 5),
+                0xE0 => (read_le_int(data(1, 8)?).to_string().into_bytes(), 9),
+                0xF0 => (read_le_int(data(1, 3)?).to_string().into_bytes(), 4),
+                0xFE => (read_le_int(data(1, 1)?).to_string().into_bytes(), 2),
+                0xF1..=0xFD => (((encoding & 0x0F) - 1).to_string().into_bytes(), 1),
+                _ => return Err(CORRUPT),
+            },
+        };
+
+        elements.push(element);
+        position += entry_length;
+    }
+
+    Ok(elements)
+}
@@ -0,0 +1,339 @@
+pub mod backlog;
+pub mod replica_link;
+
+use std::collections::hash_map::RandomState;
+use std::fmt::Write;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use crate::Msg;
+use crate::command::encode_command_into;
+use crate::replication::backlog::ReplicationBacklog;
+use crate::replication::replica_link::LinkStatus;
+
+pub const DEFAULT_BACKLOG_SIZE: usize = 1024 * 1024;
+
+const EMPTY_REPLICATION_ID: &str = "0000000000000000000000000000000000000000";
+
+struct ReplicaHandle {
+    client_id: u64,
+    /// Address the replica serves clients on, as reported by `INFO`.
+    address: (String, u16),
+    tx: mpsc::UnboundedSender<Vec<u8>>,
+    ack_offset: u64,
+    aof_ack_offset: u64,
+    last_ack: Instant,
+}
+
+/// What a `WAIT`/`WAITAOF` caller is waiting for: replicas acknowledging either the
+/// replication offset or the offset fsynced to their append-only file.
+#[derive(Clone, Copy, PartialEq)]
+pub enum AckKind {
+    Replicated,
+    Fsynced,
+}
+
+struct AckWaiter {
+    kind: AckKind,
+    target_offset: u64,
+    tx: watch::Sender<usize>,
+}
+
+struct MasterLink {
+    host: String,
+    port: u16,
+    task: JoinHandle<()>,
+    status: Arc<LinkStatus>,
+}
+
+pub struct ReplicationState {
+    replid: String,
+    replid2: String,
+    second_replid_offset: i64,
+    master_repl_offset: u64,
+    backlog: ReplicationBacklog,
+    replicas: Vec<ReplicaHandle>,
+    ack_waiters: Vec<AckWaiter>,
+    master: Option<MasterLink>,
+    listening_port: u16,
+    store_tx: mpsc::Sender<Msg>,
+    /// Buffer the commands fed to the stream are encoded into, kept between commands.
+    frame: Vec<u8>,
+}
+
+/// How a replica's `PSYNC` is answered: either everything after its offset is still in the
+/// backlog, or it needs a complete snapshot.
+pub enum SyncKind {
+    Continue(Vec<u8>),
+    Full,
+}
+
+impl ReplicationState {
+    pub fn new(backlog_size: usize, listening_port: u16, store_tx: mpsc::Sender<Msg>) -> Self {
+        ReplicationState {
+            replid: generate_replication_id(),
+            replid2: EMPTY_REPLICATION_ID.to_string(),
+            second_replid_offset: -1,
+            master_repl_offset: 0,
+            backlog: ReplicationBacklog::new(backlog_size, 0),
+            replicas: Vec::new(),
+            ack_waiters: Vec::new(),
+            master: None,
+            listening_port,
+            store_tx,
+            frame: Vec::new(),
+        }
+    }
+
+    pub fn is_replica(&self) -> bool {
+        self.master.is_some()
+    }
+
+    pub fn replid(&self) -> &str {
+        &self.replid
+    }
+
+    pub fn master_repl_offset(&self) -> u64 {
+        self.master_repl_offset
+    }
+
+    /// Appends a chunk of the replication stream to the backlog and forwards it to every
+    /// attached replica, forgetting the ones whose connection has gone away.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.master_repl_offset += bytes.len() as u64;
+        self.backlog.append(bytes);
+        self.replicas.retain(|replica| replica.tx.send(bytes.to_vec()).is_ok());
+    }
+
+    /// Feeds a command given as its arguments, the command name first.
+    pub fn feed_command(&mut self, arguments: &[Vec<u8>]) {
+        let mut frame: Vec<u8> = std::mem::take(&mut self.frame);
+        frame.clear();
+        encode_command_into(&mut frame, arguments);
+        self.feed(&frame);
+        self.frame = frame;
+    }
+
+    pub fn backlog_size(&self) -> usize {
+        self.backlog.size()
+    }
+
+    /// Changes the backlog size. The history is dropped, so replicas reconnecting afterwards
+    /// need a full resynchronization.
+    pub fn resize_backlog(&mut self, size: usize) {
+        if size != self.backlog.size() {
+            self.backlog = ReplicationBacklog::new(size, self.master_repl_offset);
+        }
+    }
+
+    /// Decides how to answer `PSYNC <replid> <offset>`. Offsets of the secondary ID are only
+    /// accepted up to the point where this node stopped following its previous master.
+    pub fn try_partial_resync(&self, replid: &str, offset: i64) -> SyncKind {
+        let known_history: bool = replid == self.replid
+            || (replid == self.replid2 && offset <= self.second_replid_offset);
+
+        if !known_history || offset < 0 {
+            return SyncKind::Full;
+        }
+
+        match self.backlog.read_from(offset as u64) {
+            Some(missing) => SyncKind::Continue(missing),
+            None => SyncKind::Full,
+        }
+    }
+
+    /// Registers the connection `client_id` as a replica that is in sync up to the current
+    /// offset and returns the channel its replication stream is written to.
+    pub fn attach_replica(&mut self, client_id: u64, address: (String, u16)) -> mpsc::UnboundedReceiver<Vec<u8>> {
+        let (tx, rx): (mpsc::UnboundedSender<Vec<u8>>, mpsc::UnboundedReceiver<Vec<u8>>)
+            = mpsc::unbounded_channel();
+        self.replicas.push(ReplicaHandle {
+            client_id,
+            address,
+            tx,
+            ack_offset: self.master_repl_offset,
+            aof_ack_offset: 0,
+            last_ack: Instant::now(),
+        });
+        rx
+    }
+
+    /// Connections currently attached as replicas.
+    pub fn replica_client_ids(&self) -> impl Iterator<Item = u64> + '_ {
+        self.replicas.iter().map(|replica| replica.client_id)
+    }
+
+    /// Records a `REPLCONF ACK` and wakes up `WAIT` callers whose replica count changed.
+    pub fn acknowledge(&mut self, client_id: u64, offset: u64, aof_offset: Option<u64>) {
+        if let Some(replica) = self.replicas.iter_mut().find(|replica| replica.client_id == client_id) {
+            replica.ack_offset = replica.ack_offset.max(offset);
+            replica.last_ack = Instant::now();
+            if let Some(aof_offset) = aof_offset {
+                replica.aof_ack_offset = replica.aof_ack_offset.max(aof_offset);
+            }
+        }
+
+        let replicas: &[ReplicaHandle] = &self.replicas;
+        self.ack_waiters.retain(|waiter| {
+            let count: usize = count_acked(replicas, waiter.kind, waiter.target_offset);
+            waiter.tx.send_if_modified(|current| {
+                let changed: bool = *current != count;
+                *current = count;
+                changed
+            });
+            !waiter.tx.is_closed()
+        });
+    }
+
+    /// Counts the replicas that acknowledged everything written so far. When that is fewer
+    /// than `needed`, asks every replica for a fresh ACK and returns a receiver that follows
+    /// the count as acknowledgements arrive.
+    pub fn wait_for_acks(&mut self, kind: AckKind, needed: usize) -> Result<usize, watch::Receiver<usize>> {
+        let target_offset: u64 = self.master_repl_offset;
+        let count: usize = count_acked(&self.replicas, kind, target_offset);
+        if count >= needed {
+            return Ok(count);
+        }
+
+        let (tx, rx): (watch::Sender<usize>, watch::Receiver<usize>) = watch::channel(count);
+        self.ack_waiters.push(AckWaiter { kind, target_offset, tx });
+        self.feed(b"*3\r\n$8\r\nREPLCONF\r\n$6\r\nGETACK\r\n$1\r\n*\r\n");
+        Err(rx)
+    }
+
+    /// Starts following `host:port`, replacing the link to any previous master.
+    pub fn replicate_from(&mut self, host: String, port: u16) {
+        if self.master.as_ref().is_some_and(|master| master.host == host && master.port == port) {
+            return;
+        }
+        self.stop_master_link();
+
+        let status: Arc<LinkStatus> = Arc::new(LinkStatus::default());
+        let task: JoinHandle<()> = tokio::spawn(replica_link::run(
+            host.clone(), port, self.listening_port, self.store_tx.clone(), status.clone()));
+        self.master = Some(MasterLink { host, port, task, status });
+    }
+
+    /// Turns a replica into a master. The old ID is kept as the secondary one so replicas
+    /// that followed the same master can still partially resynchronize with this node.
+    pub fn promote(&mut self) {
+        if self.master.is_none() {
+            return;
+        }
+        self.stop_master_link();
+        self.shift_replication_id(generate_replication_id());
+    }
+
+    /// The ID and offset a replica asks for when reconnecting, or `None` before its first sync.
+    pub fn psync_params(&self) -> Option<(String, i64)> {
+        if self.master_repl_offset == 0 {
+            return None;
+        }
+        Some((self.replid.clone(), self.master_repl_offset as i64 + 1))
+    }
+
+    /// Adopts the master's history after a full resynchronization. Sub-replicas are dropped
+    /// since the data set they hold no longer matches this node's.
+    pub fn complete_full_resync(&mut self, replid: String, offset: u64) {
+        self.replid = replid;
+        self.replid2 = EMPTY_REPLICATION_ID.to_string();
+        self.second_replid_offset = -1;
+        self.master_repl_offset = offset;
+        self.backlog = ReplicationBacklog::new(self.backlog.size(), offset);
+        self.replicas.clear();
+    }
+
+    /// Handles `+CONTINUE <replid>`; a changed ID means the master was promoted meanwhile.
+    pub fn complete_partial_resync(&mut self, replid: Option<String>) {
+        if let Some(replid) = replid.filter(|replid| *replid != self.replid) {
+            self.shift_replication_id(replid);
+        }
+    }
+
+    /// Renders the `# Replication` section of `INFO`.
+    pub fn info(&self) -> String {
+        let mut info: String = String::new();
+
+        match &self.master {
+            Some(master) => {
+                let last_io: i64 = master.status.last_io_seconds_ago().map(|seconds| seconds as i64).unwrap_or(-1);
+                writeln!(info, "role:slave\r").unwrap();
+                writeln!(info, "master_host:{}\r", master.host).unwrap();
+                writeln!(info, "master_port:{}\r", master.port).unwrap();
+                writeln!(info, "master_link_status:{}\r", if master.status.is_up() { "up" } else { "down" }).unwrap();
+                writeln!(info, "master_last_io_seconds_ago:{}\r", last_io).unwrap();
+                writeln!(info, "master_sync_in_progress:{}\r", master.status.is_syncing() as u8).unwrap();
+                writeln!(info, "slave_read_repl_offset:{}\r", self.master_repl_offset).unwrap();
+                writeln!(info, "slave_repl_offset:{}\r", self.master_repl_offset).unwrap();
+                writeln!(info, "slave_priority:100\r").unwrap();
+                writeln!(info, "slave_read_only:1\r").unwrap();
+                writeln!(info, "replica_announced:1\r").unwrap();
+            }
+            None => writeln!(info, "role:master\r").unwrap(),
+        }
+
+        writeln!(info, "connected_slaves:{}\r", self.replicas.len()).unwrap();
+        for (index, replica) in self.replicas.iter().enumerate() {
+            writeln!(
+                info,
+                "slave{}:ip={},port={},state=online,offset={},lag={}\r",
+                index,
+                replica.address.0,
+                replica.address.1,
+                replica.ack_offset,
+                replica.last_ack.elapsed().as_secs()
+            ).unwrap();
+        }
+
+        writeln!(info, "master_failover_state:no-failover\r").unwrap();
+        writeln!(info, "master_replid:{}\r", self.replid).unwrap();
+        writeln!(info, "master_replid2:{}\r", self.replid2).unwrap();
+        writeln!(info, "master_repl_offset:{}\r", self.master_repl_offset).unwrap();
+        writeln!(info, "second_repl_offset:{}\r", self.second_replid_offset).unwrap();
+        writeln!(info, "repl_backlog_active:1\r").unwrap();
+        writeln!(info, "repl_backlog_size:{}\r", self.backlog.size()).unwrap();
+        writeln!(info, "repl_backlog_first_byte_offset:{}\r", self.backlog.first_byte_offset()).unwrap();
+        writeln!(info, "repl_backlog_histlen:{}\r", self.backlog.history_length()).unwrap();
+        info
+    }
+
+    fn shift_replication_id(&mut self, new_replid: String) {
+        self.replid2 = std::mem::replace(&mut self.replid, new_replid);
+        self.second_replid_offset = self.master_repl_offset as i64 + 1;
+    }
+
+    fn stop_master_link(&mut self) {
+        if let Some(master) = self.master.take() {
+            master.task.abort();
+        }
+    }
+}
+
+fn count_acked(replicas: &[ReplicaHandle], kind: AckKind, target_offset: u64) -> usize {
+    replicas
+        .iter()
+        .filter(|replica| match kind {
+            AckKind::Replicated => replica.ack_offset >= target_offset,
+            AckKind::Fsynced => replica.aof_ack_offset >= target_offset,
+        })
+        .count()
+}
+
+pub fn generate_replication_id() -> String {
+    let seed: u128 = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos())
+        .unwrap_or(0);
+    let mut replid: String = String::with_capacity(48);
+
+    while replid.len() < 40 {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(seed);
+        write!(replid, "{:016x}", hasher.finish()).unwrap();
+    }
+
+    replid.truncate(40);
+    replid
+}
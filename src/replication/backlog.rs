@@ -0,0 +1,123 @@
+use std::cmp::min;
+
+/// Circular buffer holding the most recent part of the replication stream, so a replica
+/// that reconnects with `PSYNC <replid> <offset>` can continue where it left off.
+pub struct ReplicationBacklog {
+    buffer: Vec<u8>,
+    write_index: usize,
+    history_length: usize,
+    /// Replication offset of the first byte still held in the backlog.
+    first_byte_offset: u64,
+}
+
+impl ReplicationBacklog {
+    /// Creates an empty backlog whose next appended byte has offset `master_repl_offset + 1`.
+    pub fn new(size: usize, master_repl_offset: u64) -> Self {
+        ReplicationBacklog {
+            buffer: vec![0; size.max(1)],
+            write_index: 0,
+            history_length: 0,
+            first_byte_offset: master_repl_offset + 1,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn first_byte_offset(&self) -> u64 {
+        self.first_byte_offset
+    }
+
+    pub fn history_length(&self) -> usize {
+        self.history_length
+    }
+
+    pub fn append(&mut self, mut bytes: &[u8]) {
+        let size: usize = self.buffer.len();
+
+        if bytes.len() > size {
+            let skipped: usize = bytes.len() - size;
+            self.skip(skipped);
+            bytes = &bytes[skipped..];
+        }
+
+        while !bytes.is_empty() {
+            let chunk: usize = min(size - self.write_index, bytes.len());
+            self.buffer[self.write_index..self.write_index + chunk].copy_from_slice(&bytes[..chunk]);
+            self.write_index = (self.write_index + chunk) % size;
+            self.history_length += chunk;
+            bytes = &bytes[chunk..];
+        }
+
+        if self.history_length > size {
+            self.first_byte_offset += (self.history_length - size) as u64;
+            self.history_length = size;
+        }
+    }
+
+    /// Returns every byte from `offset` up to the end of the stream, or `None` when the
+    /// requested offset has already been overwritten or has not been produced yet.
+    pub fn read_from(&self, offset: u64) -> Option<Vec<u8>> {
+        let end_offset: u64 = self.first_byte_offset + self.history_length as u64;
+        if offset < self.first_byte_offset || offset > end_offset {
+            return None;
+        }
+
+        let size: usize = self.buffer.len();
+        let length: usize = (end_offset - offset) as usize;
+        let start: usize = (self.write_index + size - length) % size;
+
+        let mut bytes: Vec<u8> = Vec::with_capacity(length);
+        let first_chunk: usize = min(size - start, length);
+        bytes.extend_from_slice(&self.buffer[start..start + first_chunk]);
+        bytes.extend_from_slice(&self.buffer[..length - first_chunk]);
+        Some(bytes)
+    }
+
+    fn skip(&mut self, amount: usize) {
+        self.first_byte_offset += (self.history_length + amount) as u64;
+        self.history_length = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReplicationBacklog;
+
+    #[test]
+    fn reads_from_any_held_offset() {
+        let mut backlog: ReplicationBacklog = ReplicationBacklog::new(16, 100);
+        backlog.append(b"hello");
+        assert_eq!(backlog.first_byte_offset(), 101);
+        assert_eq!(backlog.history_length(), 5);
+        assert_eq!(backlog.read_from(101).as_deref(), Some(&b"hello"[..]));
+        assert_eq!(backlog.read_from(104).as_deref(), Some(&b"lo"[..]));
+        assert_eq!(backlog.read_from(106).as_deref(), Some(&b""[..]));
+        assert_eq!(backlog.read_from(100), None);
+        assert_eq!(backlog.read_from(107), None);
+    }
+
+    #[test]
+    fn wraps_around_and_drops_the_oldest_bytes() {
+        let mut backlog: ReplicationBacklog = ReplicationBacklog::new(8, 0);
+        backlog.append(b"abcdef");
+        backlog.append(b"ghij");
+        assert_eq!(backlog.first_byte_offset(), 3);
+        assert_eq!(backlog.history_length(), 8);
+        assert_eq!(backlog.read_from(3).as_deref(), Some(&b"cdefghij"[..]));
+        assert_eq!(backlog.read_from(8).as_deref(), Some(&b"hij"[..]));
+        assert_eq!(backlog.read_from(2), None);
+    }
+
+    #[test]
+    fn keeps_the_tail_of_an_append_larger_than_the_backlog() {
+        let mut backlog: ReplicationBacklog = ReplicationBacklog::new(4, 10);
+        backlog.append(b"xy");
+        backlog.append(b"0123456789");
+        assert_eq!(backlog.first_byte_offset(), 19);
+        assert_eq!(backlog.history_length(), 4);
+        assert_eq!(backlog.read_from(19).as_deref(), Some(&b"6789"[..]));
+        assert_eq!(backlog.read_from(23).as_deref(), Some(&b""[..]));
+    }
+}
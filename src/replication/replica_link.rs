@@ -0,0 +1,334 @@
+use std::io::{Error, ErrorKind};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+use tokio_rustls::rustls::pki_types::ServerName;
+use crate::{CommandMsg, CommandSource, Msg};
+use crate::command::{DataRequester, Reply};
+use crate::key_value_store::KeyValueStore;
+use crate::parser::{lookup_command, parse_frame_into, read_line, Arguments};
+use crate::rdb;
+use crate::reply::Protocol;
+use crate::scripting::RestorePolicy;
+use crate::server::{request, ServerState, Session};
+use crate::tls;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const ACK_PERIOD: Duration = Duration::from_secs(1);
+
+const LINK_CONNECTING: u8 = 0;
+const LINK_SYNCING: u8 = 1;
+const LINK_UP: u8 = 2;
+
+/// State of the link to the master, shared with the replication state for `INFO`.
+#[derive(Default)]
+pub struct LinkStatus {
+    state: AtomicU8,
+    /// Unix time in seconds of the last data received from the master, zero before any.
+    last_io: AtomicU64,
+}
+
+impl LinkStatus {
+    pub fn is_up(&self) -> bool {
+        self.state.load(Ordering::Relaxed) == LINK_UP
+    }
+
+    pub fn is_syncing(&self) -> bool {
+        self.state.load(Ordering::Relaxed) == LINK_SYNCING
+    }
+
+    /// Seconds since the master last sent something, or `None` while the link is down.
+    pub fn last_io_seconds_ago(&self) -> Option<u64> {
+        let last_io: u64 = self.last_io.load(Ordering::Relaxed);
+        (self.is_up() && last_io > 0).then(|| unix_seconds().saturating_sub(last_io))
+    }
+
+    fn set(&self, state: u8) {
+        self.state.store(state, Ordering::Relaxed);
+    }
+
+    fn touch(&self) {
+        self.last_io.store(unix_seconds(), Ordering::Relaxed);
+    }
+}
+
+fn unix_seconds() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0)
+}
+
+/// Keeps a replica attached to its master, reconnecting after network failures and
+/// resuming with `PSYNC` from the last processed offset whenever possible.
+pub async fn run(host: String, port: u16, listening_port: u16, store_tx: mpsc::Sender<Msg>, status: Arc<LinkStatus>) {
+    loop {
+        match connect(&host, port).await {
+            Ok(stream) => {
+                status.set(LINK_SYNCING);
+                if let Err(e) = sync_with_master(stream, listening_port, &store_tx, &status).await {
+                    println!("replication link error: {}", e);
+                }
+            }
+            Err(e) => println!("could not connect to master {}:{}: {}", host, port, e),
+        }
+        status.set(LINK_CONNECTING);
+
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+/// Opens the connection to the master, over TLS when `tls-replication` is enabled.
+async fn connect(host: &str, port: u16) -> Result<Box<dyn tls::Stream>, Error> {
+    let stream: TcpStream = TcpStream::connect((host, port)).await?;
+    let Some(connector) = tls::replication_connector() else {
+        return Ok(Box::new(stream));
+    };
+    let server_name: ServerName<'static> = ServerName::try_from(host.to_string())
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    Ok(Box::new(connector.connect(server_name, stream).await?))
+}
+
+struct MasterConnection {
+    stream: Box<dyn tls::Stream>,
+    buffer: Vec<u8>,
+}
+
+impl MasterConnection {
+    async fn fill(&mut self) -> Result<(), Error> {
+        let mut chunk: [u8; 4096] = [0; 4096];
+        let length: usize = self.stream.read(&mut chunk).await?;
+        if length == 0 {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "Master closed the connection"));
+        }
+        self.buffer.extend_from_slice(&chunk[..length]);
+        Ok(())
+    }
+
+    async fn read_line(&mut self) -> Result<String, Error> {
+        loop {
+            if let Some((line, end)) = read_line(&self.buffer, 0) {
+                let line: String = line.to_string();
+                self.buffer.drain(..end);
+                return Ok(line);
+            }
+            self.fill().await?;
+        }
+    }
+
+    async fn read_exact(&mut self, length: usize) -> Result<Vec<u8>, Error> {
+        while self.buffer.len() < length {
+            self.fill().await?;
+        }
+        Ok(self.buffer.drain(..length).collect())
+    }
+
+    async fn send(&mut self, arguments: &[&str]) -> Result<(), Error> {
+        let mut frame: String = format!("*{}\r\n", arguments.len());
+        for argument in arguments {
+            frame.push_str(&format!("${}\r\n{}\r\n", argument.len(), argument));
+        }
+        self.stream.write_all(frame.as_bytes()).await
+    }
+
+    async fn command(&mut self, arguments: &[&str]) -> Result<String, Error> {
+        self.send(arguments).await?;
+
+        let reply: String = self.read_line().await?;
+        if reply.starts_with('-') {
+            return Err(Error::other(format!("Master replied {}", reply)));
+        }
+        Ok(reply)
+    }
+}
+
+async fn sync_with_master(
+    stream: Box<dyn tls::Stream>,
+    listening_port: u16,
+    store_tx: &mpsc::Sender<Msg>,
+    status: &LinkStatus
+) -> Result<(), Error> {
+    let mut master: MasterConnection = MasterConnection { stream, buffer: Vec::new() };
+
+    master.command(&["PING"]).await?;
+    master.command(&["REPLCONF", "listening-port", &listening_port.to_string()]).await?;
+    master.command(&["REPLCONF", "capa", "eof", "capa", "psync2"]).await?;
+
+    let (replid, offset) = request(store_tx, |tx| PSyncParamsRequest { tx }).await?
+        .unwrap_or_else(|| ("?".to_string(), -1));
+    let reply: String = master.command(&["PSYNC", &replid, &offset.to_string()]).await?;
+    let mut reply_parts = reply[1..].split(' ');
+
+    match reply_parts.next() {
+        Some("FULLRESYNC") => {
+            let replid: String = reply_parts.next().unwrap_or_default().to_string();
+            let offset: u64 = reply_parts.next().and_then(|offset| offset.parse().ok())
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Invalid FULLRESYNC offset"))?;
+
+            let length_line: String = master.read_line().await?;
+            let length: usize = length_line.strip_prefix('$')
+                .and_then(|length| length.parse().ok())
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Invalid RDB length"))?;
+            let snapshot: Vec<u8> = master.read_exact(length).await?;
+            let snapshot: rdb::Snapshot = rdb::deserialize(&snapshot)
+                .map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+
+            request(store_tx, |tx| FullResyncRequest { replid, offset, snapshot, tx }).await?;
+        }
+        Some("CONTINUE") => {
+            let replid: Option<String> = reply_parts.next().map(|replid| replid.to_string());
+            request(store_tx, |tx| PartialResyncRequest { replid, tx }).await?;
+        }
+        _ => return Err(Error::new(ErrorKind::InvalidData, format!("Unexpected PSYNC reply {}", reply))),
+    }
+    status.set(LINK_UP);
+    status.touch();
+
+    let mut ack_interval: tokio::time::Interval = tokio::time::interval(ACK_PERIOD);
+    let mut arguments: Arguments = Arguments::new();
+    let mut output: Vec<u8> = Vec::new();
+    let (reply_tx, mut reply_rx) = mpsc::channel::<(CommandMsg, Reply)>(1);
+    let stopped = || Error::new(ErrorKind::BrokenPipe, "Data manager stopped");
+
+    loop {
+        let mut consumed: usize = 0;
+        while let Some(length) = parse_frame_into(&master.buffer[consumed..], &mut arguments)? {
+            let frame: std::ops::Range<usize> = consumed..consumed + length;
+            consumed += length;
+
+            let (spec, handler) = match lookup_command(&arguments) {
+                Ok(command) => command,
+                Err(_) => {
+                    let raw: Vec<u8> = master.buffer[frame].to_vec();
+                    store_tx.send(Msg::Internal(Box::new(StreamOnlyRequest { raw }))).await.map_err(|_| stopped())?;
+                    continue;
+                }
+            };
+
+            let command: CommandMsg = CommandMsg {
+                spec,
+                handler,
+                arguments,
+                output,
+                protocol: Protocol::Resp2,
+                // The master is trusted, its commands are not checked against ACLs.
+                session: Session { authenticated: true, ..Session::default() },
+                source: CommandSource::Master,
+                reply_tx: reply_tx.clone(),
+            };
+            store_tx.send(Msg::Command(command)).await.map_err(|_| stopped())?;
+            let (command, _reply) = reply_rx.recv().await.ok_or_else(stopped)?;
+            arguments = command.arguments;
+            output = command.output;
+
+            // Replies to the master are suppressed, except for the offset it explicitly asked for.
+            if is_getack(&arguments) {
+                master.stream.write_all(&output).await?;
+            }
+            output.clear();
+        }
+        master.buffer.drain(..consumed);
+
+        tokio::select! {
+            filled = master.fill() => {
+                filled?;
+                status.touch();
+            }
+            _ = ack_interval.tick() => {
+                let offset: u64 = request(store_tx, |tx| ReplicationOffsetRequest { tx }).await?;
+                master.send(&["REPLCONF", "ACK", &offset.to_string()]).await?;
+            }
+        }
+    }
+}
+
+fn is_getack(arguments: &[Vec<u8>]) -> bool {
+    matches!(arguments, [command, subcommand, ..]
+        if command.eq_ignore_ascii_case(b"replconf") && subcommand.eq_ignore_ascii_case(b"getack"))
+}
+
+struct PSyncParamsRequest {
+    tx: oneshot::Sender<Option<(String, i64)>>,
+}
+
+impl DataRequester for PSyncParamsRequest {
+    fn request(
+        self: Box<Self>,
+        _store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState
+    ) {
+        let _ = self.tx.send(server.replication.lock().psync_params());
+    }
+}
+
+struct ReplicationOffsetRequest {
+    tx: oneshot::Sender<u64>,
+}
+
+impl DataRequester for ReplicationOffsetRequest {
+    fn request(
+        self: Box<Self>,
+        _store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState
+    ) {
+        let _ = self.tx.send(server.replication.lock().master_repl_offset());
+    }
+}
+
+struct FullResyncRequest {
+    replid: String,
+    offset: u64,
+    snapshot: rdb::Snapshot,
+    tx: oneshot::Sender<()>,
+}
+
+impl DataRequester for FullResyncRequest {
+    fn request(
+        self: Box<Self>,
+        store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState
+    ) {
+        store.clear();
+        for (key, entry) in self.snapshot.entries {
+            store.insert(key, entry);
+        }
+        if let Err(e) = server.scripting.lock().restore_libraries(self.snapshot.libraries, RestorePolicy::Flush) {
+            println!("could not load the function libraries of the master: {}", e);
+        }
+
+        server.replication.lock().complete_full_resync(self.replid, self.offset);
+        let _ = self.tx.send(());
+    }
+}
+
+struct PartialResyncRequest {
+    replid: Option<String>,
+    tx: oneshot::Sender<()>,
+}
+
+impl DataRequester for PartialResyncRequest {
+    fn request(
+        self: Box<Self>,
+        _store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState
+    ) {
+        server.replication.lock().complete_partial_resync(self.replid);
+        let _ = self.tx.send(());
+    }
+}
+
+/// Stands in for stream content this server cannot execute, so its bytes still count
+/// towards the replication offset.
+struct StreamOnlyRequest {
+    raw: Vec<u8>,
+}
+
+impl DataRequester for StreamOnlyRequest {
+    fn request(
+        self: Box<Self>,
+        _store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState
+    ) {
+        server.replication.lock().feed(&self.raw);
+    }
+}
@@ -0,0 +1,223 @@
+use std::io::Write;
+
+/// The version of the protocol a connection speaks, RESP2 until it switches with `HELLO`.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Protocol {
+    Resp2,
+    Resp3,
+}
+
+impl Protocol {
+    pub fn version(&self) -> i64 {
+        match self {
+            Protocol::Resp2 => 2,
+            Protocol::Resp3 => 3,
+        }
+    }
+}
+
+/// Serializes RESP replies straight into a connection's output buffer, so replying does not
+/// allocate once the buffer has grown to the size of the usual replies. Types that only exist
+/// in RESP3 are sent as their closest RESP2 equivalent to connections that did not switch.
+pub struct ReplyWriter<'a> {
+    buffer: &'a mut Vec<u8>,
+    /// Where the reply being written starts, replies to earlier pipelined commands coming before.
+    start: usize,
+    protocol: Protocol,
+}
+
+impl<'a> ReplyWriter<'a> {
+    pub fn new(buffer: &'a mut Vec<u8>, protocol: Protocol) -> Self {
+        let start: usize = buffer.len();
+        ReplyWriter { buffer, start, protocol }
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    /// Switches the connection to `protocol`, starting with the reply being written.
+    pub fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = protocol;
+    }
+
+    pub fn ok(&mut self) {
+        self.buffer.extend_from_slice(b"+OK\r\n");
+    }
+
+    pub fn simple(&mut self, status: &str) {
+        self.buffer.push(b'+');
+        self.buffer.extend_from_slice(status.as_bytes());
+        self.buffer.extend_from_slice(b"\r\n");
+    }
+
+    /// Writes an error reply. `message` starts with the error code, e.g. `ERR` or `MOVED`.
+    pub fn error(&mut self, message: &str) {
+        self.buffer.push(b'-');
+        self.buffer.extend_from_slice(message.as_bytes());
+        self.buffer.extend_from_slice(b"\r\n");
+    }
+
+    pub fn integer(&mut self, value: i64) {
+        let _ = write!(self.buffer, ":{}\r\n", value);
+    }
+
+    pub fn bulk(&mut self, value: &[u8]) {
+        let _ = write!(self.buffer, "${}\r\n", value.len());
+        self.buffer.extend_from_slice(value);
+        self.buffer.extend_from_slice(b"\r\n");
+    }
+
+    pub fn null(&mut self) {
+        match self.protocol {
+            Protocol::Resp2 => self.buffer.extend_from_slice(b"$-1\r\n"),
+            Protocol::Resp3 => self.buffer.extend_from_slice(b"_\r\n"),
+        }
+    }
+
+    /// The null RESP2 uses in place of an array; RESP3 has a single null.
+    pub fn null_array(&mut self) {
+        match self.protocol {
+            Protocol::Resp2 => self.buffer.extend_from_slice(b"*-1\r\n"),
+            Protocol::Resp3 => self.buffer.extend_from_slice(b"_\r\n"),
+        }
+    }
+
+    /// A floating point number, sent as a bulk string in RESP2.
+    pub fn double(&mut self, value: f64) {
+        let text: String = if value.is_nan() {
+            "nan".to_string()
+        } else if value.is_infinite() {
+            if value > 0.0 { "inf" } else { "-inf" }.to_string()
+        } else {
+            value.to_string()
+        };
+        match self.protocol {
+            Protocol::Resp2 => self.bulk(text.as_bytes()),
+            Protocol::Resp3 => {
+                let _ = write!(self.buffer, ",{}\r\n", text);
+            }
+        }
+    }
+
+    /// A boolean, sent as the integer 1 or 0 in RESP2.
+    pub fn boolean(&mut self, value: bool) {
+        match self.protocol {
+            Protocol::Resp2 => self.integer(value as i64),
+            Protocol::Resp3 => self.buffer.extend_from_slice(if value { b"#t\r\n" } else { b"#f\r\n" }),
+        }
+    }
+
+    /// Text meant to be shown as is, like the report of `INFO`, sent as a bulk string in RESP2.
+    pub fn verbatim(&mut self, text: &str) {
+        match self.protocol {
+            Protocol::Resp2 => self.bulk(text.as_bytes()),
+            Protocol::Resp3 => {
+                let _ = write!(self.buffer, "={}\r\ntxt:", text.len() + 4);
+                self.buffer.extend_from_slice(text.as_bytes());
+                self.buffer.extend_from_slice(b"\r\n");
+            }
+        }
+    }
+
+    /// Starts an array; its `length` elements are written next.
+    pub fn array(&mut self, length: usize) {
+        let _ = write!(self.buffer, "*{}\r\n", length);
+    }
+
+    /// Starts a map; its `length` keys and values are written next, alternating. RESP2 has no
+    /// maps, so it is sent as an array of twice the length.
+    pub fn map(&mut self, length: usize) {
+        match self.protocol {
+            Protocol::Resp2 => self.array(length * 2),
+            Protocol::Resp3 => {
+                let _ = write!(self.buffer, "%{}\r\n", length);
+            }
+        }
+    }
+
+    /// Starts a set of `length` distinct elements, sent as an array in RESP2.
+    pub fn set(&mut self, length: usize) {
+        match self.protocol {
+            Protocol::Resp2 => self.array(length),
+            Protocol::Resp3 => {
+                let _ = write!(self.buffer, "~{}\r\n", length);
+            }
+        }
+    }
+
+    /// Starts an out-of-band message of `length` elements, which RESP3 clients tell apart from
+    /// replies. RESP2 clients only receive them as arrays in contexts that expect them.
+    pub fn push(&mut self, length: usize) {
+        match self.protocol {
+            Protocol::Resp2 => self.array(length),
+            Protocol::Resp3 => {
+                let _ = write!(self.buffer, ">{}\r\n", length);
+            }
+        }
+    }
+
+    /// Appends an already encoded reply.
+    pub fn raw(&mut self, reply: &[u8]) {
+        self.buffer.extend_from_slice(reply);
+    }
+
+    /// Whether the reply written so far is an error, counted as a failed call.
+    pub fn is_error(&self) -> bool {
+        self.buffer.get(self.start) == Some(&b'-')
+    }
+}
+
+/// A reply built before it can be written, e.g. by a blocked command that is served later,
+/// once the protocol of the connection it goes to is known.
+pub enum Value {
+    Integer(i64),
+    Bulk(Vec<u8>),
+    Null,
+    Double(f64),
+    Boolean(bool),
+    Verbatim(String),
+    Array(Vec<Value>),
+    Set(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+    Push(Vec<Value>),
+    /// An error, its message starting with the error code.
+    Error(String),
+}
+
+impl Value {
+    pub fn bulk(value: &str) -> Value {
+        Value::Bulk(value.as_bytes().to_vec())
+    }
+
+    pub fn write(&self, reply: &mut ReplyWriter) {
+        match self {
+            Value::Integer(value) => reply.integer(*value),
+            Value::Bulk(value) => reply.bulk(value),
+            Value::Null => reply.null(),
+            Value::Error(message) => reply.error(message),
+            Value::Double(value) => reply.double(*value),
+            Value::Boolean(value) => reply.boolean(*value),
+            Value::Verbatim(text) => reply.verbatim(text),
+            Value::Array(elements) => {
+                reply.array(elements.len());
+                elements.iter().for_each(|element| element.write(reply));
+            }
+            Value::Set(elements) => {
+                reply.set(elements.len());
+                elements.iter().for_each(|element| element.write(reply));
+            }
+            Value::Map(entries) => {
+                reply.map(entries.len());
+                for (key, value) in entries {
+                    key.write(reply);
+                    value.write(reply);
+                }
+            }
+            Value::Push(elements) => {
+                reply.push(elements.len());
+                elements.iter().for_each(|element| element.write(reply));
+            }
+        }
+    }
+}
@@ -0,0 +1,831 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Mutex, PoisonError};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use mlua::{ChunkMode, Function, HookTriggers, Lua, LuaOptions, MultiValue, RegistryKey, StdLib, Table};
+use mlua::Value as LuaValue;
+use crate::command::{CommandFlag, CommandSpec, Reply};
+use crate::eviction;
+use crate::key_value_store::KeyValueStore;
+use crate::parser::{self, LookupError};
+use crate::reply::{Protocol, ReplyWriter};
+use crate::server::{Propagation, ServerState};
+use crate::sha1;
+
+/// Keeps scripts from reading files and from creating globals.
+const PRELUDE: &str = r#"
+loadfile = nil
+dofile = nil
+
+setmetatable(_G, {
+    __index = function(_, name)
+        error("Script attempted to access nonexistent global variable '" .. tostring(name) .. "'", 2)
+    end,
+    __newindex = function(_, name)
+        error("Script attempted to create global variable '" .. tostring(name) .. "'", 2)
+    end,
+})
+"#;
+
+/// Number of VM instructions between checks for `SCRIPT KILL`.
+const KILL_CHECK_INTERVAL: u32 = 100_000;
+
+const KILLED_MESSAGE: &str = "ERR Script killed by user with SCRIPT KILL...";
+
+/// Levels of `redis.log`, lower ones being only logged at a higher verbosity.
+const LOG_LEVELS: [(&str, i64); 4] = [("LOG_DEBUG", 0), ("LOG_VERBOSE", 1), ("LOG_NOTICE", 2), ("LOG_WARNING", 3)];
+/// Least level logged.
+const LOG_VERBOSITY: i64 = 2;
+
+/// The Lua interpreter running scripts, the scripts it compiled, by the SHA1 of their body,
+/// and the function libraries loaded with `FUNCTION LOAD`. Scripts run inside the turn of
+/// the data manager, so nothing else touches the dataset while they do.
+pub struct Scripting {
+    lua: Lua,
+    scripts: HashMap<String, RegistryKey>,
+    libraries: BTreeMap<String, Library>,
+    /// The functions of every library, by their name, which is unique across libraries.
+    functions: BTreeMap<String, RegisteredFunction>,
+    /// Lua's own `pcall`, kept in case a script replaces the global one.
+    pcall: RegistryKey,
+}
+
+/// A library loaded with `FUNCTION LOAD`, kept as its code since loading it again recreates it.
+pub struct Library {
+    pub code: String,
+    pub functions: Vec<String>,
+}
+
+/// A function registered by a library with `redis.register_function`.
+pub struct RegisteredFunction {
+    pub library: String,
+    callback: RegistryKey,
+    pub flags: FunctionFlags,
+    pub description: Option<String>,
+}
+
+/// Flags a function is registered with.
+#[derive(Clone, Copy, Default)]
+pub struct FunctionFlags {
+    /// The function runs no write commands, so it may run with `FCALL_RO` and on replicas.
+    pub no_writes: bool,
+    /// The function may run commands that grow memory beyond `maxmemory`.
+    pub allow_oom: bool,
+}
+
+impl FunctionFlags {
+    /// The names of the flags set, as `FUNCTION LIST` shows them.
+    pub fn names(&self) -> Vec<&'static str> {
+        [("no-writes", self.no_writes), ("allow-oom", self.allow_oom)]
+            .into_iter()
+            .filter_map(|(name, set)| set.then_some(name))
+            .collect()
+    }
+}
+
+/// What `FUNCTION RESTORE` does with the libraries already loaded.
+#[derive(Clone, Copy, PartialEq)]
+pub enum RestorePolicy {
+    /// Fails when a restored library already exists.
+    Append,
+    /// Restored libraries replace those with the same name.
+    Replace,
+    /// Deletes every library first.
+    Flush,
+}
+
+impl Scripting {
+    pub fn new() -> Self {
+        let lua: Lua = Lua::new_with(StdLib::TABLE | StdLib::STRING | StdLib::MATH, LuaOptions::default())
+            .expect("the safe standard libraries load");
+        let pcall: RegistryKey = setup(&lua).expect("the redis library is set up in a fresh interpreter");
+        lua.set_hook(HookTriggers::new().every_nth_instruction(KILL_CHECK_INTERVAL), |_, _| {
+            if KILLED.load(Ordering::Relaxed) {
+                return Err(mlua::Error::RuntimeError(KILLED_MESSAGE.to_string()));
+            }
+            Ok(())
+        });
+        Scripting { lua, scripts: HashMap::new(), libraries: BTreeMap::new(), functions: BTreeMap::new(), pcall }
+    }
+
+    /// Compiles `body` into the cache, returning its SHA1.
+    pub fn load(&mut self, body: &str) -> Result<String, String> {
+        let sha: String = sha1::hex_digest(body.as_bytes());
+        if self.scripts.contains_key(&sha) {
+            return Ok(sha);
+        }
+        let function: Function = self.lua.load(body)
+            .set_name("@user_script")
+            .set_mode(ChunkMode::Text)
+            .into_function()
+            .map_err(|e| format!("ERR Error compiling script (new function): {}", error_message(&e)))?;
+        let key: RegistryKey = self.lua.create_registry_value(function).map_err(|e| format!("ERR {}", e))?;
+        self.scripts.insert(sha.clone(), key);
+        Ok(sha)
+    }
+
+    pub fn exists(&self, sha: &str) -> bool {
+        self.scripts.contains_key(&sha.to_ascii_lowercase())
+    }
+
+    pub fn flush(&mut self) {
+        self.scripts.clear();
+        self.lua.expire_registry_values();
+    }
+
+    /// Runs the cached script `sha` with the `KEYS` and `ARGV` globals set, writing what it
+    /// returns as the reply. `read_only` scripts may not run write commands.
+    #[allow(clippy::too_many_arguments)]
+    pub fn run(
+        &self,
+        sha: &str,
+        keys: &[&str],
+        arguments: &[&str],
+        read_only: bool,
+        store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) {
+        let sha: String = sha.to_ascii_lowercase();
+        let Some(script) = self.scripts.get(&sha) else {
+            reply.error("NOSCRIPT No matching script. Please use EVAL.");
+            return;
+        };
+        let prepared: mlua::Result<Function> = (|| {
+            let globals: Table = self.lua.globals();
+            globals.raw_set("KEYS", self.lua.create_sequence_from(keys.iter().copied())?)?;
+            globals.raw_set("ARGV", self.lua.create_sequence_from(arguments.iter().copied())?)?;
+            self.lua.registry_value(script)
+        })();
+        let oom: bool = !eviction::perform_evictions(store, server);
+        match prepared {
+            Ok(function) => self.invoke(function, MultiValue::new(), &sha, read_only, oom, store, server, reply),
+            Err(e) => reply.error(&format!("ERR {}", e)),
+        }
+    }
+
+    /// Creates the library whose code starts with `#!lua name=<name>` and returns its name.
+    /// The code registers its functions with `redis.register_function` when it is run.
+    pub fn load_library(&mut self, code: &str, replace: bool) -> Result<String, String> {
+        let name: String = library_name(code)?;
+        if self.libraries.contains_key(&name) && !replace {
+            return Err(format!("ERR Library '{}' already exists", name));
+        }
+        // The metadata line is commented out rather than removed, so line numbers still match.
+        let chunk: Function = self.lua.load(format!("--{}", code))
+            .set_name("@user_function")
+            .set_mode(ChunkMode::Text)
+            .into_function()
+            .map_err(|e| format!("ERR Error compiling function: {}", error_message(&e)))?;
+        let registered: Vec<(String, RegisteredFunction)> = self.register_functions(chunk, &name)
+            .map_err(|e| format!("ERR Error registering functions: {}", error_message(&e)))?;
+        if registered.is_empty() {
+            return Err("ERR No functions registered".to_string());
+        }
+        let taken = |function: &String| self.functions.get(function).is_some_and(|existing| existing.library != name);
+        if let Some((function, _)) = registered.iter().find(|(function, _)| taken(function)) {
+            return Err(format!("ERR Function {} already exists", function));
+        }
+
+        self.delete_library(&name);
+        let functions: Vec<String> = registered.iter().map(|(function, _)| function.clone()).collect();
+        self.functions.extend(registered);
+        self.libraries.insert(name.clone(), Library { code: code.to_string(), functions });
+        Ok(name)
+    }
+
+    /// Runs the code of a library, collecting the functions it registers.
+    fn register_functions(&self, chunk: Function, library: &str) -> mlua::Result<Vec<(String, RegisteredFunction)>> {
+        let registered: RefCell<Vec<(String, RegisteredFunction)>> = RefCell::new(Vec::new());
+        self.lua.scope(|scope| {
+            let redis: Table = self.lua.globals().raw_get("redis")?;
+            redis.raw_set("register_function", scope.create_function(|lua, arguments: MultiValue| {
+                let (name, function): (String, RegisteredFunction) = registration(lua, arguments, library)?;
+                let mut registered = registered.borrow_mut();
+                if registered.iter().any(|(existing, _)| *existing == name) {
+                    return Err(mlua::Error::RuntimeError("Function already exists in the library".to_string()));
+                }
+                registered.push((name, function));
+                Ok(())
+            })?)?;
+            let outcome: mlua::Result<()> = chunk.call(());
+            redis.raw_set("register_function", LuaValue::Nil)?;
+            outcome
+        })?;
+        Ok(registered.into_inner())
+    }
+
+    /// Deletes a library and its functions, returning whether it existed.
+    pub fn delete_library(&mut self, name: &str) -> bool {
+        let Some(library) = self.libraries.remove(name) else {
+            return false;
+        };
+        for function in library.functions {
+            self.functions.remove(&function);
+        }
+        true
+    }
+
+    pub fn flush_libraries(&mut self) {
+        self.libraries.clear();
+        self.functions.clear();
+        self.lua.expire_registry_values();
+    }
+
+    /// Loads the libraries of a `FUNCTION DUMP` payload or an RDB snapshot from their code.
+    /// Nothing changes when one of them fails to load.
+    pub fn restore_libraries(&mut self, codes: Vec<String>, policy: RestorePolicy) -> Result<(), String> {
+        let previous: Vec<String> = self.libraries.values().map(|library| library.code.clone()).collect();
+        if policy == RestorePolicy::Flush {
+            self.flush_libraries();
+        }
+        let outcome: Result<(), String> = codes.iter()
+            .try_for_each(|code| self.load_library(code, policy == RestorePolicy::Replace).map(|_| ()));
+        if outcome.is_err() {
+            self.flush_libraries();
+            for code in previous {
+                let _ = self.load_library(&code, false);
+            }
+        }
+        outcome
+    }
+
+    pub fn libraries(&self) -> impl Iterator<Item = (&String, &Library)> {
+        self.libraries.iter()
+    }
+
+    /// The code of every library, as persisted in RDB snapshots.
+    pub fn library_codes(&self) -> Vec<&str> {
+        self.libraries.values().map(|library| library.code.as_str()).collect()
+    }
+
+    pub fn function(&self, name: &str) -> Option<&RegisteredFunction> {
+        self.functions.get(name)
+    }
+
+    pub fn function_count(&self) -> usize {
+        self.functions.len()
+    }
+
+    /// Calls the function `name` with tables of its keys and arguments, writing what it returns
+    /// as the reply. `read_only` calls only run functions flagged `no-writes`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn call_function(
+        &self,
+        name: &str,
+        keys: &[&str],
+        arguments: &[&str],
+        read_only: bool,
+        store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) {
+        let Some(function) = self.functions.get(name) else {
+            reply.error("ERR Function not found");
+            return;
+        };
+        let flags: FunctionFlags = function.flags;
+        if read_only && !flags.no_writes {
+            reply.error("ERR Can not execute a script with write flag using *_ro command.");
+            return;
+        }
+        if !flags.no_writes && server.current_client.is_some() && server.replication.lock().is_replica() {
+            reply.error("READONLY You can't write against a read only replica.");
+            return;
+        }
+        let oom: bool = !eviction::perform_evictions(store, server) && !flags.allow_oom;
+        if oom && !flags.no_writes {
+            reply.error("OOM command not allowed when used memory > 'maxmemory'.");
+            return;
+        }
+
+        let prepared: mlua::Result<(Function, MultiValue)> = (|| {
+            let keys: Table = self.lua.create_sequence_from(keys.iter().copied())?;
+            let arguments: Table = self.lua.create_sequence_from(arguments.iter().copied())?;
+            let callback: Function = self.lua.registry_value(&function.callback)?;
+            Ok((callback, MultiValue::from_vec(vec![LuaValue::Table(keys), LuaValue::Table(arguments)])))
+        })();
+        match prepared {
+            Ok((callback, arguments)) => {
+                self.invoke(callback, arguments, name, flags.no_writes, oom, store, server, reply)
+            }
+            Err(e) => reply.error(&format!("ERR {}", e)),
+        }
+    }
+
+    /// Calls a compiled script or function with `arguments`, letting it run commands through
+    /// `redis.call`. `name` is what errors are reported for, and `oom` refuses the commands
+    /// that may grow memory.
+    #[allow(clippy::too_many_arguments)]
+    fn invoke<'lua>(
+        &'lua self,
+        function: Function<'lua>,
+        mut arguments: MultiValue<'lua>,
+        name: &str,
+        read_only: bool,
+        oom: bool,
+        store: &mut Box<dyn KeyValueStore>,
+        server: &mut ServerState,
+        reply: &mut ReplyWriter
+    ) {
+        let _running: RunningGuard = RunningGuard::start(Duration::from_millis(server.config.busy_reply_threshold));
+        let context: RefCell<ScriptContext> = RefCell::new(ScriptContext {
+            store,
+            server,
+            read_only,
+            oom,
+            wrote: false,
+            error_location: None,
+        });
+
+        // `redis.call` and `redis.pcall` borrow the dataset, so they only exist while the script runs.
+        let outcome: mlua::Result<()> = self.lua.scope(|scope| {
+            let redis: Table = self.lua.globals().raw_get("redis")?;
+            redis.raw_set("call", scope.create_function(|lua, command: MultiValue| {
+                match run_command(lua, &mut context.borrow_mut(), command)? {
+                    LuaValue::Table(reply) if reply.contains_key("err")? => {
+                        Err(mlua::Error::external(CommandError(reply.raw_get("err")?)))
+                    }
+                    reply => Ok(reply),
+                }
+            })?)?;
+            redis.raw_set("pcall", scope.create_function(|lua, command: MultiValue| {
+                run_command(lua, &mut context.borrow_mut(), command)
+            })?)?;
+
+            let pcall: Function = self.lua.registry_value(&self.pcall)?;
+            arguments.push_front(LuaValue::Function(function));
+            let mut results: MultiValue = pcall.call(arguments)?;
+            let succeeded: bool = matches!(results.pop_front(), Some(LuaValue::Boolean(true)));
+            let result: LuaValue = results.pop_front().unwrap_or(LuaValue::Nil);
+            if succeeded {
+                write_lua_value(&result, reply);
+            } else {
+                let error_location: Option<String> = context.borrow_mut().error_location.take();
+                reply.error(&script_error(&result, name, error_location));
+            }
+            Ok(())
+        });
+        if let Err(e) = outcome {
+            reply.error(&format!("ERR {}", error_message(&e)));
+        }
+    }
+}
+
+/// Registers the `redis` library, but for the functions running commands, returning Lua's `pcall`.
+fn setup(lua: &Lua) -> mlua::Result<RegistryKey> {
+    let redis: Table = lua.create_table()?;
+    for (name, level) in LOG_LEVELS {
+        redis.set(name, level)?;
+    }
+    redis.set("error_reply", lua.create_function(|lua, message: mlua::String| {
+        reply_table(lua, "err", message.as_bytes())
+    })?)?;
+    redis.set("status_reply", lua.create_function(|lua, message: mlua::String| {
+        reply_table(lua, "ok", message.as_bytes())
+    })?)?;
+    redis.set("sha1hex", lua.create_function(|_, data: mlua::String| Ok(sha1::hex_digest(data.as_bytes())))?)?;
+    redis.set("log", lua.create_function(log)?)?;
+
+    let globals: Table = lua.globals();
+    globals.set("redis", redis)?;
+    let pcall: RegistryKey = lua.create_registry_value(globals.get::<_, Function>("pcall")?)?;
+    lua.load(PRELUDE).set_name("=redis").exec()?;
+    Ok(pcall)
+}
+
+/// Reads the arguments of `redis.register_function`: a name and a callback, or a table
+/// holding them along with flags and a description.
+fn registration(lua: &Lua, arguments: MultiValue, library: &str) -> mlua::Result<(String, RegisteredFunction)> {
+    let invalid = |message: &str| mlua::Error::RuntimeError(message.to_string());
+    let arguments: Vec<LuaValue> = arguments.into_vec();
+    let (name, callback, flags, description): (LuaValue, LuaValue, LuaValue, LuaValue) = match arguments.as_slice() {
+        [name, callback] => (name.clone(), callback.clone(), LuaValue::Nil, LuaValue::Nil),
+        [LuaValue::Table(table)] => {
+            for pair in table.clone().pairs::<LuaValue, LuaValue>() {
+                let known: bool = match pair?.0 {
+                    LuaValue::String(key) => {
+                        matches!(key.to_str(), Ok("function_name" | "callback" | "flags" | "description"))
+                    }
+                    _ => false,
+                };
+                if !known {
+                    return Err(invalid("unknown argument given to redis.register_function"));
+                }
+            }
+            (
+                table.raw_get("function_name")?,
+                table.raw_get("callback")?,
+                table.raw_get("flags")?,
+                table.raw_get("description")?,
+            )
+        }
+        _ => return Err(invalid("wrong number of arguments to redis.register_function")),
+    };
+
+    let LuaValue::String(name) = name else {
+        return Err(invalid("function_name argument given to redis.register_function must be a string"));
+    };
+    let name: String = name.to_str()?.to_string();
+    if !valid_name(&name) {
+        return Err(invalid(
+            "Function names can only contain letters, numbers, or underscores(_) and must be at least one character long"
+        ));
+    }
+    let LuaValue::Function(callback) = callback else {
+        return Err(invalid("callback argument given to redis.register_function must be a function"));
+    };
+    let mut function_flags: FunctionFlags = FunctionFlags::default();
+    match flags {
+        LuaValue::Nil => {}
+        LuaValue::Table(flags) => {
+            for flag in flags.sequence_values::<mlua::String>() {
+                match flag?.to_str()? {
+                    "no-writes" => function_flags.no_writes = true,
+                    "allow-oom" => function_flags.allow_oom = true,
+                    _ => return Err(invalid("unknown flag given")),
+                }
+            }
+        }
+        _ => return Err(invalid("flags argument to redis.register_function must be a table representing function flags")),
+    }
+    let description: Option<String> = match description {
+        LuaValue::Nil => None,
+        LuaValue::String(description) => Some(description.to_str()?.to_string()),
+        _ => return Err(invalid("description argument given to redis.register_function must be a string")),
+    };
+
+    let function: RegisteredFunction = RegisteredFunction {
+        library: library.to_string(),
+        callback: lua.create_registry_value(callback)?,
+        flags: function_flags,
+        description,
+    };
+    Ok((name, function))
+}
+
+/// The name a library's code declares on its first line, `#!lua name=<name>`.
+fn library_name(code: &str) -> Result<String, String> {
+    let metadata: &str = code.lines().next()
+        .and_then(|line| line.strip_prefix("#!"))
+        .ok_or("ERR Missing library metadata")?;
+    let mut parts = metadata.split_whitespace();
+    let engine: &str = parts.next().unwrap_or_default();
+    if !engine.eq_ignore_ascii_case("lua") {
+        return Err(format!("ERR Engine '{}' not found", engine));
+    }
+    let mut name: Option<&str> = None;
+    for part in parts {
+        match part.strip_prefix("name=") {
+            Some(value) => name = Some(value),
+            None => return Err(format!("ERR Invalid metadata value given: {}", part)),
+        }
+    }
+    let name: &str = name.ok_or("ERR Library name was not given")?;
+    if !valid_name(name) {
+        return Err(
+            "ERR Library names can only contain letters, numbers, or underscores(_) and must be at least one \
+                character long".to_string()
+        );
+    }
+    Ok(name.to_string())
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|character| character.is_ascii_alphanumeric() || character == '_')
+}
+
+/// `redis.log(level, message...)`: prints the messages, separated by spaces, at `level`.
+fn log(_: &Lua, arguments: MultiValue) -> mlua::Result<()> {
+    let mut arguments = arguments.into_iter();
+    let level: i64 = match arguments.next() {
+        Some(LuaValue::Integer(level)) => level,
+        Some(LuaValue::Number(level)) => level as i64,
+        Some(_) => return Err(mlua::Error::RuntimeError("First argument must be a number".to_string())),
+        None => return Err(mlua::Error::RuntimeError("redis.log() requires two arguments or more.".to_string())),
+    };
+    if !LOG_LEVELS.iter().any(|(_, known)| *known == level) {
+        return Err(mlua::Error::RuntimeError("Invalid debug level.".to_string()));
+    }
+    let messages: Vec<String> = arguments
+        .filter_map(|argument| match argument {
+            LuaValue::String(text) => Some(text.to_string_lossy().into_owned()),
+            LuaValue::Integer(number) => Some(number.to_string()),
+            LuaValue::Number(number) => Some(number.to_string()),
+            _ => None,
+        })
+        .collect();
+    if messages.is_empty() {
+        return Err(mlua::Error::RuntimeError("redis.log() requires two arguments or more.".to_string()));
+    }
+    if level >= LOG_VERBOSITY {
+        println!("{}", messages.join(" "));
+    }
+    Ok(())
+}
+
+/// What the commands a script runs work on and may do.
+struct ScriptContext<'a> {
+    store: &'a mut Box<dyn KeyValueStore>,
+    server: &'a mut ServerState,
+    read_only: bool,
+    /// Whether memory was over `maxmemory` when the script started, refusing the commands
+    /// that may grow it.
+    oom: bool,
+    wrote: bool,
+    /// Where the script called the command that failed last, like `@user_script:3`.
+    error_location: Option<String>,
+}
+
+/// Runs a command for `redis.call` or `redis.pcall`, converting its reply to Lua. Errors
+/// are returned as `{err = message}` tables, which `redis.call` raises as a `CommandError`.
+fn run_command<'lua>(lua: &'lua Lua, context: &mut ScriptContext, command: MultiValue<'lua>) -> mlua::Result<LuaValue<'lua>> {
+    match prepare_command(lua, context, command) {
+        Ok((arguments, spec, handler)) => {
+            let mut output: Vec<u8> = Vec::new();
+            let mut reply: ReplyWriter = ReplyWriter::new(&mut output, Protocol::Resp2);
+            let outcome: Reply = crate::execute(spec, handler, &arguments, context.store, context.server, &mut reply);
+            let failed: bool = reply.is_error();
+            if !matches!(outcome, Reply::Immediate) {
+                // Blocking commands do not block in scripts; a command that would wait gets nothing.
+                output = b"$-1\r\n".to_vec();
+            }
+            if spec.is_write() && !failed {
+                if !context.wrote {
+                    context.wrote = true;
+                    record_write();
+                }
+            } else {
+                context.server.prevent_propagation();
+            }
+            // Replicas get the writes rather than the script; what a master sent is forwarded as is.
+            if context.server.current_client.is_some() {
+                crate::propagate(&arguments, context.server);
+            }
+            context.server.propagation = Propagation::default();
+            if failed {
+                context.error_location = script_location(lua);
+            }
+            reply_to_lua(lua, &output, &mut 0)
+        }
+        Err(message) => {
+            context.error_location = script_location(lua);
+            reply_table(lua, "err", message.as_bytes())
+        }
+    }
+}
+
+/// Converts the arguments of a `redis.call` and checks the command may run, returning the
+/// error it gets otherwise.
+fn prepare_command(
+    lua: &Lua,
+    context: &mut ScriptContext,
+    command: MultiValue
+) -> Result<(Vec<Vec<u8>>, &'static CommandSpec, crate::command::Handler), String> {
+    if command.is_empty() {
+        return Err("ERR Please specify at least one argument for this redis lib call".to_string());
+    }
+    let mut arguments: Vec<Vec<u8>> = Vec::with_capacity(command.len());
+    for value in command {
+        let argument: Option<mlua::String> = match value {
+            LuaValue::String(_) | LuaValue::Integer(_) | LuaValue::Number(_) => lua.coerce_string(value).ok().flatten(),
+            _ => None,
+        };
+        match argument {
+            Some(argument) => arguments.push(argument.as_bytes().to_vec()),
+            None => return Err("ERR Lua redis lib command arguments must be strings or integers".to_string()),
+        }
+    }
+
+    let (spec, handler) = match parser::lookup_command(&arguments) {
+        Ok(command) => command,
+        Err(LookupError::Unknown(_)) => return Err("ERR Unknown Redis command called from script".to_string()),
+        Err(LookupError::Rejected(..)) => return Err("ERR Wrong number of args calling Redis command from script".to_string()),
+    };
+    if spec.has(CommandFlag::NoScript) {
+        return Err("ERR This Redis command is not allowed from script".to_string());
+    }
+    if let Some(id) = context.server.current_client {
+        crate::authorize(spec, &arguments, id, context.server)?;
+    }
+    if spec.is_write() {
+        if context.read_only {
+            return Err("ERR Write commands are not allowed from read-only scripts.".to_string());
+        }
+        if context.server.current_client.is_some() && context.server.replication.lock().is_replica() {
+            return Err("READONLY You can't write against a read only replica.".to_string());
+        }
+    }
+    if context.oom && spec.has(CommandFlag::DenyOom) {
+        return Err("OOM command not allowed when used memory > 'maxmemory'.".to_string());
+    }
+    Ok((arguments, spec, handler))
+}
+
+/// The error reply of a command run with `redis.call`, failing the script.
+#[derive(Debug)]
+struct CommandError(String);
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+/// The chunk and line of the script being run, from the innermost frame of user code.
+fn script_location(lua: &Lua) -> Option<String> {
+    (0..32)
+        .map_while(|level| lua.inspect_stack(level))
+        .find_map(|frame| {
+            let source: String = frame.source().source?.into_owned();
+            source.starts_with("@user_").then(|| format!("{}:{}", source, frame.curr_line()))
+        })
+}
+
+fn reply_table<'lua>(lua: &'lua Lua, field: &str, message: &[u8]) -> mlua::Result<LuaValue<'lua>> {
+    let table: Table = lua.create_table()?;
+    table.set(field, lua.create_string(message)?)?;
+    Ok(LuaValue::Table(table))
+}
+
+/// Converts the RESP2 reply at `position` to Lua like Redis does: integers to numbers,
+/// bulk strings to strings, arrays to tables, status and error replies to `{ok = ...}` and
+/// `{err = ...}` tables, and nulls to false.
+fn reply_to_lua<'lua>(lua: &'lua Lua, reply: &[u8], position: &mut usize) -> mlua::Result<LuaValue<'lua>> {
+    let Some((line, next)) = parser::read_line(reply, *position) else {
+        return Ok(LuaValue::Nil);
+    };
+    *position = next;
+    let Some(kind) = line.chars().next() else {
+        return Ok(LuaValue::Nil);
+    };
+    let rest: &str = &line[1..];
+    match kind {
+        '+' => reply_table(lua, "ok", rest.as_bytes()),
+        '-' => reply_table(lua, "err", rest.as_bytes()),
+        ':' => Ok(LuaValue::Integer(rest.parse().unwrap_or_default())),
+        '$' => match rest.parse::<usize>() {
+            Ok(length) => {
+                let value: &[u8] = reply.get(next..next + length).unwrap_or_default();
+                *position = next + length + 2;
+                Ok(LuaValue::String(lua.create_string(value)?))
+            }
+            Err(_) => Ok(LuaValue::Boolean(false)),
+        },
+        '*' => match rest.parse::<usize>() {
+            Ok(length) => {
+                let table: Table = lua.create_table_with_capacity(length, 0)?;
+                for index in 1..=length {
+                    table.raw_set(index, reply_to_lua(lua, reply, position)?)?;
+                }
+                Ok(LuaValue::Table(table))
+            }
+            Err(_) => Ok(LuaValue::Boolean(false)),
+        },
+        _ => Ok(LuaValue::Nil),
+    }
+}
+
+/// Writes what a script returned like Redis converts it: numbers are truncated to integers,
+/// true is 1, false and nil are null, and tables are arrays up to their first nil unless
+/// they hold an `err` or `ok` field.
+fn write_lua_value(value: &LuaValue, reply: &mut ReplyWriter) {
+    match value {
+        LuaValue::Boolean(true) => reply.integer(1),
+        LuaValue::Integer(number) => reply.integer(*number),
+        LuaValue::Number(number) => reply.integer(*number as i64),
+        LuaValue::String(text) => reply.bulk(text.as_bytes()),
+        LuaValue::Table(table) => {
+            if let Ok(LuaValue::String(message)) = table.raw_get::<_, LuaValue>("err") {
+                reply.error(&message.to_string_lossy());
+            } else if let Ok(LuaValue::String(status)) = table.raw_get::<_, LuaValue>("ok") {
+                reply.simple(&status.to_string_lossy());
+            } else {
+                let elements: Vec<LuaValue> = (1..)
+                    .map_while(|index| table.raw_get::<_, LuaValue>(index).ok().filter(|element| !element.is_nil()))
+                    .collect();
+                reply.array(elements.len());
+                elements.iter().for_each(|element| write_lua_value(element, reply));
+            }
+        }
+        LuaValue::Error(e) => reply.error(&error_message(e)),
+        _ => reply.null(),
+    }
+}
+
+/// The error reply of a script that failed, like Redis: the message of the error, followed
+/// by the script and the line it failed at when that is known.
+fn script_error(error: &LuaValue, name: &str, error_location: Option<String>) -> String {
+    let (message, location): (String, Option<String>) = match error {
+        LuaValue::Table(table) => match table.raw_get::<_, LuaValue>("err") {
+            Ok(LuaValue::String(message)) => (message.to_string_lossy().into_owned(), None),
+            _ => ("ERR unknown error".to_string(), None),
+        },
+        LuaValue::String(message) => {
+            let message: String = message.to_string_lossy().into_owned();
+            // Lua prefixes the message with where it was raised, like `user_script:3:`.
+            let location: Option<String> = message.split_once(':')
+                .filter(|(chunk, _)| chunk.starts_with("user_"))
+                .and_then(|(chunk, rest)| Some((chunk, rest.split(':').next()?.parse::<i32>().ok()?)))
+                .map(|(chunk, line)| format!("@{}:{}", chunk, line));
+            (format!("ERR {}", message), location)
+        }
+        LuaValue::Error(e) => match command_error(e) {
+            Some(CommandError(message)) => (message.clone(), error_location),
+            None if error_message(e).starts_with("ERR ") => (error_message(e), None),
+            None => (format!("ERR {}", error_message(e)), None),
+        },
+        _ => ("ERR unknown error".to_string(), None),
+    };
+    match location {
+        Some(location) => format!("{} script: {}, on {}.", message, name, location),
+        None => message,
+    }
+}
+
+/// The error reply `redis.call` failed with, if that is what `error` is.
+fn command_error(error: &mlua::Error) -> Option<&CommandError> {
+    match error {
+        mlua::Error::CallbackError { cause, .. } => command_error(cause),
+        mlua::Error::ExternalError(error) => error.downcast_ref(),
+        _ => None,
+    }
+}
+
+/// The message of a Lua error, without the traceback of errors raised by Rust callbacks.
+fn error_message(error: &mlua::Error) -> String {
+    match error {
+        mlua::Error::CallbackError { cause, .. } => error_message(cause),
+        mlua::Error::RuntimeError(message) | mlua::Error::SyntaxError { message, .. } => message.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// The script being run, looked at by connection tasks while the data manager is busy with it.
+struct RunningScript {
+    /// When clients start getting BUSY errors instead of waiting for the script.
+    busy_at: Instant,
+    /// Whether the script changed the dataset, after which it cannot be killed.
+    wrote: bool,
+}
+
+static RUNNING: Mutex<Option<RunningScript>> = Mutex::new(None);
+/// Whether a script runs, so connection tasks only look at it while one does.
+static SCRIPT_RUNNING: AtomicBool = AtomicBool::new(false);
+/// Set by `SCRIPT KILL`, failing the script at its next check.
+static KILLED: AtomicBool = AtomicBool::new(false);
+
+/// Marks a script as running until it is dropped.
+struct RunningGuard;
+
+impl RunningGuard {
+    fn start(threshold: Duration) -> Self {
+        *RUNNING.lock().unwrap_or_else(PoisonError::into_inner) = Some(RunningScript {
+            busy_at: Instant::now() + threshold,
+            wrote: false,
+        });
+        KILLED.store(false, Ordering::Relaxed);
+        SCRIPT_RUNNING.store(true, Ordering::Relaxed);
+        RunningGuard
+    }
+}
+
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        *RUNNING.lock().unwrap_or_else(PoisonError::into_inner) = None;
+        SCRIPT_RUNNING.store(false, Ordering::Relaxed);
+        KILLED.store(false, Ordering::Relaxed);
+    }
+}
+
+fn record_write() {
+    if let Some(running) = RUNNING.lock().unwrap_or_else(PoisonError::into_inner).as_mut() {
+        running.wrote = true;
+    }
+}
+
+/// What a client command gets instead of waiting for a script that has run for longer than
+/// `busy-reply-threshold`: `SCRIPT KILL` stops the script unless it wrote, any other command
+/// is refused with a BUSY error. `None` when no script keeps the server busy.
+pub fn busy_reply(spec: &CommandSpec) -> Option<Result<(), String>> {
+    if !SCRIPT_RUNNING.load(Ordering::Relaxed) {
+        return None;
+    }
+    let running = RUNNING.lock().unwrap_or_else(PoisonError::into_inner);
+    let running: &RunningScript = running.as_ref().filter(|running| running.busy_at <= Instant::now())?;
+    if !spec.has(CommandFlag::AllowBusy) {
+        return Some(Err("BUSY Redis is busy running a script. You can only call SCRIPT KILL or SHUTDOWN NOSAVE.".to_string()));
+    }
+    if running.wrote {
+        return Some(Err("UNKILLABLE Sorry the script already executed write commands against the dataset. You can \
+            either wait the script termination or kill the server in a hard way using the SHUTDOWN NOSAVE command."
+            .to_string()));
+    }
+    KILLED.store(true, Ordering::Relaxed);
+    Some(Ok(()))
+}
+
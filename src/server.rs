@@ -0,0 +1,237 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{Error, ErrorKind};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use tokio::sync::{mpsc, oneshot, watch};
+use crate::Msg;
+use crate::acl::{self, Acl};
+use crate::client::{Connection, Unblock};
+use crate::cluster::ClusterState;
+use crate::command::DataRequester;
+use crate::config::Config;
+use crate::eviction::EvictionPool;
+use crate::key_value_store::KeyValueStore;
+use crate::notify::{self, KeyspaceEvents};
+use crate::pubsub::PubSub;
+use crate::quicklist::ListLimits;
+use crate::replication::ReplicationState;
+use crate::scripting::Scripting;
+use crate::stats::Stats;
+use crate::tracking::{self, Tracking, TrackingMode};
+
+/// A connected client, registered by its connection task.
+pub struct ClientInfo {
+    pub connection: Arc<Connection>,
+    /// Address of the peer, `None` for connections on the Unix socket.
+    pub address: Option<SocketAddr>,
+    /// Address of the server the peer connected to, `None` on the Unix socket.
+    pub local_address: Option<SocketAddr>,
+    /// Port a replica announced with `REPLCONF listening-port`.
+    pub listening_port: Option<u16>,
+    /// Name set with `CLIENT SETNAME` or `HELLO SETNAME`.
+    pub name: Option<String>,
+    /// Set by `CLIENT NO-EVICT`.
+    pub no_evict: bool,
+}
+
+/// Per-connection state that commands may change. It travels to the data manager with each
+/// command and comes back with the reply.
+#[derive(Default)]
+pub struct Session {
+    /// The ACL user the connection runs commands as.
+    pub user: String,
+    /// Whether the connection may run commands other than `AUTH` and `HELLO` when a password
+    /// is required.
+    pub authenticated: bool,
+    /// The connection itself, absent for the link to the master.
+    pub connection: Option<Arc<Connection>>,
+    /// Number of channels and patterns the connection subscribed to. A RESP2 connection
+    /// with subscriptions may only run the pub/sub commands.
+    pub subscriptions: usize,
+    /// The options tracking was enabled with by `CLIENT TRACKING`, if it was.
+    pub tracking: Option<TrackingMode>,
+    /// Set by `CLIENT CACHING` for the next command only.
+    pub caching: bool,
+}
+
+impl Session {
+    /// The session of a new connection, running as the default user. Connections made while
+    /// no password is required stay authenticated if one is set later.
+    pub fn new() -> Self {
+        Session {
+            user: acl::DEFAULT_USER.to_string(),
+            authenticated: !acl::password_required(),
+            connection: None,
+            subscriptions: 0,
+            tracking: None,
+            caching: false,
+        }
+    }
+
+    /// Subscribes a blocked command to `CLIENT UNBLOCK` of the connection.
+    pub fn unblocked(&self) -> Option<watch::Receiver<Unblock>> {
+        self.connection.as_ref().map(|connection| connection.unblocked())
+    }
+}
+
+/// State used by every keyspace shard, each locking it only for the duration of a call.
+pub struct Shared<T>(Arc<Mutex<T>>);
+
+impl<T> Shared<T> {
+    pub fn new(value: T) -> Self {
+        Shared(Arc::new(Mutex::new(value)))
+    }
+
+    /// Locks the value. A shard panicking while holding it cannot leave it half updated in
+    /// a way the others could not cope with, so poisoning is ignored.
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<T> Clone for Shared<T> {
+    fn clone(&self) -> Self {
+        Shared(self.0.clone())
+    }
+}
+
+/// Changes to how the running command reaches replicas, for commands whose effect they
+/// would not reproduce by running it themselves.
+#[derive(Default)]
+pub struct Propagation {
+    /// Whether the command itself is left out, like `MIGRATE`, which moves keys to another
+    /// instance and only propagates their deletion.
+    pub prevented: bool,
+    /// Commands propagated after it, like the pops of the blocked clients a push served.
+    pub also: Vec<Vec<Vec<u8>>>,
+}
+
+/// Server-wide state owned by the data manager next to the key-value store. Each keyspace
+/// shard has its own copy, sharing the replication and cluster state with the others.
+pub struct ServerState {
+    pub config: Config,
+    pub replication: Shared<ReplicationState>,
+    /// Present when running in cluster mode.
+    pub cluster: Option<Shared<ClusterState>>,
+    pub acl: Shared<Acl>,
+    /// The connection whose command is being executed, if it came from a client.
+    pub current_client: Option<u64>,
+    /// The session of that connection, given back to it once the command ran.
+    pub session: Session,
+    /// What replicas get for that command besides, or instead of, the command itself.
+    pub propagation: Propagation,
+    /// Connections that sent `ASKING`; the flag only applies to their next command.
+    pub asking: Shared<HashSet<u64>>,
+    /// Connected clients, only tracked by the coordinator.
+    pub clients: HashMap<u64, ClientInfo>,
+    pub pubsub: Shared<PubSub>,
+    /// The keys clients with tracking enabled may have cached.
+    pub tracking: Shared<Tracking>,
+    /// The Lua interpreter and the scripts it cached.
+    pub scripting: Shared<Scripting>,
+    pub stats: Stats,
+    pub eviction_pool: EvictionPool,
+    /// Number of stores splitting the memory budget, each evicting its share of the excess.
+    pub eviction_share: usize,
+}
+
+impl ServerState {
+    pub fn new(config: Config, replication: ReplicationState, cluster: Option<ClusterState>, acl: Acl) -> Self {
+        ServerState {
+            config,
+            replication: Shared::new(replication),
+            cluster: cluster.map(Shared::new),
+            acl: Shared::new(acl),
+            current_client: None,
+            session: Session::default(),
+            propagation: Propagation::default(),
+            asking: Shared::new(HashSet::new()),
+            clients: HashMap::new(),
+            pubsub: Shared::new(PubSub::new()),
+            tracking: Shared::new(Tracking::new()),
+            scripting: Shared::new(Scripting::new()),
+            stats: Stats::new(),
+            eviction_pool: EvictionPool::new(),
+            eviction_share: 1,
+        }
+    }
+
+    /// The state of one of `shards` keyspace shards, sharing what is server-wide with this one.
+    pub fn for_shard(&self, shards: usize) -> Self {
+        ServerState {
+            config: self.config.clone(),
+            replication: self.replication.clone(),
+            cluster: self.cluster.clone(),
+            acl: self.acl.clone(),
+            current_client: None,
+            session: Session::default(),
+            propagation: Propagation::default(),
+            asking: self.asking.clone(),
+            clients: HashMap::new(),
+            pubsub: self.pubsub.clone(),
+            tracking: self.tracking.clone(),
+            scripting: self.scripting.clone(),
+            stats: Stats::new(),
+            eviction_pool: EvictionPool::new(),
+            eviction_share: shards,
+        }
+    }
+
+    /// Tells the clients that may have cached `key` that it changed. `by` is the client whose
+    /// command changed it, absent when it expired or was evicted.
+    pub fn invalidate_key(&self, key: &[u8], by: Option<u64>) {
+        if tracking::active() {
+            self.tracking.lock().invalidate(key, by, &self.pubsub.lock());
+        }
+    }
+
+    /// Keeps the running command from being propagated.
+    pub fn prevent_propagation(&mut self) {
+        self.propagation.prevented = true;
+    }
+
+    /// Propagates `command` to replicas after the running command.
+    pub fn also_propagate(&mut self, command: &[&[u8]]) {
+        self.propagation.also.push(command.iter().map(|argument| argument.to_vec()).collect());
+    }
+
+    /// Publishes that `event`, of `class`, happened to `key`, if `notify-keyspace-events`
+    /// selects it.
+    pub fn notify_keyspace_event(&self, class: KeyspaceEvents, event: &str, key: &[u8]) {
+        let events: KeyspaceEvents = self.config.notify_keyspace_events;
+        if !events.publishes(class) {
+            return;
+        }
+        let pubsub: MutexGuard<'_, PubSub> = self.pubsub.lock();
+        if events.contains(KeyspaceEvents::KEYSPACE) {
+            pubsub.publish(&notify::keyspace_channel(key), event.as_bytes());
+        }
+        if events.contains(KeyspaceEvents::KEYEVENT) {
+            pubsub.publish(&notify::keyevent_channel(event), key);
+        }
+    }
+
+    /// Pushes parameters changed by `CONFIG SET` into the subsystems that use them.
+    pub fn apply_config(&mut self, store: &mut dyn KeyValueStore) {
+        self.replication.lock().resize_backlog(self.config.repl_backlog_size);
+        store.set_access_tracking(self.config.access_tracking());
+        ListLimits::set(self.config.list_max_listpack_size, self.config.list_compress_depth);
+        // Scores of a different policy are not comparable.
+        self.eviction_pool.clear();
+        if let Some(cluster) = self.cluster.as_ref() {
+            cluster.lock().set_node_timeout(self.config.cluster_node_timeout);
+        }
+    }
+}
+
+/// Sends an internal request to the data manager and waits for the value it hands back.
+pub async fn request<T, R, F>(store_tx: &mpsc::Sender<Msg>, build: F) -> Result<T, Error>
+where
+    R: DataRequester + Send + 'static,
+    F: FnOnce(oneshot::Sender<T>) -> R,
+{
+    let (value_tx, value_rx): (oneshot::Sender<T>, oneshot::Receiver<T>) = oneshot::channel();
+    store_tx.send(Msg::Internal(Box::new(build(value_tx)))).await
+        .map_err(|_| Error::new(ErrorKind::BrokenPipe, "Data manager stopped"))?;
+    value_rx.await.map_err(|_| Error::new(ErrorKind::BrokenPipe, "Data manager dropped the request"))
+}
@@ -0,0 +1,333 @@
+use std::io::{Error, ErrorKind};
+use tokio::sync::{mpsc, oneshot};
+use crate::{CommandMsg, Msg};
+use crate::cluster::key_hash_slot;
+use crate::command::{CommandFlag, CommandGroup, CommandSpec};
+use crate::config::Config;
+use crate::expire;
+use crate::key_value_store::{
+    AccessInfo, AccessTracking, BlpopWaiter, InMemoryKeyValueStore, KeyValueStore, KeyValueStoreEntry, Sample,
+};
+use crate::random;
+use crate::server::ServerState;
+use crate::stats::Stats;
+
+/// Index of the shard holding `key`. Keys are spread by their cluster hash slot, so keys
+/// sharing a hash tag, like `{user1}:name` and `{user1}:age`, end up in the same shard.
+fn shard_index(key: &str, shards: usize) -> usize {
+    key_hash_slot(key) as usize % shards
+}
+
+/// Whether a command reads or changes the keyspace: it has keys, or works on the keyspace
+/// as a whole. Administrative commands, like `CONFIG SET` or `DEBUG`, and scripts are
+/// counted in, as are the keyless commands reporting on every key.
+fn uses_keyspace(spec: &CommandSpec, arguments: &[Vec<u8>]) -> bool {
+    spec.key_arguments(arguments).next().is_some()
+        || spec.has(CommandFlag::Admin)
+        || spec.docs.group == CommandGroup::Scripting
+        || matches!(spec.name, "info" | "memory|stats" | "memory|doctor" | "cluster|countkeysinslot" | "cluster|getkeysinslot")
+}
+
+/// What a shard hands over to the coordinator: its whole store, and the statistics it
+/// gathered since the previous loan.
+struct Loan {
+    store: Box<dyn KeyValueStore>,
+    stats: Stats,
+}
+
+enum ShardMsg {
+    Command(CommandMsg),
+    /// Lends the store to the coordinator, which sends it back with the configuration as
+    /// it stands after its command.
+    Lend {
+        loan_tx: oneshot::Sender<Loan>,
+        return_rx: oneshot::Receiver<(Box<dyn KeyValueStore>, Config)>,
+    },
+}
+
+/// Sends commands to the task that can run them: the shard holding their keys when they
+/// all live in the same one, the coordinator for everything else.
+#[derive(Clone)]
+pub struct Router {
+    coordinator: mpsc::Sender<Msg>,
+    shards: Vec<mpsc::Sender<ShardMsg>>,
+}
+
+impl Router {
+    /// A router sending everything to the data manager, for an unsharded keyspace.
+    pub fn unsharded(coordinator: mpsc::Sender<Msg>) -> Self {
+        Router { coordinator, shards: Vec::new() }
+    }
+
+    pub fn coordinator(&self) -> &mpsc::Sender<Msg> {
+        &self.coordinator
+    }
+
+    /// The shard holding every key of `command`, if it has keys and they are all there.
+    fn route(&self, command: &CommandMsg) -> Option<&mpsc::Sender<ShardMsg>> {
+        // Scripts may run commands on keys they were not given, so they see the whole keyspace.
+        if self.shards.is_empty() || command.spec.docs.group == CommandGroup::Scripting {
+            return None;
+        }
+
+        let mut keys = command.spec.keys(&command.arguments);
+        let shard: usize = shard_index(keys.next()?, self.shards.len());
+        keys.all(|key| shard_index(key, self.shards.len()) == shard)
+            .then(|| &self.shards[shard])
+    }
+
+    pub async fn send(&self, command: CommandMsg) -> Result<(), Error> {
+        let sent: bool = match self.route(&command) {
+            Some(shard) => shard.send(ShardMsg::Command(command)).await.is_ok(),
+            None => self.coordinator.send(Msg::Command(command)).await.is_ok(),
+        };
+        if sent { Ok(()) } else { Err(Error::new(ErrorKind::BrokenPipe, "Data manager stopped")) }
+    }
+}
+
+/// The keyspace seen by commands the coordinator runs: the stores every shard lent it,
+/// each key being looked up in the one holding it.
+pub struct ShardedKeyValueStore {
+    shards: Vec<Box<dyn KeyValueStore>>,
+}
+
+impl ShardedKeyValueStore {
+    pub fn new(shards: usize) -> Self {
+        ShardedKeyValueStore {
+            shards: (0..shards).map(|_| Box::new(InMemoryKeyValueStore::new()) as Box<dyn KeyValueStore>).collect(),
+        }
+    }
+
+    fn shard(&self, key: &str) -> &dyn KeyValueStore {
+        self.shards[shard_index(key, self.shards.len())].as_ref()
+    }
+
+    fn shard_mut(&mut self, key: &str) -> &mut Box<dyn KeyValueStore> {
+        let shard: usize = shard_index(key, self.shards.len());
+        &mut self.shards[shard]
+    }
+}
+
+impl KeyValueStore for ShardedKeyValueStore {
+    fn insert(
+        &mut self,
+        key: String,
+        entry: Box<dyn KeyValueStoreEntry>
+    ) -> Option<Box<dyn KeyValueStoreEntry>> {
+        self.shard_mut(&key).insert(key, entry)
+    }
+
+    fn get(&self, key: &str) -> Option<&dyn KeyValueStoreEntry> {
+        self.shard(key).get(key)
+    }
+
+    fn get_mut(&mut self, key: &str) -> Option<&mut Box<dyn KeyValueStoreEntry>> {
+        self.shard_mut(key).get_mut(key)
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Box<dyn KeyValueStoreEntry>> {
+        self.shard_mut(key).remove(key)
+    }
+
+    fn ensure_exists_and_get_mut(
+        &mut self,
+        key: String,
+        factory_fn: fn() -> Box<dyn KeyValueStoreEntry>
+    ) -> &mut Box<dyn KeyValueStoreEntry> {
+        self.shard_mut(&key).ensure_exists_and_get_mut(key, factory_fn)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (&String, &dyn KeyValueStoreEntry)> + '_> {
+        Box::new(self.shards.iter().flat_map(|shard| shard.iter()))
+    }
+
+    fn peek(&self, key: &str) -> Option<(&dyn KeyValueStoreEntry, &AccessInfo)> {
+        self.shard(key).peek(key)
+    }
+
+    /// Picks every sample from a shard chosen in proportion to its number of keys, so all
+    /// keys are equally likely to be picked whatever their shard.
+    fn sample(&self, count: usize, volatile_only: bool) -> Vec<Sample<'_>> {
+        let sizes: Vec<usize> = self.shards.iter()
+            .map(|shard| if volatile_only { shard.volatile_key_count() } else { shard.key_count() })
+            .collect();
+        let total: usize = sizes.iter().sum();
+        if total == 0 {
+            return Vec::new();
+        }
+
+        let mut samples: Vec<Sample<'_>> = Vec::with_capacity(count);
+        for _ in 0..count {
+            let mut position: usize = random::random_below(total as u64) as usize;
+            let shard: usize = sizes.iter()
+                .position(|size| {
+                    let found: bool = position < *size;
+                    position = position.saturating_sub(*size);
+                    found
+                })
+                .expect("the position is below the total number of keys");
+            samples.extend(self.shards[shard].sample(1, volatile_only));
+        }
+        samples
+    }
+
+    fn set_access_tracking(&mut self, tracking: AccessTracking) {
+        for shard in &mut self.shards {
+            shard.set_access_tracking(tracking);
+        }
+    }
+
+    fn key_count(&self) -> usize {
+        self.shards.iter().map(|shard| shard.key_count()).sum()
+    }
+
+    fn volatile_key_count(&self) -> usize {
+        self.shards.iter().map(|shard| shard.volatile_key_count()).sum()
+    }
+
+    fn clear(&mut self) {
+        for shard in &mut self.shards {
+            shard.clear();
+        }
+    }
+
+    fn add_blpop_waiter(&mut self, key: &str, waiter: BlpopWaiter) {
+        self.shard_mut(key).add_blpop_waiter(key, waiter)
+    }
+
+    fn serve_blpop_waiters(&mut self, key: &str) -> usize {
+        self.shard_mut(key).serve_blpop_waiters(key)
+    }
+
+    fn into_shards(self: Box<Self>) -> Vec<Box<dyn KeyValueStore>> {
+        self.shards
+    }
+}
+
+/// Starts a task for each of the stores `shards`, and the coordinator taking the commands
+/// they cannot run on their own from `rx`. Returns the router clients send commands with.
+pub fn spawn(
+    rx: mpsc::Receiver<Msg>,
+    coordinator_tx: mpsc::Sender<Msg>,
+    shards: Vec<Box<dyn KeyValueStore>>,
+    server: ServerState
+) -> Router {
+    let count: usize = shards.len();
+    let shard_txs: Vec<mpsc::Sender<ShardMsg>> = shards.into_iter()
+        .map(|store| {
+            let (tx, rx): (mpsc::Sender<ShardMsg>, mpsc::Receiver<ShardMsg>) = mpsc::channel(100);
+            tokio::spawn(run_shard(rx, store, server.for_shard(count)));
+            tx
+        })
+        .collect();
+
+    let router: Router = Router { coordinator: coordinator_tx, shards: shard_txs };
+    tokio::spawn(coordinate(rx, router.clone(), server));
+    router
+}
+
+async fn run_shard(mut rx: mpsc::Receiver<ShardMsg>, mut store: Box<dyn KeyValueStore>, mut server: ServerState) {
+    let mut expire_interval: tokio::time::Interval = tokio::time::interval(expire::ACTIVE_EXPIRE_PERIOD);
+    loop {
+        let message: ShardMsg = tokio::select! {
+            message = rx.recv() => match message {
+                Some(message) => message,
+                None => return,
+            },
+            // Each shard deletes the expired keys of its own store.
+            _ = expire_interval.tick() => {
+                expire::active_expire_cycle(&mut store, &mut server);
+                continue;
+            }
+        };
+        match message {
+            ShardMsg::Command(command) => crate::process(command, &mut store, &mut server),
+            ShardMsg::Lend { loan_tx, return_rx } => {
+                let mut stats: Stats = Stats::new();
+                stats.absorb(&mut server.stats);
+                let lent: Box<dyn KeyValueStore> = std::mem::replace(&mut store, Box::new(InMemoryKeyValueStore::new()));
+                if let Err(loan) = loan_tx.send(Loan { store: lent, stats }) {
+                    store = loan.store;
+                    continue;
+                }
+
+                match return_rx.await {
+                    Ok((returned, config)) => {
+                        store = returned;
+                        // Applying the configuration clears what the shard gathered, like
+                        // its eviction candidates, so it is only done when it changed.
+                        let changed: bool = config.generation != server.config.generation;
+                        server.config = config;
+                        if changed {
+                            server.apply_config(store.as_mut());
+                        }
+                    }
+                    // The coordinator only stops when the server does.
+                    Err(_) => return,
+                }
+            }
+        }
+    }
+}
+
+/// The data manager of a sharded keyspace. Commands with keys in a single shard are passed
+/// on to it, and those not using the keyspace, like `PING` or `CLIENT SETNAME`, run here
+/// while the shards keep going. For any other, every shard lends its store so the command
+/// runs over the whole keyspace, with no shard running anything in the meantime. Internal
+/// requests are run the same way, depending on whether they use the keyspace.
+async fn coordinate(mut rx: mpsc::Receiver<Msg>, router: Router, mut server: ServerState) {
+    // What commands not using the keyspace are given as their store; nothing is ever added.
+    let mut detached: Box<dyn KeyValueStore> = Box::new(InMemoryKeyValueStore::new());
+    while let Some(message) = rx.recv().await {
+        let shard: Option<&mpsc::Sender<ShardMsg>> = match &message {
+            Msg::Command(command) => router.route(command),
+            Msg::Internal(_) => None,
+        };
+        let message: Msg = match (shard, message) {
+            (Some(shard), Msg::Command(command)) => {
+                let _ = shard.send(ShardMsg::Command(command)).await;
+                continue;
+            }
+            (None, Msg::Command(command)) if !uses_keyspace(command.spec, &command.arguments) => {
+                crate::process(command, &mut detached, &mut server);
+                continue;
+            }
+            (None, Msg::Internal(request)) if !request.uses_keyspace() => {
+                request.request(&mut detached, &mut server);
+                continue;
+            }
+            (_, message) => message,
+        };
+
+        let mut loans: Vec<oneshot::Receiver<Loan>> = Vec::with_capacity(router.shards.len());
+        let mut returns: Vec<oneshot::Sender<(Box<dyn KeyValueStore>, Config)>> = Vec::with_capacity(router.shards.len());
+        for shard in &router.shards {
+            let (loan_tx, loan_rx): (oneshot::Sender<Loan>, oneshot::Receiver<Loan>) = oneshot::channel();
+            let (return_tx, return_rx) = oneshot::channel();
+            if shard.send(ShardMsg::Lend { loan_tx, return_rx }).await.is_err() {
+                return;
+            }
+            loans.push(loan_rx);
+            returns.push(return_tx);
+        }
+
+        let mut shards: Vec<Box<dyn KeyValueStore>> = Vec::with_capacity(loans.len());
+        for loan_rx in loans {
+            let mut loan: Loan = match loan_rx.await {
+                Ok(loan) => loan,
+                Err(_) => return,
+            };
+            server.stats.absorb(&mut loan.stats);
+            shards.push(loan.store);
+        }
+
+        let mut store: Box<dyn KeyValueStore> = Box::new(ShardedKeyValueStore { shards });
+        match message {
+            Msg::Command(command) => crate::process(command, &mut store, &mut server),
+            Msg::Internal(request) => request.request(&mut store, &mut server),
+        }
+        for (shard, return_tx) in store.into_shards().into_iter().zip(returns) {
+            let _ = return_tx.send((shard, server.config.clone()));
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Bytes read from and written to client connections, counted by the connection tasks.
+pub static NET_INPUT_BYTES: AtomicU64 = AtomicU64::new(0);
+pub static NET_OUTPUT_BYTES: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Default)]
+pub struct CommandStats {
+    pub calls: u64,
+    pub usec: u64,
+    /// Calls refused before running, e.g. redirected to another cluster node.
+    pub rejected_calls: u64,
+    /// Calls that ran and replied with an error.
+    pub failed_calls: u64,
+}
+
+/// The counters of `name`, only allocating its key the first time it is seen.
+fn command_stats<'a>(commands: &'a mut BTreeMap<String, CommandStats>, name: &str) -> &'a mut CommandStats {
+    if !commands.contains_key(name) {
+        commands.insert(name.to_string(), CommandStats::default());
+    }
+    commands.get_mut(name).expect("the command was just inserted")
+}
+
+/// Counters reported by `INFO`. Everything except the startup information and the
+/// changes since the last save is cleared by `CONFIG RESETSTAT`.
+pub struct Stats {
+    pub run_id: String,
+    pub started: Instant,
+    pub start_time: SystemTime,
+    /// Writes since the dataset was last loaded or saved.
+    pub dirty: u64,
+    /// When the dataset was last loaded or saved.
+    pub last_save_time: SystemTime,
+    pub total_connections_received: u64,
+    pub total_commands_processed: u64,
+    pub total_error_replies: u64,
+    pub keyspace_hits: u64,
+    pub keyspace_misses: u64,
+    pub expired_keys: u64,
+    pub evicted_keys: u64,
+    pub sync_full: u64,
+    pub sync_partial_ok: u64,
+    pub sync_partial_err: u64,
+    commands: BTreeMap<String, CommandStats>,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Stats {
+            run_id: crate::replication::generate_replication_id(),
+            started: Instant::now(),
+            start_time: SystemTime::now(),
+            dirty: 0,
+            last_save_time: SystemTime::now(),
+            total_connections_received: 0,
+            total_commands_processed: 0,
+            total_error_replies: 0,
+            keyspace_hits: 0,
+            keyspace_misses: 0,
+            expired_keys: 0,
+            evicted_keys: 0,
+            sync_full: 0,
+            sync_partial_ok: 0,
+            sync_partial_err: 0,
+            commands: BTreeMap::new(),
+        }
+    }
+
+    pub fn record_call(&mut self, name: &str, duration: Duration, failed: bool) {
+        let stats: &mut CommandStats = command_stats(&mut self.commands, name);
+        stats.calls += 1;
+        stats.usec += duration.as_micros() as u64;
+        self.total_commands_processed += 1;
+        if failed {
+            stats.failed_calls += 1;
+            self.total_error_replies += 1;
+        }
+    }
+
+    pub fn record_rejection(&mut self, name: &str) {
+        command_stats(&mut self.commands, name).rejected_calls += 1;
+        self.total_error_replies += 1;
+    }
+
+    /// Counts a read lookup of a key, as done by commands like `GET` and `LRANGE`.
+    pub fn record_lookup(&mut self, hit: bool) {
+        if hit {
+            self.keyspace_hits += 1;
+        } else {
+            self.keyspace_misses += 1;
+        }
+    }
+
+    pub fn commands(&self) -> impl Iterator<Item = (&String, &CommandStats)> {
+        self.commands.iter()
+    }
+
+    /// Moves the counters of a keyspace shard into these, leaving the shard's at zero.
+    pub fn absorb(&mut self, shard: &mut Stats) {
+        self.dirty += std::mem::take(&mut shard.dirty);
+        self.total_commands_processed += std::mem::take(&mut shard.total_commands_processed);
+        self.total_error_replies += std::mem::take(&mut shard.total_error_replies);
+        self.keyspace_hits += std::mem::take(&mut shard.keyspace_hits);
+        self.keyspace_misses += std::mem::take(&mut shard.keyspace_misses);
+        self.expired_keys += std::mem::take(&mut shard.expired_keys);
+        self.evicted_keys += std::mem::take(&mut shard.evicted_keys);
+        for (name, command) in std::mem::take(&mut shard.commands) {
+            let stats: &mut CommandStats = self.commands.entry(name).or_default();
+            stats.calls += command.calls;
+            stats.usec += command.usec;
+            stats.rejected_calls += command.rejected_calls;
+            stats.failed_calls += command.failed_calls;
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Stats {
+            run_id: std::mem::take(&mut self.run_id),
+            started: self.started,
+            start_time: self.start_time,
+            dirty: self.dirty,
+            last_save_time: self.last_save_time,
+            ..Stats::new()
+        };
+        NET_INPUT_BYTES.store(0, Ordering::Relaxed);
+        NET_OUTPUT_BYTES.store(0, Ordering::Relaxed);
+    }
+}
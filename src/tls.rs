@@ -0,0 +1,138 @@
+use std::sync::{Arc, PoisonError, RwLock};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use crate::config::Config;
+
+/// A connection to or from the server, encrypted or not.
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// Whether clients connecting to the TLS port must present a certificate signed by the CA.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ClientAuth {
+    Required,
+    Optional,
+    Disabled,
+}
+
+impl ClientAuth {
+    pub fn parse(name: &str) -> Option<ClientAuth> {
+        match name.to_ascii_lowercase().as_str() {
+            "yes" => Some(ClientAuth::Required),
+            "optional" => Some(ClientAuth::Optional),
+            "no" => Some(ClientAuth::Disabled),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ClientAuth::Required => "yes",
+            ClientAuth::Optional => "optional",
+            ClientAuth::Disabled => "no",
+        }
+    }
+}
+
+/// The TLS settings in effect: how connections to the TLS port are accepted, and how a
+/// replica connects to its master.
+struct TlsContext {
+    acceptor: TlsAcceptor,
+    connector: TlsConnector,
+    replication: bool,
+}
+
+/// Replaced as a whole when `CONFIG SET` changes a TLS parameter, so new connections use
+/// the new certificates while established ones keep theirs.
+static CONTEXT: RwLock<Option<Arc<TlsContext>>> = RwLock::new(None);
+
+/// Whether the configuration asks for TLS at all, on the TLS port or to reach the master.
+pub fn enabled(config: &Config) -> bool {
+    config.tls_port != 0 || config.tls_replication
+}
+
+/// Loads the certificates named by the configuration and makes them the ones in effect,
+/// keeping the previous ones if they cannot be loaded.
+pub fn configure(config: &Config) -> Result<(), String> {
+    let context: Option<Arc<TlsContext>> = if enabled(config) {
+        Some(Arc::new(build_context(config)?))
+    } else {
+        None
+    };
+    *CONTEXT.write().unwrap_or_else(PoisonError::into_inner) = context;
+    Ok(())
+}
+
+/// Accepts handshakes on the TLS port, if TLS is configured.
+pub fn acceptor() -> Option<TlsAcceptor> {
+    current().map(|context| context.acceptor.clone())
+}
+
+/// Connects a replica to its master, if `tls-replication` is enabled.
+pub fn replication_connector() -> Option<TlsConnector> {
+    current().filter(|context| context.replication).map(|context| context.connector.clone())
+}
+
+fn current() -> Option<Arc<TlsContext>> {
+    CONTEXT.read().unwrap_or_else(PoisonError::into_inner).clone()
+}
+
+fn build_context(config: &Config) -> Result<TlsContext, String> {
+    let certificate_file: &str = config.tls_cert_file.as_deref().ok_or("tls-cert-file is not set")?;
+    let key_file: &str = config.tls_key_file.as_deref().ok_or("tls-key-file is not set")?;
+    let certificates: Vec<CertificateDer<'static>> = CertificateDer::pem_file_iter(certificate_file)
+        .and_then(|certificates| certificates.collect())
+        .map_err(|e| format!("Failed to load certificate: {}: {}", certificate_file, e))?;
+    let key: PrivateKeyDer<'static> = PrivateKeyDer::from_pem_file(key_file)
+        .map_err(|e| format!("Failed to load private key: {}: {}", key_file, e))?;
+
+    // Peers are verified against the CA, which client authentication and replication need.
+    let roots: Option<Arc<RootCertStore>> = match config.tls_ca_cert_file.as_deref() {
+        Some(ca_file) => Some(Arc::new(load_roots(ca_file)?)),
+        None => None,
+    };
+    let needs_roots: bool = config.tls_auth_clients != ClientAuth::Disabled || config.tls_replication;
+    if roots.is_none() && needs_roots {
+        return Err("tls-ca-cert-file must be set to verify peer certificates".to_string());
+    }
+
+    let server_builder = ServerConfig::builder();
+    let server_builder = match (config.tls_auth_clients, roots.clone()) {
+        (ClientAuth::Disabled, _) | (_, None) => server_builder.with_no_client_auth(),
+        (client_auth, Some(roots)) => {
+            let verifier = WebPkiClientVerifier::builder(roots);
+            let verifier = if client_auth == ClientAuth::Optional { verifier.allow_unauthenticated() } else { verifier };
+            server_builder.with_client_cert_verifier(verifier.build().map_err(|e| e.to_string())?)
+        }
+    };
+    let server: ServerConfig = server_builder.with_single_cert(certificates.clone(), key.clone_key())
+        .map_err(|e| format!("Invalid certificate or private key: {}", e))?;
+
+    // A replica presents the server certificate to its master, which may require one.
+    let client: ClientConfig = ClientConfig::builder()
+        .with_root_certificates(roots.unwrap_or_else(|| Arc::new(RootCertStore::empty())))
+        .with_client_auth_cert(certificates, key)
+        .map_err(|e| format!("Invalid certificate or private key: {}", e))?;
+
+    Ok(TlsContext {
+        acceptor: TlsAcceptor::from(Arc::new(server)),
+        connector: TlsConnector::from(Arc::new(client)),
+        replication: config.tls_replication,
+    })
+}
+
+fn load_roots(ca_file: &str) -> Result<RootCertStore, String> {
+    let mut roots: RootCertStore = RootCertStore::empty();
+    let certificates: Vec<CertificateDer<'static>> = CertificateDer::pem_file_iter(ca_file)
+        .and_then(|certificates| certificates.collect())
+        .map_err(|e| format!("Failed to load CA certificate(s) file: {}: {}", ca_file, e))?;
+    for certificate in certificates {
+        roots.add(certificate).map_err(|e| format!("Invalid CA certificate in {}: {}", ca_file, e))?;
+    }
+    Ok(roots)
+}
@@ -0,0 +1,225 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::client::Connection;
+use crate::pubsub::PubSub;
+use crate::reply::{Protocol, Value};
+
+/// The channel RESP2 connections subscribe to for the invalidations redirected to them.
+pub const INVALIDATION_CHANNEL: &str = "__redis__:invalidate";
+
+/// The options of `CLIENT TRACKING` a connection enabled tracking with.
+#[derive(Clone, Copy, Default)]
+pub struct TrackingMode {
+    /// Invalidations are sent for every key matching a prefix, whether it was read or not.
+    pub bcast: bool,
+    /// Only the keys read right after `CLIENT CACHING yes` are tracked.
+    pub optin: bool,
+    /// The keys read right after `CLIENT CACHING no` are not tracked.
+    pub optout: bool,
+    /// The connection is not told about the keys it changed itself.
+    pub noloop: bool,
+}
+
+impl TrackingMode {
+    /// Whether the keys read by a command are tracked, given whether `CLIENT CACHING`
+    /// preceded it.
+    pub fn remembers_keys(&self, caching: bool) -> bool {
+        if self.bcast {
+            return false;
+        }
+        if caching { !self.optout } else { !self.optin }
+    }
+}
+
+/// A connection with tracking enabled.
+struct TrackedClient {
+    connection: Arc<Connection>,
+    mode: TrackingMode,
+    /// The connection receiving the invalidations in its place.
+    redirect: Option<Arc<Connection>>,
+    /// Prefixes of the keys it is told about in BCAST mode.
+    prefixes: Vec<Vec<u8>>,
+    /// Whether the connection it redirects to is gone.
+    broken_redirect: bool,
+}
+
+/// What `CLIENT TRACKINGINFO` and `CLIENT LIST` tell about the tracking of a connection.
+pub struct TrackingInfo {
+    pub mode: TrackingMode,
+    /// Id of the connection receiving the invalidations, 0 without redirection.
+    pub redirect: u64,
+    pub prefixes: Vec<Vec<u8>>,
+    pub broken_redirect: bool,
+}
+
+/// Number of connections with tracking enabled, so changing keys does not lock the table
+/// while there are none.
+static TRACKING_CLIENTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether some connection has tracking enabled.
+pub fn active() -> bool {
+    TRACKING_CLIENTS.load(Ordering::Relaxed) > 0
+}
+
+/// The keys connections read and may have cached, shared by the data manager and every
+/// keyspace shard, telling the connections when they change.
+#[derive(Default)]
+pub struct Tracking {
+    /// Connections that read each key since it last changed, by client id.
+    keys: HashMap<Vec<u8>, HashSet<u64>>,
+    /// Connections in BCAST mode following each prefix.
+    prefixes: HashMap<Vec<u8>, HashSet<u64>>,
+    clients: HashMap<u64, TrackedClient>,
+}
+
+impl Tracking {
+    pub fn new() -> Self {
+        Tracking::default()
+    }
+
+    /// Enables tracking for a connection, or changes the options it is enabled with. Prefixes
+    /// add up to those it follows already; BCAST without any follows every key.
+    pub fn enable(&mut self, connection: Arc<Connection>, mode: TrackingMode, redirect: Option<Arc<Connection>>,
+        prefixes: &[&[u8]]) {
+        let id: u64 = connection.id;
+        if !self.clients.contains_key(&id) {
+            TRACKING_CLIENTS.fetch_add(1, Ordering::Relaxed);
+        }
+        let client: &mut TrackedClient = self.clients.entry(id).or_insert_with(|| TrackedClient {
+            connection,
+            mode,
+            redirect: None,
+            prefixes: Vec::new(),
+            broken_redirect: false,
+        });
+        client.mode = mode;
+        client.redirect = redirect;
+        client.broken_redirect = false;
+
+        let mut added: Vec<&[u8]> = prefixes.to_vec();
+        if mode.bcast && added.is_empty() && client.prefixes.is_empty() {
+            added.push(b"");
+        }
+        for prefix in added {
+            if !client.prefixes.iter().any(|existing| existing == prefix) {
+                client.prefixes.push(prefix.to_vec());
+                self.prefixes.entry(prefix.to_vec()).or_default().insert(id);
+            }
+        }
+    }
+
+    /// Disables tracking for a connection. Keys it read stay in the table until they
+    /// change, when they are found to be no longer tracked by it.
+    pub fn disable(&mut self, id: u64) {
+        let Some(client) = self.clients.remove(&id) else {
+            return;
+        };
+        TRACKING_CLIENTS.fetch_sub(1, Ordering::Relaxed);
+        for prefix in client.prefixes {
+            if let Some(followers) = self.prefixes.get_mut(&prefix) {
+                followers.remove(&id);
+                if followers.is_empty() {
+                    self.prefixes.remove(&prefix);
+                }
+            }
+        }
+    }
+
+    pub fn info(&self, id: u64) -> Option<TrackingInfo> {
+        self.clients.get(&id).map(|client| TrackingInfo {
+            mode: client.mode,
+            redirect: client.redirect.as_ref().map_or(0, |redirect| redirect.id),
+            prefixes: client.prefixes.clone(),
+            broken_redirect: client.broken_redirect,
+        })
+    }
+
+    /// The prefix of `prefixes`, or of those the connection follows already, that `prefix`
+    /// overlaps with, one being the start of the other. The first element tells whether it
+    /// is one the connection follows already.
+    pub fn overlapping_prefix<'a>(&'a self, id: u64, prefix: &[u8], prefixes: &[&'a [u8]]) -> Option<(bool, &'a [u8])> {
+        let overlaps = |other: &[u8]| other.starts_with(prefix) || prefix.starts_with(other);
+        let existing: &[Vec<u8>] = self.clients.get(&id).map_or(&[], |client| &client.prefixes);
+        // Following a prefix again changes nothing.
+        if let Some(other) = existing.iter().find(|other| other.as_slice() != prefix && overlaps(other)) {
+            return Some((true, other));
+        }
+        prefixes.iter().find(|other| overlaps(other)).map(|other| (false, *other))
+    }
+
+    /// Number of connections with tracking enabled.
+    pub fn client_count(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// Number of keys tracked, of connections tracking them summed over the keys, and of
+    /// prefixes followed in BCAST mode, as `INFO` reports them.
+    pub fn totals(&self) -> (usize, usize, usize) {
+        let items: usize = self.keys.values().map(HashSet::len).sum();
+        (self.keys.len(), items, self.prefixes.len())
+    }
+
+    /// Remembers that a connection read `keys`, to tell it when they change.
+    pub fn remember_keys<'a>(&mut self, id: u64, keys: impl Iterator<Item = &'a [u8]>) {
+        for key in keys {
+            match self.keys.get_mut(key) {
+                Some(readers) => {
+                    readers.insert(id);
+                }
+                None => {
+                    self.keys.insert(key.to_vec(), HashSet::from([id]));
+                }
+            }
+        }
+    }
+
+    /// Tells the connections that read `key`, or follow a prefix of it, that it changed.
+    /// `by` is the client whose command changed it, which NOLOOP connections are not told
+    /// about; expired and evicted keys have none.
+    pub fn invalidate(&mut self, key: &[u8], by: Option<u64>, pubsub: &PubSub) {
+        let mut targets: HashSet<u64> = self.keys.remove(key).unwrap_or_default();
+        for length in 0..=key.len() {
+            if let Some(followers) = self.prefixes.get(&key[..length]) {
+                targets.extend(followers);
+            }
+        }
+
+        for id in targets {
+            let Some(client) = self.clients.get_mut(&id) else {
+                continue;
+            };
+            if client.mode.noloop && by == Some(id) {
+                continue;
+            }
+            let keys: Value = Value::Array(vec![Value::Bulk(key.to_vec())]);
+            let Some(redirect) = client.redirect.as_ref() else {
+                // A RESP2 connection cannot receive invalidations on its own.
+                if client.connection.activity().protocol == Protocol::Resp3 {
+                    client.connection.push(Value::Push(vec![Value::bulk("invalidate"), keys]));
+                }
+                continue;
+            };
+
+            let delivered: bool = if !redirect.is_open() {
+                false
+            } else if redirect.activity().protocol == Protocol::Resp3 {
+                redirect.push(Value::Push(vec![Value::bulk("invalidate"), keys]))
+            } else if pubsub.count(redirect.id) > 0 {
+                redirect.push(Value::Push(vec![Value::bulk("message"), Value::bulk(INVALIDATION_CHANNEL), keys]))
+            } else {
+                // Like its own connection, a RESP2 connection that did not subscribe is not told.
+                true
+            };
+            if !delivered {
+                client.broken_redirect = true;
+                if client.connection.activity().protocol == Protocol::Resp3 {
+                    client.connection.push(Value::Push(vec![
+                        Value::bulk("tracking-redir-broken"),
+                        Value::Integer(redirect.id as i64),
+                    ]));
+                }
+            }
+        }
+    }
+}